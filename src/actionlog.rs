@@ -0,0 +1,123 @@
+//! A record of the actions the editor carried out and the keys typed for
+//! them, to make editing bugs reproducible. `:actionlog` dumps it, and
+//! `vim-rs --replay LOG FILE` types its keys into a headless editor on
+//! `FILE` again, checking that the buffer goes through the same revisions.
+//!
+//! Dumped, each action is a line of the buffer revision it ran at, the keys
+//! typed since the one before in vim's notation, and the action, separated
+//! by tabs. Actions not typed, such as language server replies, have no
+//! keys and are not replayed. A header of `#` lines names the buffer and
+//! the terminal size.
+
+use std::collections::VecDeque;
+
+use anyhow::{bail, Context};
+use crossterm::event::KeyEvent;
+
+use crate::keys;
+
+/// Most actions kept, the oldest being dropped first.
+const CAPACITY: usize = 1000;
+
+/// Longest an action is described, as those carrying server replies can
+/// be long.
+const MAX_ACTION_LEN: usize = 200;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Buffer revision the action ran at.
+    pub revision: usize,
+    /// Keys typed since the previous action, in vim's notation.
+    pub keys: String,
+    pub action: String,
+}
+
+/// An action log read back for replay.
+pub struct Recording {
+    /// Terminal size the actions ran in.
+    pub size: (u16, u16),
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Default)]
+pub struct ActionLog {
+    entries: VecDeque<Entry>,
+    /// Keys typed since the last action.
+    keys: String,
+}
+
+impl ActionLog {
+    /// Notes a key press, to be recorded with the action it leads to.
+    pub fn key(&mut self, key: &KeyEvent) {
+        if let Some(notation) = keys::notation(key) {
+            self.keys.push_str(&notation);
+        }
+    }
+
+    pub fn record(&mut self, mut action: String, revision: usize) {
+        if let Some((end, _)) = action.char_indices().nth(MAX_ACTION_LEN) {
+            action.truncate(end);
+            action.push('…');
+        }
+        if self.entries.len() == CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry {
+            revision,
+            keys: std::mem::take(&mut self.keys),
+            action,
+        });
+    }
+
+    /// The log as dumped, for the buffer at `path` in a terminal of `size`.
+    pub fn lines(&self, path: &str, size: (u16, u16)) -> Vec<String> {
+        let mut lines = vec![
+            format!("# vim-rs action log of {path}"),
+            format!("# size {}x{}", size.0, size.1),
+        ];
+        lines.extend(
+            self.entries
+                .iter()
+                .map(|entry| format!("{}\t{}\t{}", entry.revision, entry.keys, entry.action)),
+        );
+        lines
+    }
+}
+
+/// Reads back a dumped log.
+pub fn parse(text: &str) -> anyhow::Result<Recording> {
+    let mut size = None;
+    let mut entries = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if let Some(comment) = line.strip_prefix('#') {
+            if let Some((width, height)) = comment
+                .trim()
+                .strip_prefix("size ")
+                .and_then(|size| size.split_once('x'))
+            {
+                size = Some((width.parse()?, height.parse()?));
+            }
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let mut fields = line.splitn(3, '\t');
+        let (Some(revision), Some(keys), Some(action)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            bail!("Line {} is not a logged action", i + 1);
+        };
+        entries.push(Entry {
+            revision: revision
+                .parse()
+                .with_context(|| format!("Line {} has no revision", i + 1))?,
+            keys: keys.to_string(),
+            action: action.to_string(),
+        });
+    }
+    Ok(Recording {
+        size: size.context("The log has no size")?,
+        entries,
+    })
+}
@@ -0,0 +1,189 @@
+use std::collections::HashSet;
+use std::fs;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use ropey::Rope;
+
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::linters;
+
+/// Inactivity after an edit before analyzers are run on the buffer.
+pub const IDLE_DELAY: Duration = Duration::from_millis(500);
+
+/// Word lists looked up by the spell checker, first one found wins.
+const DICTIONARIES: &[&str] = &[
+    "/usr/share/dict/words",
+    "/usr/share/dict/american-english",
+    "/usr/share/dict/british-english",
+];
+
+/// An immutable copy of the buffer handed to analyzers.
+pub struct Snapshot {
+    pub path: String,
+    pub filetype: Option<&'static str>,
+    pub revision: usize,
+    pub text: Rope,
+}
+
+/// Something that inspects a buffer snapshot in the background and reports
+/// diagnostics, such as a spell checker or an external linter.
+pub trait Analyzer: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn applies_to(&self, snapshot: &Snapshot) -> bool;
+
+    fn analyze(&self, snapshot: &Snapshot) -> anyhow::Result<Vec<Diagnostic>>;
+}
+
+/// A snapshot along with the analyzers to run on it.
+type Job = (Snapshot, Vec<Arc<dyn Analyzer>>);
+
+/// Diagnostics produced by one analyzer for a buffer revision.
+pub struct AnalysisResult {
+    pub source: String,
+    pub revision: usize,
+    pub diagnostics: anyhow::Result<Vec<Diagnostic>>,
+}
+
+/// Runs the registered analyzers on a worker thread so typing is never
+/// blocked, handing results back through `poll`.
+pub struct Analysis {
+    analyzers: Vec<Arc<dyn Analyzer>>,
+    jobs: Option<Sender<Job>>,
+    results: Receiver<AnalysisResult>,
+    results_tx: Sender<AnalysisResult>,
+    /// Revision of the last snapshot submitted.
+    pub submitted: Option<usize>,
+}
+
+impl Analysis {
+    pub fn new() -> Self {
+        let (results_tx, results) = mpsc::channel();
+        let mut analysis = Self {
+            analyzers: Vec::new(),
+            jobs: None,
+            results,
+            results_tx,
+            submitted: None,
+        };
+        analysis.register(Arc::new(SpellCheck::new()));
+        for linter in linters::builtin() {
+            analysis.register(linter);
+        }
+        analysis
+    }
+
+    pub fn register(&mut self, analyzer: Arc<dyn Analyzer>) {
+        self.analyzers.push(analyzer);
+    }
+
+    /// Queues a snapshot for every analyzer that applies to it.
+    pub fn submit(&mut self, snapshot: Snapshot) {
+        self.submitted = Some(snapshot.revision);
+        let analyzers: Vec<_> = self
+            .analyzers
+            .iter()
+            .filter(|a| a.applies_to(&snapshot))
+            .cloned()
+            .collect();
+        if analyzers.is_empty() {
+            return;
+        }
+
+        let jobs = self.jobs.get_or_insert_with(|| {
+            let (tx, rx) = mpsc::channel();
+            let results = self.results_tx.clone();
+            thread::spawn(move || worker(rx, results));
+            tx
+        });
+        _ = jobs.send((snapshot, analyzers));
+    }
+
+    pub fn poll(&self) -> Option<AnalysisResult> {
+        self.results.try_recv().ok()
+    }
+}
+
+fn worker(jobs: Receiver<Job>, results: Sender<AnalysisResult>) {
+    while let Ok(mut job) = jobs.recv() {
+        // Only the newest snapshot is worth analyzing.
+        while let Ok(newer) = jobs.try_recv() {
+            job = newer;
+        }
+        let (snapshot, analyzers) = job;
+        for analyzer in analyzers {
+            let result = AnalysisResult {
+                source: analyzer.name().to_string(),
+                revision: snapshot.revision,
+                diagnostics: analyzer.analyze(&snapshot),
+            };
+            if results.send(result).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Flags words missing from the system word list in prose buffers.
+pub struct SpellCheck {
+    words: Option<HashSet<String>>,
+}
+
+impl SpellCheck {
+    pub fn new() -> Self {
+        let words = DICTIONARIES
+            .iter()
+            .find_map(|path| fs::read_to_string(path).ok())
+            .map(|list| list.lines().map(str::to_lowercase).collect());
+        Self { words }
+    }
+}
+
+impl Analyzer for SpellCheck {
+    fn name(&self) -> &str {
+        "spell"
+    }
+
+    fn applies_to(&self, snapshot: &Snapshot) -> bool {
+        self.words.is_some() && matches!(snapshot.filetype, None | Some("markdown"))
+    }
+
+    fn analyze(&self, snapshot: &Snapshot) -> anyhow::Result<Vec<Diagnostic>> {
+        let Some(words) = &self.words else {
+            return Ok(Vec::new());
+        };
+
+        let mut diagnostics = Vec::new();
+        for (line_i, line) in snapshot.text.lines().enumerate() {
+            let chars: Vec<char> = line.chars().collect();
+            let mut col = 0;
+            while col < chars.len() {
+                if !chars[col].is_alphabetic() {
+                    col += 1;
+                    continue;
+                }
+                let start = col;
+                while col < chars.len() && (chars[col].is_alphabetic() || chars[col] == '\'') {
+                    col += 1;
+                }
+                let word: String = chars[start..col].iter().collect();
+                let word = word.trim_end_matches('\'').to_lowercase();
+                let base = word.strip_suffix("'s").unwrap_or(&word);
+                if !words.contains(&word) && !words.contains(base) {
+                    diagnostics.push(Diagnostic {
+                        line: line_i,
+                        start_col: start,
+                        end_col: col,
+                        severity: Severity::Hint,
+                        message: format!("Unknown word: {word}"),
+                        source: self.name().to_string(),
+                    });
+                }
+            }
+        }
+        Ok(diagnostics)
+    }
+}
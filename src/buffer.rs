@@ -1,29 +1,232 @@
-use std::fs::File;
-use std::io;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind};
+use std::ops::Range;
+use std::path::Path;
+use std::time::SystemTime;
 
-use ropey::iter::{Bytes, Chars, Chunks, Lines};
 use ropey::{Rope, RopeSlice};
 
-use crate::log;
+use crate::checksum;
+use crate::diff::{self, Hunk, Merged};
+use crate::explorer;
+use crate::filetype;
+use crate::folds::Folds;
+use crate::undo::{History, Move};
+use crate::workspace_edit;
+
+/// Maximum number of positions kept in the change list, same as vim.
+const MAX_CHANGES: usize = 100;
 
 pub struct Buffer {
     pub path: String,
     pub text: Rope,
     pub dirty: bool,
+    pub filetype: Option<&'static str>,
+    /// Incremented on every modification of the text.
+    pub revision: usize,
+    /// Positions `(line, col)` of recent modifications, oldest first.
+    pub changes: Vec<(usize, usize)>,
+    /// Index into `changes` used by `g;`/`g,`, equal to `changes.len()` when
+    /// not navigating the list.
+    pub change_idx: usize,
+    /// Entries of the directory as last read from disk when the buffer is a
+    /// directory listing, edited like text and applied on write.
+    pub listing: Option<Vec<String>>,
+    /// The text as last read from or written to disk.
+    pub saved: Rope,
+    /// Modification time and checksum of the file when last read or
+    /// written, to notice changes made by others.
+    disk: Option<(SystemTime, u64)>,
+    /// Char ranges replaced since last taken, with the number of chars
+    /// inserted in their place.
+    edits: Vec<(Range<usize>, usize)>,
+    pub history: History,
+    /// Ranges of lines that can be folded away, moved along with edits.
+    pub folds: Folds,
+    /// Positions `(line, col)` of the marks set with `m`, and of `<` and
+    /// `>` at the ends of the last selection, moved along with lines.
+    pub marks: BTreeMap<char, (usize, usize)>,
+    /// Starts of the lines `:g` has yet to go through, as chars, last
+    /// first. They move along with edits and are dropped with their line.
+    marked: Vec<usize>,
 }
 
 impl Buffer {
+    pub fn open(path: &str) -> anyhow::Result<Self> {
+        if Path::new(path).is_dir() {
+            Self::from_dir(path)
+        } else {
+            Self::from_file(path)
+        }
+    }
+
     pub fn from_file(path: &str) -> anyhow::Result<Self> {
-        let text = Rope::from_reader(&mut io::BufReader::new(File::open(&path)?))?;
+        let bytes = fs::read(path)?;
+        let modified = fs::metadata(path)?.modified()?;
+        let text = Rope::from_reader(bytes.as_slice())?;
 
         Ok(Self {
             path: path.to_string(),
+            saved: text.clone(),
             text,
             dirty: false,
+            filetype: filetype::detect(path),
+            revision: 0,
+            changes: Vec::new(),
+            change_idx: 0,
+            listing: None,
+            disk: Some((modified, checksum([bytes.as_slice()]))),
+            edits: Vec::new(),
+            history: History::default(),
+            folds: Folds::default(),
+            marks: BTreeMap::new(),
+            marked: Vec::new(),
         })
     }
 
-    pub fn get(&self, line: usize) -> Option<RopeSlice> {
+    /// A buffer holding `text` that is not backed by a file yet.
+    pub fn from_text(path: &str, text: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            text: Rope::from_str(text),
+            dirty: false,
+            filetype: filetype::detect(path),
+            revision: 0,
+            changes: Vec::new(),
+            change_idx: 0,
+            listing: None,
+            saved: Rope::new(),
+            disk: None,
+            edits: Vec::new(),
+            history: History::default(),
+            folds: Folds::default(),
+            marks: BTreeMap::new(),
+            marked: Vec::new(),
+        }
+    }
+
+    pub fn from_dir(path: &str) -> anyhow::Result<Self> {
+        let entries = explorer::read_dir(Path::new(path))?;
+
+        Ok(Self {
+            path: path.to_string(),
+            text: Rope::from_str(&entries.join("\n")),
+            dirty: false,
+            filetype: None,
+            revision: 0,
+            changes: Vec::new(),
+            change_idx: 0,
+            listing: Some(entries),
+            saved: Rope::new(),
+            disk: None,
+            edits: Vec::new(),
+            history: History::default(),
+            folds: Folds::default(),
+            marks: BTreeMap::new(),
+            marked: Vec::new(),
+        })
+    }
+
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        write_atomically(Path::new(&self.path), &self.text)?;
+        self.dirty = false;
+        self.saved = self.text.clone();
+        let modified = fs::metadata(&self.path)?.modified()?;
+        self.disk = Some((modified, checksum(self.text.chunks().map(str::as_bytes))));
+        Ok(())
+    }
+
+    /// Returns the file's contents if someone else changed it since it was
+    /// last read or written. A file that appeared meanwhile counts as
+    /// changed, one that vanished does not.
+    pub fn changed_on_disk(&mut self) -> anyhow::Result<Option<String>> {
+        let Result::Ok(metadata) = fs::metadata(&self.path) else {
+            return Ok(None);
+        };
+        if metadata.is_dir() {
+            return Ok(None);
+        }
+        let modified = metadata.modified()?;
+        if matches!(self.disk, Some((time, _)) if time == modified) {
+            return Ok(None);
+        }
+
+        let contents = fs::read_to_string(&self.path)?;
+        let hash = checksum([contents.as_bytes()]);
+        match &mut self.disk {
+            // Touched but not changed.
+            Some(disk) if disk.1 == hash => {
+                disk.0 = modified;
+                Ok(None)
+            }
+            _ => Ok(Some(contents)),
+        }
+    }
+
+    /// Takes in the changes made on disk to `contents`, merging them with
+    /// the buffer's own since the last save. Returns the number of
+    /// conflicts, which are left in the text between markers.
+    pub fn merge_disk(&mut self, contents: &str) -> anyhow::Result<usize> {
+        let lines = |text: &str| -> Vec<String> {
+            text.split_inclusive('\n').map(str::to_string).collect()
+        };
+        let base = lines(&self.saved.to_string());
+        let ours = lines(&self.text.to_string());
+        let theirs = lines(contents);
+
+        let mut merged = String::new();
+        let mut conflicts = 0;
+        let terminated = |chunk: Vec<String>| {
+            let mut text = chunk.concat();
+            if !text.is_empty() && !text.ends_with('\n') {
+                text.push('\n');
+            }
+            text
+        };
+        for chunk in diff::merge(&base, &ours, &theirs) {
+            match chunk {
+                Merged::Resolved(lines) => merged.push_str(&lines.concat()),
+                Merged::Conflict { ours, theirs } => {
+                    conflicts += 1;
+                    merged.push_str("<<<<<<< buffer\n");
+                    merged.push_str(&terminated(ours));
+                    merged.push_str("=======\n");
+                    merged.push_str(&terminated(theirs));
+                    merged.push_str(">>>>>>> disk\n");
+                }
+            }
+        }
+
+        if merged != self.text {
+            self.rewrite(&merged);
+        }
+        let modified = fs::metadata(&self.path)?.modified()?;
+        self.saved = Rope::from_str(contents);
+        self.disk = Some((modified, checksum([contents.as_bytes()])));
+        Ok(conflicts)
+    }
+
+    /// Replaces the text with `contents` read from disk, as a change that
+    /// can be undone, and takes them as saved.
+    pub fn reload(&mut self, contents: &str) -> anyhow::Result<()> {
+        if self.text != contents {
+            self.rewrite(contents);
+        }
+        let modified = fs::metadata(&self.path)?.modified()?;
+        self.saved = Rope::from_str(contents);
+        self.disk = Some((modified, checksum([contents.as_bytes()])));
+        self.dirty = false;
+        Ok(())
+    }
+
+    pub fn line_text(&self, line: usize) -> String {
+        self.get(line)
+            .map(|s| s.to_string().trim_end_matches(['\n', '\r']).to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self, line: usize) -> Option<RopeSlice<'_>> {
         if self.text.len_lines() > line {
             return Some(self.text.line(line));
         }
@@ -34,8 +237,9 @@ impl Buffer {
         self.text.len_lines()
     }
 
-    pub fn lines(&self) -> Lines {
-        self.text.lines()
+    /// Whether the buffer holds no text, though it always has a line.
+    pub fn is_empty(&self) -> bool {
+        self.text.len_chars() == 0
     }
 
     pub fn line_len(&self, line_i: usize) -> usize {
@@ -44,21 +248,263 @@ impl Buffer {
 
     pub fn insert_char(&mut self, line_i: usize, x: usize, c: char) {
         let line_start = self.text.line_to_char(line_i);
+        let inserted = c.to_string();
+        self.history.record(x + line_start, "", &inserted);
+        self.move_marked(x + line_start..x + line_start, "", &inserted);
         self.text.insert_char(x + line_start, c);
+        self.edits.push((x + line_start..x + line_start, 1));
+        self.shift_lines(line_i, 0, usize::from(c == '\n'));
+        self.record_change(line_i, x);
     }
 
     pub fn insert_text(&mut self, line_i: usize, x: usize, text: &str) {
         let curs_index = self.text.line_to_char(line_i) + x;
 
         if !text.is_empty() {
+            self.history.record(curs_index, "", text);
+            self.move_marked(curs_index..curs_index, "", text);
             self.text.insert(curs_index, text);
+            self.edits
+                .push((curs_index..curs_index, text.chars().count()));
+            self.shift_lines(line_i, 0, text.matches('\n').count());
         }
-        self.dirty = true;
+        self.record_change(line_i, x);
+    }
+
+    /// Replaces the chars in `range` with `text`.
+    pub fn replace(&mut self, range: Range<usize>, text: &str) {
+        let removed = self.text.slice(range.clone()).to_string();
+        self.history.record(range.start, &removed, text);
+        self.splice(range, text);
+    }
+
+    /// Replaces the chars in `range` with `text` without recording it in
+    /// the history, as undoing does.
+    fn splice(&mut self, range: Range<usize>, text: &str) {
+        let (line_i, x) = self.char_to_pos(range.start);
+        let removed = self.text.slice(range.clone()).to_string();
+        self.move_marked(range.clone(), &removed, text);
+        self.shift_lines(
+            line_i,
+            removed.matches('\n').count(),
+            text.matches('\n').count(),
+        );
+        self.text.remove(range.clone());
+        self.text.insert(range.start, text);
+        self.edits.push((range, text.chars().count()));
+        self.record_change(line_i, x);
+    }
+
+    /// Turns the text into `new` by replacing only the lines that differ,
+    /// so that the change list and the edits taken record just those.
+    /// Returns the line hunks replaced, to move other positions along.
+    pub fn rewrite(&mut self, new: &str) -> Vec<Hunk> {
+        let current = self.text.to_string();
+        let old: Vec<&str> = current.split_inclusive('\n').collect();
+        let new: Vec<&str> = new.split_inclusive('\n').collect();
+        let hunks = diff::diff(&old, &new);
+        for (line, _) in &mut self.changes {
+            *line = diff::map_line(&hunks, *line);
+        }
+        // Lines before each hunk already read as in the new text.
+        for hunk in &hunks {
+            let start = self.text.line_to_char(hunk.new.start);
+            let end = self.text.line_to_char(hunk.new.start + hunk.old.len());
+            self.replace(start..end, &new[hunk.new.clone()].concat());
+        }
+        hunks
+    }
+
+    /// Converts a char index into a `(line, col)` position.
+    pub fn char_to_pos(&self, char_index: usize) -> (usize, usize) {
+        let line_i = self.text.char_to_line(char_index);
+        (line_i, char_index - self.text.line_to_char(line_i))
     }
 
     pub fn remove_char(&mut self, line_i: usize, x: usize) {
         let line_start = self.text.line_to_char(line_i);
         let char_index = line_start + x;
+        let removed = self.text.char(char_index).to_string();
+        self.history.record(char_index, &removed, "");
+        self.move_marked(char_index..char_index + 1, &removed, "");
+        self.shift_lines(line_i, usize::from(removed == "\n"), 0);
         self.text.remove(char_index..(char_index + 1));
+        self.edits.push((char_index..char_index + 1, 0));
+        self.record_change(line_i, x);
+    }
+
+    /// Moves the folds and marks along with an edit on `line` that removed
+    /// `removed` line breaks and inserted `inserted`.
+    fn shift_lines(&mut self, line: usize, removed: usize, inserted: usize) {
+        self.folds.shift(line, removed, inserted);
+        for (mark, _) in self.marks.values_mut() {
+            *mark = match *mark {
+                mark if mark <= line => mark,
+                mark if mark > line + removed => mark + inserted - removed,
+                _ => line,
+            };
+        }
+    }
+
+    /// Marks `lines` for `:g` to go through, replacing those marked before.
+    pub fn mark_lines(&mut self, lines: &[usize]) {
+        self.marked = lines
+            .iter()
+            .rev()
+            .map(|&line| self.text.line_to_char(line))
+            .collect();
+    }
+
+    /// Unmarks the first line still marked, returning it.
+    pub fn next_marked(&mut self) -> Option<usize> {
+        let at = self.marked.pop()?;
+        Some(self.text.char_to_line(at))
+    }
+
+    pub fn clear_marked(&mut self) {
+        self.marked.clear();
+    }
+
+    /// Moves the marked lines along with `removed` in `range` being
+    /// replaced by `inserted`. A line whose start is removed, or which is
+    /// joined to the line before, is unmarked.
+    fn move_marked(&mut self, range: Range<usize>, removed: &str, inserted: &str) {
+        if self.marked.is_empty() {
+            return;
+        }
+        let at_line_start = range.start == 0 || self.text.char(range.start - 1) == '\n';
+        let whole_lines = inserted.is_empty() || inserted.ends_with('\n');
+        let shift = |at: usize| at + inserted.chars().count() - range.len();
+        self.marked.retain_mut(|mark| {
+            match *mark {
+                at if at < range.start => return true,
+                // Text typed at the start of the line stays on it.
+                at if range.is_empty() && at == range.start && !inserted.ends_with('\n') => {}
+                at if at == range.end && range.is_empty() => *mark = shift(at),
+                at if at == range.end => match at_line_start && whole_lines {
+                    true => *mark = shift(at),
+                    false => return false,
+                },
+                at if at > range.end => *mark = shift(at),
+                at if at == range.start && !removed.contains('\n') => {}
+                _ => return false,
+            }
+            true
+        });
+    }
+
+    /// Reverts the last step of changes, returning where the first of them
+    /// was.
+    pub fn undo(&mut self) -> Option<usize> {
+        let step = self.history.undo()?;
+        self.travel(vec![(step, false)])
+    }
+
+    /// Makes the last step of changes undone again, returning where the
+    /// first of them was.
+    pub fn redo(&mut self) -> Option<usize> {
+        let step = self.history.redo()?;
+        self.travel(vec![(step, true)])
+    }
+
+    /// Makes the moves through the undo history, returning where the first
+    /// change of the last step was, or `None` when there was none to make.
+    pub fn travel(&mut self, moves: Vec<Move>) -> Option<usize> {
+        let mut at = None;
+        for (step, forward) in moves {
+            if forward {
+                for change in &step {
+                    let end = change.at + change.removed.chars().count();
+                    self.splice(change.at..end, &change.inserted);
+                }
+            } else {
+                for change in step.iter().rev() {
+                    let end = change.at + change.inserted.chars().count();
+                    self.splice(change.at..end, &change.removed);
+                }
+            }
+            at = step.iter().map(|change| change.at).min().or(at);
+        }
+        // The buffer counts as unchanged again if that brought back the
+        // text on disk.
+        if self.disk.is_some() {
+            self.dirty = self.text != self.saved;
+        }
+        at
+    }
+
+    /// The edits made since the last call, oldest first.
+    pub fn take_edits(&mut self) -> Vec<(Range<usize>, usize)> {
+        std::mem::take(&mut self.edits)
+    }
+
+    /// Marks the buffer dirty and remembers the position in the change list.
+    /// Consecutive changes on the same line collapse into a single entry.
+    fn record_change(&mut self, line_i: usize, x: usize) {
+        self.dirty = true;
+        self.revision += 1;
+
+        match self.changes.last_mut() {
+            Some(last) if last.0 == line_i => *last = (line_i, x),
+            _ => {
+                if self.changes.len() == MAX_CHANGES {
+                    self.changes.remove(0);
+                }
+                self.changes.push((line_i, x));
+            }
+        }
+        self.change_idx = self.changes.len();
+    }
+}
+
+/// Writes `text` to a file next to the one at `path`, resolving symlinks,
+/// and renames it over it once on disk, so that a crash or a full disk
+/// never leaves it half written. The file keeps its permissions and, where
+/// allowed, its owner. Where the rename cannot be made, such as for a file
+/// mounted on its own or in a directory that cannot be written, the file is
+/// overwritten in place instead.
+fn write_atomically(path: &Path, text: &Rope) -> io::Result<()> {
+    let target = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let metadata = fs::metadata(&target).ok();
+    let tmp = workspace_edit::tmp_path(&target);
+    let result =
+        write_synced(&tmp, text, metadata.as_ref()).and_then(|()| fs::rename(&tmp, &target));
+    match result {
+        Result::Ok(()) => {
+            // The rename itself is only durable once the directory is.
+            if let Some(dir) = target.parent().and_then(|dir| File::open(dir).ok()) {
+                _ = dir.sync_all();
+            }
+            Ok(())
+        }
+        Err(err) => {
+            _ = fs::remove_file(&tmp);
+            match err.kind() {
+                ErrorKind::CrossesDevices
+                | ErrorKind::ResourceBusy
+                | ErrorKind::PermissionDenied => write_synced(&target, text, None),
+                _ => Err(err),
+            }
+        }
+    }
+}
+
+/// Writes `text` to the file at `path` and waits for it to reach the disk,
+/// giving it the permissions and owner in `metadata`.
+fn write_synced(path: &Path, text: &Rope, metadata: Option<&fs::Metadata>) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(File::create(path)?);
+    text.write_to(&mut writer)?;
+    let file = writer
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?;
+    if let Some(metadata) = metadata {
+        file.set_permissions(metadata.permissions())?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            // Only root may give a file away, so the owner is kept at best.
+            _ = std::os::unix::fs::fchown(&file, Some(metadata.uid()), Some(metadata.gid()));
+        }
     }
+    file.sync_all()
 }
@@ -0,0 +1,271 @@
+use std::env;
+
+use anyhow::bail;
+use crossterm::{style::Color, terminal};
+
+/// Terminals known to support truecolor without advertising it through
+/// `COLORTERM`, matched against `TERM` and `TERM_PROGRAM`.
+const TRUECOLOR_TERMINALS: &[&str] = &[
+    "kitty",
+    "alacritty",
+    "foot",
+    "wezterm",
+    "ghostty",
+    "contour",
+    "iterm",
+    "vscode",
+];
+
+/// Terminals known to support synchronized output, matched against `TERM`
+/// and `TERM_PROGRAM`.
+const SYNC_TERMINALS: &[&str] = &[
+    "kitty",
+    "alacritty",
+    "foot",
+    "wezterm",
+    "ghostty",
+    "contour",
+    "iterm",
+];
+
+/// Terminals known to support OSC 8 hyperlinks, matched against `TERM`
+/// and `TERM_PROGRAM`. Those built on VTE are told by `VTE_VERSION`.
+const HYPERLINK_TERMINALS: &[&str] = &[
+    "kitty",
+    "alacritty",
+    "foot",
+    "wezterm",
+    "ghostty",
+    "contour",
+    "iterm",
+    "vscode",
+];
+
+/// The 16 basic colors with the RGB values xterm gives them by default.
+const BASIC_COLORS: &[(Color, (u8, u8, u8))] = &[
+    (Color::Black, (0, 0, 0)),
+    (Color::DarkRed, (205, 0, 0)),
+    (Color::DarkGreen, (0, 205, 0)),
+    (Color::DarkYellow, (205, 205, 0)),
+    (Color::DarkBlue, (0, 0, 238)),
+    (Color::DarkMagenta, (205, 0, 205)),
+    (Color::DarkCyan, (0, 205, 205)),
+    (Color::Grey, (229, 229, 229)),
+    (Color::DarkGrey, (127, 127, 127)),
+    (Color::Red, (255, 0, 0)),
+    (Color::Green, (0, 255, 0)),
+    (Color::Yellow, (255, 255, 0)),
+    (Color::Blue, (92, 92, 255)),
+    (Color::Magenta, (255, 0, 255)),
+    (Color::Cyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// How much of the UI the terminal can show, from the Linux console up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Tier {
+    /// 16 colors and ASCII glyphs only.
+    Minimal,
+    /// 256 colors and Unicode glyphs.
+    Standard,
+    /// Truecolor.
+    Full,
+}
+
+/// A capability that can be forced on or off from the command line.
+#[derive(Clone, Copy)]
+enum Feature {
+    Truecolor,
+    Sync,
+    Kitty,
+    Mouse,
+    Osc52,
+    Paste,
+    Hyperlinks,
+    Focus,
+    /// Switching this on drops to the minimal tier with everything off.
+    Minimal,
+}
+
+/// Capability overrides given with `--caps`, a comma separated list of
+/// names each optionally prefixed with `no`, like `notruecolor,mouse`.
+#[derive(Default)]
+pub struct Overrides(Vec<(Feature, bool)>);
+
+impl Overrides {
+    pub fn parse(list: &str) -> anyhow::Result<Self> {
+        let mut overrides = Vec::new();
+        for name in list.split(',').filter(|name| !name.is_empty()) {
+            let (name, enabled) = match name.strip_prefix("no") {
+                Some(name) => (name, false),
+                None => (name, true),
+            };
+            let feature = match name {
+                "truecolor" => Feature::Truecolor,
+                "sync" => Feature::Sync,
+                "kitty" => Feature::Kitty,
+                "mouse" => Feature::Mouse,
+                "osc52" => Feature::Osc52,
+                "paste" => Feature::Paste,
+                "hyperlinks" => Feature::Hyperlinks,
+                "focus" => Feature::Focus,
+                "minimal" => Feature::Minimal,
+                _ => bail!("Unknown terminal capability: {name}"),
+            };
+            overrides.push((feature, enabled));
+        }
+        Ok(Self(overrides))
+    }
+}
+
+/// What the terminal was found to support, consulted by anything drawing
+/// or enabling terminal features so the editor degrades gracefully.
+pub struct Capabilities {
+    pub tier: Tier,
+    pub truecolor: bool,
+    /// Frames can be wrapped in begin/end synchronized update markers.
+    pub synchronized_output: bool,
+    /// The kitty keyboard protocol, which tells apart keys such as Esc and
+    /// Alt combinations.
+    pub kitty_keyboard: bool,
+    pub mouse: bool,
+    /// Setting the system clipboard through OSC 52.
+    pub osc52: bool,
+    pub bracketed_paste: bool,
+    /// Text can link to URLs through OSC 8.
+    pub hyperlinks: bool,
+    /// The terminal tells when it gains or loses the focus.
+    pub focus_events: bool,
+}
+
+impl Capabilities {
+    /// Probes the terminal, which must already be in raw mode for the
+    /// keyboard protocol query, then applies the overrides.
+    pub fn probe(overrides: &Overrides) -> Self {
+        let var = |name| env::var(name).unwrap_or_default().to_lowercase();
+        let (term, program, colorterm) = (var("TERM"), var("TERM_PROGRAM"), var("COLORTERM"));
+        let is = |names: &[&str]| {
+            names
+                .iter()
+                .any(|name| term.contains(name) || program.contains(name))
+        };
+
+        let minimal =
+            term.is_empty() || term == "dumb" || term == "linux" || term.starts_with("vt");
+        let truecolor = !minimal
+            && (matches!(colorterm.as_str(), "truecolor" | "24bit")
+                || term.ends_with("-direct")
+                || is(TRUECOLOR_TERMINALS));
+
+        let mut caps = Self {
+            tier: if minimal {
+                Tier::Minimal
+            } else if truecolor {
+                Tier::Full
+            } else {
+                Tier::Standard
+            },
+            truecolor,
+            synchronized_output: !minimal && is(SYNC_TERMINALS),
+            kitty_keyboard: !minimal && terminal::supports_keyboard_enhancement().unwrap_or(false),
+            mouse: !minimal,
+            osc52: !minimal && !is(&["apple_terminal"]),
+            bracketed_paste: !minimal,
+            hyperlinks: !minimal
+                && (is(HYPERLINK_TERMINALS) || env::var_os("VTE_VERSION").is_some()),
+            focus_events: !minimal,
+        };
+
+        for &(feature, enabled) in &overrides.0 {
+            match feature {
+                Feature::Truecolor => caps.truecolor = enabled,
+                Feature::Sync => caps.synchronized_output = enabled,
+                Feature::Kitty => caps.kitty_keyboard = enabled,
+                Feature::Mouse => caps.mouse = enabled,
+                Feature::Osc52 => caps.osc52 = enabled,
+                Feature::Paste => caps.bracketed_paste = enabled,
+                Feature::Hyperlinks => caps.hyperlinks = enabled,
+                Feature::Focus => caps.focus_events = enabled,
+                Feature::Minimal if enabled => caps = Self::minimal(),
+                Feature::Minimal => {}
+            }
+        }
+        if caps.tier != Tier::Minimal {
+            caps.tier = if caps.truecolor {
+                Tier::Full
+            } else {
+                Tier::Standard
+            };
+        }
+        caps
+    }
+
+    /// Everything off, as on the dumbest terminal.
+    pub fn minimal() -> Self {
+        Self {
+            tier: Tier::Minimal,
+            truecolor: false,
+            synchronized_output: false,
+            kitty_keyboard: false,
+            mouse: false,
+            osc52: false,
+            bracketed_paste: false,
+            hyperlinks: false,
+            focus_events: false,
+        }
+    }
+
+    /// The closest color to `(r, g, b)` the terminal can show.
+    pub fn rgb(&self, r: u8, g: u8, b: u8) -> Color {
+        match self.tier {
+            Tier::Full => Color::Rgb { r, g, b },
+            Tier::Standard => Color::AnsiValue(ansi256(r, g, b)),
+            Tier::Minimal => {
+                let distance = |(_, (br, bg, bb)): &&(Color, (u8, u8, u8))| {
+                    [(r, *br), (g, *bg), (b, *bb)]
+                        .iter()
+                        .map(|&(a, b)| (a as i32 - b as i32).pow(2))
+                        .sum::<i32>()
+                };
+                BASIC_COLORS.iter().min_by_key(distance).unwrap().0
+            }
+        }
+    }
+
+    /// `glyph` where Unicode can be shown, `fallback` on minimal terminals.
+    /// `c`, or `fallback` when it is not ASCII and the terminal may not
+    /// draw it.
+    pub fn fill(&self, c: char, fallback: char) -> char {
+        if self.tier == Tier::Minimal && !c.is_ascii() {
+            fallback
+        } else {
+            c
+        }
+    }
+
+    pub fn glyph<'a>(&self, glyph: &'a str, fallback: &'a str) -> &'a str {
+        if self.tier == Tier::Minimal {
+            fallback
+        } else {
+            glyph
+        }
+    }
+}
+
+/// Maps a color onto the xterm 256 color palette, using the grayscale ramp
+/// for grays and the 6x6x6 cube otherwise.
+fn ansi256(r: u8, g: u8, b: u8) -> u8 {
+    if r == g && g == b {
+        return match r {
+            0..8 => 16,
+            249.. => 231,
+            _ => 232 + ((r as u16 - 8) * 24 / 241) as u8,
+        };
+    }
+    let level = |v: u8| match v {
+        0..48 => 0,
+        48..115 => 1,
+        _ => (v - 35) / 40,
+    };
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
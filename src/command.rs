@@ -0,0 +1,1083 @@
+use anyhow::bail;
+
+use crate::conflicts::Resolution;
+use crate::messages::Severity;
+use crate::plugins::MapMode;
+use crate::vimscript::Autocmd;
+
+/// An ex command entered on the `:` command line.
+#[derive(Debug)]
+pub enum Command {
+    Quit(Quit),
+    Changes,
+    Write {
+        force: bool,
+    },
+    Edit {
+        path: String,
+        force: bool,
+    },
+    Rename(String),
+    CodeAction,
+    Format,
+    Set(Vec<String>),
+    Files,
+    Symbols,
+    /// Lists the commands and key bindings to run one.
+    Palette,
+    Lock,
+    StageHunk,
+    ResetHunk,
+    /// Resolves the merge conflict at the cursor.
+    Resolve(Resolution),
+    Redir(Redirect),
+    /// Brings back the window closed last.
+    Reopen,
+    /// Shows the action log, or writes it to a file.
+    ActionLog(Option<String>),
+    /// Inserts the contents of a register below the cursor line.
+    Put(char),
+    Trust {
+        persist: bool,
+    },
+    /// A command defined with `:command` or by a plugin, whose names
+    /// start uppercase.
+    User {
+        name: String,
+        args: String,
+        range: Option<LineRange>,
+    },
+    /// Defines a user command, replacing one of the same name if `force`.
+    Define {
+        name: String,
+        command: UserCommand,
+        force: bool,
+    },
+    /// Lists the user commands.
+    ListCommands,
+    /// Removes a user command.
+    Undefine(String),
+    /// Writes a session to the file, or to `Session.vim`, replacing one
+    /// that exists if `force`.
+    MakeSession {
+        path: Option<String>,
+        force: bool,
+    },
+    /// Runs the ex commands in a file.
+    Source(String),
+    /// Shows the buffer side by side with a file in diff mode.
+    DiffSplit(String),
+    /// Moves the focus to the other window.
+    OtherWindow,
+    /// Moves the cursor to a line and column, both from 1, as in
+    /// `:call cursor(12, 5)`.
+    Cursor {
+        line: usize,
+        col: usize,
+    },
+    /// Runs a command telling where the options and mappings it lists
+    /// were set.
+    Verbose(Box<Command>),
+    /// Lists the keys plugins mapped, or only the one given.
+    Map(Option<String>),
+    /// Maps keys typed in the modes to others, as in `:nnoremap`.
+    MapKeys {
+        modes: Vec<MapMode>,
+        lhs: String,
+        rhs: String,
+    },
+    /// Sets a variable, or an option written `&name`, to the value of an
+    /// expression.
+    Let {
+        name: String,
+        expr: String,
+    },
+    Unlet(String),
+    /// Shows the value of an expression.
+    Echo(String),
+    /// Runs a command on an event, replacing those run on it first if
+    /// `replace`.
+    Autocmd {
+        autocmd: Autocmd,
+        replace: bool,
+    },
+    ListAutocmds,
+    ClearAutocmds,
+    /// Goes back through the undo history, whatever branch it takes.
+    Earlier(Travel),
+    /// Goes forward through the undo history.
+    Later(Travel),
+    /// Shows the messages given at least as bad as the severity.
+    Messages(Severity),
+    ClearMessages,
+    /// Gives a notification the way `notify` asks, as a plugin running a
+    /// build does once it finished.
+    Notify(String),
+    /// Checks whether the file changed on disk.
+    CheckTime,
+    /// Lists what is set up for the buffer's filetype, and why.
+    FiletypeInfo,
+    /// Shows the lines of the range, or the cursor line.
+    Print(Option<LineRange>),
+    Undo,
+    Redo,
+    /// Stops highlighting the matches of the last search until the next.
+    NoHlSearch,
+    /// Deletes the lines of the range, or the cursor line.
+    Delete(Option<LineRange>),
+    /// Copies the lines of the range, or the cursor line, to the unnamed
+    /// register.
+    Yank(Option<LineRange>),
+    /// Moves the cursor to the last line of the range, as `:12` does.
+    Goto(Option<LineRange>),
+    /// Types `keys`, in the notation of mappings, in normal mode on each
+    /// line of the range, or at the cursor. Mappings apply unless
+    /// `noremap`, as with `:normal!`.
+    Normal {
+        range: Option<LineRange>,
+        keys: String,
+        noremap: bool,
+    },
+    /// Shows what the registers hold, or only those named.
+    Registers(String),
+    /// Shows where the marks are, or only those named.
+    Marks(String),
+    /// Lists the files edited, most recent first.
+    Oldfiles,
+    /// Sorts the lines of the range, or of the buffer.
+    Sort {
+        range: Option<LineRange>,
+        sort: Sort,
+    },
+    /// Moves the lines of the range, or the cursor line, below the line
+    /// `to`, or above the first line when `None`, or copies them there
+    /// when `copy`.
+    MoveLines {
+        range: Option<LineRange>,
+        to: Option<Address>,
+        copy: bool,
+    },
+    /// Runs `command` on each line of the range, or of the buffer, matching
+    /// `pattern`, or not matching it when `invert`, as in `:g/TODO/d`.
+    Global {
+        range: Option<LineRange>,
+        pattern: String,
+        invert: bool,
+        command: String,
+    },
+}
+
+impl Command {
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let (range, input) = LineRange::parse(input.trim())?;
+        let name = input.split_whitespace().next().unwrap_or_default();
+        let args = input[name.len()..].trim();
+        if let Some(global) = parse_global(input, range)? {
+            return Ok(global);
+        }
+        if let Some(command) = parse_move(input, range)? {
+            return Ok(command);
+        }
+
+        let command = match name {
+            "q" | "quit" | "q!" | "quit!" => Command::Quit(Quit {
+                force: name.ends_with('!'),
+                ..Quit::default()
+            }),
+            "qa" | "qall" | "quita" | "quitall" | "qa!" | "qall!" | "quita!" | "quitall!" => {
+                Command::Quit(Quit {
+                    force: name.ends_with('!'),
+                    all: true,
+                    write: false,
+                })
+            }
+            "wq" | "x" | "xit" | "exi" | "exit" => Command::Quit(Quit {
+                write: true,
+                ..Quit::default()
+            }),
+            "wqa" | "wqall" | "xa" | "xall" => Command::Quit(Quit {
+                force: false,
+                all: true,
+                write: true,
+            }),
+            "changes" => Command::Changes,
+            "registers" | "reg" | "display" | "di" => Command::Registers(args.replace(' ', "")),
+            "marks" => Command::Marks(args.replace(' ', "")),
+            "oldfiles" | "ol" => Command::Oldfiles,
+            "checktime" | "checkt" => Command::CheckTime,
+            "filetype" | "filet" => match args {
+                "info" => Command::FiletypeInfo,
+                "" => bail!("Argument required"),
+                _ => bail!("Invalid argument: {args}"),
+            },
+            "undo" | "u" | "un" => Command::Undo,
+            "redo" | "red" => Command::Redo,
+            "nohlsearch" | "noh" | "nohl" => Command::NoHlSearch,
+            "print" | "p" => Command::Print(range),
+            "delete" | "d" | "de" | "del" => Command::Delete(range),
+            "yank" | "y" | "ya" => Command::Yank(range),
+            "normal" | "norm" | "normal!" | "norm!" => {
+                if args.is_empty() {
+                    bail!("Argument required");
+                }
+                Command::Normal {
+                    range,
+                    keys: args.to_string(),
+                    noremap: name.ends_with('!'),
+                }
+            }
+            "sort" | "sor" | "sort!" | "sor!" => Command::Sort {
+                range,
+                sort: Sort::parse(args, name.ends_with('!'))?,
+            },
+            "w" | "write" | "w!" | "write!" => Command::Write {
+                force: name.ends_with('!'),
+            },
+            "e" | "edit" | "e!" | "edit!" => {
+                if args.is_empty() {
+                    bail!("Argument required");
+                }
+                Command::Edit {
+                    path: args.to_string(),
+                    force: name.ends_with('!'),
+                }
+            }
+            "rename" => {
+                if args.is_empty() {
+                    bail!("Argument required");
+                }
+                Command::Rename(args.to_string())
+            }
+            "codeaction" => Command::CodeAction,
+            "format" => Command::Format,
+            "files" => Command::Files,
+            "symbols" => Command::Symbols,
+            "palette" | "pal" => Command::Palette,
+            "lock" => Command::Lock,
+            "stagehunk" => Command::StageHunk,
+            "resethunk" => Command::ResetHunk,
+            "ours" => Command::Resolve(Resolution::Ours),
+            "theirs" => Command::Resolve(Resolution::Theirs),
+            "both" => Command::Resolve(Resolution::Both),
+            "reopen" => Command::Reopen,
+            "actionlog" => Command::ActionLog((!args.is_empty()).then(|| args.to_string())),
+            "trust" | "trust!" => Command::Trust {
+                persist: name.ends_with('!'),
+            },
+            "redir" | "redir!" => Command::Redir(Redirect::parse(args, name.ends_with('!'))?),
+            "put" | "pu" => {
+                let mut chars = args.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(name), None)
+                        if name.is_ascii_alphanumeric() || "\"-%/:".contains(name) =>
+                    {
+                        Command::Put(name.to_ascii_lowercase())
+                    }
+                    (None, _) => bail!("Argument required"),
+                    _ => bail!("Invalid register name: {args}"),
+                }
+            }
+            "set" | "se" | "setlocal" | "setl" => Command::Set(set_args(args)),
+            "mksession" | "mks" | "mksession!" | "mks!" => Command::MakeSession {
+                path: (!args.is_empty()).then(|| args.to_string()),
+                force: name.ends_with('!'),
+            },
+            "source" | "so" => {
+                if args.is_empty() {
+                    bail!("Argument required");
+                }
+                Command::Source(args.to_string())
+            }
+            "diffsplit" | "diffs" => {
+                if args.is_empty() {
+                    bail!("Argument required");
+                }
+                Command::DiffSplit(args.to_string())
+            }
+            "wincmd" | "winc" => match args {
+                "w" | "W" | "p" => Command::OtherWindow,
+                "" => bail!("Argument required"),
+                _ => bail!("Invalid argument: {args}"),
+            },
+            "call" | "cal" => parse_cursor(args)?,
+            "verbose" | "verb" => {
+                if args.is_empty() {
+                    bail!("Argument required");
+                }
+                Command::Verbose(Box::new(Command::parse(args)?))
+            }
+            "map" | "noremap" | "no" | "nmap" | "nm" | "nnoremap" | "nn" | "vmap" | "vm"
+            | "vnoremap" | "vn" | "xmap" | "xm" | "xnoremap" | "xn" => parse_map(name, args)?,
+            "imap" | "im" | "inoremap" | "ino" | "cmap" | "cm" | "cnoremap" | "cno" | "map!"
+            | "noremap!" => bail!("Only normal and visual mode keys can be mapped"),
+            "let" => parse_let(args)?,
+            "unlet" | "unl" => {
+                if args.is_empty() {
+                    bail!("Argument required");
+                }
+                Command::Unlet(args.to_string())
+            }
+            "echo" | "ec" => Command::Echo(args.to_string()),
+            "autocmd" | "au" if args.is_empty() => Command::ListAutocmds,
+            "autocmd!" | "au!" if args.is_empty() => Command::ClearAutocmds,
+            "autocmd" | "au" | "autocmd!" | "au!" => Command::Autocmd {
+                autocmd: Autocmd::parse(args)?,
+                replace: name.ends_with('!'),
+            },
+            "earlier" | "ea" => Command::Earlier(Travel::parse(args)?),
+            "later" | "lat" => Command::Later(Travel::parse(args)?),
+            "messages" | "mes" => match args {
+                "" => Command::Messages(Severity::Info),
+                "clear" => Command::ClearMessages,
+                _ => match Severity::parse(args) {
+                    Some(severity) => Command::Messages(severity),
+                    None => bail!("Invalid argument: {args}"),
+                },
+            },
+            "notify" => {
+                if args.is_empty() {
+                    bail!("Argument required");
+                }
+                Command::Notify(args.to_string())
+            }
+            "command" | "com" if args.is_empty() => Command::ListCommands,
+            "command" | "com" | "command!" | "com!" => {
+                let (command_name, command) = UserCommand::parse(args)?;
+                Command::Define {
+                    name: command_name,
+                    command,
+                    force: name.ends_with('!'),
+                }
+            }
+            "delcommand" | "delc" => {
+                if args.is_empty() {
+                    bail!("Argument required");
+                }
+                Command::Undefine(args.to_string())
+            }
+            _ if name.starts_with(|c: char| c.is_ascii_uppercase()) => Command::User {
+                name: name.to_string(),
+                args: args.to_string(),
+                range,
+            },
+            "" if range.is_some() => Command::Goto(range),
+            _ => bail!("Not an editor command: {input}"),
+        };
+        if range.is_some()
+            && !matches!(
+                command,
+                Command::User { .. }
+                    | Command::Print(_)
+                    | Command::Delete(_)
+                    | Command::Yank(_)
+                    | Command::Goto(_)
+                    | Command::Sort { .. }
+                    | Command::Normal { .. }
+            )
+        {
+            bail!("No range allowed");
+        }
+        Ok(command)
+    }
+}
+
+/// Parses `:g/pattern/command`, `:g!` and `:v`, where any char but a
+/// letter, digit or space can stand for `/`. Without a command the lines
+/// are printed.
+fn parse_global(input: &str, range: Option<LineRange>) -> anyhow::Result<Option<Command>> {
+    let end = input
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(input.len());
+    let (name, rest) = input.split_at(end);
+    let (invert, rest) = match (name, rest.strip_prefix('!')) {
+        ("g" | "global", Some(rest)) => (true, rest),
+        ("g" | "global", None) => (false, rest),
+        ("v" | "vglobal", None) => (true, rest),
+        _ => return Ok(None),
+    };
+    let mut chars = rest.chars();
+    let Some(delimiter) = chars
+        .next()
+        .filter(|c| !c.is_alphanumeric() && !c.is_whitespace())
+    else {
+        bail!("Regular expression missing from :{name}");
+    };
+    let rest = chars.as_str();
+    let mut pattern = String::new();
+    let mut command = "";
+    let mut chars = rest.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            // An escaped delimiter is part of the pattern.
+            '\\' => match chars.next() {
+                Some((_, c)) if c == delimiter => pattern.push(c),
+                Some((_, c)) => pattern.extend(['\\', c]),
+                None => pattern.push(c),
+            },
+            c if c == delimiter => {
+                command = &rest[i + c.len_utf8()..];
+                break;
+            }
+            c => pattern.push(c),
+        }
+    }
+    let command = match command.trim() {
+        "" => "p",
+        command => command,
+    };
+    Ok(Some(Command::Global {
+        range,
+        pattern,
+        invert,
+        command: command.to_string(),
+    }))
+}
+
+/// Parses `:m` and `:t` or `:copy` with the address to move or copy the
+/// lines below, which may follow the name without a space as in `:t.`,
+/// and is `0` for above the first line.
+fn parse_move(input: &str, range: Option<LineRange>) -> anyhow::Result<Option<Command>> {
+    let end = input
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(input.len());
+    let (name, target) = input.split_at(end);
+    let copy = match name {
+        "m" | "mo" | "move" => false,
+        "t" | "co" | "copy" => true,
+        _ => return Ok(None),
+    };
+    let to = match target.trim() {
+        "" => bail!("Argument required"),
+        "0" => None,
+        target => match Address::parse(target)? {
+            (Some(address), "") => Some(address),
+            _ => bail!("Invalid address: {target}"),
+        },
+    };
+    Ok(Some(Command::MoveLines { range, to, copy }))
+}
+
+/// How `:q` and the commands like it quit.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Quit {
+    /// Changes not written are dropped.
+    pub force: bool,
+    /// Every window is closed, not only the focused one.
+    pub all: bool,
+    /// The buffers with changes are written first.
+    pub write: bool,
+}
+
+/// `:call` of the one function there is, `cursor(line, col)`.
+fn parse_cursor(args: &str) -> anyhow::Result<Command> {
+    let Some(position) = args
+        .strip_prefix("cursor(")
+        .and_then(|args| args.strip_suffix(')'))
+    else {
+        bail!("Unknown function: {args}");
+    };
+    let numbers: Vec<usize> = position
+        .split(',')
+        .map(|number| number.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| anyhow::anyhow!("Invalid argument: {args}"))?;
+    match numbers[..] {
+        [line, col] => Ok(Command::Cursor { line, col }),
+        _ => bail!("Invalid argument: {args}"),
+    }
+}
+
+/// `:map` and its variants for a mode: maps `{lhs} {rhs}`, or lists the
+/// mappings of `{lhs}` or all without arguments.
+fn parse_map(name: &str, args: &str) -> anyhow::Result<Command> {
+    let mut args = args;
+    while let Some(rest) = args.strip_prefix('<') {
+        let Some((attribute, rest)) = rest.split_once('>') else {
+            break;
+        };
+        match attribute.to_ascii_lowercase().as_str() {
+            "silent" | "nowait" | "unique" | "buffer" | "special" => args = rest.trim_start(),
+            "expr" | "script" => bail!("<{attribute}> mappings are not supported"),
+            _ => break,
+        }
+    }
+    let (lhs, rhs) = match args.split_once(char::is_whitespace) {
+        Some((lhs, rhs)) => (lhs, rhs.trim()),
+        None => (args, ""),
+    };
+    if rhs.is_empty() {
+        return Ok(Command::Map((!lhs.is_empty()).then(|| lhs.to_string())));
+    }
+    let modes = match name.chars().next() {
+        Some('n') if name != "noremap" && name != "no" => vec![MapMode::Normal],
+        Some('v' | 'x') => vec![MapMode::Visual],
+        _ => vec![MapMode::Normal, MapMode::Visual],
+    };
+    Ok(Command::MapKeys {
+        modes,
+        lhs: lhs.to_string(),
+        rhs: rhs.to_string(),
+    })
+}
+
+/// `:let {name} = {expr}`, where `.=`, `+=` and `-=` also take the value
+/// the variable has.
+fn parse_let(args: &str) -> anyhow::Result<Command> {
+    let Some((name, expr)) = args.split_once('=') else {
+        bail!("Invalid argument: {args}");
+    };
+    let (name, operator) = match name.trim_end().char_indices().last() {
+        Some((i, c @ ('.' | '+' | '-'))) => (name[..i].trim(), Some(c)),
+        _ => (name.trim(), None),
+    };
+    let expr = expr.trim();
+    if name.is_empty() || expr.is_empty() {
+        bail!("Invalid argument: {args}");
+    }
+    let expr = match operator {
+        Some(operator) => format!("{name} {operator} ({expr})"),
+        None => expr.to_string(),
+    };
+    Ok(Command::Let {
+        name: name.to_string(),
+        expr,
+    })
+}
+
+/// The arguments of `:set`, split at whitespace not escaped by a backslash
+/// so that an option can be set to a space as in `fillchars=eob:\ `.
+fn set_args(args: &str) -> Vec<String> {
+    let mut split = Vec::new();
+    let mut arg = String::new();
+    let mut chars = args.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek().is_some_and(|c| c.is_whitespace()) => {
+                arg.extend(chars.next());
+            }
+            c if c.is_whitespace() => {
+                if !arg.is_empty() {
+                    split.push(std::mem::take(&mut arg));
+                }
+            }
+            c => arg.push(c),
+        }
+    }
+    if !arg.is_empty() {
+        split.push(arg);
+    }
+    split
+}
+
+/// Where `:redir` sends the output of the ex commands that follow it.
+#[derive(Debug, PartialEq)]
+pub enum Redirect {
+    /// A register, appended to when named in uppercase.
+    Register(char),
+    File {
+        path: String,
+        append: bool,
+        /// Overwrite the file if it exists.
+        force: bool,
+    },
+    /// Stops redirecting.
+    End,
+}
+
+impl Redirect {
+    fn parse(args: &str, force: bool) -> anyhow::Result<Self> {
+        if args == "END" {
+            return Ok(Redirect::End);
+        }
+        if let Some(name) = args.strip_prefix('@') {
+            let mut chars = name.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(name), None) if name.is_ascii_alphabetic() => Ok(Redirect::Register(name)),
+                _ => bail!("Invalid register name: {args}"),
+            };
+        }
+        let (path, append) = match args.strip_prefix(">>") {
+            Some(path) => (path, true),
+            None => match args.strip_prefix('>') {
+                Some(path) => (path, false),
+                None => bail!("Invalid argument: {args}"),
+            },
+        };
+        let path = path.trim();
+        if path.is_empty() {
+            bail!("Argument required");
+        }
+        Ok(Redirect::File {
+            path: path.to_string(),
+            append,
+            force,
+        })
+    }
+}
+
+/// How far `:earlier` and `:later` go: a number of states, or of seconds
+/// written with `s`, `m`, `h` or `d` after it.
+#[derive(Debug, PartialEq)]
+pub enum Travel {
+    States(usize),
+    Seconds(u64),
+}
+
+impl Travel {
+    fn parse(args: &str) -> anyhow::Result<Self> {
+        if args.is_empty() {
+            return Ok(Travel::States(1));
+        }
+        let (count, unit) = match args.find(|c: char| !c.is_ascii_digit()) {
+            Some(i) => args.split_at(i),
+            None => (args, ""),
+        };
+        let Result::Ok(count) = count.parse::<u64>() else {
+            bail!("Invalid argument: {args}");
+        };
+        let seconds = match unit {
+            "" => return Ok(Travel::States(count as usize)),
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => bail!("Invalid argument: {args}"),
+        };
+        Ok(Travel::Seconds(count.saturating_mul(seconds)))
+    }
+}
+
+/// How `:sort` orders lines: by the first number in them with `n`, else
+/// as text, regardless of case with `i`. Lines comparing equal keep their
+/// order, and all but the first of them are dropped with `u`. With `!` the
+/// order is reversed.
+#[derive(Debug, Default, PartialEq)]
+pub struct Sort {
+    pub reverse: bool,
+    pub numeric: bool,
+    pub unique: bool,
+    pub ignore_case: bool,
+}
+
+impl Sort {
+    fn parse(args: &str, reverse: bool) -> anyhow::Result<Self> {
+        let mut sort = Sort {
+            reverse,
+            ..Sort::default()
+        };
+        for flag in args.chars().filter(|c| !c.is_whitespace()) {
+            match flag {
+                'n' => sort.numeric = true,
+                'u' => sort.unique = true,
+                'i' => sort.ignore_case = true,
+                _ => bail!("Invalid argument: {args}"),
+            }
+        }
+        Ok(sort)
+    }
+
+    pub fn apply(&self, lines: Vec<String>) -> Vec<String> {
+        // Lines without a number go first, as if it were the lowest.
+        let number = |line: &str| -> Option<i64> {
+            let start = line.find(|c: char| c.is_ascii_digit())?;
+            let end = line[start..]
+                .find(|c: char| !c.is_ascii_digit())
+                .map_or(line.len(), |len| start + len);
+            let number: i64 = line[start..end].parse().unwrap_or(i64::MAX);
+            Some(match line[..start].ends_with('-') {
+                true => -number,
+                false => number,
+            })
+        };
+        let compare = |a: &String, b: &String| match (self.numeric, self.ignore_case) {
+            (true, _) => number(a).cmp(&number(b)),
+            (false, true) => a.to_lowercase().cmp(&b.to_lowercase()),
+            (false, false) => a.cmp(b),
+        };
+        let mut lines = lines;
+        lines.sort_by(compare);
+        if self.unique {
+            lines.dedup_by(|b, a| compare(a, b).is_eq());
+        }
+        if self.reverse {
+            lines.reverse();
+        }
+        lines
+    }
+}
+
+/// Names of the built-in commands, offered by `-complete=command`.
+pub const NAMES: &[&str] = &[
+    "actionlog",
+    "autocmd",
+    "both",
+    "call",
+    "changes",
+    "checktime",
+    "codeaction",
+    "command",
+    "copy",
+    "delcommand",
+    "delete",
+    "diffsplit",
+    "display",
+    "earlier",
+    "echo",
+    "edit",
+    "files",
+    "filetype",
+    "format",
+    "global",
+    "later",
+    "let",
+    "lock",
+    "map",
+    "marks",
+    "messages",
+    "mksession",
+    "move",
+    "nnoremap",
+    "nohlsearch",
+    "normal",
+    "noremap",
+    "notify",
+    "oldfiles",
+    "ours",
+    "palette",
+    "print",
+    "put",
+    "qall",
+    "quit",
+    "redo",
+    "redir",
+    "registers",
+    "rename",
+    "reopen",
+    "resethunk",
+    "set",
+    "setlocal",
+    "sort",
+    "source",
+    "stagehunk",
+    "symbols",
+    "theirs",
+    "trust",
+    "undo",
+    "unlet",
+    "verbose",
+    "vglobal",
+    "vnoremap",
+    "wincmd",
+    "wqall",
+    "write",
+    "xall",
+    "xit",
+    "yank",
+];
+
+/// Lines a command applies to, typed before its name as in `:1,3Name`,
+/// `:.,+5Name`, `:'<,'>Name` or `:%Name`. After `;` rather than `,` the
+/// second address counts from the first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineRange {
+    pub start: Address,
+    pub end: Address,
+    pub from_start: bool,
+}
+
+/// A line, as `12`, `.`, `$` or a mark such as `'a`, moved down or up by
+/// the offset written after it, as in `.+5` or `$-1`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Address {
+    pub base: Base,
+    pub offset: isize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Base {
+    /// A line number, from 1.
+    Line(usize),
+    Current,
+    Last,
+    Mark(char),
+}
+
+impl LineRange {
+    /// Splits the range off the start of `input`, if there is one.
+    fn parse(input: &str) -> anyhow::Result<(Option<Self>, &str)> {
+        if let Some(rest) = input.strip_prefix('%') {
+            let range = LineRange {
+                start: Address::at(Base::Line(1)),
+                end: Address::at(Base::Last),
+                from_start: false,
+            };
+            return Ok((Some(range), rest));
+        }
+        let (start, rest) = Address::parse(input)?;
+        let (from_start, rest) = match rest.chars().next() {
+            Some(',') => (false, &rest[1..]),
+            Some(';') => (true, &rest[1..]),
+            _ => {
+                let range = start.map(|start| LineRange {
+                    start,
+                    end: start,
+                    from_start: false,
+                });
+                return Ok((range, rest));
+            }
+        };
+        let (end, rest) = Address::parse(rest)?;
+        let range = LineRange {
+            start: start.unwrap_or(Address::at(Base::Current)),
+            end: end.unwrap_or(Address::at(Base::Current)),
+            from_start,
+        };
+        Ok((Some(range), rest))
+    }
+
+    /// The lines from 0, given the cursor line, the last line and the
+    /// line of each mark set.
+    pub fn resolve(
+        self,
+        current: usize,
+        last: usize,
+        mark: impl Fn(char) -> Option<usize>,
+    ) -> anyhow::Result<(usize, usize)> {
+        let start = self.start.resolve(current, last, &mark)?;
+        let from = if self.from_start { start } else { current };
+        let end = self.end.resolve(from, last, &mark)?;
+        if start > end || end > last {
+            bail!("Invalid range");
+        }
+        Ok((start, end))
+    }
+}
+
+impl Address {
+    fn at(base: Base) -> Self {
+        Address { base, offset: 0 }
+    }
+
+    /// Splits the address off the start of `input`, if there is one.
+    fn parse(input: &str) -> anyhow::Result<(Option<Self>, &str)> {
+        let digits = |text: &str| {
+            text.find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(text.len())
+        };
+        let (base, mut rest) = match input.chars().next() {
+            Some('.') => (Some(Base::Current), &input[1..]),
+            Some('$') => (Some(Base::Last), &input[1..]),
+            Some('\'') => match input[1..].chars().next() {
+                Some(mark) => (Some(Base::Mark(mark)), &input[1 + mark.len_utf8()..]),
+                None => bail!("Invalid range: {input}"),
+            },
+            Some(c) if c.is_ascii_digit() => {
+                let (number, rest) = input.split_at(digits(input));
+                match number.parse() {
+                    Ok(line) if line > 0 => (Some(Base::Line(line)), rest),
+                    _ => bail!("Invalid range: {number}"),
+                }
+            }
+            _ => (None, input),
+        };
+        let mut offset = None;
+        while let Some(sign @ ('+' | '-')) = rest.chars().next() {
+            let (number, after) = rest[1..].split_at(digits(&rest[1..]));
+            let count: isize = match number {
+                "" => 1,
+                _ => number.parse()?,
+            };
+            let count = if sign == '-' { -count } else { count };
+            offset = Some(offset.unwrap_or(0) + count);
+            rest = after;
+        }
+        if base.is_none() && offset.is_none() {
+            return Ok((None, input));
+        }
+        let address = Address {
+            base: base.unwrap_or(Base::Current),
+            offset: offset.unwrap_or(0),
+        };
+        Ok((Some(address), rest))
+    }
+
+    /// The line from 0, given the cursor line, the last line and the line
+    /// of each mark set.
+    pub fn resolve(
+        self,
+        current: usize,
+        last: usize,
+        mark: impl Fn(char) -> Option<usize>,
+    ) -> anyhow::Result<usize> {
+        let line = match self.base {
+            Base::Line(line) => line - 1,
+            Base::Current => current,
+            Base::Last => last,
+            Base::Mark(name) => match mark(name) {
+                Some(line) => line,
+                None => bail!("Mark not set: {name}"),
+            },
+        };
+        match line.checked_add_signed(self.offset) {
+            Some(line) => Ok(line),
+            None => bail!("Invalid range"),
+        }
+    }
+}
+
+/// `input` without the range typed before the command name.
+pub fn strip_range(input: &str) -> &str {
+    match LineRange::parse(input) {
+        Ok((_, rest)) => rest,
+        Err(_) => input,
+    }
+}
+
+/// An ex command defined with `:command`, run by substituting its
+/// arguments and range into the `replacement` command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserCommand {
+    pub nargs: Nargs,
+    pub complete: Option<Complete>,
+    /// The lines it applies to when run without a range, `None` if it
+    /// takes none.
+    pub range: Option<DefaultRange>,
+    pub replacement: String,
+}
+
+/// How many arguments a user command takes, as given to `-nargs`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Nargs {
+    /// `0`, the default.
+    None,
+    /// `1`: its whole argument, spaces included.
+    One,
+    /// `?`: none or one.
+    Optional,
+    /// `*`: any number.
+    Any,
+    /// `+`: at least one.
+    Some,
+}
+
+/// What the arguments of a user command complete to, as given to
+/// `-complete`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Complete {
+    File,
+    Option,
+    Command,
+    /// The candidates a global function of the Lua plugins returns when
+    /// called with the argument being typed.
+    Custom(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DefaultRange {
+    /// `-range`: the cursor line.
+    CurrentLine,
+    /// `-range=%`: the whole buffer.
+    WholeBuffer,
+}
+
+impl UserCommand {
+    /// Parses the arguments of `:command`: attributes such as `-nargs=1`,
+    /// then the name and the replacement.
+    fn parse(args: &str) -> anyhow::Result<(String, Self)> {
+        let mut command = UserCommand {
+            nargs: Nargs::None,
+            complete: None,
+            range: None,
+            replacement: String::new(),
+        };
+        let mut rest = args;
+        while let Some(attribute) = rest.strip_prefix('-') {
+            let end = attribute.find(' ').unwrap_or(attribute.len());
+            let (key, value) = match attribute[..end].split_once('=') {
+                Some((key, value)) => (key, Some(value)),
+                None => (&attribute[..end], None),
+            };
+            match (key, value) {
+                ("nargs", Some(nargs)) => {
+                    command.nargs = match nargs {
+                        "0" => Nargs::None,
+                        "1" => Nargs::One,
+                        "?" => Nargs::Optional,
+                        "*" => Nargs::Any,
+                        "+" => Nargs::Some,
+                        _ => bail!("Invalid number of arguments: {nargs}"),
+                    }
+                }
+                ("complete", Some(complete)) => {
+                    command.complete = Some(match complete.split_once(',') {
+                        Some(("custom", function)) => Complete::Custom(function.to_string()),
+                        None if complete == "file" => Complete::File,
+                        None if complete == "option" => Complete::Option,
+                        None if complete == "command" => Complete::Command,
+                        _ => bail!("Invalid complete value: {complete}"),
+                    })
+                }
+                ("range", None) => command.range = Some(DefaultRange::CurrentLine),
+                ("range", Some("%")) => command.range = Some(DefaultRange::WholeBuffer),
+                _ => bail!("Invalid attribute: -{}", &attribute[..end]),
+            }
+            rest = attribute[end..].trim_start();
+        }
+        let (name, replacement) = rest.split_once(' ').unwrap_or((rest, ""));
+        if !name.starts_with(|c: char| c.is_ascii_uppercase())
+            || !name.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            bail!("User defined commands must start with an uppercase letter: {name}");
+        }
+        command.replacement = replacement.trim().to_string();
+        if command.replacement.is_empty() {
+            bail!("Argument required");
+        }
+        Ok((name.to_string(), command))
+    }
+
+    /// The command line to run for `args` over `lines`, numbered from 1,
+    /// after checking the number of arguments.
+    pub fn expand(&self, args: &str, lines: (usize, usize)) -> anyhow::Result<String> {
+        let count = args.split_whitespace().count();
+        match self.nargs {
+            Nargs::None if count > 0 => bail!("Trailing characters: {args}"),
+            Nargs::One | Nargs::Some if count == 0 => bail!("Argument required"),
+            Nargs::Optional if count > 1 => bail!("Trailing characters: {args}"),
+            _ => {}
+        }
+        Ok(self
+            .replacement
+            .replace("<args>", args)
+            .replace("<line1>", &lines.0.to_string())
+            .replace("<line2>", &lines.1.to_string())
+            .replace("<lt>", "<"))
+    }
+
+    /// How it is listed by `:command`.
+    pub fn describe(&self, name: &str) -> String {
+        let nargs = match self.nargs {
+            Nargs::None => "0",
+            Nargs::One => "1",
+            Nargs::Optional => "?",
+            Nargs::Any => "*",
+            Nargs::Some => "+",
+        };
+        let range = match self.range {
+            None => "",
+            Some(DefaultRange::CurrentLine) => ".",
+            Some(DefaultRange::WholeBuffer) => "%",
+        };
+        let complete = match &self.complete {
+            None => String::new(),
+            Some(Complete::File) => "file".to_string(),
+            Some(Complete::Option) => "option".to_string(),
+            Some(Complete::Command) => "command".to_string(),
+            Some(Complete::Custom(function)) => format!("custom,{function}"),
+        };
+        format!(
+            "{name:<12} {nargs:<4} {range:<5} {complete:<12} {}",
+            self.replacement
+        )
+    }
+}
@@ -0,0 +1,57 @@
+/// Comments or uncomments `lines` as a block: if every non-blank line is
+/// already commented they are all uncommented, otherwise they are all
+/// commented at the indentation of the least indented one. Blank lines are
+/// left alone.
+pub fn toggle(lines: &[String], (start, end): (&str, &str)) -> Vec<String> {
+    let code: Vec<&String> = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    let commented = |line: &str| {
+        let line = line.trim();
+        line.starts_with(start) && line.ends_with(end)
+    };
+
+    if !code.is_empty() && code.iter().all(|line| commented(line)) {
+        return lines
+            .iter()
+            .map(|line| match uncomment(line, start, end) {
+                Some(line) => line,
+                None => line.clone(),
+            })
+            .collect();
+    }
+
+    let indent = code
+        .iter()
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|line| {
+            if line.trim().is_empty() {
+                return line.clone();
+            }
+            let (indentation, rest) = line.split_at(indent);
+            match end {
+                "" => format!("{indentation}{start} {rest}"),
+                _ => format!("{indentation}{start} {rest} {end}"),
+            }
+        })
+        .collect()
+}
+
+/// Removes the comment markers around the code on `line`, along with the
+/// space padding them.
+fn uncomment(line: &str, start: &str, end: &str) -> Option<String> {
+    let code = line.trim_start();
+    let indentation = &line[..line.len() - code.len()];
+    let code = code.trim_end().strip_prefix(start)?.strip_suffix(end)?;
+    let code = code.strip_prefix(' ').unwrap_or(code);
+    let code = match end {
+        "" => code,
+        _ => code.strip_suffix(' ').unwrap_or(code),
+    };
+    Some(format!("{indentation}{code}"))
+}
@@ -0,0 +1,219 @@
+use std::collections::HashSet;
+use std::ops::Range;
+
+use ropey::Rope;
+
+/// Most candidates shown at once in the completion menu.
+pub const MAX_VISIBLE: usize = 10;
+
+/// A candidate offered by a completion source.
+#[derive(Debug)]
+pub struct CompletionItem {
+    pub label: String,
+    pub detail: String,
+    /// Text matched against what has been typed.
+    pub filter_text: String,
+    pub new_text: String,
+    /// Char range replaced by `new_text`; defaults to the typed word.
+    pub range: Option<Range<usize>>,
+    /// Further edits made on acceptance, such as adding an import.
+    pub additional_edits: Vec<(Range<usize>, String)>,
+    /// Whether `new_text` is a snippet with tab stops to expand.
+    pub snippet: bool,
+}
+
+/// The insert mode popup menu listing completion candidates for the word
+/// being typed.
+pub struct CompletionMenu {
+    items: Vec<CompletionItem>,
+    /// Indices into `items` matching the typed prefix.
+    matches: Vec<usize>,
+    pub selected: usize,
+    /// Whether the source wants to be asked again as typing continues.
+    pub incomplete: bool,
+    pub line: usize,
+    /// Column where the completed word starts.
+    pub start_col: usize,
+}
+
+impl CompletionMenu {
+    pub fn new(
+        items: Vec<CompletionItem>,
+        incomplete: bool,
+        line: usize,
+        start_col: usize,
+    ) -> Self {
+        Self {
+            matches: (0..items.len()).collect(),
+            items,
+            selected: 0,
+            incomplete,
+            line,
+            start_col,
+        }
+    }
+
+    /// Keeps only the candidates containing the typed characters in order,
+    /// case-insensitively, listing those that start with them first.
+    pub fn filter(&mut self, typed: &str) {
+        let prefix = typed.to_lowercase();
+        let typed: Vec<char> = prefix.chars().collect();
+        self.matches = (0..self.items.len())
+            .filter(|&i| {
+                let mut wanted = typed.iter().peekable();
+                for c in self.items[i]
+                    .filter_text
+                    .chars()
+                    .flat_map(char::to_lowercase)
+                {
+                    if wanted.peek() == Some(&&c) {
+                        wanted.next();
+                    }
+                }
+                wanted.peek().is_none()
+            })
+            .collect();
+        self.matches.sort_by_key(|&i| {
+            !self.items[i]
+                .filter_text
+                .to_lowercase()
+                .starts_with(&prefix)
+        });
+        self.selected = 0;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    pub fn select_next(&mut self) {
+        if !self.matches.is_empty() {
+            self.selected = (self.selected + 1) % self.matches.len();
+        }
+    }
+
+    pub fn select_prev(&mut self) {
+        if !self.matches.is_empty() {
+            self.selected = (self.selected + self.matches.len() - 1) % self.matches.len();
+        }
+    }
+
+    pub fn take_selected(mut self) -> Option<CompletionItem> {
+        let index = *self.matches.get(self.selected)?;
+        Some(self.items.swap_remove(index))
+    }
+
+    /// Returns the rows to draw, scrolled so the selection is visible, along
+    /// with whether each one is selected.
+    pub fn visible(&self) -> impl Iterator<Item = (bool, &CompletionItem)> {
+        let top = (self.selected + 1).saturating_sub(MAX_VISIBLE);
+        self.matches
+            .iter()
+            .enumerate()
+            .skip(top)
+            .take(MAX_VISIBLE)
+            .map(|(i, &item)| (i == self.selected, &self.items[item]))
+    }
+
+    pub fn height(&self) -> usize {
+        self.matches.len().min(MAX_VISIBLE)
+    }
+}
+
+/// Words in `text` starting with `prefix`, the way vim's `Ctrl-n` finds
+/// them: scanning forward from `cursor` and wrapping around, so the nearest
+/// following words come first and the nearest preceding ones last.
+pub fn buffer_words(text: &Rope, cursor: usize, prefix: &str) -> Vec<CompletionItem> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let mut words: Vec<(usize, String)> = Vec::new();
+    let mut word = String::new();
+    for (i, c) in text.chars().chain([' ']).enumerate() {
+        if is_word(c) {
+            word.push(c);
+        } else if !word.is_empty() {
+            let start = i - word.chars().count();
+            words.push((start, std::mem::take(&mut word)));
+        }
+    }
+
+    let split = words.partition_point(|(start, _)| *start < cursor);
+    let (before, after) = words.split_at(split);
+    let mut seen = HashSet::new();
+    after
+        .iter()
+        .chain(before)
+        .filter(|(start, word)| {
+            // The word being completed ends at the cursor.
+            let typed = *start + word.chars().count() == cursor;
+            !typed && word.starts_with(prefix) && word != prefix
+        })
+        .filter(|(_, word)| seen.insert(word.as_str()))
+        .map(|(_, word)| CompletionItem {
+            label: word.clone(),
+            detail: String::new(),
+            filter_text: word.clone(),
+            new_text: word.clone(),
+            range: None,
+            additional_edits: Vec::new(),
+            snippet: false,
+        })
+        .collect()
+}
+
+/// Completions of the partial path `typed`: entries of its directory that
+/// start with the typed file name, directories ending in `/`. Hidden entries
+/// are only offered once a `.` has been typed.
+pub fn paths(typed: &str) -> Vec<String> {
+    let (dir, name) = match typed.rfind('/') {
+        Some(i) => (&typed[..=i], &typed[i + 1..]),
+        None => ("", typed),
+    };
+    let lookup = match dir.strip_prefix("~/") {
+        Some(rest) => format!("{}/{rest}", std::env::var("HOME").unwrap_or_default()),
+        None if dir.is_empty() => ".".to_string(),
+        None => dir.to_string(),
+    };
+    let Result::Ok(entries) = std::fs::read_dir(&lookup) else {
+        return Vec::new();
+    };
+
+    let mut paths: Vec<String> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            if !file_name.starts_with(name)
+                || (file_name.starts_with('.') && !name.starts_with('.'))
+            {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{dir}{file_name}{slash}"))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Candidates cycled through with Tab on the command line, shown in a row
+/// above it.
+pub struct Wildmenu {
+    pub candidates: Vec<String>,
+    pub selected: usize,
+    /// Command line text before the completed argument.
+    pub prefix: String,
+}
+
+impl Wildmenu {
+    pub fn select_next(&mut self) {
+        self.selected = (self.selected + 1) % self.candidates.len();
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = (self.selected + self.candidates.len() - 1) % self.candidates.len();
+    }
+
+    /// The command line with the selected candidate filled in.
+    pub fn cmdline(&self) -> String {
+        format!("{}{}", self.prefix, self.candidates[self.selected])
+    }
+}
@@ -0,0 +1,228 @@
+//! The config file, `config.toml` in the config directory. It holds
+//! options set for one filetype in `[filetype.<name>]` sections, such as
+//!
+//! ```toml
+//! [filetype.python]
+//! textwidth = 79
+//! colorcolumn = "+1"
+//! commentstring = "# %s"
+//! ```
+//!
+//! which apply while a buffer of that filetype is shown.
+//!
+//! Parsing a large config at every start would slow it down, so the options
+//! found are kept in `config` in the cache directory, in a binary layout of
+//! its own, and read from there while the config file keeps the same
+//! modification time, size and contents. The cache holds only data, checked
+//! against a checksum, so a damaged or foreign one is ignored and made
+//! again. The theme and filetype detection are built in, and keys are
+//! mapped by the scripts `vimrc` and plugins run, so there is nothing else
+//! to cache.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use anyhow::{bail, Context};
+use toml_edit::{Document, Item, Value};
+
+use crate::{checksum, error};
+
+/// Starts a config cache in the layout below, changed along with it.
+const CACHE_MAGIC: &[u8] = b"vim-rs config cache 2\n";
+
+/// What a config file was as it was cached: its modification time, in
+/// seconds and nanoseconds since the epoch, its size and the checksum of
+/// its contents.
+type Stamp = (u64, u32, u64, u64);
+
+#[derive(Default)]
+pub struct Config {
+    /// Where the config was read from.
+    pub path: String,
+    /// Options of each filetype as `:set` takes them, with their line.
+    filetypes: HashMap<String, Vec<(String, usize)>>,
+}
+
+impl Config {
+    /// Reads the config at `path` from the `cache` made of it, or parses it
+    /// when the cache is missing or was made of the file as it was before,
+    /// and caches it there.
+    pub fn load(path: &Path, cache: Option<&Path>) -> anyhow::Result<Self> {
+        let name = path.display().to_string();
+        let metadata = fs::metadata(path).with_context(|| format!("Cannot read {name}"))?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?;
+        let text = fs::read_to_string(path).with_context(|| format!("Cannot read {name}"))?;
+        let stamp = (
+            modified.as_secs(),
+            modified.subsec_nanos(),
+            metadata.len(),
+            checksum([text.as_bytes()]),
+        );
+        let cached = cache
+            .and_then(|cache| fs::read(cache).ok())
+            .and_then(|bytes| Self::decode(&bytes, &name, stamp));
+        if let Some(config) = cached {
+            return Ok(config);
+        }
+        let config = Self::parse(&name, &text)?;
+        if let Some(Err(err)) = cache.map(|cache| config.write_cache(cache, stamp)) {
+            error!("Cannot cache the config: {err:#}");
+        }
+        Ok(config)
+    }
+
+    pub fn parse(path: &str, text: &str) -> anyhow::Result<Self> {
+        let document = Document::parse(text).with_context(|| format!("{path}: invalid TOML"))?;
+        let line = |span: Option<std::ops::Range<usize>>| {
+            span.map_or(0, |span| text[..span.start].matches('\n').count() + 1)
+        };
+        let mut filetypes = HashMap::new();
+        for (section, item) in document.as_table() {
+            let Some(table) = item.as_table().filter(|_| section == "filetype") else {
+                bail!(
+                    "{path} line {}: unknown section {section}",
+                    line(item.span())
+                );
+            };
+            for (filetype, item) in table {
+                let Some(options) = item.as_table() else {
+                    bail!(
+                        "{path} line {}: [filetype.{filetype}] must be a section",
+                        line(item.span())
+                    );
+                };
+                let mut args = Vec::new();
+                for (name, item) in options {
+                    let Item::Value(value) = item else {
+                        bail!("{path}: {name} of [filetype.{filetype}] must be a value");
+                    };
+                    let arg = match value {
+                        Value::Boolean(flag) if *flag.value() => name.to_string(),
+                        Value::Boolean(_) => format!("no{name}"),
+                        Value::Integer(number) => format!("{name}={}", number.value()),
+                        Value::String(text) => format!("{name}={}", text.value()),
+                        Value::Array(items) => {
+                            let items: Option<Vec<&str>> =
+                                items.iter().map(Value::as_str).collect();
+                            let items = items.with_context(|| {
+                                format!(
+                                    "{path} line {}: {name} must list strings",
+                                    line(value.span())
+                                )
+                            })?;
+                            format!("{name}={}", items.join(","))
+                        }
+                        _ => bail!(
+                            "{path} line {}: {name} must be a flag, number, string or list",
+                            line(value.span())
+                        ),
+                    };
+                    args.push((arg, line(value.span())));
+                }
+                filetypes.insert(filetype.to_string(), args);
+            }
+        }
+        Ok(Self {
+            path: path.to_string(),
+            filetypes,
+        })
+    }
+
+    /// The options set for `filetype`, as `:set` takes them, each with the
+    /// line it is on.
+    pub fn filetype(&self, filetype: &str) -> &[(String, usize)] {
+        self.filetypes.get(filetype).map_or(&[], Vec::as_slice)
+    }
+
+    /// Writes the config to `cache` as made of a file as `stamp` tells,
+    /// through a temporary file so that a cache is never left half written.
+    fn write_cache(&self, cache: &Path, stamp: Stamp) -> anyhow::Result<()> {
+        let mut body = Vec::new();
+        put_str(&mut body, &self.path);
+        body.extend(stamp.0.to_le_bytes());
+        body.extend(stamp.1.to_le_bytes());
+        body.extend(stamp.2.to_le_bytes());
+        body.extend(stamp.3.to_le_bytes());
+        body.extend((self.filetypes.len() as u64).to_le_bytes());
+        for (filetype, args) in &self.filetypes {
+            put_str(&mut body, filetype);
+            body.extend((args.len() as u64).to_le_bytes());
+            for (arg, line) in args {
+                put_str(&mut body, arg);
+                body.extend((*line as u64).to_le_bytes());
+            }
+        }
+        let mut bytes = CACHE_MAGIC.to_vec();
+        bytes.extend(checksum([body.as_slice()]).to_le_bytes());
+        bytes.extend(body);
+        if let Some(dir) = cache.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = cache.with_extension("tmp");
+        fs::write(&tmp, bytes).with_context(|| format!("Cannot write {}", tmp.display()))?;
+        fs::rename(&tmp, cache).with_context(|| format!("Cannot write {}", cache.display()))?;
+        Ok(())
+    }
+
+    /// The config cached in `bytes`, if it was made of the file at `path`
+    /// as `stamp` tells.
+    fn decode(bytes: &[u8], path: &str, stamp: Stamp) -> Option<Self> {
+        let mut reader = Reader(bytes.strip_prefix(CACHE_MAGIC)?);
+        let sum = reader.u64()?;
+        if checksum([reader.0]) != sum {
+            return None;
+        }
+        let cached_path = reader.str()?;
+        let cached_stamp = (reader.u64()?, reader.u32()?, reader.u64()?, reader.u64()?);
+        if cached_path != path || cached_stamp != stamp {
+            return None;
+        }
+        let mut filetypes = HashMap::new();
+        for _ in 0..reader.u64()? {
+            let filetype = reader.str()?;
+            let mut args = Vec::new();
+            for _ in 0..reader.u64()? {
+                args.push((reader.str()?, reader.u64()? as usize));
+            }
+            filetypes.insert(filetype, args);
+        }
+        reader.0.is_empty().then_some(Self {
+            path: cached_path,
+            filetypes,
+        })
+    }
+}
+
+/// Writes `text` after its length.
+fn put_str(bytes: &mut Vec<u8>, text: &str) {
+    bytes.extend((text.len() as u64).to_le_bytes());
+    bytes.extend(text.as_bytes());
+}
+
+/// Takes the fields of a config cache off the front of its bytes.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (bytes, rest) = self.0.split_first_chunk()?;
+        self.0 = rest;
+        Some(*bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.bytes().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.bytes().map(u64::from_le_bytes)
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = usize::try_from(self.u64()?).ok()?;
+        let (text, rest) = self.0.split_at_checked(len)?;
+        self.0 = rest;
+        String::from_utf8(text.to_vec()).ok()
+    }
+}
@@ -0,0 +1,116 @@
+//! Merge conflicts git leaves in a file between `<<<<<<<` and `>>>>>>>`
+//! markers, and their resolution by keeping one side or both.
+
+use std::ops::Range;
+
+use ropey::Rope;
+
+/// Lines of a conflict, markers included.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    /// The `<<<<<<<` line opening our side.
+    pub start: usize,
+    /// The `|||||||` line opening the common ancestor, in the diff3 style.
+    pub base: Option<usize>,
+    /// The `=======` line opening their side.
+    pub separator: usize,
+    /// The `>>>>>>>` line closing the conflict.
+    pub end: usize,
+}
+
+/// Which side of a conflict to keep.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resolution {
+    Ours,
+    Theirs,
+    Both,
+}
+
+/// The part of a conflict a line is in, for highlighting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Marker,
+    Ours,
+    /// The common ancestor, shown in the diff3 style.
+    Base,
+    Theirs,
+}
+
+impl Conflict {
+    pub fn lines(&self) -> Range<usize> {
+        self.start..self.end + 1
+    }
+
+    pub fn ours(&self) -> Range<usize> {
+        self.start + 1..self.base.unwrap_or(self.separator)
+    }
+
+    pub fn theirs(&self) -> Range<usize> {
+        self.separator + 1..self.end
+    }
+
+    /// The lines left once resolved.
+    pub fn kept(&self, resolution: Resolution) -> Vec<Range<usize>> {
+        match resolution {
+            Resolution::Ours => vec![self.ours()],
+            Resolution::Theirs => vec![self.theirs()],
+            Resolution::Both => vec![self.ours(), self.theirs()],
+        }
+    }
+
+    pub fn region(&self, line: usize) -> Option<Region> {
+        if self.ours().contains(&line) {
+            Some(Region::Ours)
+        } else if self.theirs().contains(&line) {
+            Some(Region::Theirs)
+        } else if self
+            .base
+            .is_some_and(|base| base < line && line < self.separator)
+        {
+            Some(Region::Base)
+        } else if self.lines().contains(&line) {
+            Some(Region::Marker)
+        } else {
+            None
+        }
+    }
+}
+
+/// The conflicts in `text`, in order. Markers that do not close a conflict
+/// are ignored.
+pub fn find(text: &Rope) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+    let mut start = None;
+    let mut base = None;
+    let mut separator = None;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.chars().take(8).collect::<String>();
+        if is_marker(&line, '<') {
+            (start, base, separator) = (Some(i), None, None);
+        } else if start.is_some() && separator.is_none() && is_marker(&line, '|') {
+            base = Some(i);
+        } else if start.is_some() && is_marker(&line, '=') {
+            separator = Some(i);
+        } else if is_marker(&line, '>') {
+            if let (Some(start), Some(separator)) = (start, separator) {
+                conflicts.push(Conflict {
+                    start,
+                    base,
+                    separator,
+                    end: i,
+                });
+            }
+            (start, base, separator) = (None, None, None);
+        }
+    }
+    conflicts
+}
+
+/// Whether `line` starts with a marker made of seven `c`, followed by
+/// nothing or a space and a label.
+fn is_marker(line: &str, c: char) -> bool {
+    let Some(rest) = line.strip_prefix(&c.to_string().repeat(7)) else {
+        return false;
+    };
+    rest.is_empty() || rest.starts_with([' ', '\n', '\r'])
+}
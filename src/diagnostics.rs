@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+    Hint,
+}
+
+impl Severity {
+    /// Maps the numeric severity used by LSP and most linters.
+    pub fn from_lsp(severity: u64) -> Self {
+        match severity {
+            1 => Severity::Error,
+            2 => Severity::Warning,
+            3 => Severity::Info,
+            _ => Severity::Hint,
+        }
+    }
+}
+
+/// A problem reported for a span of a single line, in char columns.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub start_col: usize,
+    pub end_col: usize,
+    pub severity: Severity,
+    pub message: String,
+    pub source: String,
+}
+
+/// Diagnostics of the current buffer, grouped by the source that published
+/// them so each source replaces only its own results.
+#[derive(Default)]
+pub struct Diagnostics {
+    by_source: HashMap<String, Vec<Diagnostic>>,
+}
+
+impl Diagnostics {
+    pub fn publish(&mut self, source: &str, diagnostics: Vec<Diagnostic>) {
+        self.by_source.insert(source.to_string(), diagnostics);
+    }
+
+    pub fn clear(&mut self) {
+        self.by_source.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_source.values().all(Vec::is_empty)
+    }
+
+    /// Diagnostics touching `line`, most severe first.
+    pub fn on_line(&self, line: usize) -> Vec<&Diagnostic> {
+        let mut diagnostics: Vec<&Diagnostic> = self
+            .by_source
+            .values()
+            .flatten()
+            .filter(|d| d.line == line)
+            .collect();
+        diagnostics.sort_by_key(|d| (d.severity, d.start_col));
+        diagnostics
+    }
+}
@@ -0,0 +1,212 @@
+use std::ops::Range;
+
+/// A run of items that differ between two sequences: `old` is replaced by
+/// `new`, either of which may be empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+/// Computes the hunks turning `old` into `new` with Myers' algorithm, after
+/// trimming the common prefix and suffix.
+pub fn diff<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Hunk> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let mut hunks = Vec::new();
+    let (mut old_i, mut new_i) = (0, 0);
+    for (x, y) in matches(a, b) {
+        if x > old_i || y > new_i {
+            hunks.push(Hunk {
+                old: prefix + old_i..prefix + x,
+                new: prefix + new_i..prefix + y,
+            });
+        }
+        old_i = x + 1;
+        new_i = y + 1;
+    }
+    if old_i < a.len() || new_i < b.len() {
+        hunks.push(Hunk {
+            old: prefix + old_i..prefix + a.len(),
+            new: prefix + new_i..prefix + b.len(),
+        });
+    }
+    hunks
+}
+
+/// Where item `line` of the old sequence is in the new one after `hunks`:
+/// items past a hunk move with it, those within one keep their offset into
+/// it as far as what replaced them goes.
+pub fn map_line(hunks: &[Hunk], line: usize) -> usize {
+    let mut shift = 0;
+    for hunk in hunks {
+        if line < hunk.old.start {
+            break;
+        }
+        if line < hunk.old.end {
+            let offset = (line - hunk.old.start).min(hunk.new.len().saturating_sub(1));
+            return hunk.new.start + offset;
+        }
+        shift = hunk.new.end as isize - hunk.old.end as isize;
+    }
+    line.saturating_add_signed(shift)
+}
+
+/// Returns the index pairs of items kept unchanged on a shortest edit path,
+/// in ascending order.
+fn matches<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    if n == 0 || m == 0 {
+        return Vec::new();
+    }
+
+    // `v[k]` is the furthest x reached on diagonal k; `trace[d]` keeps the
+    // diagonals -d-1..=d+1 as they were before step d.
+    let offset = n + m + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    let mut trace = Vec::new();
+    let at = |k: isize| (offset + k) as usize;
+
+    'search: for d in 0..=n + m {
+        trace.push(v[at(-d - 1)..=at(d + 1)].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+                v[at(k + 1)]
+            } else {
+                v[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[at(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut pairs = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let get = |k: isize| v[(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = get(prev_k);
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y && x > 0 && y > 0 {
+            x -= 1;
+            y -= 1;
+            pairs.push((x as usize, y as usize));
+        }
+        if d > 0 {
+            x = prev_x;
+            y = prev_y;
+        }
+    }
+    pairs.reverse();
+    pairs
+}
+
+/// A stretch of a three-way merge.
+#[derive(Debug, PartialEq)]
+pub enum Merged<T> {
+    /// Items taken from the base or from whichever side changed them.
+    Resolved(Vec<T>),
+    /// Both sides changed the same stretch of the base differently.
+    Conflict { ours: Vec<T>, theirs: Vec<T> },
+}
+
+/// Merges the changes `ours` and `theirs` each made to `base`. Changes
+/// overlapping or touching on the base conflict unless they are identical.
+pub fn merge<T: PartialEq + Clone>(base: &[T], ours: &[T], theirs: &[T]) -> Vec<Merged<T>> {
+    let sides = [diff(base, ours), diff(base, theirs)];
+    let mut next = [0, 0];
+    let mut merged = Vec::new();
+    let mut resolved = Vec::new();
+    let mut pos = 0;
+
+    loop {
+        let start = (0..2)
+            .filter_map(|side| sides[side].get(next[side]))
+            .map(|hunk| hunk.old.start)
+            .min();
+        let Some(start) = start else {
+            break;
+        };
+        resolved.extend_from_slice(&base[pos..start]);
+
+        // Grow the region until no further hunk of either side touches it.
+        let mut end = start;
+        let mut taken = [next[0], next[1]];
+        loop {
+            let mut grown = false;
+            for side in 0..2 {
+                while let Some(hunk) = sides[side].get(taken[side]) {
+                    if hunk.old.start > end {
+                        break;
+                    }
+                    end = end.max(hunk.old.end);
+                    taken[side] += 1;
+                    grown = true;
+                }
+            }
+            if !grown {
+                break;
+            }
+        }
+
+        // Each side's version of the base region.
+        let version = |side: usize, new: &[T]| {
+            let mut items = Vec::new();
+            let mut at = start;
+            for hunk in &sides[side][next[side]..taken[side]] {
+                items.extend_from_slice(&base[at..hunk.old.start]);
+                items.extend_from_slice(&new[hunk.new.clone()]);
+                at = hunk.old.end;
+            }
+            items.extend_from_slice(&base[at..end]);
+            items
+        };
+        let ours_changed = taken[0] > next[0];
+        let theirs_changed = taken[1] > next[1];
+        let ours_version = version(0, ours);
+        let theirs_version = version(1, theirs);
+        if !theirs_changed || ours_version == theirs_version {
+            resolved.extend(ours_version);
+        } else if !ours_changed {
+            resolved.extend(theirs_version);
+        } else {
+            if !resolved.is_empty() {
+                merged.push(Merged::Resolved(std::mem::take(&mut resolved)));
+            }
+            merged.push(Merged::Conflict {
+                ours: ours_version,
+                theirs: theirs_version,
+            });
+        }
+        next = taken;
+        pos = end;
+    }
+
+    resolved.extend_from_slice(&base[pos..]);
+    if !resolved.is_empty() {
+        merged.push(Merged::Resolved(resolved));
+    }
+    merged
+}
@@ -0,0 +1,108 @@
+//! Diff mode, comparing two files side by side as started by `vim-rs -d a
+//! b`. The focused file is the editor's buffer, the other one waits here
+//! with its cursor until focus moves to it.
+
+use std::ops::Range;
+
+use ropey::Rope;
+
+use crate::buffer::Buffer;
+use crate::diff::{self, Hunk};
+
+/// A screen row of the comparison: the line of each file shown on it, or
+/// `None` for filler where the other file has lines this one lacks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Row {
+    /// Line of the focused buffer.
+    pub ours: Option<usize>,
+    /// Line of the other buffer.
+    pub theirs: Option<usize>,
+    /// Whether the row is part of a difference.
+    pub changed: bool,
+}
+
+pub struct DiffView {
+    pub other: Buffer,
+    /// Cursor line and column in the other buffer.
+    pub other_cursor: (usize, usize),
+    /// Whether the focused buffer is shown on the left.
+    pub focus_left: bool,
+    /// Where the cursor was last drawn on the screen.
+    pub cursor: (u16, u16),
+    /// Rows for the revisions of the focused and the other buffer.
+    rows: Option<((usize, usize), Vec<Row>)>,
+}
+
+impl DiffView {
+    pub fn new(other: Buffer) -> Self {
+        Self {
+            other,
+            other_cursor: (0, 0),
+            focus_left: true,
+            cursor: (0, 0),
+            rows: None,
+        }
+    }
+
+    /// How `buffer` differs from the other buffer: `old` ranges are lines of
+    /// the other, `new` ranges lines of `buffer`.
+    pub fn hunks(&self, buffer: &Buffer) -> Vec<Hunk> {
+        diff::diff(&lines(&self.other.text), &lines(&buffer.text))
+    }
+
+    /// The two buffers aligned on rows, `buffer` being the focused one.
+    pub fn rows(&mut self, buffer: &Buffer) -> &[Row] {
+        let revisions = (buffer.revision, self.other.revision);
+        if self
+            .rows
+            .as_ref()
+            .is_none_or(|(aligned, _)| *aligned != revisions)
+        {
+            self.rows = Some((revisions, align(&self.hunks(buffer), buffer, &self.other)));
+        }
+        self.rows.as_ref().map_or(&[], |(_, rows)| rows)
+    }
+
+    /// Forgets the alignment, as when focus moved to the other buffer.
+    pub fn realign(&mut self) {
+        self.rows = None;
+    }
+}
+
+/// Chars spanned by `lines` of `text`, line breaks included.
+pub fn char_range(text: &Rope, lines: Range<usize>) -> Range<usize> {
+    text.line_to_char(lines.start)..text.line_to_char(lines.end)
+}
+
+fn lines(text: &Rope) -> Vec<String> {
+    text.lines()
+        .map(|line| line.to_string().trim_end_matches(['\n', '\r']).to_string())
+        .collect()
+}
+
+fn align(hunks: &[Hunk], ours: &Buffer, theirs: &Buffer) -> Vec<Row> {
+    let mut rows = Vec::new();
+    let (mut our_line, mut their_line) = (0, 0);
+    let same = |rows: &mut Vec<Row>, count: usize, ours: usize, theirs: usize| {
+        rows.extend((0..count).map(|i| Row {
+            ours: Some(ours + i),
+            theirs: Some(theirs + i),
+            changed: false,
+        }));
+    };
+    for hunk in hunks {
+        same(&mut rows, hunk.new.start - our_line, our_line, their_line);
+        for i in 0..hunk.new.len().max(hunk.old.len()) {
+            rows.push(Row {
+                ours: (i < hunk.new.len()).then_some(hunk.new.start + i),
+                theirs: (i < hunk.old.len()).then_some(hunk.old.start + i),
+                changed: true,
+            });
+        }
+        our_line = hunk.new.end;
+        their_line = hunk.old.end;
+    }
+    same(&mut rows, ours.len() - our_line, our_line, their_line);
+    debug_assert_eq!(their_line + ours.len() - our_line, theirs.len());
+    rows
+}
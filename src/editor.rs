@@ -1,20 +1,90 @@
-use std::io::{stdout, Stdout, Write};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::{stdout, BufRead, BufWriter, Stdout, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use anyhow::Ok;
+use anyhow::{Context, Ok};
 
 use crossterm::{
     cursor,
-    event::{self, read, Event, KeyModifiers},
-    style::{self, Stylize},
+    event::{self, read, Event, KeyEvent, KeyModifiers},
+    style::{self, StyledContent, Stylize},
     terminal::{self, Clear, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand, QueueableCommand,
 };
 use ropey::RopeSlice;
+use serde_json::{json, Value};
 
+use crate::actionlog::{ActionLog, Recording};
+use crate::analysis::{self, Analysis, Snapshot};
 use crate::buffer::Buffer;
+use crate::capabilities::{Capabilities, Overrides};
+use crate::command::{
+    self, Command, Complete, DefaultRange, LineRange, Quit, Redirect, Travel, UserCommand,
+};
+use crate::comment;
+use crate::completion::{self, CompletionItem, CompletionMenu, Wildmenu};
+use crate::config::Config;
+use crate::conflicts::{self, Conflict, Resolution};
+use crate::diagnostics::{Diagnostic, Diagnostics, Severity};
+use crate::diff;
+use crate::diffview::{self, DiffView};
+use crate::explorer::{self, FsOp};
+use crate::filetype;
+use crate::folds::{self, FoldOp};
+use crate::git::{self, Blamer, Sign, Signs, StatusWatcher};
+use crate::history::{self, CommandHistory, Recall};
+use crate::keys;
+use crate::links::{self, Target};
+use crate::linters;
+use crate::lsp::{self, LspClient, LspEvent};
+use crate::messages;
+use crate::motion;
+use crate::notifications::{self, Channel, Notice, MAX_TOASTS};
+use crate::options::{Options, Origin};
+use crate::pager::Pager;
+use crate::pairs;
+use crate::palette::{self, Entry};
+use crate::picker::{self, Pick, Picker};
+use crate::plugins::{self, MapMode, Plugins};
+use crate::popup::{LineStyle, Popup};
+use crate::screen::{Component, Screen};
+use crate::search::{self, Search};
+use crate::segments::Segments;
+use crate::session;
+use crate::signs::{Differ, Marker, Source};
+use crate::snippets::{self, Session, Snippet};
+use crate::state::State;
+use crate::statusline::Fields;
+use crate::theme::{Rgb, Style, Theme};
+use crate::trust;
+use crate::undo;
+use crate::vimscript::{self, Autocmd};
+use crate::wasm::WasmPlugins;
+use crate::watcher::FileWatcher;
+use crate::workspace_edit;
+use crate::{error, log, warn};
+
+/// How often background work such as language server replies is checked for
+/// while waiting on the terminal.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Bytes written to the terminal at once, enough for a frame redrawing all
+/// of a large one.
+const FRAME_BUFFER: usize = 256 * 1024;
+
+/// Most user commands running others may nest, so that one running itself
+/// fails rather than overflowing the stack.
+const MAX_COMMAND_DEPTH: usize = 100;
 
+/// Most files listed by the file picker.
+const MAX_PICKER_FILES: usize = 50_000;
+
+#[derive(Debug)]
 enum Action {
-    Quit,
+    Quit(Quit),
     MoveUp,
     MoveDown,
     MoveLeft,
@@ -24,77 +94,1046 @@ enum Action {
     NewLine,
     PageDown,
     PageUp,
+    Undo,
+    Redo,
+    /// Changes the case of the visual selection.
+    ChangeCase(Case),
+    DeleteSelection,
+    /// Deletes as many chars from the cursor on as the count, within the
+    /// line, keeping them in the unnamed register as `x` does.
+    DeleteChars(usize),
+    /// Puts the unnamed register as many times as the count after the
+    /// cursor, or before it: on lines of their own when it ends with a
+    /// line break.
+    Put {
+        before: bool,
+        count: usize,
+    },
+    /// Joins as many lines as the count, two at least, from the cursor
+    /// line on, as `J` does.
+    JoinLines(usize),
+    /// Replaces the visual selection with the text of the unnamed register,
+    /// which takes the selection in turn.
+    PasteOverSelection,
+    /// Goes to the state of the text made before the current one, or after
+    /// it, on whatever branch of the undo history.
+    OlderState,
+    NewerState,
     EndOfLine,
     StartOfLine,
+    /// Goes to the first char of the line that is not blank, as `^` does.
+    FirstNonBlank,
+    /// Goes to the first char that is not blank, or to the first column
+    /// when already there, as Home does.
+    SmartHome,
     DelCharBefore,
     DelCharAtCursor,
+    OlderChange,
+    NewerChange,
+    /// Comments or uncomments the lines from `first` to `last`.
+    ToggleComment {
+        first: usize,
+        last: usize,
+    },
+    /// Folds the lines from `first` to `last`, closed.
+    CreateFold {
+        first: usize,
+        last: usize,
+    },
+    /// Opens, closes or deletes the folds at the cursor, or all of them.
+    Fold(FoldOp),
+    /// Sets a mark at the cursor, to be given as a line in ex ranges.
+    SetMark(char),
+    /// Moves the cursor line, or the lines selected, down or up by one.
+    DragLines {
+        down: bool,
+    },
+    JumpToChange(usize),
+    /// Moves the cursor to a buffer line and column.
+    Goto(usize, usize),
+    /// Selects again what was last selected in visual mode, as `gv` does.
+    Reselect,
+    /// Moves the cursor to the other end of the selection, which stays.
+    SwapSelectionEnds,
+    /// Opens the command line to search forward for a pattern, or
+    /// backward.
+    StartSearch {
+        backward: bool,
+    },
+    /// Moves to the next match of the last search, or the one the other
+    /// way when `reverse`.
+    SearchNext {
+        reverse: bool,
+    },
+    /// Searches for the word under the cursor, forward or backward, as a
+    /// whole word unless `partial`.
+    SearchWord {
+        backward: bool,
+        partial: bool,
+    },
+    CommandChar(char),
+    /// Brings back the previous entry of the history starting with what
+    /// was typed, or the next when not `older`.
+    RecallHistory {
+        older: bool,
+    },
+    /// Searches the history for entries holding what is typed next, or
+    /// goes on to an older one when already searching.
+    SearchHistory,
+    /// Completes the command name or argument being typed, cycling
+    /// through candidates when pressed again.
+    CommandComplete {
+        backward: bool,
+    },
+    /// Deletes the word before the cursor, or with `line` all of its line
+    /// before it, as `Ctrl-w` and `Ctrl-u` do in insert mode.
+    DeleteBack {
+        line: bool,
+    },
+    /// Text pasted into the terminal, inserted at once rather than typed.
+    Paste(String),
+    /// Inserts the text of a register as if pasted, after `Ctrl-r`.
+    PasteRegister(char),
+    /// Writes the buffer if `autosave` is set, as when the terminal lost
+    /// the focus.
+    AutoSave,
+    /// Hands the terminal back to the shell and stops the editor until it
+    /// is resumed, as with Ctrl-Z.
+    Suspend,
+    CommandBackspace,
+    ExecuteCommand,
+    PagerSelect,
+    ClosePager,
+    PickerInput(picker::Input),
+    /// Replaces the buffer with the file at `path`, even when it has
+    /// unsaved changes if `force`.
+    EditFile {
+        path: String,
+        force: bool,
+    },
+    ShowSymbols(Vec<lsp::Symbol>),
+    Hover,
+    ShowHover(String),
+    ScrollPopup(isize),
+    OpenEntry,
+    OpenParent,
+    ApplyFsOps(Vec<FsOp>),
+    TriggerCompletion,
+    /// Completes the path before the cursor in insert mode, or inserts a
+    /// tab when there is none.
+    CompletePath,
+    /// Completes from words in the buffer, selecting the nearest match after
+    /// the cursor, or before it when `backward`.
+    CompleteWords {
+        backward: bool,
+    },
+    /// Expands the snippet whose prefix is before the cursor.
+    ExpandSnippet,
+    /// Moves to the next tab stop of the snippet being filled in, or the
+    /// previous one when `backward`.
+    JumpTabStop {
+        backward: bool,
+    },
+    ShowCompletion(Vec<CompletionItem>, bool),
+    CompletionNext,
+    CompletionPrev,
+    AcceptCompletion,
+    CloseCompletion,
+    PublishDiagnostics(String, Vec<Diagnostic>),
+    ShowCodeActions(Vec<Value>),
+    /// Applies a code action, resolving it first when the server left its
+    /// edit out of the listing.
+    CodeActionChosen {
+        action: Value,
+        resolved: bool,
+    },
+    /// Applies a workspace edit, answering the server's request for it if
+    /// there was one.
+    ApplyWorkspaceEdit {
+        edit: Value,
+        reply_to: Option<Value>,
+    },
+    /// Moves to the start of the next or previous hunk differing from the
+    /// git index, or from the other buffer in diff mode.
+    NextHunk {
+        backward: bool,
+    },
+    /// Moves focus to the other buffer of diff mode.
+    SwitchWindow,
+    /// Closes the focused window of diff mode, leaving the other one.
+    CloseWindow,
+    /// Copies the difference at the cursor from the other buffer of diff
+    /// mode, or to it when `put`.
+    DiffCopy {
+        put: bool,
+    },
+    /// Moves to the start of the next or previous merge conflict.
+    NextConflict {
+        backward: bool,
+    },
+    /// Edits the file named under the cursor or on the selected pager
+    /// line, or with `external` opens its link with the desktop's handler.
+    FollowLink {
+        external: bool,
+    },
+    /// Runs the function a plugin mapped to the key.
+    RunKeymap(KeyEvent),
+    /// Types keys a mapping stands for, in the notation of `keys`.
+    FeedKeys(String),
+    /// Trusts the buffer's workspace to run programs for it, from now on
+    /// if `persist`, otherwise until the editor exits.
+    TrustWorkspace {
+        persist: bool,
+    },
+    /// Writes the buffer even though the file changed on disk.
+    ForceWrite,
+    /// Shows how the buffer differs from the file on disk.
+    ShowDiskDiff,
+    /// Merges changes made to the file on disk into the buffer.
+    MergeDisk,
+    /// Replaces the buffer with the file on disk.
+    ReloadFile,
+    /// Tells whether the file changed on disk, as when the terminal gained
+    /// the focus.
+    CheckDisk,
+    /// Applies the text edits of a formatting response, null if the server
+    /// had none to offer.
+    ApplyFormatting(Value),
+    /// Tells that a long-running job finished or failed.
+    Notify(messages::Severity, String),
 }
 
 #[derive(Debug)]
 enum Mode {
     Normal,
     Insert,
+    Command,
+    /// Selecting chars from where it started to the cursor.
+    Visual,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Case {
+    Lower,
+    Upper,
+    /// Each char to the other case.
+    Toggle,
+}
+
+impl Case {
+    fn apply(self, text: &str) -> String {
+        match self {
+            Case::Lower => text.to_lowercase(),
+            Case::Upper => text.to_uppercase(),
+            Case::Toggle => text
+                .chars()
+                .flat_map(|c| match c.is_lowercase() {
+                    true => c.to_uppercase().collect::<Vec<_>>(),
+                    false => c.to_lowercase().collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A window closed in diff mode, kept for `:reopen` to bring back.
+struct ClosedWindow {
+    buffer: Buffer,
+    cursor: (usize, usize),
+    /// Whether it was the left one.
+    left: bool,
+}
+
+/// Where the output of ex commands goes while `:redir` is on.
+enum Redirection {
+    /// Collected until `:redir END` stores it in the register.
+    Register {
+        name: char,
+        text: String,
+    },
+    File(fs::File),
 }
 
 pub struct Editor {
     buffer: Buffer,
-    stdout: Stdout,
+    /// Written to through a buffer large enough for a whole frame, so that
+    /// drawing one takes one write, however many pieces make it up.
+    stdout: BufWriter<Stdout>,
+    screen: Screen,
+    /// Terminal size the screen was last drawn for.
+    drawn_size: (u16, u16),
     size: (u16, u16),
     vtop: u16,
     vleft: u16,
     cx: u16,
     cy: u16,
+    /// Column the cursor returns to when moving across shorter lines.
+    cx_history: u16,
     mode: Mode,
+    cmdline: String,
+    /// The char before the command line: `:` for a command, `/` or `?` for
+    /// a search forward or backward.
+    prompt: char,
+    /// The last search, and whether its matches are highlighted under
+    /// `hlsearch`, until `:nohlsearch`.
+    search: Option<Search>,
+    search_shown: bool,
+    /// The commands and patterns entered before, and where in them the
+    /// command line is while going through them.
+    history: CommandHistory,
+    recall: Option<Recall>,
+    /// Keys typed so far of a normal mode command such as `gc`.
+    pending: Option<&'static str>,
+    /// The count typed before a command in normal or visual mode, such as
+    /// the 3 of `3}`.
+    count: Option<usize>,
+    pager: Option<Pager<Action>>,
+    picker: Option<Box<dyn Pick<Action>>>,
+    popup: Option<Popup>,
+    lsp: Option<LspClient>,
+    completion: Option<CompletionMenu>,
+    wildmenu: Option<Wildmenu>,
+    analysis: Analysis,
+    diagnostics: Diagnostics,
+    differ: Differ,
+    /// How lines differ from the git index, `None` for untracked files.
+    git_signs: Option<Signs>,
+    /// Lines changed since the buffer was last written.
+    modified: Signs,
+    blamer: Blamer,
+    /// Branch and worktree state of the buffer's repository.
+    repo: StatusWatcher,
+    /// Commands defined with `:command`.
+    user_commands: BTreeMap<String, UserCommand>,
+    /// User commands being run, each by the one before.
+    command_depth: usize,
+    /// Text stored by name, such as with `:redir @a`.
+    registers: HashMap<char, String>,
+    /// Registers, marks and files remembered from earlier sessions, and
+    /// kept for the next.
+    state: State,
+    redirection: Option<Redirection>,
+    /// The other buffer when comparing two side by side.
+    diff: Option<DiffView>,
+    /// The rows long lines are shown in while the buffer has any longer
+    /// than `segmentlength`.
+    segments: Option<Segments>,
+    /// Windows closed in diff mode, the last closed at the end.
+    closed: Vec<ClosedWindow>,
+    /// Merge conflicts in the buffer and the revision they were found in.
+    conflicts: Option<(usize, Vec<Conflict>)>,
+    plugins: Plugins,
+    wasm: WasmPlugins,
+    /// Set while applying what a plugin did, so that it cannot trigger
+    /// events running plugins again.
+    in_plugin: bool,
+    /// Actions carried out lately, to reproduce what led to a bug.
+    actions: ActionLog,
+    /// When the last terminal event arrived, used to detect idleness.
+    last_input: Instant,
+    /// Action run when the prompt shown in the pager is answered with `y`.
+    confirm: Option<Action>,
+    options: Options,
+    theme: Theme,
+    caps: Capabilities,
+    /// Set when driven by synthesized events with no terminal attached.
+    headless: bool,
+    /// Revision a formatting request was sent for, and whether the buffer
+    /// is written once the result is applied.
+    formatting: Option<(usize, bool)>,
+    /// Snippets for the buffer's filetype.
+    snippets: Vec<Snippet>,
+    /// The expanded snippet whose tab stops are being filled in.
+    snippet: Option<Session>,
+    /// Set while the screen is hidden after being left idle, until the next
+    /// key press.
+    locked: bool,
+    /// Whether the pager last drew lines with links, which the statusline
+    /// tells how to follow when the terminal cannot click them.
+    links_shown: bool,
+    /// Where the ex commands being run come from, remembered with the
+    /// options they set.
+    origin: Origin,
+    /// Set while `:verbose` runs a command.
+    verbose: bool,
+    /// The revision `autosave` last tried to write once typing paused,
+    /// tried only once so that a write refused does not come back.
+    autosaved: Option<usize>,
+    /// Reports the file changing on disk, unless headless.
+    watcher: Option<FileWatcher>,
+    /// The size told while the terminal sends resize events, laid out
+    /// once they stop.
+    resized: Option<(u16, u16)>,
+    /// The char where the selection of visual mode started.
+    visual_start: usize,
+    /// Whether the cursor was at the start of the last selection made,
+    /// where `gv` puts it back.
+    visual_reversed: bool,
+    /// The char where the text being inserted starts, which `Ctrl-w` and
+    /// `Ctrl-u` stop at before deleting further back.
+    insert_start: usize,
+    /// The contents on disk last told of, so that they are told of once.
+    disk_noticed: Option<String>,
+    /// The notification shown in place of the statusline for a moment.
+    flash: Option<Notice>,
+    /// Toasts in the top right corner, the newest last.
+    toasts: Vec<Notice>,
+    /// The cursor shape last set, or `None` while the terminal shows its
+    /// own.
+    cursor_shape: Option<cursor::SetCursorStyle>,
+    /// The terminal title last set, or `None` while the terminal shows its
+    /// own.
+    title: Option<String>,
+    /// Variables set with `:let`, named without the `g:` they may be
+    /// written with.
+    variables: HashMap<String, vimscript::Value>,
+    /// Keys mapped with `:map` and its variants to the keys they stand for,
+    /// both in the notation of `keys`, with where they were mapped.
+    key_maps: BTreeMap<(MapMode, String), (String, Origin)>,
+    /// The keys typed so far of a mapping of more than one.
+    map_typed: String,
+    /// Set while the keys a mapping stands for are fed, which are not
+    /// mapped again.
+    feeding_keys: bool,
+    /// Commands run on events with `:autocmd`, with where they were set.
+    autocmds: Vec<(Autocmd, Origin)>,
+    /// Set while autocommands run, so that they cannot set others off.
+    in_autocmd: bool,
+    /// Set while `:g` runs, which cannot run itself.
+    in_global: bool,
+    /// The config file, with the options of each filetype.
+    config: Config,
+    /// Options as they were before those of the filetype of the buffer were
+    /// set, to set them back when another buffer is shown.
+    filetype_restore: Vec<(String, Option<Origin>)>,
+    /// Messages given before a key was last pressed, which the message
+    /// area below the statusline no longer shows.
+    messages_seen: usize,
 }
 
 impl Drop for Editor {
     fn drop(&mut self) {
-        _ = self.stdout.flush();
-        _ = self.stdout.execute(LeaveAlternateScreen);
-        _ = terminal::disable_raw_mode();
+        if self.headless {
+            return;
+        }
+        leave_terminal(&mut self.stdout, &self.caps);
+    }
+}
+
+/// Has the terminal save its title, to be given back by [`POP_TITLE`].
+const PUSH_TITLE: &[u8] = b"\x1b[22;0t";
+const POP_TITLE: &[u8] = b"\x1b[23;0t";
+
+/// Sets the terminal up to be drawn on: raw mode, the alternate screen and
+/// the reports the editor asks of it.
+fn enter_terminal(stdout: &mut impl Write, caps: &Capabilities) -> anyhow::Result<()> {
+    terminal::enable_raw_mode()?;
+    stdout.write_all(PUSH_TITLE)?;
+    stdout.queue(EnterAlternateScreen)?;
+    stdout.queue(Clear(terminal::ClearType::All))?;
+    if caps.kitty_keyboard {
+        stdout.queue(event::PushKeyboardEnhancementFlags(
+            event::KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES,
+        ))?;
+    }
+    if caps.bracketed_paste {
+        stdout.queue(event::EnableBracketedPaste)?;
+    }
+    if caps.focus_events {
+        stdout.queue(event::EnableFocusChange)?;
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Writes a buffer to its file, copying the file as it was to a backup
+/// first with `writebackup` or `backup`. The backup is left when the write
+/// fails, and kept after it only with `backup`.
+fn save_buffer(buffer: &mut Buffer, options: &Options) -> anyhow::Result<()> {
+    if buffer.path.is_empty() {
+        anyhow::bail!("No file name");
+    }
+    let path = Path::new(&buffer.path);
+    let backup = options
+        .backup_path(path, SystemTime::now())
+        .filter(|_| path.is_file());
+    if let Some(backup) = &backup {
+        if let Some(dir) = backup.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+        }
+        fs::copy(path, backup)
+            .with_context(|| format!("Cannot write backup {}", backup.display()))?;
+    }
+    buffer.save()?;
+    if let Some(backup) = backup.filter(|_| !options.backup) {
+        _ = fs::remove_file(backup);
     }
+    Ok(())
+}
+
+/// Gives the terminal back as it was before [`enter_terminal`].
+fn leave_terminal(stdout: &mut impl Write, caps: &Capabilities) {
+    if caps.bracketed_paste {
+        _ = stdout.queue(event::DisableBracketedPaste);
+    }
+    if caps.focus_events {
+        _ = stdout.queue(event::DisableFocusChange);
+    }
+    if caps.kitty_keyboard {
+        _ = stdout.queue(event::PopKeyboardEnhancementFlags);
+    }
+    _ = stdout.queue(cursor::SetCursorStyle::DefaultUserShape);
+    _ = stdout.queue(LeaveAlternateScreen);
+    _ = stdout.write_all(POP_TITLE);
+    _ = stdout.flush();
+    _ = terminal::disable_raw_mode();
 }
 
 impl Editor {
-    pub fn new(buffer: Buffer) -> anyhow::Result<Self> {
-        let mut stdout = stdout();
+    pub fn new(buffer: Buffer, overrides: &Overrides) -> anyhow::Result<Self> {
+        let mut stdout = BufWriter::with_capacity(FRAME_BUFFER, stdout());
         terminal::enable_raw_mode()?;
+        let caps = Capabilities::probe(overrides);
+        enter_terminal(&mut stdout, &caps)?;
 
-        stdout.execute(EnterAlternateScreen)?;
-        stdout.execute(Clear(terminal::ClearType::All))?;
+        let lsp = start_lsp(&buffer);
+        let snippets = load_snippets(buffer.filetype);
+        let state = State::load();
 
-        Ok(Editor {
+        let mut editor = Editor {
             buffer,
             stdout,
+            screen: Screen::new(),
+            drawn_size: (0, 0),
             size: terminal::size()?,
             vtop: 0,
             vleft: 0,
             cx: 0,
             cy: 0,
+            cx_history: 0,
             mode: Mode::Normal,
-        })
+            cmdline: String::new(),
+            prompt: ':',
+            search: None,
+            search_shown: false,
+            history: CommandHistory::load(),
+            recall: None,
+            pending: None,
+            count: None,
+            pager: None,
+            picker: None,
+            popup: None,
+            lsp,
+            completion: None,
+            wildmenu: None,
+            analysis: Analysis::new(),
+            diagnostics: Diagnostics::default(),
+            differ: Differ::new(),
+            git_signs: None,
+            modified: Signs::new(),
+            blamer: Blamer::new(),
+            repo: StatusWatcher::new(),
+            user_commands: BTreeMap::new(),
+            command_depth: 0,
+            registers: state.registers.clone(),
+            state,
+            redirection: None,
+            diff: None,
+            segments: None,
+            closed: Vec::new(),
+            conflicts: None,
+            plugins: Plugins::new()?,
+            wasm: WasmPlugins::new()?,
+            in_plugin: false,
+            actions: ActionLog::default(),
+            last_input: Instant::now(),
+            confirm: None,
+            options: Options::default(),
+            theme: Theme::default(),
+            formatting: None,
+            caps,
+            headless: false,
+            snippets,
+            snippet: None,
+            locked: false,
+            links_shown: false,
+            origin: Origin::CommandLine,
+            verbose: false,
+            autosaved: None,
+            watcher: None,
+            disk_noticed: None,
+            visual_start: 0,
+            visual_reversed: false,
+            insert_start: 0,
+            resized: None,
+            flash: None,
+            toasts: Vec::new(),
+            cursor_shape: None,
+            title: None,
+            variables: HashMap::new(),
+            key_maps: BTreeMap::new(),
+            map_typed: String::new(),
+            feeding_keys: false,
+            autocmds: Vec::new(),
+            in_autocmd: false,
+            in_global: false,
+            config: Config::default(),
+            filetype_restore: Vec::new(),
+            messages_seen: messages::given(),
+        };
+        editor.watcher = FileWatcher::new().map(Some).unwrap_or_else(|err| {
+            error!("{err:#}");
+            None
+        });
+        if let Some(watcher) = &mut editor.watcher {
+            watcher.watch(&editor.buffer.path);
+        }
+        editor.offer_trust();
+        for (name, source) in Plugins::scripts() {
+            if let Err(err) = editor.load_plugin(&name, &source) {
+                error!("{err:#}");
+            }
+        }
+        for (name, bytes) in WasmPlugins::modules() {
+            if let Err(err) = editor.load_wasm_plugin(&name, &bytes) {
+                error!("{err:#}");
+            }
+        }
+        if let Some(path) = crate::config_dir()
+            .map(|dir| dir.join("vimrc"))
+            .filter(|path| path.is_file())
+        {
+            match fs::read_to_string(&path) {
+                Result::Ok(script) => editor.source(&path.display().to_string(), &script),
+                Err(err) => {
+                    error!("{}: {err}", path.display());
+                }
+            }
+        }
+        let config = crate::config_dir()
+            .map(|dir| dir.join("config.toml"))
+            .filter(|path| path.is_file());
+        let cache = crate::cache_dir().map(|dir| dir.join("config"));
+        if let Some(Err(err)) = config.map(|path| editor.load_config(&path, cache.as_deref())) {
+            error!("{err:#}");
+        }
+        editor.update_segments();
+        editor.read_undo_file();
+        editor.recall_marks();
+        editor.emit("open", "");
+        editor.run_autocmds("VimEnter");
+        Ok(editor)
+    }
+
+    /// Creates an editor that never touches the terminal, to be driven with
+    /// `feed` as if its events came from a terminal of the given size.
+    pub fn headless(buffer: Buffer, size: (u16, u16)) -> Self {
+        let snippets = load_snippets(buffer.filetype);
+        Editor {
+            buffer,
+            stdout: BufWriter::with_capacity(FRAME_BUFFER, stdout()),
+            screen: Screen::new(),
+            drawn_size: (0, 0),
+            size,
+            vtop: 0,
+            vleft: 0,
+            cx: 0,
+            cy: 0,
+            cx_history: 0,
+            mode: Mode::Normal,
+            cmdline: String::new(),
+            prompt: ':',
+            search: None,
+            search_shown: false,
+            history: CommandHistory::default(),
+            recall: None,
+            pending: None,
+            count: None,
+            pager: None,
+            picker: None,
+            popup: None,
+            lsp: None,
+            completion: None,
+            wildmenu: None,
+            analysis: Analysis::new(),
+            diagnostics: Diagnostics::default(),
+            differ: Differ::new(),
+            git_signs: None,
+            modified: Signs::new(),
+            blamer: Blamer::new(),
+            repo: StatusWatcher::new(),
+            user_commands: BTreeMap::new(),
+            command_depth: 0,
+            registers: HashMap::new(),
+            state: State::default(),
+            redirection: None,
+            diff: None,
+            segments: None,
+            closed: Vec::new(),
+            conflicts: None,
+            plugins: Plugins::new().expect("the plugin API installs"),
+            wasm: WasmPlugins::new().expect("the plugin API installs"),
+            in_plugin: false,
+            actions: ActionLog::default(),
+            last_input: Instant::now(),
+            confirm: None,
+            options: Options::default(),
+            theme: Theme::default(),
+            formatting: None,
+            caps: Capabilities::minimal(),
+            headless: true,
+            snippets,
+            snippet: None,
+            locked: false,
+            links_shown: false,
+            origin: Origin::CommandLine,
+            verbose: false,
+            autosaved: None,
+            watcher: None,
+            disk_noticed: None,
+            visual_start: 0,
+            visual_reversed: false,
+            insert_start: 0,
+            resized: None,
+            flash: None,
+            toasts: Vec::new(),
+            cursor_shape: None,
+            title: None,
+            variables: HashMap::new(),
+            key_maps: BTreeMap::new(),
+            map_typed: String::new(),
+            feeding_keys: false,
+            autocmds: Vec::new(),
+            in_autocmd: false,
+            in_global: false,
+            config: Config::default(),
+            filetype_restore: Vec::new(),
+            messages_seen: messages::given(),
+        }
+    }
+
+    /// Types the keys of a recorded action log again, failing at the first
+    /// action that finds the buffer at another revision than it was.
+    pub fn replay(&mut self, recording: &Recording) -> anyhow::Result<()> {
+        for (i, entry) in recording.entries.iter().enumerate() {
+            if entry.keys.is_empty() {
+                continue;
+            }
+            if entry.revision != self.buffer.revision {
+                anyhow::bail!(
+                    "Action {} ({}) ran at revision {} but replays at {}",
+                    i + 1,
+                    entry.action,
+                    entry.revision,
+                    self.buffer.revision
+                );
+            }
+            for key in keys::parse_keys(&entry.keys)? {
+                if !self.feed(Event::Key(key))? {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the ex commands read from `input` one line at a time, writing
+    /// what they show to `output`, in place of the screen on terminals that
+    /// cannot draw one. `prompt` is written before each line is read.
+    pub fn run_lines(
+        &mut self,
+        mut input: impl BufRead,
+        mut output: impl Write,
+        prompt: &str,
+    ) -> anyhow::Result<()> {
+        let mut line = String::new();
+        loop {
+            write!(output, "{prompt}")?;
+            output.flush()?;
+            line.clear();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            self.cmdline = line.trim().trim_start_matches(':').to_string();
+            if self.cmdline.is_empty() {
+                continue;
+            }
+            let given = messages::given();
+            let running = self.dispatch(Action::ExecuteCommand)?;
+            self.end_undo_step();
+            if let Some(pager) = self.pager.take() {
+                for line in pager.lines() {
+                    writeln!(output, "{line}")?;
+                }
+            }
+            for message in messages::since(given) {
+                writeln!(output, "{}", message.text)?;
+            }
+            if !running {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Handles one event the way the main loop does, returning `false` once
+    /// the editor has quit.
+    pub fn feed(&mut self, event: Event) -> anyhow::Result<bool> {
+        self.assert_cursor_boundaries();
+        let Some(action) = self.handle_event(event)? else {
+            return Ok(true);
+        };
+        let running = self.dispatch(action)?;
+        self.track_snippet();
+        self.end_undo_step();
+        self.assert_cursor_boundaries();
+        Ok(running)
+    }
+
+    /// Whether typing `keys` in normal mode comes to an action, or opens
+    /// a picker, at the last of them, without carrying it out.
+    pub fn binds(&mut self, keys: &[KeyEvent]) -> anyhow::Result<bool> {
+        for (i, key) in keys.iter().enumerate() {
+            let action = self.handle_normal_event(Event::Key(*key))?;
+            let opened = self.picker.take().is_some();
+            if i + 1 == keys.len() {
+                self.pending = None;
+                return Ok(action.is_some() || opened);
+            }
+            // The keys before the last must be waiting for more.
+            if self.pending.is_none() {
+                return Ok(false);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Does the background work the main loop does between keys, as if
+    /// typing had paused, returning whether any of it came back with
+    /// something to show.
+    pub fn idle(&mut self) -> bool {
+        let now = Instant::now();
+        self.last_input = now.checked_sub(analysis::IDLE_DELAY).unwrap_or(now);
+        let resized = self.take_resize();
+        let diffs = self.poll_diffs();
+        let blame = self.poll_blame();
+        let repo = self.poll_repo();
+        resized || diffs || blame || repo
+    }
+
+    pub fn text(&self) -> String {
+        self.buffer.text.to_string()
+    }
+
+    /// The cursor position as buffer line and char column.
+    pub fn cursor(&self) -> (usize, usize) {
+        match &self.segments {
+            Some(view) => self.buffer.char_to_pos(view.cursor),
+            None => (self.buffer_line(), self.cx as usize),
+        }
+    }
+
+    /// The mode's name as shown in the statusline, such as `NORMAL`.
+    pub fn mode_name(&self) -> String {
+        format!("{:?}", self.mode).to_uppercase()
     }
 
     fn draw(&mut self) -> anyhow::Result<()> {
-        self.stdout.execute(Clear(terminal::ClearType::All))?;
-        self.draw_viewport()?;
-        self.draw_statusline()?;
-        self.stdout.queue(cursor::MoveTo(self.cx, self.cy))?;
+        let frame = self.render()?;
+        self.stdout.write_all(&frame)?;
         self.stdout.flush()?;
         Ok(())
     }
 
+    /// Draws the screen, returning what to write to the terminal to bring
+    /// it up to date: only the parts that changed since the last frame.
+    pub fn render(&mut self) -> anyhow::Result<Vec<u8>> {
+        if self.size != self.drawn_size {
+            self.screen.invalidate();
+            self.drawn_size = self.size;
+        }
+        if self.locked {
+            self.draw_lock()?;
+        } else {
+            self.screen.begin(Component::Viewport);
+            if self.picker.is_some() {
+                self.draw_picker()?;
+            } else if self.pager.is_some() {
+                self.draw_pager()?;
+            } else if self.diff.is_some() {
+                self.draw_diff()?;
+            } else if self.segments.is_some() {
+                self.draw_segments()?;
+            } else {
+                self.draw_viewport()?;
+            }
+            self.screen.begin(Component::Statusline);
+            if self.flash.as_ref().is_some_and(|flash| !flash.is_over()) {
+                self.draw_flash()?;
+            } else {
+                self.draw_statusline()?;
+            }
+            self.screen.begin(Component::Message);
+            self.draw_cmdline()?;
+            self.screen.begin(Component::Popups);
+            self.draw_popup()?;
+            self.draw_completion()?;
+            self.draw_wildmenu()?;
+            self.draw_toasts()?;
+        }
+
+        let mut frame = Vec::new();
+        let mut changes = Vec::new();
+        let synchronized = self.screen.present(&mut changes)? && self.caps.synchronized_output;
+        if synchronized {
+            frame.queue(terminal::BeginSynchronizedUpdate)?;
+        }
+        frame.append(&mut changes);
+        match self.mode {
+            _ if self.locked => frame.queue(cursor::MoveTo(0, 0))?,
+            _ if self.picker.is_some() => {
+                let query = self
+                    .picker
+                    .as_ref()
+                    .map_or(0, |p| p.query().chars().count());
+                frame.queue(cursor::MoveTo(query as u16 + 2, 0))?
+            }
+            Mode::Command => frame.queue(cursor::MoveTo(
+                self.cmdline_text().chars().count() as u16,
+                self.size.1.saturating_sub(1),
+            ))?,
+            _ => match (&self.diff, &self.segments) {
+                (Some(view), _) => frame.queue(cursor::MoveTo(view.cursor.0, view.cursor.1))?,
+                (None, Some(view)) => {
+                    frame.queue(cursor::MoveTo(view.screen_cursor.0, view.screen_cursor.1))?
+                }
+                (None, None) => frame.queue(cursor::MoveTo(
+                    self.cx.saturating_sub(self.vleft) + self.gutter_width(),
+                    self.cursor_row(),
+                ))?,
+            },
+        };
+        // A bar where typing inserts text, a block where keys are commands.
+        let shape = match self.mode {
+            _ if self.locked => cursor::SetCursorStyle::SteadyBlock,
+            _ if self.picker.is_some() => cursor::SetCursorStyle::SteadyBar,
+            Mode::Insert => cursor::SetCursorStyle::SteadyBar,
+            Mode::Normal | Mode::Command | Mode::Visual => cursor::SetCursorStyle::SteadyBlock,
+        };
+        if self.cursor_shape != Some(shape) {
+            frame.queue(shape)?;
+            self.cursor_shape = Some(shape);
+        }
+        let title = self.window_title();
+        if self.title.as_ref() != Some(&title) {
+            frame.queue(terminal::SetTitle(&title))?;
+            self.title = Some(title);
+        }
+        if synchronized {
+            frame.queue(terminal::EndSynchronizedUpdate)?;
+        }
+        Ok(frame)
+    }
+
+    /// The terminal title: the file name, marked when modified, and kept
+    /// hidden while locked.
+    fn window_title(&self) -> String {
+        if self.locked {
+            return "vim-rs".to_string();
+        }
+        let name = match Path::new(&self.buffer.path).file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => "[No Name]".to_string(),
+        };
+        let modified = if self.buffer.dirty { " +" } else { "" };
+        format!("{name}{modified} \u{2014} vim-rs")
+    }
+
+    /// Shows nothing of the buffer but how to get back to it.
+    fn draw_lock(&mut self) -> anyhow::Result<()> {
+        let message = "Locked, press any key to resume";
+        let col = (self.size.0 as usize).saturating_sub(message.len()) / 2;
+        self.screen.begin(Component::Viewport);
+        self.screen
+            .move_to(col as u16, self.size.1 / 2)
+            .queue(style::PrintStyledContent(message.dim()))?;
+        Ok(())
+    }
+
+    /// Rows of the viewport, at least one however small the terminal.
     fn vheight(&self) -> u16 {
-        self.size.1 - 2
+        self.size.1.saturating_sub(2).max(1)
     }
 
     fn vwidth(&self) -> u16 {
-        self.size.0
+        self.size.0.max(1)
+    }
+
+    /// Columns left of the text taken by the sign column, shown when any
+    /// of its sources apply to the buffer.
+    fn gutter_width(&self) -> u16 {
+        if self.diff.is_some() {
+            return 0;
+        }
+        let shown = self.sign_sources().any(|source| match source {
+            Source::Diagnostics => !self.diagnostics.is_empty(),
+            Source::Git => self.git_signs.is_some(),
+            Source::Modified => self.buffer.dirty,
+            Source::LongLines => self.options.text_width > 0,
+        });
+        if shown {
+            2
+        } else {
+            0
+        }
+    }
+
+    fn sign_sources(&self) -> impl Iterator<Item = Source> + '_ {
+        self.options
+            .signs
+            .iter()
+            .filter_map(|name| Source::parse(name))
+    }
+
+    /// The marker of the first source with one for `line`.
+    fn marker(&self, line: usize) -> Option<Marker> {
+        self.sign_sources().find_map(|source| match source {
+            Source::Diagnostics => {
+                let diagnostic = *self.diagnostics.on_line(line).first()?;
+                let glyph = match diagnostic.severity {
+                    Severity::Error => 'E',
+                    Severity::Warning => 'W',
+                    Severity::Info => 'I',
+                    Severity::Hint => 'H',
+                };
+                Some(Marker::new(glyph, severity_color(diagnostic.severity)))
+            }
+            Source::Git => {
+                let sign = *self.git_signs.as_ref()?.get(&line)?;
+                let color = match sign {
+                    Sign::Added => style::Color::Green,
+                    Sign::Changed => style::Color::Yellow,
+                    Sign::Deleted => style::Color::Red,
+                };
+                Some(Marker::new(sign.glyph(), color))
+            }
+            Source::Modified => {
+                self.modified.get(&line).filter(|_| self.buffer.dirty)?;
+                Some(Marker::new('*', style::Color::Blue))
+            }
+            Source::LongLines => {
+                let width = self.options.text_width;
+                (width > 0 && self.buffer.line_text(line).chars().count() > width)
+                    .then(|| Marker::new('>', style::Color::Magenta))
+            }
+        })
     }
 
+    /// Length of the cursor line, not counting its line break.
     fn line_length(&self) -> u16 {
         if let Some(line) = self.viewport_line(self.cy) {
-            return line.len_chars() as u16;
+            let len = line.len_chars();
+            let newline = line.chars_at(len).prev() == Some('\n');
+            return (len - newline as usize) as u16;
         }
         0
     }
@@ -103,292 +1142,5497 @@ impl Editor {
         (self.vtop + self.cy) as usize
     }
 
-    fn viewport_line(&self, n: u16) -> Option<RopeSlice> {
+    fn viewport_line(&self, n: u16) -> Option<RopeSlice<'_>> {
         let buffer_line = self.vtop + n;
         self.buffer.get(buffer_line as usize)
     }
 
-    fn draw_viewport(&mut self) -> anyhow::Result<()> {
-        let vwidth = self.vwidth() as usize;
-
-        for i in 0..self.vheight() {
-            let line = match self.viewport_line(i) {
-                None => String::new(),
-                Some(s) => s.to_string(),
-            };
+    /// Moves the cursor to a buffer position, scrolling the viewport to
+    /// center the line when it is off screen.
+    fn goto(&mut self, line: usize, col: usize) {
+        let line = line.min(self.buffer.len().saturating_sub(1));
+        let vheight = self.vheight() as usize;
+        let vtop = self.vtop as usize;
 
-            self.stdout.queue(cursor::MoveTo(0, i))?;
-            self.stdout
-                .queue(style::Print(format!("{line:<width$}", width = vwidth)))?;
+        // Lines hidden in a closed fold are shown by opening it.
+        if self.buffer.folds.row_start(line) != line {
+            self.buffer.folds.open(line);
         }
 
-        Ok(())
+        if line < vtop || line >= vtop + vheight {
+            let max_top = self.buffer.len().saturating_sub(vheight);
+            self.vtop = line.saturating_sub(vheight / 2).min(max_top) as u16;
+        }
+        self.cy = (line - self.vtop as usize) as u16;
+        self.cx = col.min(self.buffer.line_text(line).chars().count()) as u16;
     }
 
-    fn draw_statusline(&mut self) -> anyhow::Result<()> {
-        let separator = "\u{e0b0}";
-        let separator_rev = "\u{e0b2}";
-        let file = format!(" [{}]", self.buffer.path);
-        let mode = format!(" {:?} ", self.mode).to_uppercase();
-        let pos = format!(" {}:{} ", self.cx, self.cy);
-        let file_width = self.size.0 - mode.len() as u16 - pos.len() as u16 - 2;
-        self.stdout.queue(cursor::MoveTo(0, self.size.1 - 2))?;
-        self.stdout.queue(style::PrintStyledContent(
-            mode.with(style::Color::Black)
-                .on(style::Color::Rgb {
-                    r: 184,
-                    g: 144,
-                    b: 243,
-                })
-                .bold(),
-        ))?;
-        self.stdout.queue(style::PrintStyledContent(
-            separator
-                .on(style::Color::Rgb {
-                    r: 67,
-                    g: 70,
-                    b: 89,
-                })
-                .with(style::Color::Rgb {
-                    r: 184,
-                    g: 144,
-                    b: 243,
-                }),
-        ))?;
-        self.stdout.queue(style::PrintStyledContent(
-            format!("{:<width$}", file, width = file_width as usize)
-                .on(style::Color::Rgb {
-                    r: 67,
-                    g: 70,
-                    b: 89,
-                })
-                .with(style::Color::Rgb {
-                    r: 255,
-                    g: 255,
-                    b: 255,
-                }),
-        ))?;
-        self.stdout.queue(style::PrintStyledContent(
-            separator_rev
-                .on(style::Color::Rgb {
-                    r: 67,
-                    g: 70,
-                    b: 89,
-                })
-                .with(style::Color::Rgb {
-                    r: 184,
-                    g: 144,
-                    b: 243,
-                }),
-        ))?;
-        self.stdout.queue(style::PrintStyledContent(
-            pos.with(style::Color::Black)
-                .on(style::Color::Rgb {
-                    r: 184,
-                    g: 144,
-                    b: 243,
-                })
-                .bold(),
-        ))?;
+    /// The chars of the lines shown in the viewport.
+    fn viewport_chars(&self) -> Range<usize> {
+        let text = &self.buffer.text;
+        let mut end = self.vtop as usize;
+        for _ in 0..self.vheight() {
+            end = self.buffer.folds.next_row(end).min(text.len_lines());
+        }
+        text.line_to_char(self.vtop as usize)..text.line_to_char(end)
+    }
 
-        Ok(())
+    /// The bracket under the cursor, or else right before it, and the one
+    /// matching it, under `matchparen`.
+    fn matched_brackets(&self) -> Vec<usize> {
+        if !self.options.match_paren {
+            return Vec::new();
+        }
+        let cursor = self.cursor_char();
+        let line_start = self.buffer.text.line_to_char(self.buffer_line());
+        [
+            Some(cursor),
+            cursor.checked_sub(1).filter(|&at| at >= line_start),
+        ]
+        .into_iter()
+        .flatten()
+        .find_map(|at| Some(vec![at, pairs::matching_bracket(&self.buffer.text, at)?]))
+        .unwrap_or_default()
     }
 
-    fn assert_cursor_boundaries(&mut self, mut cx_history: u16) -> u16 {
-        let bottom_scroll_limit = self.vtop + self.vheight();
-        let cursor_below_vp = self.cy > self.vheight() - 1;
+    /// Moves to the next match of the last search, in the direction it went
+    /// or the other way when `reverse`.
+    fn search_next(&mut self, reverse: bool) {
+        let Some(Search { pattern, backward }) = self.search.clone() else {
+            error!("No previous search pattern");
+            return;
+        };
+        let backward = backward != reverse;
+        let options = &self.options;
+        let regex = match search::compile(&pattern, options.ignore_case, options.smart_case) {
+            Result::Ok(regex) => regex,
+            Err(err) => {
+                error!("{err:#}");
+                return;
+            }
+        };
+        self.search_shown = true;
+        let (from, to) = if backward {
+            ("TOP", "BOTTOM")
+        } else {
+            ("BOTTOM", "TOP")
+        };
+        let cursor = self.cursor_char();
+        match search::find(
+            &self.buffer.text,
+            &regex,
+            cursor,
+            backward,
+            options.wrap_scan,
+        ) {
+            Some((found, wrapped)) => {
+                if wrapped {
+                    warn!("Search hit {from}, continuing at {to}");
+                }
+                self.goto_char(found.start);
+            }
+            None if self.options.wrap_scan => {
+                error!("Pattern not found: {pattern}");
+            }
+            None => {
+                error!("Search hit {from} without match for: {pattern}");
+            }
+        }
+    }
 
-        if (bottom_scroll_limit) > self.buffer.len() as u16 {
-            self.vtop = self.buffer.len() as u16 - self.vheight();
+    /// The matches of the last search in the viewport, when they are
+    /// highlighted.
+    fn search_matches(&self) -> Vec<Range<usize>> {
+        let options = &self.options;
+        let Some(search) = self
+            .search
+            .as_ref()
+            .filter(|_| options.hl_search && self.search_shown)
+        else {
+            return Vec::new();
+        };
+        match search::compile(&search.pattern, options.ignore_case, options.smart_case) {
+            Result::Ok(regex) => search::matches(&self.buffer.text, &regex, self.viewport_chars()),
+            Err(_) => Vec::new(),
         }
-        if cursor_below_vp && (bottom_scroll_limit) < self.buffer.len() as u16 {
-            self.vtop += 1;
+    }
+
+    /// The history of what the command line is for.
+    fn history_kind(&self) -> history::Kind {
+        match self.prompt {
+            ':' => history::Kind::Command,
+            _ => history::Kind::Search,
         }
-        if cursor_below_vp {
-            self.cy = self.vheight() - 1;
+    }
+
+    /// Brings back the entry before the one shown, or after it unless
+    /// `older`, of those starting with what was typed before going through
+    /// them. Going past the newest gives back what was typed.
+    fn recall_history(&mut self, older: bool) {
+        self.accept_history_search();
+        let entries = self.history.entries(self.history_kind());
+        let (index, typed) = match self.recall.take() {
+            Some(Recall::Prefix { index, typed }) => (index, typed),
+            _ => (entries.len(), self.cmdline.clone()),
+        };
+        match history::find(&entries, index, older, |entry| entry.starts_with(&typed)) {
+            Some(found) => {
+                self.cmdline = entries[found].to_string();
+                self.recall = Some(Recall::Prefix {
+                    index: found,
+                    typed,
+                });
+            }
+            None if older => self.recall = Some(Recall::Prefix { index, typed }),
+            None => self.cmdline = typed,
         }
+    }
 
-        if self.cx > self.vwidth() || self.cx >= self.line_length() {
-            if self.cy < self.vheight() - 1 {
-                self.cx = 0;
-                self.cy += 1;
+    /// Starts searching the history with Ctrl-r, or goes on to the next
+    /// older entry holding the query, staying at the one shown when there
+    /// is none.
+    fn search_history(&mut self) {
+        let kind = self.history_kind();
+        let entries = self.history.entries(kind);
+        match &mut self.recall {
+            Some(Recall::Reverse { query, index }) => {
+                let from = index.unwrap_or(entries.len());
+                let found = history::find(&entries, from, true, |e| e.contains(query.as_str()));
+                *index = found.or(*index);
             }
-            if (bottom_scroll_limit) < self.buffer.len() as u16 {
-                self.cx = 0;
-                self.vtop += 1;
-            } else if (bottom_scroll_limit) > self.buffer.len() as u16 || self.line_length() == 0 {
-                self.cx = 0;
+            _ => {
+                self.recall = Some(Recall::Reverse {
+                    query: String::new(),
+                    index: None,
+                })
             }
-            cx_history = self.cx;
         }
-
-        cx_history
     }
 
-    pub fn run(&mut self) -> anyhow::Result<()> {
-        let mut cx_history = self.cx;
-        loop {
-            cx_history = self.assert_cursor_boundaries(cx_history);
-            self.draw()?;
-
-            if let Some(action) = self.handle_event(read()?)? {
-                match action {
-                    Action::Quit => break,
-                    Action::MoveUp => {
-                        self.cy = self.cy.saturating_sub(1);
-                        if self.cy == 0 && self.vtop > 0 {
-                            self.vtop -= 1;
-                        }
-                        if cx_history <= self.line_length() {
-                            self.cx = cx_history;
-                        } else if cx_history > self.line_length() {
-                            self.cx = self.line_length();
-                        }
-                    }
-                    Action::MoveDown => {
-                        self.cy += 1;
+    /// Looks again for the newest entry holding the query, once it changed.
+    fn refresh_history_search(&mut self) {
+        let kind = self.history_kind();
+        let entries = self.history.entries(kind);
+        if let Some(Recall::Reverse { query, index }) = &mut self.recall {
+            *index = match query.is_empty() {
+                true => None,
+                false => history::find(&entries, entries.len(), true, |e| {
+                    e.contains(query.as_str())
+                }),
+            };
+        }
+    }
 
-                        if cx_history <= self.line_length() {
-                            self.cx = cx_history;
-                        } else if cx_history > self.line_length() {
-                            self.cx = self.line_length();
-                        }
-                    }
-                    Action::MoveLeft => {
-                        if self.cx == self.vleft {
-                            if self.cy == 0 && self.vtop > 0 {
-                                self.vtop -= 1;
-                                self.cx = self.line_length();
-                            } else if self.cy > 0 {
-                                self.cy = self.cy.saturating_sub(1);
-                                self.cx = self.line_length();
-                            }
-                        }
-                        self.cx = self.cx.saturating_sub(1);
-                        cx_history = self.cx;
-                    }
-                    Action::MoveRight => {
-                        self.cx += 1;
-                        cx_history = self.cx;
-                    }
-                    Action::EnterMode(new_mode) => {
-                        self.mode = new_mode;
-                        self.stdout.execute(Clear(terminal::ClearType::Purge))?;
-                    }
-                    Action::InsertChar(c) => {
-                        self.buffer
-                            .insert_char(self.buffer_line(), self.cx as usize, c);
-                        //self.cx += 1;
-                    }
-                    Action::DelCharBefore => {
-                        if self.cx > self.vleft {
-                            self.buffer
-                                .remove_char(self.buffer_line(), self.cx as usize - 1);
-                            self.cx = self.cx.saturating_sub(1);
-                            cx_history = self.cx;
-                        }
-                    }
-                    Action::DelCharAtCursor => {
-                        if self.cx < self.line_length() && self.line_length() > 0 {
-                            self.buffer
-                                .remove_char(self.buffer_line(), self.cx as usize);
-                        }
-                        if (self.vtop + self.vheight()) > self.buffer.len() as u16 {
-                            self.cy += 1;
-                        }
-                    }
-                    Action::NewLine => {
-                        self.buffer
-                            .insert_text(self.buffer_line(), self.cx as usize, "\u{000a}");
-                        self.cx = 0;
-                        self.cy += 1;
-                    }
-                    Action::PageDown => {
-                        self.vtop += self.vheight();
-                        if (self.vtop + self.vheight()) > self.buffer.len() as u16 {
-                            self.cy = self.vheight() - 1;
-                        }
+    /// Puts the entry found by searching the history on the command line,
+    /// to be edited or run.
+    fn accept_history_search(&mut self) {
+        if let Some(Recall::Reverse { index, .. }) = self.recall {
+            if let Some(index) = index {
+                self.cmdline = self.history.entries(self.history_kind())[index].to_string();
+            }
+            self.recall = None;
+        }
+    }
 
-                        if cx_history <= self.line_length() {
-                            self.cx = cx_history;
-                        } else if cx_history > self.line_length() {
-                            self.cx = self.line_length();
-                        }
-                    }
-                    Action::PageUp => {
-                        if self.vtop >= self.vheight() {
-                            self.vtop -= self.vheight();
-                        } else {
-                            self.vtop = 0;
-                            self.cy = 0;
-                        }
-                        if cx_history <= self.line_length() {
-                            self.cx = cx_history;
-                        } else if cx_history > self.line_length() {
-                            self.cx = self.line_length();
-                        }
-                    }
-                    Action::EndOfLine => {
-                        self.cx = self.line_length();
-                        cx_history = self.cx;
-                    }
-                    Action::StartOfLine => {
-                        self.cx = self.vleft;
-                        cx_history = self.cx;
-                    }
+    /// What the command line shows: the prompt and what was typed, or the
+    /// query and the entry found while searching the history.
+    fn cmdline_text(&self) -> String {
+        match &self.recall {
+            Some(Recall::Reverse { query, index }) => match index {
+                Some(index) => {
+                    let entries = self.history.entries(self.history_kind());
+                    format!("(reverse-i-search)'{query}': {}", entries[*index])
                 }
-            }
+                None if query.is_empty() => "(reverse-i-search)'': ".to_string(),
+                None => format!("(failed reverse-i-search)'{query}': "),
+            },
+            _ => format!("{}{}", self.prompt, self.cmdline),
         }
+    }
 
-        Ok(())
+    /// The char under the cursor.
+    fn cursor_char(&self) -> usize {
+        self.buffer.text.line_to_char(self.buffer_line()) + self.cx as usize
     }
 
-    fn handle_event(&mut self, ev: Event) -> anyhow::Result<Option<Action>> {
-        if matches!(ev, Event::Resize(_, _)) {
-            self.size = terminal::size()?;
-        }
-        match self.mode {
-            Mode::Normal => self.handle_normal_event(ev),
-            Mode::Insert => self.handle_insert_event(ev),
-        }
+    /// The chars selected in visual mode, from where it started to the
+    /// cursor, both included.
+    fn selection(&self) -> Range<usize> {
+        let cursor = self.cursor_char();
+        let start = self.visual_start.min(cursor);
+        let end = self.visual_start.max(cursor) + 1;
+        start..end.min(self.buffer.text.len_chars())
     }
 
-    fn handle_insert_event(&self, ev: Event) -> anyhow::Result<Option<Action>> {
-        let action = match ev {
-            Event::Key(key_event) => match key_event.kind {
-                event::KeyEventKind::Press => match key_event.code {
-                    event::KeyCode::Esc => Some(Action::EnterMode(Mode::Normal)),
-                    event::KeyCode::Up => Some(Action::MoveUp),
-                    event::KeyCode::Down => Some(Action::MoveDown),
-                    event::KeyCode::Left => Some(Action::MoveLeft),
-                    event::KeyCode::Right => Some(Action::MoveRight),
-                    event::KeyCode::Enter => Some(Action::NewLine),
-                    event::KeyCode::Char(c) => Some(Action::InsertChar(c)),
-                    event::KeyCode::PageDown => Some(Action::PageDown),
-                    event::KeyCode::PageUp => Some(Action::PageUp),
-                    event::KeyCode::End => Some(Action::EndOfLine),
-                    event::KeyCode::Home => Some(Action::StartOfLine),
-                    event::KeyCode::Backspace => Some(Action::DelCharBefore),
-                    event::KeyCode::Delete => Some(Action::DelCharAtCursor),
-                    _ => None,
-                },
-                _ => None,
-            },
-            _ => None,
-        };
-        Ok(action)
+    /// Moves the cursor to a char index of the buffer.
+    fn goto_char(&mut self, at: usize) {
+        let (line, col) = self
+            .buffer
+            .char_to_pos(at.min(self.buffer.text.len_chars()));
+        self.goto(line, col);
+        self.cx_history = self.cx;
     }
 
-    fn handle_normal_event(&self, ev: Event) -> anyhow::Result<Option<Action>> {
-        let action = match ev {
+    /// Makes the moves through the undo history, leaving the cursor where
+    /// the text changed last, or tells there were none to make going back
+    /// if `older` or forward otherwise.
+    fn travel(&mut self, moves: Vec<undo::Move>, older: bool) {
+        match self.buffer.travel(moves) {
+            Some(at) => self.goto_char(at),
+            None if older => {
+                log!("Already at oldest change");
+            }
+            None => {
+                log!("Already at newest change");
+            }
+        }
+    }
+
+    fn draw_viewport(&mut self) -> anyhow::Result<()> {
+        let gutter = self.gutter_width() as usize;
+        let vwidth = self.vwidth() as usize - gutter;
+        let placeholder = match self.mode {
+            Mode::Visual => Some(self.selection()),
+            _ => self
+                .snippet
+                .as_ref()
+                .filter(|session| session.selected)
+                .map(Session::range),
+        };
+        let linked = self
+            .snippet
+            .as_ref()
+            .map(|session| session.linked().to_vec())
+            .filter(|ranges| ranges.len() > 1)
+            .unwrap_or_default();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let conflicts = self.conflicts().to_vec();
+        // Screen columns of `colorcolumn` and, with `cursorcolumn`, of the
+        // cursor.
+        let color_columns: Vec<usize> = self
+            .options
+            .color_columns()
+            .into_iter()
+            .filter_map(|col| col.checked_sub(self.vleft as usize))
+            .collect();
+        let cursor_column = (self.options.cursor_column && self.cx >= self.vleft)
+            .then(|| (self.cx - self.vleft) as usize);
+        let caps = &self.caps;
+        let styled = |text: String, style: Style| styled(caps, text, style);
+        let indent_levels = match self.options.indent_guides {
+            true => folds::indent_levels(&self.buffer.text, 1),
+            false => Vec::new(),
+        };
+        let guide = caps.fill('\u{2502}', '|');
+        let brackets = self.matched_brackets();
+        let search_matches = self.search_matches();
+        let depths = match self.options.rainbow {
+            true => pairs::bracket_depths(&self.buffer.text, self.viewport_chars()),
+            false => HashMap::new(),
+        };
+
+        // A closed fold takes a single row.
+        let mut next_line = self.vtop as usize;
+        for i in 0..self.vheight() {
+            let line_i = next_line;
+            next_line = self.buffer.folds.next_row(line_i);
+            let chars: Vec<char> = match self.buffer.get(line_i) {
+                None => Vec::new(),
+                Some(s) => s.chars().filter(|c| !matches!(c, '\n' | '\r')).collect(),
+            };
+            let trail_start = chars
+                .iter()
+                .rposition(|c| !matches!(c, ' ' | '\t'))
+                .map_or(0, |i| i + 1);
+            // Whitespace shown with `list`, by its column.
+            let list_chars = self.options.list.then_some(&self.options.list_chars);
+            let glyph_at = |col: usize| list_chars?.glyph(chars[col], col >= trail_start);
+            // Not while typing at the end of the line.
+            let trailing_shown = self.options.highlight_trailing
+                && !(matches!(self.mode, Mode::Insert) && line_i == self.buffer_line());
+            let trailing_at = |col: usize| trailing_shown && col >= trail_start;
+            // Indent guides, left to whitespace shown with `list`.
+            let guides = guide_columns(&indent_levels, line_i);
+            let guide_at = |col: usize| {
+                guides.contains(&col)
+                    && chars.get(col).is_none_or(|c| matches!(c, ' ' | '\t'))
+                    && (col >= chars.len() || glyph_at(col).is_none())
+            };
+            let line: Vec<char> = (0..chars.len())
+                .map(|col| match guide_at(col) {
+                    true => guide,
+                    false => glyph_at(col).unwrap_or(chars[col]),
+                })
+                .collect();
+            let left = (self.vleft as usize).min(line.len());
+            let line = &line[left..line.len().min(left + vwidth)];
+            let diagnostics = self.diagnostics.on_line(line_i);
+            let line_start =
+                (line_i < self.buffer.len()).then(|| self.buffer.text.line_to_char(line_i));
+            let cursor_line = line_i == self.buffer_line();
+
+            if gutter > 0 {
+                let marker = match self.marker(line_i).filter(|_| line_start.is_some()) {
+                    Some(marker) => format!("{} ", marker.glyph).with(marker.color),
+                    None => "  ".to_string().stylize(),
+                };
+                self.screen.begin(Component::Gutter);
+                self.screen
+                    .move_to(0, i)
+                    .queue(style::PrintStyledContent(marker))?;
+                self.screen.begin(Component::Viewport);
+            }
+            self.screen.move_to(gutter as u16, i);
+            if line_start.is_none() {
+                let eob = caps.fill(self.options.fill_chars.eob, '~');
+                self.screen.queue(style::PrintStyledContent(styled(
+                    format!("{eob:<vwidth$}"),
+                    self.theme.compose([&self.theme.end_of_buffer]),
+                )))?;
+                continue;
+            }
+            if let Some(lines) = self.buffer.folds.closed_at(line_i) {
+                let first: String = chars.iter().collect();
+                let level = self.buffer.folds.level(&lines);
+                let summary = folds::summary(&self.options.fold_text, lines.count(), level, &first);
+                let fill = caps.fill(self.options.fill_chars.fold, '-');
+                let summary: String = summary
+                    .chars()
+                    .chain(std::iter::repeat(fill))
+                    .take(vwidth)
+                    .collect();
+                let highlights = [&self.theme.folded].into_iter().chain(
+                    Some(&self.theme.cursor_line)
+                        .filter(|_| line_i == self.buffer_line() && self.options.cursor_line),
+                );
+                self.screen.queue(style::PrintStyledContent(styled(
+                    summary,
+                    self.theme.compose(highlights),
+                )))?;
+                continue;
+            }
+            let severity_at = |col: usize| {
+                diagnostics
+                    .iter()
+                    .filter(|d| d.start_col <= col && col < d.end_col.max(d.start_col + 1))
+                    .map(|d| d.severity)
+                    .min()
+            };
+            // The snippet placeholder typing would replace, or the visual
+            // selection.
+            let selected_at = |col: usize| {
+                placeholder
+                    .as_ref()
+                    .zip(line_start)
+                    .is_some_and(|(range, start)| range.contains(&(start + col)))
+            };
+            // Occurrences of a placeholder that are edited together.
+            let linked_at = |col: usize| {
+                line_start
+                    .is_some_and(|start| linked.iter().any(|range| range.contains(&(start + col))))
+            };
+            let theme = &self.theme;
+            let cursor_line_highlight =
+                Some(&theme.cursor_line).filter(|_| cursor_line && self.options.cursor_line);
+            // Highlights of the screen column, over the text and past it.
+            let guides_at = |col: usize| {
+                [
+                    Some(&theme.color_column).filter(|_| color_columns.contains(&col)),
+                    Some(&theme.cursor_column).filter(|_| cursor_column == Some(col)),
+                ]
+            };
+            let conflict_highlight = conflicts
+                .iter()
+                .find_map(|conflict| conflict.region(line_i))
+                .map(|region| theme.conflict(region));
+            // Highlights covering a char, lowest first, composed into the
+            // style runs of text sharing it are printed with.
+            let style_at = |col: usize| {
+                let highlights = [conflict_highlight, cursor_line_highlight]
+                    .into_iter()
+                    .chain(guides_at(col));
+                let col = left + col;
+                let highlights = highlights.chain([
+                    Some(&theme.indent_guide).filter(|_| guide_at(col)),
+                    Some(&theme.whitespace).filter(|_| glyph_at(col).is_some()),
+                    line_start
+                        .and_then(|start| depths.get(&(start + col)))
+                        .map(|depth| &theme.rainbow[depth % theme.rainbow.len()]),
+                    Some(&theme.trailing).filter(|_| trailing_at(col)),
+                    severity_at(col).map(|severity| theme.diagnostic(severity)),
+                    Some(&theme.search).filter(|_| {
+                        line_start.is_some_and(|start| {
+                            search_matches
+                                .iter()
+                                .any(|found| found.contains(&(start + col)))
+                        })
+                    }),
+                    Some(&theme.linked).filter(|_| linked_at(col)),
+                    Some(&theme.match_paren).filter(|_| {
+                        line_start.is_some_and(|start| brackets.contains(&(start + col)))
+                    }),
+                    Some(&theme.selection).filter(|_| selected_at(col)),
+                ]);
+                theme.compose(highlights.flatten())
+            };
+            let mut col = 0;
+            while col < line.len() {
+                let style = style_at(col);
+                let end = (col..line.len())
+                    .find(|&c| style_at(c) != style)
+                    .unwrap_or(line.len());
+                let text = line[col..end].iter().collect::<String>();
+                self.screen
+                    .queue(style::PrintStyledContent(styled(text, style)))?;
+                col = end;
+            }
+            let rest = theme.compose(
+                [conflict_highlight, cursor_line_highlight]
+                    .into_iter()
+                    .flatten(),
+            );
+
+            let mut used = line.len();
+            if let Some(diagnostic) = diagnostics.first().filter(|_| cursor_line) {
+                let message: String = format!("  {} [{}]", diagnostic.message, diagnostic.source)
+                    .chars()
+                    .take(vwidth - used)
+                    .collect();
+                used += message.chars().count();
+                let style = Style {
+                    fg: theme.diagnostic(diagnostic.severity).fg,
+                    ..rest
+                };
+                self.screen
+                    .queue(style::PrintStyledContent(styled(message, style).dim()))?;
+            } else if let Some(blame) = self
+                .blamer
+                .get(self.buffer.revision, line_i)
+                .filter(|_| cursor_line && self.options.blame)
+            {
+                let message: String = format!("    {}", blame.describe(now))
+                    .chars()
+                    .take(vwidth - used)
+                    .collect();
+                used += message.chars().count();
+                self.screen
+                    .queue(style::PrintStyledContent(styled(message, rest).dim()))?;
+            }
+            // Past the end of the line the column highlights still show, and
+            // the indent guides of blank lines.
+            let vleft = self.vleft as usize;
+            let fill_guide_at = |col: usize| used == line.len() && guide_at(vleft + col);
+            let fill_at = |col: usize| {
+                theme.compose(
+                    [conflict_highlight, cursor_line_highlight]
+                        .into_iter()
+                        .chain(guides_at(col))
+                        .chain([Some(&theme.indent_guide).filter(|_| fill_guide_at(col))])
+                        .flatten(),
+                )
+            };
+            let mut col = used;
+            while col < vwidth {
+                let style = fill_at(col);
+                let end = (col..vwidth)
+                    .find(|&c| fill_at(c) != style)
+                    .unwrap_or(vwidth);
+                let text = (col..end)
+                    .map(|c| if fill_guide_at(c) { guide } else { ' ' })
+                    .collect();
+                self.screen
+                    .queue(style::PrintStyledContent(styled(text, style)))?;
+                col = end;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Draws the rows of a segmented buffer, a long line taking as many as
+    /// it is broken into.
+    fn draw_segments(&mut self) -> anyhow::Result<()> {
+        let vheight = self.vheight() as usize;
+        let vwidth = self.vwidth() as usize;
+        let eob = self.caps.fill(self.options.fill_chars.eob, '~');
+        let Some(view) = self.segments.as_mut() else {
+            return Ok(());
+        };
+        view.refresh(&self.buffer, vwidth);
+        view.scroll(vheight);
+        let row = view.row();
+        let start = view.rows().get(row).map_or(0, |range| range.start);
+        view.screen_cursor = ((view.cursor - start) as u16, (row - view.top) as u16);
+
+        for i in 0..vheight {
+            let (text, style) = match view.rows().get(view.top + i) {
+                Some(range) => (
+                    self.buffer.text.slice(range.clone()).to_string(),
+                    self.theme.compose(None),
+                ),
+                None => (
+                    eob.to_string(),
+                    self.theme.compose([&self.theme.end_of_buffer]),
+                ),
+            };
+            self.screen
+                .move_to(0, i as u16)
+                .queue(style::PrintStyledContent(styled(
+                    &self.caps,
+                    format!("{text:<vwidth$}"),
+                    style,
+                )))?;
+        }
+        Ok(())
+    }
+
+    /// Draws the two buffers of diff mode side by side, aligned so that the
+    /// lines they share are on the same row.
+    fn draw_diff(&mut self) -> anyhow::Result<()> {
+        let vheight = self.vheight() as usize;
+        let vwidth = self.vwidth() as usize;
+        let line = self.buffer_line();
+        let vtop = self.vtop as usize;
+        let fill_chars = &self.options.fill_chars;
+        let separator = self.caps.fill(fill_chars.vert, '|').to_string();
+        let eob = self.caps.fill(fill_chars.eob, '~').to_string();
+        let filler = self.caps.fill(fill_chars.diff, '-').to_string();
+        let Some(view) = self.diff.as_mut() else {
+            return Ok(());
+        };
+        let rows = view.rows(&self.buffer).to_vec();
+        let row_of = |line: usize| rows.iter().position(|row| row.ours == Some(line));
+        let cursor_row = row_of(line).unwrap_or(0);
+        // Filler rows can push the cursor line below the viewport.
+        let top = row_of(vtop)
+            .unwrap_or(0)
+            .max((cursor_row + 1).saturating_sub(vheight));
+
+        let left_width = (vwidth - 1) / 2;
+        let right_width = vwidth - left_width - 1;
+        let (our_col, our_width) = match view.focus_left {
+            true => (0, left_width),
+            false => (left_width + 1, right_width),
+        };
+        view.cursor = (
+            (our_col + (self.cx as usize).min(our_width.saturating_sub(1))) as u16,
+            (cursor_row - top) as u16,
+        );
+
+        let theme = &self.theme;
+        let caps = &self.caps;
+        for i in 0..vheight {
+            let row = rows.get(top + i);
+            let mut cells = [
+                (row.and_then(|row| row.ours), &self.buffer),
+                (row.and_then(|row| row.theirs), &view.other),
+            ]
+            .map(|(line, buffer)| (line, line.map(|line| buffer.line_text(line))));
+            if !view.focus_left {
+                cells.reverse();
+            }
+            let both = cells.iter().all(|(line, _)| line.is_some());
+            for (side, (_, text)) in cells.into_iter().enumerate() {
+                let (col, width) = match side {
+                    0 => (0, left_width),
+                    _ => (left_width + 1, right_width),
+                };
+                let (text, highlight) = match (row, text) {
+                    (None, _) => (eob.clone(), Some(&theme.end_of_buffer)),
+                    (Some(_), None) => (filler.repeat(width), Some(&theme.diff_filler)),
+                    (Some(row), Some(text)) => {
+                        let highlight = match (row.changed, both) {
+                            (false, _) => None,
+                            (true, true) => Some(&theme.diff_change),
+                            (true, false) => Some(&theme.diff_add),
+                        };
+                        (text, highlight)
+                    }
+                };
+                let text: String = text.chars().take(width).collect();
+                let text = format!("{text:<width$}");
+                self.screen
+                    .move_to(col as u16, i as u16)
+                    .queue(style::PrintStyledContent(styled(
+                        caps,
+                        text,
+                        theme.compose(highlight),
+                    )))?;
+                if side == 0 {
+                    self.screen.queue(style::PrintStyledContent(styled(
+                        caps,
+                        separator.clone(),
+                        theme.compose([&theme.separator]),
+                    )))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Draws the picker over the viewport: the query on top, the matches
+    /// below, and the preview to the right when there is room for it.
+    fn draw_picker(&mut self) -> anyhow::Result<()> {
+        let vheight = self.vheight() as usize;
+        let vwidth = self.vwidth() as usize;
+        let accent = self.caps.rgb(184, 144, 243);
+        let background = self.caps.rgb(67, 70, 89);
+        let separator = self.caps.glyph("\u{2502}", "|");
+        let Some(picker) = self.picker.as_mut() else {
+            return Ok(());
+        };
+        let view = picker.view(vheight - 1);
+
+        let count = format!(" {}/{} ", view.matched, view.total);
+        let prompt: String = format!("> {}", view.query)
+            .chars()
+            .take(vwidth.saturating_sub(count.len()))
+            .collect();
+        self.screen.move_to(0, 0);
+        self.screen.queue(style::Print(format!(
+            "{prompt:<w$}{count}",
+            w = vwidth.saturating_sub(count.len())
+        )))?;
+
+        let preview = view.preview.filter(|_| vwidth >= 60);
+        let list_width = if preview.is_some() {
+            vwidth / 2
+        } else {
+            vwidth
+        };
+        for i in 0..vheight - 1 {
+            self.screen.move_to(0, i as u16 + 1);
+            let mut used = 0;
+            if let Some(row) = view.rows.get(i) {
+                let marker = if row.marked { "+ " } else { "  " };
+                let styled = |text: StyledContent<String>| match row.selected {
+                    true => text.on(background),
+                    false => text,
+                };
+                self.screen.queue(style::PrintStyledContent(styled(
+                    marker.to_string().stylize(),
+                )))?;
+                for (col, c) in row.label.chars().take(list_width - 2).enumerate() {
+                    let text = match row.positions.contains(&col) {
+                        true => c.to_string().with(accent).bold(),
+                        false => c.to_string().stylize(),
+                    };
+                    self.screen.queue(style::PrintStyledContent(styled(text)))?;
+                    used = col + 3;
+                }
+            }
+            self.screen
+                .queue(style::Print(" ".repeat(list_width.saturating_sub(used))))?;
+
+            if let Some(preview) = preview {
+                let line: String = preview
+                    .get(i)
+                    .map(|line| line.chars().take(vwidth - list_width - 2).collect())
+                    .unwrap_or_default();
+                self.screen.queue(style::PrintStyledContent(
+                    separator.with(style::Color::DarkGrey),
+                ))?;
+                self.screen.queue(style::Print(format!(" {line}")))?;
+                self.screen
+                    .queue(Clear(terminal::ClearType::UntilNewLine))?;
+            }
+        }
+
+        if let Some((names, selected)) = view.menu {
+            let width = names
+                .iter()
+                .map(|name| name.chars().count())
+                .max()
+                .unwrap_or(0)
+                + 2;
+            let top = vheight.saturating_sub(names.len());
+            self.screen.begin(Component::Popups);
+            for (i, name) in names.iter().enumerate() {
+                let text = format!(" {name:<w$} ", w = width - 2);
+                let text = if i == selected {
+                    text.with(style::Color::Black).on(accent)
+                } else {
+                    text.with(style::Color::White).on(background)
+                };
+                self.screen.move_to(2, (top + i) as u16);
+                self.screen.queue(style::PrintStyledContent(text))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn draw_pager(&mut self) -> anyhow::Result<()> {
+        let vheight = self.vheight();
+        let vwidth = self.vwidth() as usize;
+        let Some(pager) = self.pager.as_mut() else {
+            return Ok(());
+        };
+
+        self.links_shown = false;
+        for (i, (selected, line)) in pager.visible(vheight as usize).enumerate() {
+            let links = links::find(line);
+            self.links_shown |= !links.is_empty();
+            let mut line = format!("{line:<width$}", width = vwidth);
+            if self.caps.hyperlinks {
+                line = links::hyperlink(&line, &links);
+            }
+            self.screen.move_to(0, i as u16);
+            if selected {
+                self.screen
+                    .queue(style::PrintStyledContent(line.reverse()))?;
+            } else {
+                self.screen.queue(style::Print(line))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Draws the popup below the cursor, or above it when there is not
+    /// enough room left in the viewport.
+    fn draw_popup(&mut self) -> anyhow::Result<()> {
+        let Some(popup) = &self.popup else {
+            return Ok(());
+        };
+        let vheight = self.vheight() as usize;
+        let vwidth = self.vwidth() as usize;
+        let cy = self.cursor_row() as usize;
+
+        let (top, height) = if cy + 1 + popup.height() <= vheight {
+            (cy + 1, popup.height())
+        } else if cy >= popup.height() {
+            (cy - popup.height(), popup.height())
+        } else {
+            (cy + 1, vheight - cy - 1)
+        };
+        let width = (popup.width + 2).min(vwidth);
+        let left = ((self.cx.saturating_sub(self.vleft) + self.gutter_width()) as usize)
+            .min(vwidth - width);
+        let background = self.caps.rgb(67, 70, 89);
+
+        for (i, (line_style, line)) in popup
+            .lines
+            .iter()
+            .skip(popup.scroll)
+            .take(height)
+            .enumerate()
+        {
+            let text = match line_style {
+                LineStyle::Rule => self.caps.glyph("\u{2500}", "-").repeat(width - 2),
+                _ if self.caps.hyperlinks => {
+                    let text: String = line.chars().take(width - 2).collect();
+                    links::hyperlink(&text, &links::find(&text))
+                }
+                _ => line.chars().take(width - 2).collect(),
+            };
+            let text = format!(" {text:<w$} ", w = width - 2).on(background);
+            let text = match line_style {
+                LineStyle::Text => text.with(style::Color::White),
+                LineStyle::Code => text.with(self.caps.rgb(184, 144, 243)),
+                LineStyle::Heading => text.with(style::Color::White).bold(),
+                LineStyle::Rule => text.with(style::Color::DarkGrey),
+            };
+            self.screen.move_to(left as u16, (top + i) as u16);
+            self.screen.queue(style::PrintStyledContent(text))?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_completion(&mut self) -> anyhow::Result<()> {
+        let Some(menu) = &self.completion else {
+            return Ok(());
+        };
+        let vheight = self.vheight() as usize;
+        let vwidth = self.vwidth() as usize;
+        let cy = self.cursor_row() as usize;
+
+        let label_width = menu
+            .visible()
+            .map(|(_, item)| item.label.chars().count())
+            .max();
+        let detail_width = menu
+            .visible()
+            .map(|(_, item)| item.detail.chars().count())
+            .max();
+        let width =
+            (label_width.unwrap_or(0) + detail_width.unwrap_or(0) + 3).clamp(1, 60.min(vwidth));
+        let top = if cy + 1 + menu.height() <= vheight {
+            cy + 1
+        } else {
+            cy.saturating_sub(menu.height())
+        };
+        let left = (menu.start_col + self.gutter_width() as usize)
+            .saturating_sub(self.vleft as usize)
+            .min(vwidth - width);
+        let background = self.caps.rgb(67, 70, 89);
+        let accent = self.caps.rgb(184, 144, 243);
+
+        for (i, (selected, item)) in menu.visible().enumerate() {
+            let text = format!(
+                " {:<lw$} {}",
+                item.label,
+                item.detail,
+                lw = label_width.unwrap_or(0)
+            );
+            let text: String = text.chars().take(width).collect();
+            let text = format!("{text:<width$}");
+            let text = if selected {
+                text.with(style::Color::Black).on(accent)
+            } else {
+                text.with(style::Color::White).on(background)
+            };
+            self.screen.move_to(left as u16, (top + i) as u16);
+            self.screen.queue(style::PrintStyledContent(text))?;
+        }
+
+        Ok(())
+    }
+
+    /// Draws the command line completion candidates over the statusline,
+    /// scrolled so the selected one is visible.
+    fn draw_wildmenu(&mut self) -> anyhow::Result<()> {
+        let Some(menu) = &self.wildmenu else {
+            return Ok(());
+        };
+        let vwidth = self.vwidth() as usize;
+        let names: Vec<&str> = menu
+            .candidates
+            .iter()
+            .map(|path| {
+                let start = path.trim_end_matches('/').rfind('/').map_or(0, |i| i + 1);
+                &path[start..]
+            })
+            .collect();
+        let width = |names: &[&str]| names.iter().map(|n| n.chars().count() + 2).sum::<usize>();
+        let mut first = 0;
+        while first < menu.selected && width(&names[first..=menu.selected]) > vwidth {
+            first += 1;
+        }
+
+        let background = self.caps.rgb(67, 70, 89);
+        let accent = self.caps.rgb(184, 144, 243);
+        self.screen.move_to(0, self.size.1.saturating_sub(2));
+        let mut used = 0;
+        for (i, name) in names.iter().enumerate().skip(first) {
+            let text: String = format!(" {name} ").chars().take(vwidth - used).collect();
+            used += text.chars().count();
+            let text = if i == menu.selected {
+                text.with(style::Color::Black).on(accent)
+            } else {
+                text.with(style::Color::White).on(background)
+            };
+            self.screen.queue(style::PrintStyledContent(text))?;
+            if used == vwidth {
+                break;
+            }
+        }
+        self.screen.queue(style::PrintStyledContent(
+            " ".repeat(vwidth - used).on(background),
+        ))?;
+        Ok(())
+    }
+
+    /// Draws the command line being typed, or otherwise the last message
+    /// given since a key was last pressed.
+    fn draw_cmdline(&mut self) -> anyhow::Result<()> {
+        let row = self.size.1.saturating_sub(1);
+        if matches!(self.mode, Mode::Command) {
+            let text = self.cmdline_text();
+            self.screen
+                .move_to(0, row)
+                .queue(style::Print(text))?
+                .queue(Clear(terminal::ClearType::UntilNewLine))?;
+            return Ok(());
+        }
+        let Some(message) = messages::since(self.messages_seen).pop() else {
+            return Ok(());
+        };
+        // Only the first line fits, and only as much of it as the width.
+        let text: String = message
+            .text
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .take(self.size.0 as usize)
+            .collect();
+        let text = match message.severity {
+            messages::Severity::Error => text.with(self.caps.rgb(243, 139, 168)),
+            messages::Severity::Warning => text.with(self.caps.rgb(249, 226, 175)),
+            messages::Severity::Info => text.stylize(),
+        };
+        self.screen
+            .move_to(0, row)
+            .queue(style::PrintStyledContent(text))?
+            .queue(Clear(terminal::ClearType::UntilNewLine))?;
+        Ok(())
+    }
+
+    fn draw_statusline(&mut self) -> anyhow::Result<()> {
+        if !self.options.status_line.is_empty() {
+            return self.draw_status_format();
+        }
+        let separator = self.caps.glyph("\u{e0b0}", " ");
+        let separator_rev = self.caps.glyph("\u{e0b2}", " ");
+        let file = match (&self.picker, &self.pager) {
+            (Some(picker), _) => format!(" [{}]", picker.title()),
+            (None, Some(pager)) if self.links_shown && !self.caps.hyperlinks => {
+                format!(" [{}] gf/gx follow links", pager.title)
+            }
+            (None, Some(pager)) => format!(" [{}]", pager.title),
+            (None, None) => format!(" [{}]", self.buffer.path),
+        };
+        // Given room whether shown or not, so that the file name is not
+        // redrawn when the buffer changes.
+        let modified = match self.buffer.dirty && self.picker.is_none() && self.pager.is_none() {
+            true => " [+]",
+            false => "    ",
+        };
+        let mode = format!(" {} ", self.mode_name());
+        // Like vim's ruler, from 1, with how far through the file the
+        // cursor line is.
+        let (line, col) = self.cursor();
+        let lines = self.last_line() + 1;
+        let percent = (line + 1) * 100 / lines;
+        let pos = format!(" {}:{} {percent}% {lines}L ", line + 1, col + 1);
+        let repo = self.repo.status.as_ref().map_or(String::new(), |status| {
+            let mut segment = format!("{}{}", self.caps.glyph("\u{e0a0} ", ""), status.branch);
+            if status.ahead > 0 {
+                segment.push_str(&format!(" {}{}", self.caps.glyph("↑", "+"), status.ahead));
+            }
+            if status.behind > 0 {
+                segment.push_str(&format!(" {}{}", self.caps.glyph("↓", "-"), status.behind));
+            }
+            if status.dirty {
+                segment.push_str(" *");
+            }
+            format!("{segment} ")
+        });
+        // Errors given since `:messages` last showed them.
+        let errors = match messages::unseen_errors() {
+            0 => String::new(),
+            count => format!(" {}{count} ", self.caps.glyph("\u{2716} ", "E")),
+        };
+        let file_width = self
+            .size
+            .0
+            .saturating_sub(mode.len() as u16 + pos.len() as u16 + 2)
+            .saturating_sub(modified.len() as u16)
+            .saturating_sub(repo.chars().count() as u16)
+            .saturating_sub(errors.chars().count() as u16);
+        // Separately placed, so the segments are redrawn one at a time.
+        let row = self.size.1.saturating_sub(2);
+        let file_col = mode.len() as u16 + 1;
+        self.screen.move_to(0, row);
+        self.screen.queue(style::PrintStyledContent(
+            mode.with(style::Color::Black)
+                .on(self.caps.rgb(184, 144, 243))
+                .bold(),
+        ))?;
+        self.screen.queue(style::PrintStyledContent(
+            separator
+                .on(self.caps.rgb(67, 70, 89))
+                .with(self.caps.rgb(184, 144, 243)),
+        ))?;
+        self.screen.move_to(file_col, row);
+        self.screen.queue(style::PrintStyledContent(
+            format!("{:<width$}", file, width = file_width as usize)
+                .on(self.caps.rgb(67, 70, 89))
+                .with(self.caps.rgb(255, 255, 255)),
+        ))?;
+        self.screen.move_to(file_col + file_width, row);
+        self.screen.queue(style::PrintStyledContent(
+            modified
+                .on(self.caps.rgb(67, 70, 89))
+                .with(self.caps.rgb(255, 255, 255)),
+        ))?;
+        self.screen
+            .move_to(file_col + file_width + modified.len() as u16, row);
+        self.screen.queue(style::PrintStyledContent(
+            repo.on(self.caps.rgb(67, 70, 89))
+                .with(self.caps.rgb(184, 144, 243)),
+        ))?;
+        self.screen.queue(style::PrintStyledContent(
+            errors
+                .with(style::Color::Black)
+                .on(self.caps.rgb(243, 139, 168))
+                .bold(),
+        ))?;
+        self.screen.queue(style::PrintStyledContent(
+            separator_rev
+                .on(self.caps.rgb(67, 70, 89))
+                .with(self.caps.rgb(184, 144, 243)),
+        ))?;
+        self.screen
+            .move_to(self.size.0.saturating_sub(pos.len() as u16), row);
+        self.screen.queue(style::PrintStyledContent(
+            pos.with(style::Color::Black)
+                .on(self.caps.rgb(184, 144, 243))
+                .bold(),
+        ))?;
+
+        Ok(())
+    }
+
+    /// Draws a notification across the statusline row, colored by how bad
+    /// what it tells is.
+    fn draw_flash(&mut self) -> anyhow::Result<()> {
+        let Some(flash) = &self.flash else {
+            return Ok(());
+        };
+        let width = self.size.0 as usize;
+        let text: String = format!(" {}", flash.text).chars().take(width).collect();
+        let bg = self.notice_color(flash.severity);
+        self.screen
+            .move_to(0, self.size.1.saturating_sub(2))
+            .queue(style::PrintStyledContent(
+                format!("{text:<width$}")
+                    .with(style::Color::Black)
+                    .on(bg)
+                    .bold(),
+            ))?;
+        Ok(())
+    }
+
+    /// Draws the toasts stacked in the top right corner, the oldest on
+    /// top, each as wide as its text up to half the screen.
+    fn draw_toasts(&mut self) -> anyhow::Result<()> {
+        let vwidth = self.vwidth() as usize;
+        let most = (vwidth / 2).max(20).min(vwidth);
+        let toasts: Vec<(String, style::Color)> = self
+            .toasts
+            .iter()
+            .filter(|toast| !toast.is_over())
+            .take(self.vheight() as usize)
+            .map(|toast| {
+                let text: String = toast
+                    .text
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .chars()
+                    .take(most.saturating_sub(2))
+                    .collect();
+                (format!(" {text} "), self.notice_color(toast.severity))
+            })
+            .collect();
+        for (row, (text, bg)) in toasts.into_iter().enumerate() {
+            let col = vwidth.saturating_sub(text.chars().count());
+            self.screen
+                .move_to(col as u16, row as u16)
+                .queue(style::PrintStyledContent(
+                    text.with(style::Color::Black).on(bg),
+                ))?;
+        }
+        Ok(())
+    }
+
+    /// The background of a flash or toast of `severity`.
+    fn notice_color(&self, severity: messages::Severity) -> style::Color {
+        match severity {
+            messages::Severity::Error => self.caps.rgb(243, 139, 168),
+            messages::Severity::Warning => self.caps.rgb(249, 226, 175),
+            messages::Severity::Info => self.caps.rgb(184, 144, 243),
+        }
+    }
+
+    /// Draws the statusline laid out by the `statusline` option, the text
+    /// on the left cut short rather than what is against the right edge.
+    fn draw_status_format(&mut self) -> anyhow::Result<()> {
+        let file = match (&self.picker, &self.pager) {
+            (Some(picker), _) => picker.title().to_string(),
+            (None, Some(pager)) => pager.title.clone(),
+            (None, None) => self.buffer.path.clone(),
+        };
+        let (line, col) = self.cursor();
+        let fields = Fields {
+            file: &file,
+            modified: self.buffer.dirty,
+            filetype: self.buffer.filetype,
+            line,
+            col,
+            lines: self.last_line() + 1,
+        };
+        let (left, right) = self.options.status_line.render(&fields);
+        let width = self.size.0 as usize;
+        let right_width: usize = right
+            .iter()
+            .map(|segment| segment.text.chars().count())
+            .sum();
+        let mut room = width.saturating_sub(right_width);
+
+        let row = self.size.1.saturating_sub(2);
+        let (default_fg, default_bg) = (Rgb(255, 255, 255), Rgb(67, 70, 89));
+        let color = |caps: &Capabilities, rgb: Option<Rgb>, default: Rgb| {
+            let Rgb(r, g, b) = rgb.unwrap_or(default);
+            caps.rgb(r, g, b)
+        };
+        self.screen.move_to(0, row);
+        for segment in &left {
+            let text: String = segment.text.chars().take(room).collect();
+            room -= text.chars().count();
+            self.screen.queue(style::PrintStyledContent(
+                text.with(color(&self.caps, segment.fg, default_fg))
+                    .on(color(&self.caps, segment.bg, default_bg)),
+            ))?;
+        }
+        self.screen.queue(style::PrintStyledContent(
+            " ".repeat(room).on(color(&self.caps, None, default_bg)),
+        ))?;
+        for segment in right {
+            self.screen.queue(style::PrintStyledContent(
+                segment
+                    .text
+                    .with(color(&self.caps, segment.fg, default_fg))
+                    .on(color(&self.caps, segment.bg, default_bg)),
+            ))?;
+        }
+        Ok(())
+    }
+
+    /// Keeps the cursor on the text and the viewport on the cursor, with
+    /// `scrolloff` lines and `sidescrolloff` columns around it shown where
+    /// there are any. Moving right past the end of a line goes on to the
+    /// start of the next.
+    fn assert_cursor_boundaries(&mut self) {
+        self.update_folds();
+        let last_line = self.buffer.len().saturating_sub(1);
+        let folds = &self.buffer.folds;
+        let mut line = self.buffer_line().min(last_line);
+        let length = |line: usize| self.buffer.line_text(line).chars().count();
+        if self.cx as usize > length(line) {
+            if folds.next_row(line) <= last_line {
+                line = folds.next_row(line);
+                self.cx = 0;
+            } else {
+                self.cx = length(line) as u16;
+            }
+            self.cx_history = self.cx;
+        }
+        // A closed fold is shown on the row of its first line.
+        line = folds.row_start(line);
+        self.cx = self.cx.min(length(line) as u16);
+
+        // Half the viewport at most, where the cursor line stays centered.
+        // Counted in rows, a closed fold taking one.
+        let vheight = self.vheight() as usize;
+        let off = self.options.scroll_off.min((vheight - 1) / 2);
+        let mut vtop = folds.row_start(self.vtop as usize);
+        let below = folds.rows_down(line, off, last_line);
+        if folds.rows_up(line, off) < vtop {
+            vtop = folds.rows_up(line, off);
+        } else if folds.rows_between(vtop, below) >= vheight {
+            vtop = folds.rows_up(below, vheight - 1);
+        }
+        let max_top = folds.rows_up(last_line, vheight - 1);
+        self.vtop = vtop.min(max_top) as u16;
+        self.cy = (line - self.vtop as usize) as u16;
+
+        let width = (self.vwidth() - self.gutter_width()).max(1) as usize;
+        let off = self.options.side_scroll_off.min((width - 1) / 2);
+        let (cx, mut vleft) = (self.cx as usize, self.vleft as usize);
+        if cx < vleft + off {
+            vleft = cx.saturating_sub(off);
+        } else if cx + off >= vleft + width {
+            vleft = cx + off + 1 - width;
+        }
+        self.vleft = vleft as u16;
+    }
+
+    /// Makes the folds again from indentation under `foldmethod=indent`
+    /// once the buffer changed, those made when it is first set closed.
+    fn update_folds(&mut self) {
+        let folds = &mut self.buffer.folds;
+        if self.options.fold_method != "indent" {
+            folds.indented_for = None;
+        } else if folds.indented_for != Some(self.buffer.revision) {
+            folds.indent(&self.buffer.text, folds.indented_for.is_none());
+            folds.indented_for = Some(self.buffer.revision);
+        }
+    }
+
+    /// The row of the viewport the cursor is on, below the closed folds
+    /// shown above it.
+    fn cursor_row(&self) -> u16 {
+        let vtop = self.vtop as usize;
+        self.buffer
+            .folds
+            .rows_between(vtop, vtop + self.cy as usize) as u16
+    }
+
+    pub fn run(&mut self) -> anyhow::Result<()> {
+        loop {
+            self.assert_cursor_boundaries();
+            self.draw()?;
+
+            let Some(action) = self.next_action()? else {
+                continue;
+            };
+            if !self.dispatch(action)? {
+                break;
+            }
+            self.track_snippet();
+            self.end_undo_step();
+        }
+        self.save_state();
+
+        Ok(())
+    }
+
+    /// Puts back the marks last set in the buffer's file, and makes it the
+    /// most recent file.
+    fn recall_marks(&mut self) {
+        if self.buffer.listing.is_some() {
+            return;
+        }
+        if self.buffer.marks.is_empty() {
+            self.buffer.marks = self.state.marks(&self.buffer.path);
+        }
+        self.state.remember(&self.buffer.path, &self.buffer.marks);
+    }
+
+    /// Keeps the registers, and the marks of the buffers shown, for the
+    /// next session.
+    fn save_state(&mut self) {
+        let others = self.diff.iter().map(|view| &view.other);
+        let closed = self.closed.iter().map(|window| &window.buffer);
+        // The buffer with the focus goes last, to be the most recent.
+        let buffers = others.chain(closed).chain(std::iter::once(&self.buffer));
+        for buffer in buffers.filter(|buffer| buffer.listing.is_none()) {
+            self.state.remember(&buffer.path, &buffer.marks);
+        }
+        self.state.registers = self.registers.clone();
+        if let Err(err) = self.state.save() {
+            error!("Could not save the registers and marks: {err}");
+        }
+    }
+
+    /// Carries out an action, returning `false` once the editor should quit.
+    fn dispatch(&mut self, action: Action) -> anyhow::Result<bool> {
+        self.actions
+            .record(format!("{action:?}"), self.buffer.revision);
+        // Any step the action ends keeps as many states as `undolevels`
+        // says at the time.
+        self.buffer.history.levels = self.options.undo_levels;
+        // Editing in a closed fold opens it.
+        if matches!(
+            action,
+            Action::EnterMode(Mode::Insert)
+                | Action::InsertChar(_)
+                | Action::NewLine
+                | Action::DelCharBefore
+                | Action::DeleteBack { .. }
+                | Action::DelCharAtCursor
+                | Action::DeleteChars(_)
+                | Action::JoinLines(_)
+                | Action::Paste(_)
+        ) && !matches!(self.mode, Mode::Command)
+        {
+            self.buffer.folds.open(self.buffer_line());
+        }
+        if !matches!(
+            action,
+            Action::InsertChar(_)
+                | Action::DelCharBefore
+                | Action::DeleteBack { .. }
+                | Action::ShowCompletion(..)
+                | Action::CompleteWords { .. }
+                | Action::CompletePath
+                | Action::CompletionNext
+                | Action::CompletionPrev
+                | Action::AcceptCompletion
+        ) {
+            self.completion = None;
+        }
+        let moving = matches!(
+            action,
+            Action::MoveUp
+                | Action::MoveDown
+                | Action::MoveLeft
+                | Action::MoveRight
+                | Action::PageUp
+                | Action::PageDown
+                | Action::EndOfLine
+                | Action::StartOfLine
+                | Action::FirstNonBlank
+                | Action::SmartHome
+        );
+        if let Some(session) = self.snippet.as_mut().filter(|_| moving) {
+            session.selected = false;
+        }
+        if !matches!(action, Action::CommandComplete { .. }) {
+            self.wildmenu = None;
+        }
+
+        match action {
+            Action::Quit(quit) => match self.quit(quit) {
+                Result::Ok(false) => return Ok(false),
+                Result::Ok(true) => {}
+                Err(err) => {
+                    error!("{err:#}");
+                }
+            },
+            Action::OlderChange | Action::NewerChange => {
+                let idx = if matches!(action, Action::OlderChange) {
+                    self.buffer.change_idx.checked_sub(1)
+                } else {
+                    Some(self.buffer.change_idx + 1).filter(|&idx| idx < self.buffer.changes.len())
+                };
+                match idx {
+                    Some(idx) if idx < self.buffer.changes.len() => {
+                        return self.dispatch(Action::JumpToChange(idx));
+                    }
+                    _ => {
+                        warn!("No change to jump to in the changelist");
+                    }
+                }
+            }
+            Action::ToggleComment { first, last } => {
+                // Closed folds are commented whole.
+                let first = self.buffer.folds.row_start(first);
+                let last = self.buffer.folds.closed_at(last).map_or(last, |l| *l.end());
+                let builtin = self.buffer.filetype.and_then(filetype::comment);
+                let Some(markers) = self.options.comment_markers().or(builtin) else {
+                    warn!("No comment markers for this filetype");
+                    return Ok(true);
+                };
+                let lines: Vec<String> = (first..=last).map(|l| self.buffer.line_text(l)).collect();
+                let toggled = comment::toggle(&lines, markers);
+                for (i, (old, new)) in lines.iter().zip(&toggled).enumerate().rev() {
+                    if old != new {
+                        let start = self.buffer.text.line_to_char(first + i);
+                        self.buffer.replace(start..start + old.chars().count(), new);
+                    }
+                }
+                let col = self.cx as usize;
+                self.goto(first, col);
+            }
+            Action::CreateFold { first, last } => {
+                if self.options.fold_method != "manual" {
+                    warn!(
+                        "Cannot create a fold with foldmethod={}",
+                        self.options.fold_method
+                    );
+                } else {
+                    let first = self.buffer.folds.row_start(first);
+                    let last = self.buffer.folds.closed_at(last).map_or(last, |l| *l.end());
+                    self.buffer.folds.create(first, last);
+                    let col = self.cx as usize;
+                    self.goto(first, col);
+                }
+                if matches!(self.mode, Mode::Visual) {
+                    return self.dispatch(Action::EnterMode(Mode::Normal));
+                }
+            }
+            Action::Fold(op) => {
+                if matches!(op, FoldOp::Delete | FoldOp::DeleteAll)
+                    && self.options.fold_method != "manual"
+                {
+                    warn!(
+                        "Cannot delete a fold with foldmethod={}",
+                        self.options.fold_method
+                    );
+                } else if !self.buffer.folds.apply(op, self.buffer_line()) {
+                    warn!("No fold found");
+                }
+            }
+            Action::FollowLink { external } => {
+                if let Err(err) = self.follow_link(external) {
+                    error!("{err:#}");
+                }
+            }
+            Action::FeedKeys(notation) => {
+                let outer = std::mem::replace(&mut self.feeding_keys, true);
+                let mut running = true;
+                for key in keys::parse_keys(&notation)? {
+                    running = self.feed(Event::Key(key))?;
+                    if !running {
+                        break;
+                    }
+                }
+                self.feeding_keys = outer;
+                if !running {
+                    return Ok(false);
+                }
+            }
+            Action::RunKeymap(key) => {
+                let view = self.plugin_view();
+                let mode = match self.mode {
+                    Mode::Visual => MapMode::Visual,
+                    _ => MapMode::Normal,
+                };
+                match self.plugins.run_keymap(mode, &key, view) {
+                    Result::Ok(outcome) => return self.apply_plugin(outcome),
+                    Err(err) => {
+                        error!("{err:#}");
+                    }
+                }
+            }
+            Action::NextConflict { backward } => {
+                let line = self.buffer_line();
+                let mut starts = self.conflicts().iter().map(|conflict| conflict.start);
+                let target = if backward {
+                    starts.rfind(|&l| l < line)
+                } else {
+                    starts.find(|&l| l > line)
+                };
+                match target {
+                    Some(target) => {
+                        self.goto(target, 0);
+                        self.cx_history = self.cx;
+                    }
+                    None => {
+                        warn!("No more conflicts");
+                    }
+                }
+            }
+            Action::SwitchWindow => {
+                if let Err(err) = self.switch_window() {
+                    error!("{err:#}");
+                }
+            }
+            Action::CloseWindow => {
+                if !self.close_window() {
+                    error!("Cannot close last window");
+                }
+            }
+            Action::DiffCopy { put } => {
+                if let Err(err) = self.diff_copy(put) {
+                    error!("{err:#}");
+                }
+            }
+            Action::NextHunk { backward } => {
+                let hunks = match &self.diff {
+                    Some(view) => Result::Ok(view.hunks(&self.buffer)),
+                    None => self.git_hunks().map(|(hunks, _)| hunks),
+                };
+                let hunks = match hunks {
+                    Result::Ok(hunks) => hunks,
+                    Err(err) => {
+                        error!("{err:#}");
+                        return Ok(true);
+                    }
+                };
+                let line = self.buffer_line();
+                let mut lines = hunks.iter().map(git::hunk_line);
+                let target = if backward {
+                    lines.rfind(|&l| l < line)
+                } else {
+                    lines.find(|&l| l > line)
+                };
+                match target {
+                    Some(target) => {
+                        self.goto(target, 0);
+                        self.cx_history = self.cx;
+                    }
+                    None => {
+                        warn!("No more hunks");
+                    }
+                }
+            }
+            Action::JumpToChange(idx) => {
+                let (line, col) = self.buffer.changes[idx];
+                self.buffer.change_idx = idx;
+                self.goto(line, col);
+                self.cx_history = self.cx;
+            }
+            Action::CommandChar(c) => match &mut self.recall {
+                Some(Recall::Reverse { query, .. }) => {
+                    query.push(c);
+                    self.refresh_history_search();
+                }
+                _ => {
+                    self.recall = None;
+                    self.cmdline.push(c);
+                }
+            },
+            Action::RecallHistory { older } => self.recall_history(older),
+            Action::SearchHistory => self.search_history(),
+            Action::PasteRegister(name) => {
+                if let Some(text) = self.register(name) {
+                    self.dispatch(Action::Paste(text))?;
+                }
+            }
+            Action::StartSearch { backward } => {
+                self.dispatch(Action::EnterMode(Mode::Command))?;
+                self.prompt = if backward { '?' } else { '/' };
+            }
+            Action::SearchNext { reverse } => self.search_next(reverse),
+            Action::DragLines { down } => {
+                let line = self.buffer_line();
+                let (first, end) = match self.mode {
+                    Mode::Visual => {
+                        let selection = self.selection();
+                        let text = &self.buffer.text;
+                        (
+                            text.char_to_line(selection.start),
+                            text.char_to_line(selection.end.saturating_sub(1)),
+                        )
+                    }
+                    _ => (line, line),
+                };
+                let to = match down {
+                    true if end < self.last_line() => Some(end + 1),
+                    false if first > 0 => first.checked_sub(2),
+                    _ => return Ok(true),
+                };
+                let anchor = self.buffer.char_to_pos(self.visual_start);
+                let col = self.cx as usize;
+                self.move_lines(first, end, to, false)?;
+                // The selection and cursor go along with the lines.
+                let shift = |line: usize| if down { line + 1 } else { line - 1 };
+                if matches!(self.mode, Mode::Visual) {
+                    self.visual_start = self.buffer.text.line_to_char(shift(anchor.0)) + anchor.1;
+                }
+                self.goto(shift(line), col);
+            }
+            Action::SetMark(name) => {
+                let at = (self.buffer_line(), self.cx as usize);
+                self.buffer.marks.insert(name, at);
+            }
+            Action::SearchWord { backward, partial } => {
+                let line = self.buffer_line();
+                let text = self.buffer.line_text(line);
+                let Some((start, word)) = search::word_at(&text, self.cx as usize) else {
+                    error!("No string under cursor");
+                    return Ok(true);
+                };
+                let pattern = search::word_pattern(&word, !partial);
+                self.history
+                    .add(history::Kind::Search, &pattern, self.options.history);
+                self.search = Some(Search { pattern, backward });
+                // From the start of the word, so that `#` skips it.
+                self.goto(line, start);
+                self.search_next(false);
+            }
+            // Patterns are not completed.
+            Action::CommandComplete { .. } if self.prompt != ':' => {}
+            Action::CommandComplete { .. }
+                if matches!(self.recall, Some(Recall::Reverse { .. })) =>
+            {
+                self.accept_history_search()
+            }
+            Action::CommandComplete { backward } => match self.wildmenu.as_mut() {
+                Some(menu) => {
+                    if backward {
+                        menu.select_prev();
+                    } else {
+                        menu.select_next();
+                    }
+                    self.cmdline = menu.cmdline();
+                }
+                None => {
+                    // The name is completed until a space is typed after
+                    // it, and then the argument.
+                    let (prefix, candidates) = match self.cmdline.rfind(' ') {
+                        Some(space) => {
+                            let (prefix, typed) = self.cmdline.split_at(space + 1);
+                            (prefix, self.complete_argument(prefix, typed))
+                        }
+                        None => {
+                            let name = command::strip_range(&self.cmdline);
+                            let prefix = &self.cmdline[..self.cmdline.len() - name.len()];
+                            (prefix, self.complete_command(name))
+                        }
+                    };
+                    if candidates.is_empty() {
+                        return Ok(true);
+                    }
+                    let menu = Wildmenu {
+                        selected: if backward { candidates.len() - 1 } else { 0 },
+                        candidates,
+                        prefix: prefix.to_string(),
+                    };
+                    self.cmdline = menu.cmdline();
+                    if menu.candidates.len() > 1 {
+                        self.wildmenu = Some(menu);
+                    }
+                }
+            },
+            Action::Paste(text) if matches!(self.mode, Mode::Command) => {
+                self.cmdline
+                    .push_str(text.lines().next().unwrap_or_default());
+            }
+            Action::Paste(text) => {
+                self.take_placeholder();
+                let text = text.replace("\r\n", "\n").replace('\r', "\n");
+                let line = self.buffer_line();
+                let cursor = self.buffer.text.line_to_char(line) + self.cx as usize;
+                self.buffer.insert_text(line, self.cx as usize, &text);
+                let (line, col) = self.buffer.char_to_pos(cursor + text.chars().count());
+                self.goto(line, col);
+                self.cx_history = self.cx;
+            }
+            Action::CommandBackspace => {
+                if let Some(Recall::Reverse { query, .. }) = &mut self.recall {
+                    query.pop();
+                    self.refresh_history_search();
+                    return Ok(true);
+                }
+                self.recall = None;
+                if self.cmdline.pop().is_none() {
+                    self.mode = Mode::Normal;
+                }
+            }
+            Action::ExecuteCommand => {
+                self.accept_history_search();
+                self.recall = None;
+                self.mode = Mode::Normal;
+                self.emit("mode", "normal");
+                let input = std::mem::take(&mut self.cmdline);
+                self.history
+                    .add(self.history_kind(), &input, self.options.history);
+                let prompt = std::mem::replace(&mut self.prompt, ':');
+                if prompt != ':' {
+                    // An empty pattern searches for the last one again.
+                    let pattern = match (input.is_empty(), &self.search) {
+                        (true, Some(search)) => search.pattern.clone(),
+                        _ => input,
+                    };
+                    self.search = Some(Search {
+                        pattern,
+                        backward: prompt == '?',
+                    });
+                    self.search_next(false);
+                    return Ok(true);
+                }
+                let had_pager = self.pager.is_some();
+                match Command::parse(&input) {
+                    Result::Ok(Command::Quit(quit)) => match self.quit(quit) {
+                        Result::Ok(false) => return Ok(false),
+                        Result::Ok(true) => {}
+                        Err(err) => {
+                            error!("{err:#}");
+                            self.capture(&format!("{err:#}"));
+                        }
+                    },
+                    Result::Ok(Command::User { name, args, range }) => {
+                        match self.run_user_command(&name, &args, range) {
+                            Result::Ok(false) => return Ok(false),
+                            Result::Ok(true) => {}
+                            Err(err) => {
+                                error!("{err:#}");
+                                self.capture(&format!("{err:#}"));
+                            }
+                        }
+                    }
+                    Result::Ok(Command::Redir(redirect)) => {
+                        if let Err(err) = self.redirect(redirect) {
+                            error!("{err:#}");
+                        }
+                    }
+                    Result::Ok(command) => {
+                        if let Err(err) = self.execute(command) {
+                            error!("{err:#}");
+                            self.capture(&format!("{err:#}"));
+                        } else if let Some(pager) = self.pager.as_ref().filter(|_| !had_pager) {
+                            let output = pager.lines().collect::<Vec<_>>().join("\n");
+                            self.capture(&output);
+                        }
+                    }
+                    Err(err) => {
+                        error!("{err}");
+                        self.capture(&err.to_string());
+                    }
+                }
+            }
+            Action::PagerSelect => {
+                if let Some(action) = self.pager.take().and_then(Pager::into_selected) {
+                    return self.dispatch(action);
+                }
+            }
+            Action::ClosePager => self.pager = None,
+            Action::PickerInput(input) => {
+                let Some(picker) = self.picker.as_mut() else {
+                    return Ok(true);
+                };
+                match picker.input(input) {
+                    picker::Outcome::Continue => {}
+                    picker::Outcome::Cancel => self.picker = None,
+                    picker::Outcome::Done(action) => {
+                        self.picker = None;
+                        return self.dispatch(action);
+                    }
+                }
+            }
+            Action::AutoSave => self.autosave(),
+            Action::Notify(severity, text) => self.notify(severity, text),
+            Action::Suspend => self.suspend()?,
+            Action::EditFile { path, force } => {
+                self.autosave();
+                if self.buffer.dirty && !force {
+                    error!("No write since last change (add ! to override)");
+                } else if let Err(err) = self.open(&path) {
+                    error!("{err:#}");
+                }
+            }
+            Action::ShowSymbols(symbols) => {
+                if symbols.is_empty() {
+                    warn!("No symbols found");
+                    return Ok(true);
+                }
+                let text = self.buffer.text.clone();
+                let picker = Picker::new("symbols", symbols)
+                    .with_preview(move |symbol: &lsp::Symbol| {
+                        text.lines_at(symbol.line)
+                            .take(100)
+                            .map(|line| line.to_string().trim_end().replace('\t', "    "))
+                            .collect()
+                    })
+                    .on_select(|symbols| Action::Goto(symbols[0].line, symbols[0].col));
+                self.picker = Some(Box::new(picker));
+            }
+            Action::Hover => {
+                let (line, col) = (self.buffer_line(), self.cx as usize);
+                if self.lsp.is_none() {
+                    error!("No language server for {}", self.buffer.path);
+                }
+                self.lsp_request(|lsp, buffer| lsp.hover(buffer, line, col));
+            }
+            Action::ShowHover(text) => {
+                self.popup = Some(Popup::from_markdown(&text, self.vwidth() as usize - 2));
+            }
+            Action::OpenEntry => {
+                let name = self.buffer.line_text(self.buffer_line());
+                let path = Path::new(&self.buffer.path).join(name.trim());
+                self.autosave();
+                if self.buffer.dirty {
+                    error!("No write since last change");
+                } else if let Err(err) = self.open(&path.to_string_lossy()) {
+                    error!("{err:#}");
+                }
+            }
+            Action::OpenParent => {
+                self.autosave();
+                if self.buffer.dirty {
+                    error!("No write since last change");
+                } else {
+                    match fs::canonicalize(&self.buffer.path) {
+                        Result::Ok(path) => {
+                            let parent = path.parent().unwrap_or(&path);
+                            self.open(&parent.to_string_lossy())?;
+                        }
+                        Err(err) => {
+                            error!("{err:#}");
+                        }
+                    }
+                }
+            }
+            Action::ApplyFsOps(ops) => {
+                self.pager = None;
+                if let Err(err) = explorer::apply(&ops) {
+                    error!("{err:#}");
+                }
+                let (line, col) = (self.buffer_line(), self.cx as usize);
+                self.buffer = Buffer::from_dir(&self.buffer.path)?;
+                self.goto(line, col);
+            }
+            Action::TriggerCompletion => self.request_completion(None),
+            Action::CompletePath => {
+                let line = self.buffer_line();
+                let before: Vec<char> = self
+                    .buffer
+                    .line_text(line)
+                    .chars()
+                    .take(self.cx as usize)
+                    .collect();
+                let start = before
+                    .iter()
+                    .rposition(|c| c.is_whitespace() || "\"'`()<>[]{},;=".contains(*c))
+                    .map_or(0, |i| i + 1);
+                let typed: String = before[start..].iter().collect();
+                if !typed.contains('/') {
+                    return self.dispatch(Action::InsertChar('\t'));
+                }
+
+                let line_start = self.buffer.text.line_to_char(line);
+                let range = line_start + start..line_start + before.len();
+                let items: Vec<CompletionItem> = completion::paths(&typed)
+                    .into_iter()
+                    .map(|path| CompletionItem {
+                        label: path[path.trim_end_matches('/').rfind('/').map_or(0, |i| i + 1)..]
+                            .to_string(),
+                        detail: String::new(),
+                        filter_text: path.clone(),
+                        new_text: path,
+                        range: Some(range.clone()),
+                        additional_edits: Vec::new(),
+                        snippet: false,
+                    })
+                    .collect();
+                if items.is_empty() {
+                    error!("No matching files");
+                } else {
+                    self.completion = Some(CompletionMenu::new(items, false, line, start));
+                }
+            }
+            Action::CompleteWords { backward } => {
+                let line = self.buffer_line();
+                let start_col = self.word_start();
+                let cursor = self.buffer.text.line_to_char(line) + self.cx as usize;
+                let items = completion::buffer_words(&self.buffer.text, cursor, &self.typed_word());
+                let mut menu = CompletionMenu::new(items, false, line, start_col);
+                if backward {
+                    menu.select_prev();
+                }
+                if menu.is_empty() {
+                    error!("Pattern not found");
+                } else {
+                    self.completion = Some(menu);
+                }
+            }
+            Action::ExpandSnippet => {
+                let Some((start_col, body)) = self.snippet_trigger() else {
+                    return Ok(true);
+                };
+                let line_start = self.buffer.text.line_to_char(self.buffer_line());
+                self.insert_snippet(line_start + start_col..line_start + self.cx as usize, &body);
+            }
+            Action::JumpTabStop { backward } => {
+                if let Some(session) = self.snippet.as_mut() {
+                    if backward {
+                        session.prev();
+                    } else {
+                        session.next();
+                    }
+                }
+                self.select_tab_stop();
+            }
+            Action::ShowCompletion(items, incomplete) => {
+                let line = self.buffer_line();
+                let start_col = self.word_start();
+                let mut menu = CompletionMenu::new(items, incomplete, line, start_col);
+                menu.filter(&self.typed_word());
+                self.completion = (!menu.is_empty()).then_some(menu);
+            }
+            Action::CompletionNext => {
+                if let Some(menu) = self.completion.as_mut() {
+                    menu.select_next();
+                }
+            }
+            Action::CompletionPrev => {
+                if let Some(menu) = self.completion.as_mut() {
+                    menu.select_prev();
+                }
+            }
+            Action::AcceptCompletion => {
+                self.accept_completion();
+                self.cx_history = self.cx;
+            }
+            Action::CloseCompletion => {}
+            Action::PublishDiagnostics(source, diagnostics) => {
+                self.diagnostics.publish(&source, diagnostics);
+            }
+            Action::ShowCodeActions(actions) => {
+                if actions.is_empty() {
+                    warn!("No code actions available");
+                    return Ok(true);
+                }
+                let mut pager = Pager::new("code actions");
+                for action in actions {
+                    let title = action["title"].as_str().unwrap_or_default().to_string();
+                    pager.push_target(
+                        title,
+                        Action::CodeActionChosen {
+                            action,
+                            resolved: false,
+                        },
+                    );
+                }
+                self.pager = Some(pager);
+            }
+            Action::CodeActionChosen { action, resolved } => {
+                if self.lsp.is_none() {
+                    return Ok(true);
+                }
+                // A bare command rather than a code action literal.
+                if action["command"].is_string() {
+                    self.lsp_request(|lsp, _| lsp.execute_command(action));
+                    return Ok(true);
+                }
+                if action.get("edit").is_none() && action.get("data").is_some() && !resolved {
+                    self.lsp_request(|lsp, _| lsp.resolve_code_action(action));
+                    return Ok(true);
+                }
+                if let Some(edit) = action.get("edit") {
+                    self.dispatch(Action::ApplyWorkspaceEdit {
+                        edit: edit.clone(),
+                        reply_to: None,
+                    })?;
+                }
+                if let Some(command) = action.get("command") {
+                    self.lsp_request(|lsp, _| lsp.execute_command(command.clone()));
+                }
+            }
+            Action::ApplyWorkspaceEdit { edit, reply_to } => {
+                let result = workspace_edit::apply(&edit, &mut self.buffer);
+                let (line, col) = (self.buffer_line(), self.cx as usize);
+                self.goto(line, col);
+                let reply = match &result {
+                    Result::Ok(files) => {
+                        log!("Applied edits to {files} file(s)");
+                        json!({ "applied": true })
+                    }
+                    Err(err) => {
+                        error!("Cannot apply edit: {err:#}");
+                        json!({ "applied": false, "failureReason": format!("{err:#}") })
+                    }
+                };
+                self.lsp_request(|lsp, buffer| {
+                    lsp.sync(buffer)?;
+                    match reply_to {
+                        Some(id) => lsp.respond(id, reply),
+                        None => Ok(()),
+                    }
+                });
+            }
+            Action::TrustWorkspace { persist } => {
+                self.pager = None;
+                if let Err(err) = self.trust_workspace(persist) {
+                    error!("{err:#}");
+                }
+            }
+            Action::ForceWrite => {
+                self.pager = None;
+                if let Err(err) = self.write(true) {
+                    error!("{err:#}");
+                }
+            }
+            Action::ShowDiskDiff => {
+                if let Err(err) = self.show_disk_diff() {
+                    error!("{err:#}");
+                }
+            }
+            Action::ReloadFile => {
+                self.pager = None;
+                let (line, col) = (self.buffer_line(), self.cx as usize);
+                let reloaded = fs::read_to_string(&self.buffer.path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|contents| self.buffer.reload(&contents));
+                match reloaded {
+                    Result::Ok(()) => self.toast(
+                        messages::Severity::Info,
+                        format!("Reloaded {}", self.buffer.path),
+                    ),
+                    Err(err) => {
+                        error!("{err:#}");
+                    }
+                }
+                self.goto(line, col);
+            }
+            Action::CheckDisk => {
+                if let Err(err) = self.check_disk() {
+                    error!("{err:#}");
+                }
+            }
+            Action::MergeDisk => {
+                self.pager = None;
+                let (line, col) = (self.buffer_line(), self.cx as usize);
+                match self.buffer.changed_on_disk() {
+                    Result::Ok(Some(contents)) => match self.buffer.merge_disk(&contents) {
+                        Result::Ok(0) => {
+                            log!("Merged the changes from disk");
+                        }
+                        Result::Ok(conflicts) => {
+                            warn!("Merged the changes from disk with {conflicts} conflict(s)");
+                        }
+                        Err(err) => {
+                            error!("{err:#}");
+                        }
+                    },
+                    Result::Ok(None) => {}
+                    Err(err) => {
+                        error!("{err:#}");
+                    }
+                }
+                self.goto(line, col);
+            }
+            Action::Goto(line, col) => {
+                self.goto(line, col);
+                self.cx_history = self.cx;
+            }
+            Action::Reselect => {
+                let marks = &self.buffer.marks;
+                let (Some(&start), Some(&end)) = (marks.get(&'<'), marks.get(&'>')) else {
+                    warn!("No selection made yet");
+                    return Ok(true);
+                };
+                let (anchor, head) = match self.visual_reversed {
+                    true => (end, start),
+                    false => (start, end),
+                };
+                self.goto(anchor.0, anchor.1);
+                self.dispatch(Action::EnterMode(Mode::Visual))?;
+                self.goto(head.0, head.1);
+                self.cx_history = self.cx;
+            }
+            Action::SwapSelectionEnds => {
+                let cursor = self.cursor_char();
+                self.goto_char(self.visual_start);
+                self.visual_start = cursor;
+            }
+            Action::ApplyFormatting(edits) => {
+                let Some((revision, save)) = self.formatting.take() else {
+                    return Ok(true);
+                };
+                if revision != self.buffer.revision {
+                    warn!("Buffer changed while formatting, result discarded");
+                } else if !edits.is_null() {
+                    let mut text = self.buffer.text.clone();
+                    match workspace_edit::apply_text_edits(&mut text, &edits) {
+                        Result::Ok(()) if text != self.buffer.text => {
+                            let (line, col) = (self.buffer_line(), self.cx as usize);
+                            let hunks = self.buffer.rewrite(&text.to_string());
+                            self.goto(diff::map_line(&hunks, line), col);
+                        }
+                        Result::Ok(()) => {}
+                        Err(err) => {
+                            error!("Cannot apply formatting: {err:#}");
+                        }
+                    }
+                }
+                if let Some(Err(err)) = save.then(|| save_buffer(&mut self.buffer, &self.options)) {
+                    error!("{err:#}");
+                }
+            }
+            Action::ScrollPopup(delta) => {
+                if let Some(popup) = self.popup.as_mut() {
+                    popup.scroll_by(delta);
+                }
+            }
+            _ if moving && self.segments.is_some() && self.pager.is_none() => {
+                self.move_in_segments(&action);
+            }
+            Action::MoveUp if self.pager.is_some() => {
+                self.pager.as_mut().unwrap().move_up(1);
+            }
+            Action::MoveDown if self.pager.is_some() => {
+                self.pager.as_mut().unwrap().move_down(1);
+            }
+            Action::PageUp if self.pager.is_some() => {
+                let vheight = self.vheight() as usize;
+                self.pager.as_mut().unwrap().move_up(vheight);
+            }
+            Action::PageDown if self.pager.is_some() => {
+                let vheight = self.vheight() as usize;
+                self.pager.as_mut().unwrap().move_down(vheight);
+            }
+            Action::MoveUp => {
+                // Onto the first line of a closed fold above.
+                if let Some(above) = self.buffer_line().checked_sub(1) {
+                    let above = self.buffer.folds.row_start(above);
+                    self.vtop = self.vtop.min(above as u16);
+                    self.cy = (above - self.vtop as usize) as u16;
+                }
+                if self.cx_history <= self.line_length() {
+                    self.cx = self.cx_history;
+                } else if self.cx_history > self.line_length() {
+                    self.cx = self.line_length();
+                }
+            }
+            Action::MoveDown => {
+                // Past the lines of a closed fold.
+                let below = self.buffer.folds.next_row(self.buffer_line());
+                self.cy = (below - self.vtop as usize) as u16;
+
+                if self.cx_history <= self.line_length() {
+                    self.cx = self.cx_history;
+                } else if self.cx_history > self.line_length() {
+                    self.cx = self.line_length();
+                }
+            }
+            Action::MoveLeft => {
+                match self.buffer_line().checked_sub(1) {
+                    Some(above) if self.cx == 0 => {
+                        let above = self.buffer.folds.row_start(above);
+                        self.vtop = self.vtop.min(above as u16);
+                        self.cy = (above - self.vtop as usize) as u16;
+                        self.cx = self.line_length();
+                    }
+                    _ => {}
+                }
+                self.cx = self.cx.saturating_sub(1);
+                self.cx_history = self.cx;
+            }
+            Action::MoveRight => {
+                self.cx += 1;
+                self.cx_history = self.cx;
+            }
+            Action::EnterMode(new_mode) => {
+                if matches!(new_mode, Mode::Visual) {
+                    self.visual_start = self.cursor_char();
+                }
+                if matches!(new_mode, Mode::Insert) && !matches!(self.mode, Mode::Insert) {
+                    self.insert_start = self.cursor_char();
+                }
+                let left_visual = matches!(self.mode, Mode::Visual);
+                if left_visual {
+                    let selection = self.selection();
+                    let end = selection.end.saturating_sub(1).max(selection.start);
+                    let (start, end) = (
+                        self.buffer.char_to_pos(selection.start),
+                        self.buffer.char_to_pos(end),
+                    );
+                    self.buffer.marks.insert('<', start);
+                    self.buffer.marks.insert('>', end);
+                    self.visual_reversed = self.cursor_char() < self.visual_start;
+                }
+                let left_insert = matches!(self.mode, Mode::Insert);
+                self.mode = new_mode;
+                self.prompt = ':';
+                self.recall = None;
+                // What was typed on a command line left with Esc is dropped,
+                // and one opened from visual mode is for the selection.
+                self.cmdline.clear();
+                if left_visual && matches!(self.mode, Mode::Command) {
+                    self.cmdline.push_str("'<,'>");
+                }
+                if !self.headless {
+                    self.stdout.execute(Clear(terminal::ClearType::Purge))?;
+                }
+                self.emit("mode", &self.mode_name().to_lowercase());
+                if left_insert && !matches!(self.mode, Mode::Insert) {
+                    self.run_autocmds("InsertLeave");
+                }
+            }
+            Action::InsertChar(c) => {
+                self.take_placeholder();
+                let (before, after) = self.chars_around_cursor();
+                if self.options.auto_pairs && pairs::skips(c, after) {
+                    self.cx += 1;
+                    self.cx_history = self.cx;
+                    return Ok(true);
+                }
+                self.buffer
+                    .insert_char(self.buffer_line(), self.cx as usize, c);
+                let closing = pairs::closing(c, before, after, self.buffer.filetype)
+                    .filter(|_| self.options.auto_pairs);
+                if let Some(closing) = closing {
+                    self.buffer
+                        .insert_char(self.buffer_line(), self.cx as usize + 1, closing);
+                }
+                self.cx += 1;
+                self.cx_history = self.cx;
+                self.update_completion(Some(c))?;
+            }
+            Action::DelCharBefore => {
+                if self.take_placeholder() {
+                    self.update_completion(None)?;
+                } else if self.cx > 0 {
+                    let (before, after) = self.chars_around_cursor();
+                    if self.options.auto_pairs && pairs::is_empty_pair(before, after) {
+                        self.buffer
+                            .remove_char(self.buffer_line(), self.cx as usize);
+                    }
+                    self.buffer
+                        .remove_char(self.buffer_line(), self.cx as usize - 1);
+                    self.cx = self.cx.saturating_sub(1);
+                    self.cx_history = self.cx;
+                    self.update_completion(None)?;
+                }
+            }
+            Action::DeleteBack { line } => {
+                self.take_placeholder();
+                let start = self.delete_back_start(line);
+                let cursor = self.cursor_char();
+                let line_start = cursor - self.cx as usize;
+                if line_start + start < cursor {
+                    self.buffer.replace(line_start + start..cursor, "");
+                    self.cx = start as u16;
+                    self.cx_history = self.cx;
+                    self.insert_start = self.insert_start.min(line_start + start);
+                    self.update_completion(None)?;
+                }
+            }
+            Action::DelCharAtCursor => {
+                if self.take_placeholder() {
+                    return Ok(true);
+                }
+                if self.cx < self.line_length() && self.line_length() > 0 {
+                    self.buffer
+                        .remove_char(self.buffer_line(), self.cx as usize);
+                }
+            }
+            Action::NewLine => {
+                // Each line typed is undone on its own.
+                self.buffer.history.end_step();
+                self.buffer
+                    .insert_text(self.buffer_line(), self.cx as usize, "\u{000a}");
+                self.cx = 0;
+                self.cy += 1;
+            }
+            Action::PageDown => {
+                let top = self.vtop + self.vheight();
+                let max_top = (self.buffer.len() as u16).saturating_sub(self.vheight());
+                if top > max_top {
+                    // Past the end the cursor goes to the last line.
+                    self.vtop = max_top;
+                    self.cy = (self.buffer.len() as u16).saturating_sub(1) - max_top;
+                } else {
+                    self.vtop = top;
+                }
+
+                if self.cx_history <= self.line_length() {
+                    self.cx = self.cx_history;
+                } else if self.cx_history > self.line_length() {
+                    self.cx = self.line_length();
+                }
+            }
+            Action::Undo => match self.buffer.undo() {
+                Some(at) => self.goto_char(at),
+                None => {
+                    log!("Already at oldest change");
+                }
+            },
+            Action::Redo => match self.buffer.redo() {
+                Some(at) => self.goto_char(at),
+                None => {
+                    log!("Already at newest change");
+                }
+            },
+            Action::ChangeCase(case) => {
+                let range = self.selection();
+                let text = case.apply(&self.buffer.text.slice(range.clone()).to_string());
+                self.buffer.replace(range.clone(), &text);
+                self.goto_char(range.start);
+                return self.dispatch(Action::EnterMode(Mode::Normal));
+            }
+            Action::DeleteSelection => {
+                let range = self.selection();
+                let deleted = self.buffer.text.slice(range.clone()).to_string();
+                self.store_deleted(deleted);
+                self.buffer.replace(range.clone(), "");
+                self.goto_char(range.start);
+                return self.dispatch(Action::EnterMode(Mode::Normal));
+            }
+            Action::DeleteChars(count) => {
+                let cursor = self.cursor_char();
+                let end = cursor + count.min((self.line_length() - self.cx) as usize);
+                if end > cursor {
+                    let deleted = self.buffer.text.slice(cursor..end).to_string();
+                    self.store_deleted(deleted);
+                    self.buffer.replace(cursor..end, "");
+                }
+            }
+            Action::Put { before, count } => {
+                let Some(text) = self.registers.get(&'"').cloned() else {
+                    warn!("Nothing in register \"");
+                    return Ok(true);
+                };
+                let text = text.repeat(count);
+                let line = self.buffer_line();
+                if text.ends_with('\n') {
+                    let target = if before { line } else { line + 1 };
+                    let at = self.buffer.text.line_to_char(target.min(self.buffer.len()));
+                    // Past a last line without a line break, one goes first.
+                    match target >= self.buffer.len() {
+                        true => {
+                            let text = format!("\n{}", &text[..text.len() - 1]);
+                            self.buffer.replace(at..at, &text);
+                        }
+                        false => self.buffer.replace(at..at, &text),
+                    }
+                    self.goto(target, self.first_non_blank(target));
+                    self.cx_history = self.cx;
+                } else {
+                    let after = !before && self.cx < self.line_length();
+                    let at = self.cursor_char() + after as usize;
+                    self.buffer.replace(at..at, &text);
+                    self.goto_char((at + text.chars().count()).saturating_sub(1).max(at));
+                }
+            }
+            Action::JoinLines(count) => {
+                let line = self.buffer_line();
+                let joins = (count.max(2) - 1).min(self.last_line().saturating_sub(line));
+                let mut col = self.cx as usize;
+                for _ in 0..joins {
+                    let current = self.buffer.line_text(line);
+                    let next = self.buffer.line_text(line + 1);
+                    let rest = next.trim_start_matches([' ', '\t']);
+                    let indent = next.chars().count() - rest.chars().count();
+                    // A space goes between, unless there is one or the
+                    // next line closes a parenthesis or is blank.
+                    let space = !(current.is_empty()
+                        || current.ends_with([' ', '\t'])
+                        || rest.is_empty()
+                        || rest.starts_with(')'));
+                    col = current.chars().count();
+                    let start = self.buffer.text.line_to_char(line) + col;
+                    let end = self.buffer.text.line_to_char(line + 1) + indent;
+                    self.buffer
+                        .replace(start..end, if space { " " } else { "" });
+                }
+                self.goto(line, col);
+                self.cx_history = self.cx;
+            }
+            Action::PasteOverSelection => {
+                let Some(text) = self.registers.get(&'"').cloned() else {
+                    warn!("Nothing in register \"");
+                    return Ok(true);
+                };
+                let range = self.selection();
+                let deleted = self.buffer.text.slice(range.clone()).to_string();
+                // Lines put in place of part of one go on lines of their own.
+                let (text, cursor) = match text.ends_with('\n') {
+                    true => (format!("\n{text}"), range.start + 1),
+                    false => {
+                        let end = range.start + text.chars().count();
+                        (text, end.saturating_sub(1).max(range.start))
+                    }
+                };
+                self.buffer.replace(range, &text);
+                self.store_deleted(deleted);
+                self.goto_char(cursor);
+                return self.dispatch(Action::EnterMode(Mode::Normal));
+            }
+            Action::OlderState => {
+                let moves = self.buffer.history.older(1);
+                self.travel(moves, true);
+            }
+            Action::NewerState => {
+                let moves = self.buffer.history.newer(1);
+                self.travel(moves, false);
+            }
+            Action::PageUp => {
+                if self.vtop >= self.vheight() {
+                    self.vtop -= self.vheight();
+                } else {
+                    self.vtop = 0;
+                    self.cy = 0;
+                }
+                if self.cx_history <= self.line_length() {
+                    self.cx = self.cx_history;
+                } else if self.cx_history > self.line_length() {
+                    self.cx = self.line_length();
+                }
+            }
+            Action::EndOfLine => {
+                self.cx = self.line_length();
+                self.cx_history = self.cx;
+            }
+            Action::StartOfLine => {
+                self.cx = 0;
+                self.cx_history = self.cx;
+            }
+            Action::FirstNonBlank | Action::SmartHome => {
+                let first = self.first_non_blank(self.buffer_line()) as u16;
+                self.cx = match action {
+                    Action::SmartHome if self.cx == first => 0,
+                    _ => first,
+                };
+                self.cx_history = self.cx;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Takes the size told by the last resize event, returning whether
+    /// there was one.
+    fn take_resize(&mut self) -> bool {
+        let Some(size) = self.resized.take() else {
+            return false;
+        };
+        self.size = size;
+        true
+    }
+
+    /// Waits for the next terminal event, handling language server replies
+    /// that arrive in the meantime.
+    fn next_action(&mut self) -> anyhow::Result<Option<Action>> {
+        loop {
+            if event::poll(POLL_INTERVAL)? {
+                self.last_input = Instant::now();
+                let ev = read()?;
+                let resize = matches!(ev, Event::Resize(..));
+                let action = self.handle_event(ev)?;
+                // Nothing to draw until the resize events stop.
+                if resize {
+                    continue;
+                }
+                return Ok(action);
+            }
+            if self.flash.as_ref().is_some_and(Notice::is_over) {
+                self.flash = None;
+                return Ok(None);
+            }
+            if self.toasts.iter().any(Notice::is_over) {
+                self.toasts.retain(|toast| !toast.is_over());
+                return Ok(None);
+            }
+            // Also noticed without an event, which some terminals and
+            // multiplexers fail to send.
+            if self.take_resize() {
+                return Ok(None);
+            }
+            if terminal::size()? != self.size {
+                self.size = terminal::size()?;
+                return Ok(None);
+            }
+            let idle_lock = Duration::from_secs(self.options.idle_lock * 60);
+            if !self.locked && self.options.idle_lock > 0 && self.last_input.elapsed() >= idle_lock
+            {
+                self.locked = true;
+                return Ok(None);
+            }
+            if let Some(action) = self.poll_lsp() {
+                return Ok(Some(action));
+            }
+            if let Some(action) = self.poll_analysis() {
+                return Ok(Some(action));
+            }
+            if self.poll_diffs() || self.poll_blame() || self.poll_repo() {
+                return Ok(None);
+            }
+            if self.poll_autosave() {
+                return Ok(None);
+            }
+            if self.watcher.as_ref().is_some_and(FileWatcher::poll) {
+                if let Err(err) = self.check_disk() {
+                    error!("{err:#}");
+                }
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Writes the buffer with `autosave` set once typing paused for
+    /// `autosavedelay`, returning whether it tried to.
+    fn poll_autosave(&mut self) -> bool {
+        let delay = Duration::from_millis(self.options.autosave_delay);
+        if !self.options.autosave
+            || !self.buffer.dirty
+            || self.autosaved == Some(self.buffer.revision)
+            || self.last_input.elapsed() < delay
+        {
+            return false;
+        }
+        self.autosaved = Some(self.buffer.revision);
+        self.autosave();
+        true
+    }
+
+    /// Writes the buffer if `autosave` is set and it changed, unless it has
+    /// no file yet, lists a directory, whose edits are confirmed first, or
+    /// is being inserted into, which would split the insert's undo step.
+    fn autosave(&mut self) {
+        if !self.options.autosave
+            || !self.buffer.dirty
+            || self.buffer.path.is_empty()
+            || self.buffer.listing.is_some()
+            || matches!(self.mode, Mode::Insert)
+        {
+            return;
+        }
+        if let Err(err) = self.execute(Command::Write { force: false }) {
+            error!("{err:#}");
+        }
+    }
+
+    /// Collects finished analyzer results, and once typing has paused,
+    /// submits the current buffer for analysis if it changed.
+    fn poll_analysis(&mut self) -> Option<Action> {
+        while let Some(result) = self.analysis.poll() {
+            if result.revision != self.buffer.revision {
+                continue;
+            }
+            match result.diagnostics {
+                Result::Ok(diagnostics) => {
+                    return Some(Action::PublishDiagnostics(result.source, diagnostics));
+                }
+                Err(err) => {
+                    error!("{} failed: {err:#}", result.source);
+                }
+            }
+        }
+
+        if self.analysis.submitted != Some(self.buffer.revision)
+            && self.last_input.elapsed() >= analysis::IDLE_DELAY
+        {
+            self.analysis.submit(Snapshot {
+                path: self.buffer.path.clone(),
+                filetype: self.buffer.filetype,
+                revision: self.buffer.revision,
+                text: self.buffer.text.clone(),
+            });
+        }
+        None
+    }
+
+    /// Takes in the lines found changed in the current revision, and once
+    /// typing has paused, diffs the buffer again if it changed. Returns
+    /// whether anything new was found.
+    fn poll_diffs(&mut self) -> bool {
+        let mut changed = false;
+        while let Some(diffs) = self.differ.poll() {
+            if diffs.revision == self.buffer.revision
+                && (diffs.git != self.git_signs || diffs.modified != self.modified)
+            {
+                self.git_signs = diffs.git;
+                self.modified = diffs.modified;
+                changed = true;
+            }
+        }
+        if self.buffer.listing.is_none()
+            && self.differ.submitted != Some(self.buffer.revision)
+            && self.last_input.elapsed() >= analysis::IDLE_DELAY
+        {
+            let saved = self
+                .sign_sources()
+                .any(|source| source == Source::Modified)
+                .then(|| self.buffer.saved.clone());
+            self.differ.submit(
+                &self.buffer.path,
+                self.buffer.revision,
+                self.buffer.text.clone(),
+                saved,
+            );
+        }
+        changed
+    }
+
+    /// Takes in blames as they arrive, and once the cursor has rested on a
+    /// line, asks for its blame. Returns whether one arrived.
+    fn poll_blame(&mut self) -> bool {
+        if !self.options.blame {
+            return false;
+        }
+        let received = self.blamer.poll();
+        if self.buffer.listing.is_none() && self.last_input.elapsed() >= analysis::IDLE_DELAY {
+            let line = self.buffer_line();
+            self.blamer.request(
+                &self.buffer.path,
+                self.buffer.revision,
+                line,
+                &self.buffer.text,
+            );
+        }
+        received
+    }
+
+    /// Takes in the repository status when it was refreshed, and refreshes
+    /// it again when due. Returns whether it changed.
+    fn poll_repo(&mut self) -> bool {
+        let changed = self.repo.poll();
+        if self.buffer.listing.is_none() {
+            self.repo.refresh(&self.buffer.path);
+        }
+        changed
+    }
+
+    fn poll_lsp(&mut self) -> Option<Action> {
+        let event = match self.lsp.as_mut()?.poll() {
+            Result::Ok(event) => event?,
+            Err(err) => {
+                self.lsp = None;
+                return Some(Action::Notify(
+                    messages::Severity::Error,
+                    format!("Language server stopped: {err:#}"),
+                ));
+            }
+        };
+        self.lsp_event(event)
+    }
+
+    /// Handles a request or notification as if the language server had
+    /// sent `message`, returning `false` once the editor has quit.
+    pub fn lsp_message(&mut self, mut message: Value) -> anyhow::Result<bool> {
+        let method = message["method"]
+            .as_str()
+            .context("message without a method")?
+            .to_string();
+        let params = message["params"].take();
+        let event = match message.get("id") {
+            Some(id) => LspEvent::Request {
+                id: id.clone(),
+                method,
+                params,
+            },
+            None => LspEvent::Notification { method, params },
+        };
+        match self.lsp_event(event) {
+            Some(action) => self.dispatch(action),
+            None => Ok(true),
+        }
+    }
+
+    /// The action answering what the language server sent.
+    fn lsp_event(&mut self, event: LspEvent) -> Option<Action> {
+        match event {
+            LspEvent::Initialized => {
+                self.lsp_started();
+                None
+            }
+            LspEvent::Response {
+                method: "textDocument/hover",
+                result,
+            } => match result.map(|value| lsp::hover_text(&value)) {
+                Result::Ok(Some(text)) => Some(Action::ShowHover(text)),
+                Result::Ok(None) => {
+                    warn!("No hover information available");
+                    None
+                }
+                Err(err) => {
+                    error!("Hover failed: {err}");
+                    None
+                }
+            },
+            LspEvent::Response {
+                method: "textDocument/completion",
+                result,
+            } => match result {
+                Result::Ok(value) if matches!(self.mode, Mode::Insert) => {
+                    let (items, incomplete) = lsp::completion_items(&value, &self.buffer.text);
+                    Some(Action::ShowCompletion(items, incomplete))
+                }
+                Result::Ok(_) => None,
+                Err(err) => {
+                    error!("Completion failed: {err}");
+                    None
+                }
+            },
+            LspEvent::Response {
+                method: "textDocument/formatting",
+                result,
+            } => match result {
+                Result::Ok(edits) => Some(Action::ApplyFormatting(edits)),
+                Err(err) => {
+                    error!("Formatting failed: {err}");
+                    Some(Action::ApplyFormatting(Value::Null))
+                }
+            },
+            LspEvent::Response {
+                method: "textDocument/rename",
+                result,
+            } => match result {
+                Result::Ok(Value::Null) => {
+                    warn!("Nothing to rename");
+                    None
+                }
+                Result::Ok(edit) => Some(Action::ApplyWorkspaceEdit {
+                    edit,
+                    reply_to: None,
+                }),
+                Err(err) => {
+                    error!("Rename failed: {err}");
+                    None
+                }
+            },
+            LspEvent::Response {
+                method: "textDocument/codeAction",
+                result,
+            } => match result {
+                Result::Ok(value) => Some(Action::ShowCodeActions(
+                    value.as_array().cloned().unwrap_or_default(),
+                )),
+                Err(err) => {
+                    error!("Code actions failed: {err}");
+                    None
+                }
+            },
+            LspEvent::Response {
+                method: "codeAction/resolve",
+                result,
+            } => match result {
+                Result::Ok(action) => Some(Action::CodeActionChosen {
+                    action,
+                    resolved: true,
+                }),
+                Err(err) => {
+                    error!("Code action failed: {err}");
+                    None
+                }
+            },
+            LspEvent::Response {
+                method: "textDocument/documentSymbol",
+                result,
+            } => match result {
+                Result::Ok(value) => Some(Action::ShowSymbols(lsp::document_symbols(
+                    &value,
+                    &self.buffer.text,
+                ))),
+                Err(err) => {
+                    error!("Symbols failed: {err}");
+                    None
+                }
+            },
+            LspEvent::Response {
+                method: "workspace/executeCommand",
+                result: Err(err),
+            } => {
+                error!("Command failed: {err}");
+                None
+            }
+            LspEvent::Request { id, method, params } if method == "workspace/applyEdit" => {
+                Some(Action::ApplyWorkspaceEdit {
+                    edit: params["edit"].clone(),
+                    reply_to: Some(id),
+                })
+            }
+            LspEvent::Notification { method, params }
+                if method == "textDocument/publishDiagnostics" =>
+            {
+                let path = std::path::absolute(&self.buffer.path).ok()?;
+                let uri = lsp::path_to_uri(&path);
+                let diagnostics = lsp::diagnostics(&params, &uri, &self.buffer.text)?;
+                Some(Action::PublishDiagnostics("lsp".to_string(), diagnostics))
+            }
+            LspEvent::Notification { method, params } if method == "window/showMessage" => {
+                let message = params["message"].as_str().unwrap_or_default();
+                match params["type"].as_u64() {
+                    Some(1) => {
+                        error!("Language server: {message}");
+                    }
+                    Some(2) => {
+                        warn!("Language server: {message}");
+                    }
+                    _ => {
+                        log!("Language server: {message}");
+                    }
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Column of the first char of `line` that is not blank.
+    fn first_non_blank(&self, line: usize) -> usize {
+        self.buffer
+            .line_text(line)
+            .chars()
+            .take_while(|c| matches!(c, ' ' | '\t'))
+            .count()
+    }
+
+    /// Column where the word ending at the cursor starts.
+    fn word_start(&self) -> usize {
+        let line = self.buffer.line_text(self.buffer_line());
+        let before: Vec<char> = line.chars().take(self.cx as usize).collect();
+        let word_len = before
+            .iter()
+            .rev()
+            .take_while(|c| c.is_alphanumeric() || **c == '_')
+            .count();
+        before.len() - word_len
+    }
+
+    /// Column `Ctrl-w`, or `Ctrl-u` with `line`, deletes back to: the start
+    /// of the word or run of punctuation before the cursor, blanks after it
+    /// included, or of the line. What was typed in this insert goes first,
+    /// when it starts after that.
+    fn delete_back_start(&self, line: bool) -> usize {
+        let text = self.buffer.line_text(self.buffer_line());
+        let before: Vec<char> = text.chars().take(self.cx as usize).collect();
+        let start = match line {
+            true => 0,
+            false => {
+                let is_word = |c: char| c.is_alphanumeric() || c == '_';
+                let mut start = before.len();
+                while start > 0 && before[start - 1].is_whitespace() {
+                    start -= 1;
+                }
+                let word = start > 0 && is_word(before[start - 1]);
+                while start > 0
+                    && !before[start - 1].is_whitespace()
+                    && is_word(before[start - 1]) == word
+                {
+                    start -= 1;
+                }
+                start
+            }
+        };
+        let line_start = self.cursor_char() - before.len();
+        match self.insert_start.checked_sub(line_start) {
+            Some(typed) if start < typed && typed < before.len() => typed,
+            _ => start,
+        }
+    }
+
+    /// The chars right before and after the cursor on its line.
+    fn chars_around_cursor(&self) -> (Option<char>, Option<char>) {
+        let line: Vec<char> = self.buffer.line_text(self.buffer_line()).chars().collect();
+        let cx = self.cx as usize;
+        let before = cx.checked_sub(1).and_then(|i| line.get(i)).copied();
+        (before, line.get(cx).copied())
+    }
+
+    /// The part of the word before the cursor that has been typed so far.
+    fn typed_word(&self) -> String {
+        let line = self.buffer.line_text(self.buffer_line());
+        line.chars()
+            .skip(self.word_start())
+            .take(self.cx as usize - self.word_start())
+            .collect()
+    }
+
+    fn request_completion(&mut self, trigger: Option<char>) {
+        let (line, col) = (self.buffer_line(), self.cx as usize);
+        self.lsp_request(|lsp, buffer| lsp.completion(buffer, line, col, trigger));
+    }
+
+    /// Sends a request to the language server, if there is one. A server
+    /// that cannot be written to has most likely stopped, and is dropped
+    /// so that editing goes on without it.
+    fn lsp_request(&mut self, request: impl FnOnce(&mut LspClient, &Buffer) -> anyhow::Result<()>) {
+        let Some(lsp) = self.lsp.as_mut() else {
+            return;
+        };
+        if let Err(err) = request(lsp, &self.buffer) {
+            self.lsp = None;
+            self.notify(
+                messages::Severity::Error,
+                format!("Language server stopped: {err:#}"),
+            );
+        }
+    }
+
+    /// Refilters the open completion menu after the typed word changed, or
+    /// asks the language server for candidates when typing calls for it.
+    fn update_completion(&mut self, typed: Option<char>) -> anyhow::Result<()> {
+        let line = self.buffer_line();
+        let start_col = self.word_start();
+        let word = self.typed_word();
+
+        if let Some(menu) = self.completion.as_mut() {
+            if menu.line == line && menu.start_col == start_col && !word.is_empty() {
+                menu.filter(&word);
+                let incomplete = menu.incomplete;
+                if menu.is_empty() {
+                    self.completion = None;
+                }
+                if !incomplete {
+                    return Ok(());
+                }
+            } else {
+                self.completion = None;
+            }
+        }
+
+        let Some(c) = typed else {
+            return Ok(());
+        };
+        let Some(lsp) = self.lsp.as_ref() else {
+            return Ok(());
+        };
+        if lsp.trigger_characters.contains(&c) {
+            self.request_completion(Some(c));
+        } else if c.is_alphanumeric() || c == '_' {
+            self.request_completion(None);
+        }
+        Ok(())
+    }
+
+    /// Applies the selected candidate's edit along with its additional
+    /// edits, leaving the cursor after the completed text.
+    fn accept_completion(&mut self) {
+        let Some(menu) = self.completion.take() else {
+            return;
+        };
+        let (line, start_col) = (menu.line, menu.start_col);
+        let Some(item) = menu.take_selected() else {
+            return;
+        };
+
+        let line_start = self.buffer.text.line_to_char(line);
+        let cursor = line_start + self.cx as usize;
+        // Characters typed after the request was sent are replaced as well.
+        let range = match item.range {
+            Some(range) => range.start..range.end.max(cursor),
+            None => line_start + start_col..cursor,
+        };
+
+        // Snippets are expanded once the other edits are in place.
+        let (new_text, snippet) = match item.snippet {
+            true => (String::new(), Some(item.new_text)),
+            false => (item.new_text, None),
+        };
+        let mut cursor_after = (range.start + new_text.chars().count()) as isize;
+        let mut edits = item.additional_edits;
+        for (edit_range, text) in &edits {
+            if edit_range.start < range.start {
+                cursor_after += text.chars().count() as isize - edit_range.len() as isize;
+            }
+        }
+        edits.push((range, new_text));
+        edits.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+        for (range, text) in edits {
+            self.buffer.replace(range, &text);
+        }
+
+        let cursor_after = cursor_after.max(0) as usize;
+        match snippet {
+            Some(body) => self.insert_snippet(cursor_after..cursor_after, &body),
+            None => {
+                let (line, col) = self.buffer.char_to_pos(cursor_after);
+                self.goto(line, col);
+            }
+        }
+    }
+
+    /// The start column and body of the snippet whose prefix ends at the
+    /// cursor, either the whole word before it or its trailing keyword.
+    fn snippet_trigger(&self) -> Option<(usize, String)> {
+        let before: Vec<char> = self
+            .buffer
+            .line_text(self.buffer_line())
+            .chars()
+            .take(self.cx as usize)
+            .collect();
+        let word_start = before
+            .iter()
+            .rposition(|c| c.is_whitespace())
+            .map_or(0, |i| i + 1);
+        [word_start, self.word_start()]
+            .into_iter()
+            .filter(|&start| start < before.len())
+            .find_map(|start| {
+                let typed: String = before[start..].iter().collect();
+                let snippet = self.snippets.iter().find(|s| s.prefix == typed)?;
+                Some((start, snippet.body.clone()))
+            })
+    }
+
+    /// Replaces `range` with the expansion of a snippet body and selects
+    /// its first tab stop.
+    fn insert_snippet(&mut self, range: Range<usize>, body: &str) {
+        let line = self.buffer.char_to_pos(range.start).0;
+        let indent: String = self
+            .buffer
+            .line_text(line)
+            .chars()
+            .take_while(|c| c.is_whitespace())
+            .collect();
+        let expansion = snippets::expand(body, &indent, &|name| self.snippet_variable(name));
+
+        self.buffer.replace(range.clone(), &expansion.text);
+        self.buffer.take_edits();
+        self.snippet = Some(Session::new(range.start, expansion));
+        self.select_tab_stop();
+    }
+
+    /// Values of the variables snippets can refer to.
+    fn snippet_variable(&self, name: &str) -> Option<String> {
+        let path = Path::new(&self.buffer.path);
+        let line = self.buffer_line();
+        let absolute = || fs::canonicalize(path).ok();
+        Some(match name {
+            "TM_FILENAME" => path.file_name()?.to_string_lossy().into_owned(),
+            "TM_FILENAME_BASE" => path.file_stem()?.to_string_lossy().into_owned(),
+            "TM_DIRECTORY" => absolute()?.parent()?.to_string_lossy().into_owned(),
+            "TM_FILEPATH" => absolute()?.to_string_lossy().into_owned(),
+            "TM_LINE_INDEX" => line.to_string(),
+            "TM_LINE_NUMBER" => (line + 1).to_string(),
+            "TM_CURRENT_LINE" => self.buffer.line_text(line),
+            _ => return None,
+        })
+    }
+
+    /// Moves the cursor to the current tab stop, selecting its placeholder
+    /// and offering its choices. The final tab stop ends the snippet.
+    fn select_tab_stop(&mut self) {
+        let Some(session) = self.snippet.as_mut() else {
+            return;
+        };
+        let range = session.range();
+        let choices = session.current().choices.clone();
+        let last = session.is_last();
+        session.selected = !range.is_empty() && !last;
+
+        let (line, col) = self.buffer.char_to_pos(range.start);
+        self.goto(line, col);
+        self.cx_history = self.cx;
+        if last {
+            self.snippet = None;
+        } else if !choices.is_empty() {
+            let items = choices
+                .into_iter()
+                .map(|choice| CompletionItem {
+                    label: choice.clone(),
+                    detail: String::new(),
+                    filter_text: choice.clone(),
+                    new_text: choice,
+                    range: Some(range.clone()),
+                    additional_edits: Vec::new(),
+                    snippet: false,
+                })
+                .collect();
+            self.completion = Some(CompletionMenu::new(items, false, line, col));
+        }
+    }
+
+    /// Deletes the selected placeholder, which is what typing replaces.
+    fn take_placeholder(&mut self) -> bool {
+        let Some(session) = self.snippet.as_mut().filter(|session| session.selected) else {
+            return false;
+        };
+        session.selected = false;
+        let range = session.range();
+        self.buffer.replace(range.clone(), "");
+        let (line, col) = self.buffer.char_to_pos(range.start);
+        self.goto(line, col);
+        true
+    }
+
+    /// Ends the undo step of the action just carried out, unless it is
+    /// part of an insert still going on.
+    fn end_undo_step(&mut self) {
+        if !matches!(self.mode, Mode::Insert) {
+            self.buffer.history.end_step();
+        }
+    }
+
+    /// Follows the edits just made with the snippet being filled in,
+    /// copying whichever occurrence of the placeholder was edited to the
+    /// others. Edits elsewhere or leaving insert mode end the snippet.
+    fn track_snippet(&mut self) {
+        let edits = self.buffer.take_edits();
+        if self.snippet.is_none() {
+            return;
+        }
+        let before = self.buffer.text.line_to_char(self.buffer_line()) + self.cx as usize;
+        let Some(session) = self.snippet.as_mut() else {
+            return;
+        };
+        if !matches!(self.mode, Mode::Insert)
+            || !edits
+                .into_iter()
+                .all(|(range, inserted)| session.edit(range, inserted))
+        {
+            self.snippet = None;
+            return;
+        }
+
+        // The cursor follows mirrors changing length before it.
+        let mut cursor = before;
+        let text = self.buffer.text.slice(session.range()).to_string();
+        let len = text.chars().count();
+        for i in 0..session.linked().len() {
+            let range = session.linked()[i].clone();
+            if self.buffer.text.slice(range.clone()) != text.as_str() {
+                if range.end <= cursor {
+                    cursor = cursor + len - range.len();
+                }
+                self.buffer.replace(range, &text);
+                session.mirror(i, len);
+            }
+        }
+        self.buffer.take_edits();
+        if cursor != before {
+            let (line, col) = self.buffer.char_to_pos(cursor);
+            self.goto(line, col);
+        }
+    }
+
+    fn execute(&mut self, command: Command) -> anyhow::Result<()> {
+        match command {
+            Command::Quit(_) => {}
+            Command::Reopen => self.reopen_window()?,
+            Command::ActionLog(path) => {
+                let lines = self.actions.lines(&self.buffer.path, self.size);
+                match path {
+                    Some(path) => fs::write(&path, lines.join("\n") + "\n")
+                        .with_context(|| format!("Cannot write {path}"))?,
+                    None => {
+                        let mut pager = Pager::new("actionlog");
+                        for line in lines {
+                            pager.push(line);
+                        }
+                        self.pager = Some(pager);
+                    }
+                }
+            }
+            Command::Changes => self.show_changes(),
+            Command::Registers(names) => self.show_registers(&names),
+            Command::Marks(names) => self.show_marks(&names),
+            Command::Oldfiles => {
+                let mut pager = Pager::new("oldfiles");
+                for (i, path) in self.state.oldfiles().enumerate() {
+                    let action = Action::EditFile {
+                        path: path.to_string(),
+                        force: false,
+                    };
+                    pager.push_target(format!("{}: {path}", i + 1), action);
+                }
+                self.pager = Some(pager);
+            }
+            Command::Write { force } => {
+                self.write(force)?;
+                if !self.buffer.dirty {
+                    self.write_undo_file();
+                    self.emit("write", "");
+                }
+            }
+            Command::User { name, args, range } => {
+                self.run_user_command(&name, &args, range)?;
+            }
+            Command::Define {
+                name,
+                command,
+                force,
+            } => {
+                if self.user_commands.contains_key(&name) && !force {
+                    anyhow::bail!("Command already exists: {name} (add ! to replace)");
+                }
+                self.user_commands.insert(name, command);
+            }
+            Command::Undefine(name) => {
+                if self.user_commands.remove(&name).is_none() {
+                    anyhow::bail!("No such user-defined command: {name}");
+                }
+            }
+            Command::ListCommands => {
+                let mut pager = Pager::new("commands");
+                pager.push(format!(
+                    "{:<12} {:<4} {:<5} {:<12} Definition",
+                    "Name", "Args", "Range", "Complete"
+                ));
+                for (name, command) in &self.user_commands {
+                    pager.push(command.describe(name));
+                }
+                self.pager = Some(pager);
+            }
+            Command::Trust { persist } => self.trust_workspace(persist)?,
+            Command::MakeSession { path, force } => self.make_session(path, force)?,
+            Command::Source(path) => {
+                let script =
+                    fs::read_to_string(&path).with_context(|| format!("Cannot read {path}"))?;
+                self.source(&path, &script);
+            }
+            Command::DiffSplit(path) => self.diff_with(Buffer::open(&path)?),
+            Command::OtherWindow => self.switch_window()?,
+            Command::Cursor { line, col } => {
+                self.goto(line.saturating_sub(1), col.saturating_sub(1));
+                self.cx_history = self.cx;
+            }
+            Command::Edit { path, force } => {
+                self.autosave();
+                if self.buffer.dirty && !force {
+                    anyhow::bail!("No write since last change (add ! to override)");
+                }
+                self.open(&path)?;
+            }
+            Command::Rename(new_name) => {
+                let (line, col) = (self.buffer_line(), self.cx as usize);
+                self.lsp.as_ref().context("No language server")?;
+                self.lsp_request(|lsp, buffer| lsp.rename(buffer, line, col, &new_name));
+            }
+            Command::CodeAction => {
+                let line = self.buffer_line();
+                self.lsp.as_ref().context("No language server")?;
+                self.lsp_request(|lsp, buffer| lsp.code_action(buffer, line));
+            }
+            Command::Format => self.format(false)?,
+            Command::Files => self.pick_files(),
+            Command::Palette => self.pick_command(),
+            Command::Lock => self.locked = true,
+            Command::StageHunk => {
+                let (hunk, index) = self.hunk_at_cursor()?;
+                let index: Vec<&str> = index.split_inclusive('\n').collect();
+                let mut staged = index[..hunk.old.start].concat();
+                for line in hunk.new.clone() {
+                    staged.extend(self.buffer.text.line(line).chunks());
+                }
+                staged.push_str(&index[hunk.old.end..].concat());
+                git::stage(&self.buffer.path, &staged)?;
+                self.repo.invalidate();
+                self.git_signs = Some(git::signs(&staged, &self.buffer.text.to_string()));
+            }
+            Command::ResetHunk => {
+                let (hunk, index) = self.hunk_at_cursor()?;
+                let index: Vec<&str> = index.split_inclusive('\n').collect();
+                let start = self.buffer.text.line_to_char(hunk.new.start);
+                let end = self.buffer.text.line_to_char(hunk.new.end);
+                self.buffer
+                    .replace(start..end, &index[hunk.old.clone()].concat());
+                self.goto(git::hunk_line(&hunk), 0);
+                self.git_hunks()?;
+            }
+            Command::Resolve(resolution) => self.resolve_conflict(resolution)?,
+            Command::Symbols => {
+                self.lsp.as_ref().context("No language server")?;
+                self.lsp_request(|lsp, buffer| lsp.document_symbols(buffer));
+            }
+            Command::Redir(redirect) => self.redirect(redirect)?,
+            Command::Messages(severity) => {
+                let mut pager = Pager::new("messages");
+                for message in messages::show(severity) {
+                    pager.push(message.text);
+                }
+                self.pager = Some(pager);
+            }
+            Command::ClearMessages => messages::clear(),
+            Command::Notify(text) => self.notify(messages::Severity::Info, text),
+            Command::CheckTime => self.check_disk()?,
+            Command::FiletypeInfo => self.show_filetype_info(),
+            Command::Print(range) => {
+                let (first, end) = self.range_or_line(range)?;
+                let mut pager = Pager::new("print");
+                for line in first..=end {
+                    pager.push(self.buffer.line_text(line));
+                }
+                self.pager = Some(pager);
+            }
+            Command::Delete(range) => {
+                let (first, end) = self.range_or_line(range)?;
+                self.delete_lines(first, end);
+            }
+            Command::Goto(range) => {
+                let (_, line) = self.range_or_line(range)?;
+                let col = self.first_non_blank(line);
+                self.goto(line, col);
+                self.cx_history = self.cx;
+            }
+            Command::Normal {
+                range,
+                keys,
+                noremap,
+            } => self.normal(range, &keys, noremap)?,
+            Command::Sort { range, sort } => {
+                let (first, end) = match range {
+                    Some(range) => self.resolve_range(range)?,
+                    None => (0, self.last_line()),
+                };
+                let lines = (first..=end).map(|l| self.buffer.line_text(l)).collect();
+                self.replace_lines(first, end, sort.apply(lines));
+                self.goto(first.min(self.last_line()), 0);
+                self.cx_history = self.cx;
+            }
+            Command::MoveLines { range, to, copy } => {
+                let (first, end) = self.range_or_line(range)?;
+                let to = match to {
+                    Some(to) => {
+                        let marks = &self.buffer.marks;
+                        let to = to.resolve(self.buffer_line(), self.last_line(), |name| {
+                            marks.get(&name).map(|&(line, _)| line)
+                        })?;
+                        if to > self.last_line() {
+                            anyhow::bail!("Invalid address");
+                        }
+                        Some(to)
+                    }
+                    None => None,
+                };
+                self.move_lines(first, end, to, copy)?;
+            }
+            Command::Yank(range) => {
+                let (first, end) = self.range_or_line(range)?;
+                let text = &self.buffer.text;
+                let lines = text.slice(text.line_to_char(first)..text.line_to_char(end + 1));
+                let lines = format!("{}\n", lines.to_string().trim_end_matches('\n'));
+                self.registers.insert('0', lines.clone());
+                self.registers.insert('"', lines);
+            }
+            Command::Global {
+                range,
+                pattern,
+                invert,
+                command,
+            } => self.global(range, &pattern, invert, &command)?,
+            Command::Undo => {
+                self.dispatch(Action::Undo)?;
+            }
+            Command::Redo => {
+                self.dispatch(Action::Redo)?;
+            }
+            Command::NoHlSearch => self.search_shown = false,
+            Command::Earlier(travel) => {
+                let moves = match travel {
+                    Travel::States(count) => self.buffer.history.older(count),
+                    Travel::Seconds(secs) => self.buffer.history.earlier(secs),
+                };
+                self.travel(moves, true);
+            }
+            Command::Later(travel) => {
+                let moves = match travel {
+                    Travel::States(count) => self.buffer.history.newer(count),
+                    Travel::Seconds(secs) => self.buffer.history.later(secs),
+                };
+                self.travel(moves, false);
+            }
+            Command::Put(name) => {
+                let text = self
+                    .register(name)
+                    .with_context(|| format!("Nothing in register {name}"))?;
+                let text = text.trim_end_matches('\n');
+                let line = self.buffer_line() + 1;
+                // The last line has no line break to insert after.
+                let (at, text) = match line < self.buffer.len() {
+                    true => (self.buffer.text.line_to_char(line), format!("{text}\n")),
+                    false => (self.buffer.text.len_chars(), format!("\n{text}")),
+                };
+                self.buffer.replace(at..at, &text);
+                self.goto(line, 0);
+                self.cx_history = self.cx;
+            }
+            Command::Set(args) if args == ["all"] => {
+                let mut pager = Pager::new("options");
+                for option in self.options.all() {
+                    pager.push(option);
+                }
+                self.pager = Some(pager);
+            }
+            Command::Set(args) if args.iter().any(|arg| arg.ends_with('?')) => {
+                let mut pager = Pager::new("options");
+                for arg in args {
+                    let (value, origin) = self.options.show(arg.trim_end_matches('?'))?;
+                    pager.push(value);
+                    if let Some(origin) = origin.filter(|_| self.verbose) {
+                        pager.push(format!("\t{}", origin.describe()));
+                    }
+                }
+                self.pager = Some(pager);
+            }
+            Command::Set(args) => {
+                for arg in args {
+                    self.options.set(&arg, self.origin.clone())?;
+                }
+                self.update_segments();
+            }
+            Command::Verbose(command) => {
+                self.verbose = true;
+                let result = self.execute(*command);
+                self.verbose = false;
+                result?;
+            }
+            Command::Map(key) => {
+                let key = match key {
+                    Some(key) => Some(
+                        keys::parse_keys(&self.expand_leader(&key))?
+                            .iter()
+                            .map(keys::notation)
+                            .collect::<Option<String>>()
+                            .unwrap_or(key),
+                    ),
+                    None => None,
+                };
+                let lua = self
+                    .plugins
+                    .mappings()
+                    .into_iter()
+                    .map(|(mode, lhs, origin)| (mode, lhs, "<Lua function>".to_string(), origin));
+                let keys = self.key_maps.iter().map(|((mode, lhs), (rhs, origin))| {
+                    (*mode, lhs.clone(), rhs.clone(), origin.clone())
+                });
+                let mut mappings: Vec<(MapMode, String, String, Origin)> = lua
+                    .chain(keys)
+                    .filter(|(_, lhs, _, _)| key.as_ref().is_none_or(|key| key == lhs))
+                    .collect();
+                if mappings.is_empty() {
+                    anyhow::bail!("No mapping found");
+                }
+                mappings.sort_by(|a, b| (&a.1, a.0).cmp(&(&b.1, b.0)));
+                let mut pager = Pager::new("mappings");
+                for (mode, lhs, rhs, origin) in mappings {
+                    pager.push(format!("{}  {lhs:<12}{rhs}", mode.letter()));
+                    if self.verbose {
+                        pager.push(format!("\t{}", origin.describe()));
+                    }
+                }
+                self.pager = Some(pager);
+            }
+            Command::MapKeys { modes, lhs, rhs } => {
+                let lhs = keys::parse_keys(&self.expand_leader(&lhs))?
+                    .iter()
+                    .map(keys::notation)
+                    .collect::<Option<String>>()
+                    .with_context(|| format!("Cannot map {lhs}"))?;
+                let rhs = self.expand_leader(&rhs);
+                keys::parse_keys(&rhs)?;
+                for mode in modes {
+                    self.key_maps
+                        .insert((mode, lhs.clone()), (rhs.clone(), self.origin.clone()));
+                }
+            }
+            Command::Let { name, expr } => {
+                let value = vimscript::eval(&expr, &|name| self.variable(name))?;
+                match name.strip_prefix('&') {
+                    Some(option) => {
+                        let arg = match self.options.show(option)?.0.contains('=') {
+                            true => format!("{option}={value}"),
+                            false if value.is_true() => option.to_string(),
+                            false => format!("no{option}"),
+                        };
+                        self.options.set(&arg, self.origin.clone())?;
+                        self.update_segments();
+                    }
+                    None => {
+                        let name = name.strip_prefix("g:").unwrap_or(&name);
+                        if !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                            anyhow::bail!("Invalid variable name: {name}");
+                        }
+                        self.variables.insert(name.to_string(), value);
+                    }
+                }
+            }
+            Command::Unlet(name) => {
+                if self
+                    .variables
+                    .remove(name.strip_prefix("g:").unwrap_or(&name))
+                    .is_none()
+                {
+                    anyhow::bail!("No such variable: {name}");
+                }
+            }
+            Command::Echo(expr) => {
+                let value = vimscript::eval(&expr, &|name| self.variable(name))?;
+                log!("{value}");
+                self.capture(&value.to_string());
+            }
+            Command::Autocmd { autocmd, replace } => {
+                if replace {
+                    self.autocmds.retain(|(other, _)| {
+                        other.pattern != autocmd.pattern
+                            || !other
+                                .events
+                                .iter()
+                                .any(|event| autocmd.events.contains(event))
+                    });
+                } else if autocmd.command.is_empty() {
+                    anyhow::bail!("Argument required");
+                }
+                if !autocmd.command.is_empty() {
+                    self.autocmds.push((autocmd, self.origin.clone()));
+                }
+            }
+            Command::ListAutocmds => {
+                let mut pager = Pager::new("autocommands");
+                for (autocmd, origin) in &self.autocmds {
+                    pager.push(autocmd.describe());
+                    if self.verbose {
+                        pager.push(format!("\t{}", origin.describe()));
+                    }
+                }
+                self.pager = Some(pager);
+            }
+            Command::ClearAutocmds => self.autocmds.clear(),
+        }
+        Ok(())
+    }
+
+    /// The hunks the buffer differs from the git index in and the staged
+    /// text, refreshing the signs as the index may have changed since.
+    fn git_hunks(&mut self) -> anyhow::Result<(Vec<diff::Hunk>, String)> {
+        let index = git::index_text(&self.buffer.path).context("Not tracked by git")?;
+        let text = self.buffer.text.to_string();
+        self.git_signs = Some(git::signs(&index, &text));
+        Ok((git::hunks(&index, &text), index))
+    }
+
+    fn hunk_at_cursor(&mut self) -> anyhow::Result<(diff::Hunk, String)> {
+        let line = self.buffer_line();
+        let (hunks, index) = self.git_hunks()?;
+        let hunk = hunks
+            .into_iter()
+            .find(|hunk| hunk.new.contains(&line) || git::hunk_line(hunk) == line)
+            .context("No hunk at the cursor")?;
+        Ok((hunk, index))
+    }
+
+    /// Sends the output of the ex commands that follow to `redirect`, until
+    /// `:redir END`.
+    fn redirect(&mut self, redirect: Redirect) -> anyhow::Result<()> {
+        if let Some(Redirection::Register { name, text }) = self.redirection.take() {
+            let register = self.registers.entry(name.to_ascii_lowercase()).or_default();
+            if name.is_ascii_uppercase() {
+                register.push_str(&text);
+            } else {
+                *register = text;
+            }
+        }
+        self.redirection = match redirect {
+            Redirect::End => None,
+            Redirect::Register(name) => Some(Redirection::Register {
+                name,
+                text: String::new(),
+            }),
+            Redirect::File {
+                path,
+                append,
+                force,
+            } => {
+                let mut options = OpenOptions::new();
+                match (append, force) {
+                    (true, _) => options.append(true).create(true),
+                    (false, true) => options.write(true).create(true).truncate(true),
+                    (false, false) => options.write(true).create_new(true),
+                };
+                let file = options.open(&path).map_err(|err| match err.kind() {
+                    std::io::ErrorKind::AlreadyExists => {
+                        anyhow::anyhow!("File exists (add ! to override): {path}")
+                    }
+                    _ => anyhow::Error::new(err).context(path),
+                })?;
+                Some(Redirection::File(file))
+            }
+        };
+        Ok(())
+    }
+
+    /// Adds the output of an ex command to where `:redir` sends it.
+    fn capture(&mut self, output: &str) {
+        match &mut self.redirection {
+            None => {}
+            Some(Redirection::Register { text, .. }) => {
+                text.push_str(output);
+                text.push('\n');
+            }
+            Some(Redirection::File(file)) => {
+                if let Err(err) = writeln!(file, "{output}") {
+                    error!("Redirection failed: {err}");
+                    self.redirection = None;
+                }
+            }
+        }
+    }
+
+    /// The merge conflicts in the buffer, found again once it changed.
+    fn conflicts(&mut self) -> &[Conflict] {
+        let revision = self.buffer.revision;
+        if self
+            .conflicts
+            .as_ref()
+            .is_none_or(|(found, _)| *found != revision)
+        {
+            self.conflicts = Some((revision, conflicts::find(&self.buffer.text)));
+        }
+        self.conflicts
+            .as_ref()
+            .map_or(&[], |(_, conflicts)| conflicts)
+    }
+
+    /// Replaces the conflict at the cursor with the side or sides kept.
+    fn resolve_conflict(&mut self, resolution: Resolution) -> anyhow::Result<()> {
+        let line = self.buffer_line();
+        let conflict = self
+            .conflicts()
+            .iter()
+            .find(|conflict| conflict.lines().contains(&line))
+            .cloned()
+            .context("No conflict at the cursor")?;
+        let text = &self.buffer.text;
+        let line_start = |line: usize| text.line_to_char(line.min(text.len_lines()));
+        let kept: String = conflict
+            .kept(resolution)
+            .into_iter()
+            .map(|lines| {
+                text.slice(line_start(lines.start)..line_start(lines.end))
+                    .to_string()
+            })
+            .collect();
+        let lines = conflict.lines();
+        let range = line_start(lines.start)..line_start(lines.end);
+        self.buffer.replace(range, &kept);
+        self.goto(conflict.start, 0);
+        self.cx_history = self.cx;
+        Ok(())
+    }
+
+    /// Replaces the current buffer with the file or directory at `path`.
+    fn open(&mut self, path: &str) -> anyhow::Result<()> {
+        let buffer = Buffer::open(path)?;
+        if self.buffer.listing.is_none() {
+            self.state.remember(&self.buffer.path, &self.buffer.marks);
+        }
+        self.buffer = buffer;
+        self.recall_marks();
+        self.buffer_replaced();
+        self.vtop = 0;
+        self.cx = 0;
+        self.cy = 0;
+        self.read_undo_file();
+        if !self.headless {
+            self.offer_trust();
+        }
+        self.emit("open", "");
+        Ok(())
+    }
+
+    /// The last line of the buffer, not counting the empty one after a
+    /// final line break.
+    fn last_line(&self) -> usize {
+        let last = self.buffer.len().saturating_sub(1);
+        match self.buffer.line_text(last).is_empty() {
+            true if last > 0 => last - 1,
+            _ => last,
+        }
+    }
+
+    /// Runs the plugin script `source`, named `name` in errors.
+    pub fn load_plugin(&mut self, name: &str, source: &str) -> anyhow::Result<()> {
+        let outcome = self.plugins.load(name, source, self.plugin_view())?;
+        self.apply_plugin(outcome)?;
+        Ok(())
+    }
+
+    /// Instantiates the WebAssembly plugin in `bytes`, named `name` in
+    /// errors.
+    pub fn load_wasm_plugin(&mut self, name: &str, bytes: &[u8]) -> anyhow::Result<()> {
+        let outcome = self.wasm.load(name, bytes, self.plugin_view())?;
+        self.apply_plugin(outcome)?;
+        Ok(())
+    }
+
+    /// The editor as plugins see it.
+    fn plugin_view(&self) -> plugins::View {
+        plugins::View {
+            path: self.buffer.path.clone(),
+            lines: (0..=self.last_line())
+                .map(|line| self.buffer.line_text(line))
+                .collect(),
+            cursor: self.cursor(),
+            mode: self.mode_name().to_lowercase(),
+        }
+    }
+
+    /// Applies what a plugin changed, returning `false` if it quit.
+    fn apply_plugin(&mut self, outcome: plugins::Outcome) -> anyhow::Result<bool> {
+        let (mut line, mut col) = self.cursor();
+        if let Some(lines) = outcome.lines {
+            let mut text = lines.join("\n");
+            if self.buffer.text.to_string().ends_with('\n') {
+                text.push('\n');
+            }
+            let hunks = self.buffer.rewrite(&text);
+            line = diff::map_line(&hunks, line);
+        }
+        if let Some(cursor) = outcome.cursor {
+            (line, col) = cursor;
+        }
+        self.goto(line, col);
+        self.cx_history = self.cx;
+        let nested = std::mem::replace(&mut self.in_plugin, true);
+        let mut running = true;
+        for (input, origin) in outcome.commands {
+            let outer = std::mem::replace(&mut self.origin, origin);
+            let result = match Command::parse(&input) {
+                Result::Ok(Command::Quit(quit)) => match self.quit(quit) {
+                    Result::Ok(true) => Ok(()),
+                    Result::Ok(false) => {
+                        self.origin = outer;
+                        running = false;
+                        break;
+                    }
+                    Err(err) => Err(err),
+                },
+                Result::Ok(command) => self.execute(command),
+                Err(err) => Err(err),
+            };
+            self.origin = outer;
+            if let Err(err) = result {
+                error!("{err:#}");
+            }
+        }
+        self.in_plugin = nested;
+        Ok(running)
+    }
+
+    /// Runs a command defined with `:command` or by a plugin, returning
+    /// `false` if it quit.
+    fn run_user_command(
+        &mut self,
+        name: &str,
+        args: &str,
+        range: Option<LineRange>,
+    ) -> anyhow::Result<bool> {
+        if let Some(command) = self.user_commands.get(name).cloned() {
+            if self.command_depth >= MAX_COMMAND_DEPTH {
+                anyhow::bail!("Command nested too deeply: {name}");
+            }
+            self.command_depth += 1;
+            let result = self.run_defined_command(&command, args, range);
+            self.command_depth -= 1;
+            return result;
+        }
+        if range.is_some() {
+            anyhow::bail!("No range allowed");
+        }
+        let view = self.plugin_view();
+        let outcome = if self.wasm.has_command(name) {
+            self.wasm.run_command(name, args, view)?
+        } else {
+            self.plugins.run_command(name, args, view)?
+        };
+        self.apply_plugin(outcome)
+    }
+
+    fn run_defined_command(
+        &mut self,
+        command: &UserCommand,
+        args: &str,
+        range: Option<LineRange>,
+    ) -> anyhow::Result<bool> {
+        let (line, last) = (self.buffer_line(), self.last_line());
+        let (first, end) = match (range, command.range) {
+            (Some(_), None) => anyhow::bail!("No range allowed"),
+            (Some(range), Some(_)) => self.resolve_range(range)?,
+            (None, Some(DefaultRange::WholeBuffer)) => (0, last),
+            (None, _) => (line, line),
+        };
+        let input = command.expand(args, (first + 1, end + 1))?;
+        match Command::parse(&input)? {
+            Command::Quit(quit) => self.quit(quit),
+            Command::User { name, args, range } => self.run_user_command(&name, &args, range),
+            command => self.execute(command).map(|()| true),
+        }
+    }
+
+    /// The lines from 0 of `range`, with the marks set in the buffer.
+    fn resolve_range(&self, range: LineRange) -> anyhow::Result<(usize, usize)> {
+        let marks = &self.buffer.marks;
+        range.resolve(self.buffer_line(), self.last_line(), |name| {
+            marks.get(&name).map(|&(line, _)| line)
+        })
+    }
+
+    /// The lines from 0 of `range`, or else the cursor line.
+    fn range_or_line(&self, range: Option<LineRange>) -> anyhow::Result<(usize, usize)> {
+        match range {
+            Some(range) => self.resolve_range(range),
+            None => Ok((self.buffer_line(), self.buffer_line())),
+        }
+    }
+
+    /// Deletes the lines from `first` to `end`, keeping them in the unnamed
+    /// register, and moves to the line after them.
+    fn delete_lines(&mut self, first: usize, end: usize) {
+        let text = &self.buffer.text;
+        let mut range = text.line_to_char(first)..text.line_to_char(end + 1);
+        // The last line has no line break of its own to take along.
+        if end + 1 >= self.buffer.len() && first > 0 {
+            range.start -= 1;
+        }
+        let deleted = text.slice(range.clone()).to_string();
+        let deleted = deleted.strip_prefix('\n').unwrap_or(&deleted);
+        self.store_deleted(format!("{}\n", deleted.trim_end_matches('\n')));
+        self.buffer.replace(range, "");
+        self.goto(first.min(self.last_line()), 0);
+        self.cx_history = self.cx;
+    }
+
+    /// Types `keys` in normal mode on each line of `range`, following the
+    /// lines through the edits as `:g` does, or at the cursor. Keys left
+    /// in another mode are ended as with Esc, and the edits made are
+    /// undone together.
+    fn normal(
+        &mut self,
+        range: Option<LineRange>,
+        keys: &str,
+        noremap: bool,
+    ) -> anyhow::Result<()> {
+        if self.command_depth >= MAX_COMMAND_DEPTH {
+            anyhow::bail!("Command nested too deeply: normal");
+        }
+        let keys = keys::parse_keys(keys)?;
+        let lines = match range {
+            Some(_) if self.in_global => anyhow::bail!("No range allowed inside :global"),
+            Some(range) => {
+                let (first, end) = self.resolve_range(range)?;
+                self.buffer.mark_lines(&(first..=end).collect::<Vec<_>>());
+                true
+            }
+            None => false,
+        };
+        self.command_depth += 1;
+        let outer = std::mem::replace(&mut self.feeding_keys, noremap);
+        let mut result = Ok(());
+        loop {
+            if lines {
+                let Some(line) = self.buffer.next_marked() else {
+                    break;
+                };
+                self.goto(line, 0);
+            }
+            self.mode = Mode::Normal;
+            result = self.type_keys(&keys);
+            if result.is_err() || !lines {
+                break;
+            }
+        }
+        self.feeding_keys = outer;
+        self.command_depth -= 1;
+        if lines {
+            self.buffer.clear_marked();
+        }
+        result
+    }
+
+    /// Types `keys` as if at the keyboard, ending in normal mode.
+    fn type_keys(&mut self, keys: &[KeyEvent]) -> anyhow::Result<()> {
+        let escape = KeyEvent::new(event::KeyCode::Esc, KeyModifiers::NONE);
+        let mut keys = keys.iter();
+        loop {
+            let key = match keys.next() {
+                Some(key) => *key,
+                None if matches!(self.mode, Mode::Normal) && self.pending.is_none() => break,
+                None => {
+                    self.pending = None;
+                    escape
+                }
+            };
+            if let Some(action) = self.handle_event(Event::Key(key))? {
+                self.dispatch(action)?;
+                self.track_snippet();
+            }
+            if key == escape && keys.len() == 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the lines from `first` to `end` below the line `to`, or above
+    /// the first line when `None`, or copies them there when `copy`,
+    /// leaving the cursor on the last of them.
+    fn move_lines(
+        &mut self,
+        first: usize,
+        end: usize,
+        to: Option<usize>,
+        copy: bool,
+    ) -> anyhow::Result<()> {
+        let lines: Vec<String> = (first..=end).map(|l| self.buffer.line_text(l)).collect();
+        let count = lines.len();
+        let last = match to {
+            Some(to) if copy => {
+                let mut new = vec![self.buffer.line_text(to)];
+                new.extend(lines);
+                self.replace_lines(to, to, new);
+                to + count
+            }
+            None if copy => {
+                let mut new = lines;
+                new.push(self.buffer.line_text(0));
+                self.replace_lines(0, 0, new);
+                count - 1
+            }
+            Some(to) if to >= first && to < end => {
+                anyhow::bail!("Cannot move a range of lines into itself")
+            }
+            Some(to) if to >= end => {
+                let mut new: Vec<String> =
+                    (end + 1..=to).map(|l| self.buffer.line_text(l)).collect();
+                new.extend(lines);
+                self.replace_lines(first, to, new);
+                to
+            }
+            _ => {
+                let above = to.map_or(0, |to| to + 1);
+                let mut new = lines;
+                new.extend((above..first).map(|l| self.buffer.line_text(l)));
+                self.replace_lines(above, end, new);
+                above + count - 1
+            }
+        };
+        let col = self.first_non_blank(last);
+        self.goto(last, col);
+        self.cx_history = self.cx;
+        Ok(())
+    }
+
+    /// Replaces the lines from `first` to `end` with `lines`, in a single
+    /// edit unless nothing changes.
+    fn replace_lines(&mut self, first: usize, end: usize, lines: Vec<String>) {
+        let text = &self.buffer.text;
+        let range = text.line_to_char(first)..text.line_to_char(end + 1);
+        let mut new = lines.join("\n");
+        // The last line may have no line break.
+        if range.end > range.start && text.char(range.end - 1) == '\n' {
+            new.push('\n');
+        }
+        if text.slice(range.clone()) != new.as_str() {
+            self.buffer.replace(range, &new);
+        }
+    }
+
+    /// Runs `command` on each line of `range`, or of the buffer, that
+    /// matches `pattern`, or that does not when `invert`. The lines are
+    /// marked first and followed through the edits made, so that those
+    /// deleted on the way are skipped. What the lines print is shown
+    /// together.
+    fn global(
+        &mut self,
+        range: Option<LineRange>,
+        pattern: &str,
+        invert: bool,
+        command: &str,
+    ) -> anyhow::Result<()> {
+        if self.in_global {
+            anyhow::bail!("Cannot run :global inside :global");
+        }
+        let (first, end) = match range {
+            Some(range) => self.resolve_range(range)?,
+            None => (0, self.last_line()),
+        };
+        // An empty pattern is the last one searched for.
+        let pattern = match (pattern, &self.search) {
+            ("", Some(search)) => search.pattern.clone(),
+            ("", None) => anyhow::bail!("No previous search pattern"),
+            (pattern, _) => pattern.to_string(),
+        };
+        let options = &self.options;
+        let regex = search::compile(&pattern, options.ignore_case, options.smart_case)?;
+        let lines: Vec<usize> = (first..=end)
+            .filter(|&line| regex.is_match(&self.buffer.line_text(line)) != invert)
+            .collect();
+        if lines.is_empty() {
+            match invert {
+                true => anyhow::bail!("Pattern found in every line: {pattern}"),
+                false => anyhow::bail!("Pattern not found: {pattern}"),
+            }
+        }
+
+        self.buffer.mark_lines(&lines);
+        self.in_global = true;
+        let mut printed: Option<Pager<Action>> = None;
+        let mut result = Ok(());
+        while let Some(line) = self.buffer.next_marked() {
+            self.goto(line, 0);
+            self.pager = None;
+            result = match Command::parse(command) {
+                Result::Ok(Command::Quit(_)) => Err(anyhow::anyhow!("Cannot quit inside :global")),
+                Result::Ok(Command::User { name, args, range }) => {
+                    self.run_user_command(&name, &args, range).map(|_| ())
+                }
+                Result::Ok(command) => self.execute(command),
+                Err(err) => Err(err),
+            };
+            if let Some(pager) = self.pager.take() {
+                let all = printed.get_or_insert_with(|| Pager::new("global"));
+                for line in pager.lines() {
+                    all.push(line.to_string());
+                }
+            }
+            if result.is_err() {
+                break;
+            }
+        }
+        self.in_global = false;
+        self.buffer.clear_marked();
+        self.pager = printed;
+        self.cx_history = self.cx;
+        result
+    }
+
+    /// Completions of `typed` as a command name, built in or user.
+    fn complete_command(&self, typed: &str) -> Vec<String> {
+        command::NAMES
+            .iter()
+            .map(|name| name.to_string())
+            .chain(self.user_commands.keys().cloned())
+            .filter(|name| name.starts_with(typed))
+            .collect()
+    }
+
+    /// Completions of `typed` as an argument of the command typed before
+    /// it on the command line: what a user command asks for, options for
+    /// `:set`, buffers then paths for `:edit` and `:diffsplit`, and paths
+    /// for the others.
+    fn complete_argument(&self, cmdline: &str, typed: &str) -> Vec<String> {
+        let name = command::strip_range(cmdline)
+            .split_whitespace()
+            .next()
+            .unwrap_or_default();
+        match name {
+            "set" | "se" | "setlocal" | "setl" => return self.complete_option(typed, true),
+            "e" | "edit" | "e!" | "edit!" | "diffsplit" | "diffs" => {
+                let others = self.diff.iter().map(|view| &view.other);
+                let closed = self.closed.iter().map(|window| &window.buffer);
+                let mut names: Vec<String> = others
+                    .chain(closed)
+                    .map(|buffer| buffer.path.clone())
+                    .filter(|path| !path.is_empty() && path.starts_with(typed))
+                    .collect();
+                names.dedup();
+                for path in completion::paths(typed) {
+                    if !names.contains(&path) {
+                        names.push(path);
+                    }
+                }
+                return names;
+            }
+            _ => {}
+        }
+        let Some(command) = self.user_commands.get(name) else {
+            return completion::paths(typed);
+        };
+        match &command.complete {
+            None => Vec::new(),
+            Some(Complete::File) => completion::paths(typed),
+            Some(Complete::Option) => self.complete_option(typed, false),
+            Some(Complete::Command) => self.complete_command(typed),
+            Some(Complete::Custom(function)) => self
+                .plugins
+                .complete(function, typed)
+                .unwrap_or_else(|err| {
+                    error!("{err:#}");
+                    Vec::new()
+                })
+                .into_iter()
+                .filter(|name| name.starts_with(typed))
+                .collect(),
+        }
+    }
+
+    /// Completions of `typed` as an option name, and with `negated` as the
+    /// name of a flag after `no` too.
+    fn complete_option(&self, typed: &str, negated: bool) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut flags = Vec::new();
+        for option in self.options.all() {
+            match option.split_once('=') {
+                Some((name, _)) => names.push(name.to_string()),
+                None => {
+                    let name = option.strip_prefix("no").unwrap_or(option.as_str());
+                    names.push(name.to_string());
+                    flags.push(format!("no{name}"));
+                }
+            }
+        }
+        if negated {
+            names.extend(flags);
+        }
+        names.retain(|name| name.starts_with(typed));
+        names
+    }
+
+    /// Runs the functions plugins registered for `event`, logging errors.
+    fn emit(&mut self, event: &str, arg: &str) {
+        match (event, arg) {
+            ("open", _) => {
+                let new = !self.buffer.path.is_empty() && !Path::new(&self.buffer.path).exists();
+                let read = if new { "BufNewFile" } else { "BufRead" };
+                for event in [read, "BufReadPost", "FileType", "BufEnter"] {
+                    self.run_autocmds(event);
+                }
+            }
+            ("write", _) => self.run_autocmds("BufWritePost"),
+            ("mode", "insert") => self.run_autocmds("InsertEnter"),
+            _ => {}
+        }
+        if self.in_plugin {
+            return;
+        }
+        if !self.plugins.is_empty() {
+            let result = self
+                .plugins
+                .emit(event, arg, self.plugin_view())
+                .and_then(|outcome| self.apply_plugin(outcome));
+            if let Err(err) = result {
+                error!("{err:#}");
+            }
+        }
+        if !self.wasm.is_empty() {
+            let result = self
+                .wasm
+                .emit(event, arg, self.plugin_view())
+                .and_then(|outcome| self.apply_plugin(outcome));
+            if let Err(err) = result {
+                error!("{err:#}");
+            }
+        }
+    }
+
+    /// Tells that a long-running job finished or failed on each channel of
+    /// `notify`, keeping the message for `:messages`.
+    fn notify(&mut self, severity: messages::Severity, text: String) {
+        crate::logger::record(severity, text.clone());
+        let channels: Vec<Channel> = self
+            .options
+            .notify
+            .iter()
+            .filter_map(|name| Channel::parse(name))
+            .collect();
+        for channel in channels {
+            match channel {
+                Channel::Bell if !self.headless => {
+                    if let Err(err) = self
+                        .stdout
+                        .write_all(b"\x07")
+                        .and_then(|()| self.stdout.flush())
+                    {
+                        error!("{err}");
+                    }
+                }
+                Channel::Desktop if !self.headless => notifications::desktop(text.clone()),
+                Channel::Flash => self.flash = Some(Notice::flash(severity, text.clone())),
+                Channel::Bell | Channel::Desktop => {}
+            }
+        }
+    }
+
+    /// Records `text` and shows it as a toast for a moment, unless the
+    /// `toasts` option is off.
+    fn toast(&mut self, severity: messages::Severity, text: String) {
+        crate::logger::record(severity, text.clone());
+        if self.options.toasts {
+            self.toasts.push(Notice::toast(severity, text));
+            let excess = self.toasts.len().saturating_sub(MAX_TOASTS);
+            self.toasts.drain(..excess);
+        }
+    }
+
+    /// Tells that the language server for the buffer is ready.
+    fn lsp_started(&mut self) {
+        if self.lsp.is_some() {
+            let server = self.buffer.filetype.and_then(lsp::server);
+            let text = match server {
+                Some(server) => format!("Language server ready: {server}"),
+                None => "Language server ready".to_string(),
+            };
+            self.toast(messages::Severity::Info, text);
+        }
+    }
+
+    /// Forgets what was known about the previous buffer once another took
+    /// its place.
+    fn buffer_replaced(&mut self) {
+        self.apply_filetype_options();
+        self.snippets = load_snippets(self.buffer.filetype);
+        self.snippet = None;
+        self.lsp = start_lsp(&self.buffer);
+        self.popup = None;
+        self.diagnostics.clear();
+        self.analysis.submitted = None;
+        self.differ.submitted = None;
+        self.git_signs = None;
+        self.modified.clear();
+        self.blamer.clear();
+        self.repo.reset();
+        self.conflicts = None;
+        self.disk_noticed = None;
+        if let Some(watcher) = &mut self.watcher {
+            watcher.watch(&self.buffer.path);
+        }
+        self.segments = None;
+        self.update_segments();
+    }
+
+    /// Reads the config file at `path`, or what of it is kept in `cache`,
+    /// setting the options it has for the filetype of the buffer.
+    pub fn load_config(&mut self, path: &Path, cache: Option<&Path>) -> anyhow::Result<()> {
+        self.config = Config::load(path, cache)?;
+        self.apply_filetype_options();
+        self.update_segments();
+        Ok(())
+    }
+
+    /// Sets the options of the config for the filetype of the buffer, once
+    /// those set for the buffer shown before are set back.
+    fn apply_filetype_options(&mut self) {
+        for (shown, origin) in std::mem::take(&mut self.filetype_restore).into_iter().rev() {
+            if let Err(err) = self.options.restore(&shown, origin) {
+                error!("{err:#}");
+            }
+        }
+        let Some(filetype) = self.buffer.filetype else {
+            return;
+        };
+        for (arg, line) in self.config.filetype(filetype).to_vec() {
+            let origin = Origin::Script {
+                path: self.config.path.clone(),
+                line,
+            };
+            let result = self.options.saved(&arg).and_then(|saved| {
+                self.options.set(&arg, origin)?;
+                self.filetype_restore.push(saved);
+                Ok(())
+            });
+            if let Err(err) = result {
+                error!("{} line {line}: {err:#}", self.config.path);
+            }
+        }
+    }
+
+    /// Shows the buffer in segments when it has a line longer than
+    /// `segmentlength`, and line by line again once it has none.
+    fn update_segments(&mut self) {
+        let limit = self.options.segment_length;
+        let long = limit > 0
+            && self
+                .buffer
+                .text
+                .lines()
+                .any(|line| line.len_chars() > limit);
+        match (long, self.segments.take()) {
+            (true, None) => {
+                // The cursor is left behind by a buffer just replaced.
+                let cursor = match self.buffer.get(self.buffer_line()) {
+                    Some(_) => self.cursor_char(),
+                    None => 0,
+                };
+                self.segments = Some(Segments::new(cursor));
+                log!("Long lines shown in segments, read-only until :set segmentlength=0");
+            }
+            (true, Some(view)) => self.segments = Some(view),
+            (false, Some(view)) => self.goto_char(view.cursor),
+            (false, None) => {}
+        }
+    }
+
+    /// Moves the cursor over the rows of the segmented buffer.
+    fn move_in_segments(&mut self, action: &Action) {
+        let vheight = self.vheight() as isize;
+        let vwidth = self.vwidth() as usize;
+        let Some(view) = self.segments.as_mut() else {
+            return;
+        };
+        view.refresh(&self.buffer, vwidth);
+        match action {
+            Action::MoveUp => view.move_rows(-1),
+            Action::MoveDown => view.move_rows(1),
+            Action::MoveLeft => view.move_left(),
+            Action::MoveRight => view.move_right(),
+            Action::PageUp => view.move_rows(-vheight),
+            Action::PageDown => view.move_rows(vheight),
+            Action::StartOfLine => view.line_start(),
+            Action::EndOfLine => view.line_end(),
+            _ => {}
+        }
+    }
+
+    /// Compares the buffer with `other` side by side.
+    pub fn diff_with(&mut self, other: Buffer) {
+        self.diff = Some(DiffView::new(other));
+    }
+
+    /// Moves focus to the other buffer of diff mode, where its cursor was.
+    fn switch_window(&mut self) -> anyhow::Result<()> {
+        let cursor = (self.buffer_line(), self.cx as usize);
+        if self.diff.is_some() {
+            self.autosave();
+        }
+        let view = self.diff.as_mut().context("Not in diff mode")?;
+        std::mem::swap(&mut self.buffer, &mut view.other);
+        let (line, col) = std::mem::replace(&mut view.other_cursor, cursor);
+        view.focus_left = !view.focus_left;
+        view.realign();
+        self.buffer_replaced();
+        self.goto(line, col);
+        self.cx_history = self.cx;
+        Ok(())
+    }
+
+    /// Follows the link under the cursor, or the first on the selected line
+    /// of the pager: edits the file it names, or with `external` opens it
+    /// with the desktop's handler.
+    fn follow_link(&mut self, external: bool) -> anyhow::Result<()> {
+        let (text, col) = match &self.pager {
+            Some(pager) => (pager.selected_text().unwrap_or_default().to_string(), None),
+            None => (
+                self.buffer.line_text(self.buffer_line()),
+                Some(self.cx as usize),
+            ),
+        };
+        let link = links::find(&text)
+            .into_iter()
+            .find(|link| col.is_none_or(|col| link.cols.contains(&col)));
+        if external {
+            let uri = link.context("No link under cursor")?.target.uri();
+            if self.headless {
+                log!("Opening {uri}");
+                return Ok(());
+            }
+            return links::open(&uri);
+        }
+        let Some(Target::File { path, line }) = link.map(|link| link.target) else {
+            anyhow::bail!("No file name under cursor");
+        };
+        self.autosave();
+        if self.buffer.dirty {
+            anyhow::bail!("No write since last change (add ! to override)");
+        }
+        self.pager = None;
+        self.open(&path.to_string_lossy())?;
+        if let Some(line) = line {
+            self.goto(line.saturating_sub(1), 0);
+            self.cx_history = self.cx;
+        }
+        Ok(())
+    }
+
+    /// Stops the editor with the terminal given back to the shell, as it
+    /// would be had Ctrl-Z not been read in raw mode, and sets it up again
+    /// and redraws everything once the shell resumes it. The buffer is
+    /// written first with `autosave`.
+    fn suspend(&mut self) -> anyhow::Result<()> {
+        self.autosave();
+        if !self.headless {
+            leave_terminal(&mut self.stdout, &self.caps);
+            // Returns once resumed by SIGCONT.
+            #[cfg(unix)]
+            // SAFETY: raise only sends a signal to this process.
+            unsafe {
+                libc::raise(libc::SIGTSTP);
+            }
+            enter_terminal(&mut self.stdout, &self.caps)?;
+            self.size = terminal::size()?;
+        }
+        self.screen.invalidate();
+        self.cursor_shape = None;
+        self.title = None;
+        Ok(())
+    }
+
+    /// Keeps the undo history of the file just written in the undo
+    /// directory, with `undofile` set.
+    fn write_undo_file(&mut self) {
+        if !self.options.undo_file || self.buffer.listing.is_some() {
+            return;
+        }
+        self.buffer.history.end_step();
+        let Some(dir) = self.options.undo_dir() else {
+            return;
+        };
+        if let Err(err) = undo::write(
+            &dir,
+            &self.buffer.path,
+            &self.buffer.text,
+            &self.buffer.history,
+        ) {
+            error!("{err:#}");
+        }
+    }
+
+    /// Takes the undo history of the file just opened from its undo file,
+    /// with `undofile` set.
+    fn read_undo_file(&mut self) {
+        if !self.options.undo_file || self.buffer.listing.is_some() {
+            return;
+        }
+        let Some(dir) = self.options.undo_dir() else {
+            return;
+        };
+        if let Some(history) = undo::read(&dir, &self.buffer.path, &self.buffer.text) {
+            self.buffer.history = history;
+        }
+    }
+
+    /// Writes the options and the windows with their files and cursors to
+    /// `path` as a script restoring them.
+    fn make_session(&mut self, path: Option<String>, force: bool) -> anyhow::Result<()> {
+        let path = path.unwrap_or_else(|| session::DEFAULT_PATH.to_string());
+        if !force && Path::new(&path).exists() {
+            anyhow::bail!("File exists (add ! to override): {path}");
+        }
+        let focused = session::Window {
+            path: self.buffer.path.clone(),
+            cursor: (self.buffer_line(), self.cx as usize),
+        };
+        let (windows, focus) = match &self.diff {
+            None => (vec![focused], 0),
+            Some(view) => {
+                let other = session::Window {
+                    path: view.other.path.clone(),
+                    cursor: view.other_cursor,
+                };
+                match view.focus_left {
+                    true => (vec![focused, other], 0),
+                    false => (vec![other, focused], 1),
+                }
+            }
+        };
+        let script = session::script(&self.options.all(), &windows, focus);
+        fs::write(&path, script).with_context(|| format!("Cannot write {path}"))?;
+        Ok(())
+    }
+
+    /// Runs the ex commands of `script`, such as a session, logging those
+    /// that fail with their line in `name`.
+    pub fn source(&mut self, name: &str, script: &str) {
+        let outer = self.origin.clone();
+        let mut runner = vimscript::Runner::default();
+        for (number, line) in vimscript::logical_lines(script) {
+            self.origin = Origin::Script {
+                path: name.to_string(),
+                line: number,
+            };
+            let result = runner
+                .step(&line, &|name| self.variable(name))
+                .and_then(|run| match run && !vimscript::ignored(&line) {
+                    true => Command::parse(&line).and_then(|command| self.execute(command)),
+                    false => Ok(()),
+                });
+            if let Err(err) = result {
+                error!("{name} line {number}: {err:#}");
+            }
+        }
+        if let Err(err) = runner.finish() {
+            error!("{name}: {err:#}");
+        }
+        self.origin = outer;
+    }
+
+    /// The value of a vimscript variable, or of an option written `&name`.
+    fn variable(&self, name: &str) -> Option<vimscript::Value> {
+        let Some(option) = name.strip_prefix('&') else {
+            return self
+                .variables
+                .get(name.strip_prefix("g:").unwrap_or(name))
+                .cloned();
+        };
+        let (shown, _) = self.options.show(option).ok()?;
+        Some(match shown.split_once('=') {
+            Some((_, value)) => value.parse().map_or_else(
+                |_| vimscript::Value::String(value.to_string()),
+                vimscript::Value::Number,
+            ),
+            None => vimscript::Value::Number(!shown.starts_with("no") as i64),
+        })
+    }
+
+    /// `keys` with `<Leader>` and `<LocalLeader>` replaced by the keys
+    /// `mapleader` and `maplocalleader` are set to, or a backslash.
+    fn expand_leader(&self, keys: &str) -> String {
+        let mut expanded = keys.to_string();
+        for (name, variable) in [
+            ("<leader>", "mapleader"),
+            ("<localleader>", "maplocalleader"),
+        ] {
+            let leader = self
+                .variables
+                .get(variable)
+                .map_or("\\".to_string(), vimscript::Value::to_string);
+            while let Some(i) = expanded.to_ascii_lowercase().find(name) {
+                expanded.replace_range(i..i + name.len(), &leader);
+            }
+        }
+        expanded
+    }
+
+    /// Takes `key` toward a mapping of `mode`. Once the keys typed make
+    /// one, the keys it stands for are fed, and once they cannot start one
+    /// they are fed as typed. Returns `None` for a key no mapping starts
+    /// with, which is handled as usual.
+    fn map_key(&mut self, mode: MapMode, key: &KeyEvent) -> Option<Option<Action>> {
+        if self.feeding_keys || self.key_maps.is_empty() {
+            return None;
+        }
+        let typed = format!("{}{}", self.map_typed, keys::notation(key)?);
+        let mut mapped = self
+            .key_maps
+            .range((mode, typed.clone())..)
+            .take_while(|((m, lhs), _)| *m == mode && lhs.starts_with(&typed));
+        match mapped.next() {
+            Some(((_, lhs), (rhs, _))) if *lhs == typed => {
+                let rhs = rhs.clone();
+                self.map_typed.clear();
+                Some(Some(Action::FeedKeys(rhs)))
+            }
+            Some(_) => {
+                self.map_typed = typed;
+                Some(None)
+            }
+            None if self.map_typed.is_empty() => None,
+            None => {
+                self.map_typed.clear();
+                Some(Some(Action::FeedKeys(typed)))
+            }
+        }
+    }
+
+    /// Runs the commands of `:autocmd` for `event` on the buffer, logging
+    /// those that fail.
+    fn run_autocmds(&mut self, event: &str) {
+        if self.in_autocmd {
+            return;
+        }
+        let name = match event {
+            "FileType" => self.buffer.filetype.unwrap_or_default().to_string(),
+            _ => self.buffer.path.clone(),
+        };
+        let commands: Vec<(String, Origin)> = self
+            .autocmds
+            .iter()
+            .filter(|(autocmd, _)| autocmd.matches(event, &name))
+            .map(|(autocmd, origin)| (autocmd.command.clone(), origin.clone()))
+            .collect();
+        self.in_autocmd = true;
+        for (command, origin) in commands {
+            let outer = std::mem::replace(&mut self.origin, origin);
+            if let Err(err) = Command::parse(&command).and_then(|command| self.execute(command)) {
+                error!("{event} autocommand: {err:#}");
+            }
+            self.origin = outer;
+        }
+        self.in_autocmd = false;
+    }
+
+    /// Carries out `:q` and the commands like it: closes the focused window
+    /// of diff mode, or quits once it is the last or with `all`, returning
+    /// whether the editor keeps running. A window closed is kept for
+    /// `:reopen`, but quitting fails while a buffer has changes not
+    /// written, unless forced.
+    fn quit(&mut self, quit: Quit) -> anyhow::Result<bool> {
+        if quit.write {
+            if self.buffer.dirty {
+                self.execute(Command::Write { force: false })?;
+            }
+            if quit.all {
+                let others = self.diff.iter_mut().map(|view| &mut view.other);
+                let closed = self.closed.iter_mut().map(|window| &mut window.buffer);
+                for buffer in others.chain(closed).filter(|buffer| buffer.dirty) {
+                    save_buffer(buffer, &self.options)?;
+                }
+            }
+        }
+        if !quit.all && self.close_window() {
+            return Ok(true);
+        }
+        if !quit.force {
+            let others = self.diff.iter().map(|view| &view.other);
+            let closed = self.closed.iter().map(|window| &window.buffer);
+            let mut buffers = std::iter::once(&self.buffer).chain(others).chain(closed);
+            if let Some(buffer) = buffers.find(|buffer| buffer.dirty) {
+                let name = match buffer.path.as_str() {
+                    "" => "[No Name]",
+                    path => path,
+                };
+                anyhow::bail!(
+                    "No write since last change for buffer \"{name}\" (add ! to override)"
+                );
+            }
+        }
+        Ok(false)
+    }
+
+    /// Closes the focused window of diff mode, keeping it for `:reopen`,
+    /// and returns whether there was one to close other than the last.
+    fn close_window(&mut self) -> bool {
+        let Some(view) = self.diff.take() else {
+            return false;
+        };
+        let cursor = self.cursor();
+        let buffer = std::mem::replace(&mut self.buffer, view.other);
+        self.closed.push(ClosedWindow {
+            buffer,
+            cursor,
+            left: view.focus_left,
+        });
+        self.buffer_replaced();
+        self.goto(view.other_cursor.0, view.other_cursor.1);
+        self.cx_history = self.cx;
+        true
+    }
+
+    /// Brings back the window closed last, focused and with its buffer and
+    /// cursor as they were, comparing it with the one open.
+    fn reopen_window(&mut self) -> anyhow::Result<()> {
+        if self.diff.is_some() {
+            anyhow::bail!("No room for another window");
+        }
+        let window = self.closed.pop().context("No closed window")?;
+        let other = std::mem::replace(&mut self.buffer, window.buffer);
+        let mut view = DiffView::new(other);
+        view.other_cursor = self.cursor();
+        view.focus_left = window.left;
+        self.diff = Some(view);
+        self.buffer_replaced();
+        self.goto(window.cursor.0, window.cursor.1);
+        self.cx_history = self.cx;
+        Ok(())
+    }
+
+    /// Copies the difference at the cursor from the other buffer of diff
+    /// mode, or to it when `put`.
+    fn diff_copy(&mut self, put: bool) -> anyhow::Result<()> {
+        let line = self.buffer_line();
+        let view = self.diff.as_mut().context("Not in diff mode")?;
+        let hunk = view
+            .hunks(&self.buffer)
+            .into_iter()
+            .find(|hunk| hunk.new.contains(&line) || git::hunk_line(hunk) == line)
+            .context("No difference at the cursor")?;
+        let ours = diffview::char_range(&self.buffer.text, hunk.new);
+        let theirs = diffview::char_range(&view.other.text, hunk.old);
+        if put {
+            let text = self.buffer.text.slice(ours).to_string();
+            view.other.replace(theirs, &text);
+        } else {
+            let text = view.other.text.slice(theirs).to_string();
+            self.buffer.replace(ours, &text);
+        }
+        Ok(())
+    }
+
+    /// Strips whitespace at the end of lines with `trimtrailing`, and adds
+    /// the line break missing at the end of the file with `fixendofline`,
+    /// telling what was changed.
+    fn tidy_for_write(&mut self) {
+        let (trim, fix) = (self.options.trim_trailing, self.options.fix_end_of_line);
+        if !trim && !fix {
+            return;
+        }
+        let text = self.buffer.text.to_string();
+        let mut tidy = String::with_capacity(text.len());
+        let mut trimmed = 0;
+        for line in text.split_inclusive('\n') {
+            let body = line.trim_end_matches(['\n', '\r']);
+            let kept = match trim {
+                true => body.trim_end_matches([' ', '\t']),
+                false => body,
+            };
+            trimmed += (kept.len() < body.len()) as usize;
+            tidy.push_str(kept);
+            tidy.push_str(&line[body.len()..]);
+        }
+        let fixed = fix && !tidy.is_empty() && !tidy.ends_with('\n');
+        if fixed {
+            tidy.push_str(if text.contains("\r\n") { "\r\n" } else { "\n" });
+        }
+        if tidy == text {
+            return;
+        }
+        let (line, col) = (self.buffer_line(), self.cx as usize);
+        let hunks = self.buffer.rewrite(&tidy);
+        self.goto(diff::map_line(&hunks, line), col);
+        let trimmed = match trimmed {
+            0 => None,
+            1 => Some("Trimmed trailing whitespace on 1 line".to_string()),
+            n => Some(format!("Trimmed trailing whitespace on {n} lines")),
+        };
+        let message = match (trimmed, fixed) {
+            (Some(trimmed), true) => format!("{trimmed}, added a final newline"),
+            (Some(trimmed), false) => trimmed,
+            (None, _) => "Added a final newline".to_string(),
+        };
+        log!("{message}");
+    }
+
+    /// Saves the buffer, or for a directory listing asks for confirmation
+    /// of the filesystem operations its edits amount to.
+    fn write(&mut self, force: bool) -> anyhow::Result<()> {
+        if !force && self.buffer.listing.is_none() && self.buffer.changed_on_disk()?.is_some() {
+            self.show_disk_conflict();
+            return Ok(());
+        }
+        self.repo.invalidate();
+        let Some(listing) = &self.buffer.listing else {
+            self.tidy_for_write();
+            let format = self.buffer.filetype.is_some_and(|filetype| {
+                self.options.format_on_save.iter().any(|ft| ft == filetype)
+            });
+            if format && self.lsp.is_some() {
+                return self.format(true);
+            }
+            return save_buffer(&mut self.buffer, &self.options);
+        };
+
+        let edited: Vec<String> = (0..self.buffer.len())
+            .map(|line| self.buffer.line_text(line))
+            .collect();
+        let ops = explorer::plan(Path::new(&self.buffer.path), listing, &edited)?;
+        if ops.is_empty() {
+            self.buffer.dirty = false;
+            return Ok(());
+        }
+
+        let mut pager = Pager::new("confirm");
+        for op in &ops {
+            pager.push(op.to_string());
+        }
+        pager.push(String::new());
+        pager.push(format!("Apply {} change(s)? [y/N]", ops.len()));
+        pager.select(ops.len() + 1);
+        self.pager = Some(pager);
+        self.confirm = Some(Action::ApplyFsOps(ops));
+        Ok(())
+    }
+
+    /// Lists the files under the working directory to edit one, or insert
+    /// the paths of those marked.
+    fn pick_files(&mut self) {
+        let files = explorer::walk(Path::new("."), MAX_PICKER_FILES);
+        let edit = |force| {
+            move |paths: Vec<&PathBuf>| Action::EditFile {
+                path: paths[0].to_string_lossy().into_owned(),
+                force,
+            }
+        };
+        let picker = Picker::new("files", files)
+            .with_preview(|path: &PathBuf| preview_file(path))
+            .on_select(edit(false))
+            .with_action("edit", edit(false))
+            .with_action("edit, discarding changes", edit(true))
+            .with_action("insert paths", |paths| {
+                let paths: Vec<_> = paths.iter().map(|path| path.to_string_lossy()).collect();
+                Action::Paste(paths.join(" "))
+            });
+        self.picker = Some(Box::new(picker));
+    }
+
+    /// Lists the commands and the keys bound in normal mode, with those
+    /// mapped, to run one by typing its keys.
+    fn pick_command(&mut self) {
+        let mut entries: Vec<Entry> = palette::BINDINGS
+            .iter()
+            .map(|(keys, description)| Entry::binding(keys, description, palette::typed(keys)))
+            .collect();
+        for ((mode, lhs), (rhs, _)) in &self.key_maps {
+            if *mode == MapMode::Normal {
+                entries.push(Entry::binding(lhs, rhs, rhs));
+            }
+        }
+        for (mode, lhs, _) in self.plugins.mappings() {
+            if mode == MapMode::Normal {
+                entries.push(Entry::binding(&lhs, "<Lua function>", &lhs));
+            }
+        }
+        let names = command::NAMES.iter().copied();
+        entries.extend(
+            names
+                .chain(self.user_commands.keys().map(String::as_str))
+                .map(Entry::command),
+        );
+        let picker = Picker::new("palette", entries)
+            .on_select(|entries: Vec<&Entry>| Action::FeedKeys(entries[0].keys.clone()))
+            .with_action("run", |entries| Action::FeedKeys(entries[0].keys.clone()))
+            .with_action("edit on the command line", |entries| {
+                Action::FeedKeys(entries[0].partial().to_string())
+            });
+        self.picker = Some(Box::new(picker));
+    }
+
+    /// Asks whether to trust the buffer's project before running language
+    /// servers, linters or git for it.
+    fn offer_trust(&mut self) {
+        let path = Path::new(&self.buffer.path);
+        if self.buffer.listing.is_some() || !lsp::in_project(path) || trust::is_trusted(path) {
+            return;
+        }
+        let mut pager = Pager::new("workspace trust");
+        pager.push(format!(
+            "Trust the workspace {}?",
+            trust::root(path).display()
+        ));
+        pager.push("Language servers, linters and git read its configuration,".to_string());
+        pager.push("which can make them run any program.".to_string());
+        pager.push(String::new());
+        pager.push_target(
+            "Trust it for this session".to_string(),
+            Action::TrustWorkspace { persist: false },
+        );
+        pager.push_target(
+            "Always trust it".to_string(),
+            Action::TrustWorkspace { persist: true },
+        );
+        pager.push_target("Don't trust it".to_string(), Action::ClosePager);
+        pager.select(4);
+        self.pager = Some(pager);
+    }
+
+    /// Trusts the buffer's project and starts what was held back for it.
+    fn trust_workspace(&mut self, persist: bool) -> anyhow::Result<()> {
+        trust::trust(Path::new(&self.buffer.path), persist)?;
+        if self.lsp.is_none() && !self.headless {
+            self.lsp = LspClient::for_buffer(&self.buffer)?;
+        }
+        self.analysis.submitted = None;
+        self.differ.submitted = None;
+        self.blamer.clear();
+        self.repo.invalidate();
+        Ok(())
+    }
+
+    /// Tells when the file changed on disk since it was read or written:
+    /// offers to reload a buffer without changes, and warns of the
+    /// conflict with one that has them. Each version on disk is told of
+    /// once.
+    fn check_disk(&mut self) -> anyhow::Result<()> {
+        if self.buffer.listing.is_some() {
+            return Ok(());
+        }
+        let Some(contents) = self.buffer.changed_on_disk()? else {
+            return Ok(());
+        };
+        if self.disk_noticed.as_ref() == Some(&contents) {
+            return Ok(());
+        }
+        self.disk_noticed = Some(contents);
+        if self.buffer.dirty {
+            self.show_disk_conflict();
+            return Ok(());
+        }
+        let mut pager = Pager::new("file changed on disk");
+        pager.push("The file has been changed since reading it.".to_string());
+        pager.push(String::new());
+        pager.push_target("Reload it".to_string(), Action::ReloadFile);
+        pager.push_target("Show the differences".to_string(), Action::ShowDiskDiff);
+        pager.push_target("Keep the buffer".to_string(), Action::ClosePager);
+        pager.select(2);
+        self.pager = Some(pager);
+        Ok(())
+    }
+
+    /// Offers what to do about the file having changed on disk since it was
+    /// read, instead of silently overwriting it.
+    fn show_disk_conflict(&mut self) {
+        let mut pager = Pager::new("file changed on disk");
+        pager.push("WARNING: The file has been changed since reading it!!!".to_string());
+        pager.push(String::new());
+        pager.push_target("Overwrite it (:w!)".to_string(), Action::ForceWrite);
+        pager.push_target("Show the differences".to_string(), Action::ShowDiskDiff);
+        pager.push_target(
+            "Merge its changes into the buffer".to_string(),
+            Action::MergeDisk,
+        );
+        pager.push_target(
+            "Reload it, dropping the changes in the buffer".to_string(),
+            Action::ReloadFile,
+        );
+        pager.push_target("Cancel".to_string(), Action::ClosePager);
+        pager.select(2);
+        self.pager = Some(pager);
+    }
+
+    /// Lists the lines the buffer and the file on disk disagree on, with a
+    /// little context around each hunk.
+    fn show_disk_diff(&mut self) -> anyhow::Result<()> {
+        const CONTEXT: usize = 2;
+
+        let disk = fs::read_to_string(&self.buffer.path)?;
+        let old: Vec<&str> = disk.lines().collect();
+        let text = self.buffer.text.to_string();
+        let new: Vec<&str> = text.lines().collect();
+
+        let mut pager = Pager::new("diff disk buffer");
+        pager.push("--- disk".to_string());
+        pager.push("+++ buffer".to_string());
+        for hunk in diff::diff(&old, &new) {
+            let before = hunk.old.start.saturating_sub(CONTEXT);
+            let after = (hunk.old.end + CONTEXT).min(old.len());
+            let context = (hunk.old.start - before) + (after - hunk.old.end);
+            pager.push_target(
+                format!(
+                    "@@ -{},{} +{},{} @@",
+                    before + 1,
+                    hunk.old.len() + context,
+                    hunk.new.start - (hunk.old.start - before) + 1,
+                    hunk.new.len() + context
+                ),
+                Action::Goto(hunk.new.start, 0),
+            );
+            for line in &old[before..hunk.old.start] {
+                pager.push(format!(" {line}"));
+            }
+            for line in &old[hunk.old.clone()] {
+                pager.push(format!("-{line}"));
+            }
+            for line in &new[hunk.new.clone()] {
+                pager.push(format!("+{line}"));
+            }
+            for line in &old[hunk.old.end..after] {
+                pager.push(format!(" {line}"));
+            }
+        }
+        self.pager = Some(pager);
+        Ok(())
+    }
+
+    /// Asks the language server to format the buffer, writing it once the
+    /// result is applied if `save` is set.
+    fn format(&mut self, save: bool) -> anyhow::Result<()> {
+        self.lsp.as_ref().context("No language server")?;
+        self.lsp_request(|lsp, buffer| lsp.formatting(buffer));
+        if self.lsp.is_some() {
+            self.formatting = Some((self.buffer.revision, save));
+        }
+        Ok(())
+    }
+
+    /// Lists the change list in the pager like vim's `:changes`, with the
+    /// distance from the current change list position in the first column.
+    fn show_changes(&mut self) {
+        let mut pager = Pager::new("changes");
+        pager.push("change line  col text".to_string());
+
+        let current = self.buffer.change_idx;
+        for (i, &(line, col)) in self.buffer.changes.iter().enumerate() {
+            let text = self
+                .buffer
+                .get(line)
+                .map(|s| s.to_string().trim_end().to_string())
+                .unwrap_or_default();
+            let marker = if i == current { '>' } else { ' ' };
+            pager.push_target(
+                format!(
+                    "{marker}{:>5} {:>5} {:>4} {text}",
+                    current.abs_diff(i),
+                    line + 1,
+                    col
+                ),
+                Action::JumpToChange(i),
+            );
+        }
+        if current == self.buffer.changes.len() {
+            pager.push(">".to_string());
+        }
+
+        pager.select(current + 1);
+        self.pager = Some(pager);
+    }
+
+    /// Keeps deleted `text` in the unnamed register, and in `"1` when it
+    /// holds a line break, the older ones there moving on up to `"9`, or
+    /// else in `"-`.
+    fn store_deleted(&mut self, text: String) {
+        match text.contains('\n') {
+            true => {
+                for n in (1..9).rev() {
+                    let name = char::from(b'0' + n);
+                    if let Some(older) = self.registers.remove(&name) {
+                        self.registers.insert(char::from(b'1' + n), older);
+                    }
+                }
+                self.registers.insert('1', text.clone());
+            }
+            false => _ = self.registers.insert('-', text.clone()),
+        }
+        self.registers.insert('"', text);
+    }
+
+    /// The text of register `name`, or of the ones read from elsewhere:
+    /// `%` the file name, `/` the last search pattern and `:` the last
+    /// command.
+    fn register(&self, name: char) -> Option<String> {
+        match name {
+            '%' => Some(self.buffer.path.clone()).filter(|path| !path.is_empty()),
+            '/' => self.search.as_ref().map(|search| search.pattern.clone()),
+            ':' => self
+                .history
+                .entries(history::Kind::Command)
+                .last()
+                .map(|entry| entry.to_string()),
+            name => self.registers.get(&name.to_ascii_lowercase()).cloned(),
+        }
+    }
+
+    /// Lists the registers holding text, or those of `names`, with line
+    /// breaks shown as `^J`.
+    fn show_registers(&mut self, names: &str) {
+        let mut pager = Pager::new("registers");
+        pager.push("Name Content".to_string());
+        let mut stored: Vec<char> = self.registers.keys().copied().collect();
+        // The unnamed register first, then numbered and named ones.
+        stored.sort_by_key(|&name| (name != '"', !name.is_ascii_digit(), name));
+        stored.extend(['%', '/', ':']);
+        for name in stored {
+            if !names.is_empty() && !names.contains(name) {
+                continue;
+            }
+            if let Some(text) = self.register(name) {
+                pager.push(format!("  \"{name} {}", text.replace('\n', "^J")));
+            }
+        }
+        self.pager = Some(pager);
+    }
+
+    /// Lists the marks set, or those of `names`, each jumped to when picked.
+    fn show_marks(&mut self, names: &str) {
+        let mut pager = Pager::new("marks");
+        pager.push("mark line  col text".to_string());
+        for (&name, &(line, col)) in &self.buffer.marks {
+            if !names.is_empty() && !names.contains(name) {
+                continue;
+            }
+            let text = self.buffer.line_text(line);
+            pager.push_target(
+                format!(" {name} {:>6} {col:>4} {}", line + 1, text.trim()),
+                Action::Goto(line, col),
+            );
+        }
+        self.pager = Some(pager);
+    }
+
+    /// Lists what is set up for the buffer's filetype, each with why it is
+    /// or is not active, to tell why a language feature does not work.
+    fn show_filetype_info(&mut self) {
+        let mut pager = Pager::new("filetype info");
+        let row = |name: &str, status: String| format!("{name:<12} {status}");
+        let Some(filetype) = self.buffer.filetype else {
+            let extension = Path::new(&self.buffer.path)
+                .extension()
+                .map(|ext| ext.to_string_lossy().into_owned());
+            pager.push(row(
+                "filetype",
+                match extension {
+                    Some(ext) => format!("none, the extension .{ext} is not known"),
+                    None => "none, the file has no extension".to_string(),
+                },
+            ));
+            self.pager = Some(pager);
+            return;
+        };
+        let path = Path::new(&self.buffer.path);
+        let trusted = trust::is_trusted(path);
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        pager.push(row(
+            "filetype",
+            format!("{filetype}, from the extension .{extension}"),
+        ));
+        pager.push(row(
+            "highlighter",
+            "none, no grammar is built in".to_string(),
+        ));
+        pager.push(row(
+            "indent",
+            "none, new lines start at the first column".to_string(),
+        ));
+
+        let server = lsp::server(filetype);
+        let lsp = match server {
+            None => format!("none, no server is configured for {filetype}"),
+            Some(cmd) if self.lsp.is_some() => format!("{cmd}, running"),
+            Some(cmd) if !trusted => {
+                format!("{cmd}, not started as the workspace is not trusted (:trust)")
+            }
+            Some(cmd) if !linters::on_path(cmd) => format!("{cmd}, not installed"),
+            Some(cmd) => format!("{cmd}, not running, see :messages for why"),
+        };
+        let on_save = self.options.format_on_save.iter().any(|ft| ft == filetype);
+        let formatter = match server {
+            Some(cmd) if self.lsp.is_some() && on_save => {
+                format!("{cmd}, with :format and on save")
+            }
+            Some(cmd) if self.lsp.is_some() => {
+                format!("{cmd}, with :format (:set formatonsave+={filetype} to format on save)")
+            }
+            _ => "none, formatting needs the language server".to_string(),
+        };
+        pager.push(row("formatter", formatter));
+        pager.push(row("lsp", lsp));
+
+        let specs = linters::for_filetype(filetype);
+        let linters = match specs.is_empty() {
+            true => format!("none, no linter is configured for {filetype}"),
+            false => specs
+                .into_iter()
+                .map(|(name, program)| {
+                    if !linters::on_path(program) {
+                        format!("{name}, {program} not installed")
+                    } else if !trusted {
+                        format!("{name}, not run as the workspace is not trusted")
+                    } else {
+                        format!("{name}, run once typing pauses")
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("; "),
+        };
+        pager.push(row("linters", linters));
+
+        let comments = match (self.options.comment_markers(), filetype::comment(filetype)) {
+            (Some((start, "")), _) => format!("{start}, from commentstring"),
+            (Some((start, end)), _) => format!("{start} {end}, from commentstring"),
+            (None, builtin) => match builtin {
+                Some((start, "")) => format!("{start}, from the built-in markers"),
+                Some((start, end)) => format!("{start} {end}, from the built-in markers"),
+                None => format!("none, no comment markers are known for {filetype}"),
+            },
+        };
+        pager.push(row("comments", comments));
+
+        let builtin = snippets::builtin(filetype).len();
+        let user = self.snippets.len().saturating_sub(builtin);
+        let snippets = match snippets::user_file(filetype) {
+            Some(file) if file.is_file() => {
+                format!("{builtin} built-in, {user} from {}", file.display())
+            }
+            Some(file) => format!("{builtin} built-in, none in {}", file.display()),
+            None => format!("{builtin} built-in, no configuration directory"),
+        };
+        pager.push(row("snippets", snippets));
+        self.pager = Some(pager);
+    }
+
+    fn handle_event(&mut self, ev: Event) -> anyhow::Result<Option<Action>> {
+        match &ev {
+            Event::Key(key_event) if key_event.kind == event::KeyEventKind::Press => {
+                self.actions.key(key_event);
+                self.messages_seen = messages::given();
+            }
+            _ => {}
+        }
+        // Resizing by dragging sends an event per step: the layout waits
+        // until they stop, or until something else arrives.
+        if let Event::Resize(width, height) = ev {
+            self.resized = Some((width, height));
+            return Ok(None);
+        }
+        self.take_resize();
+        match ev {
+            Event::FocusLost => return Ok(Some(Action::AutoSave)),
+            Event::FocusGained => return Ok(Some(Action::CheckDisk)),
+            _ => {}
+        }
+        if self.locked {
+            // The key that unlocks does nothing else.
+            if matches!(ev, Event::Key(key_event) if key_event.kind == event::KeyEventKind::Press) {
+                self.locked = false;
+            }
+            return Ok(None);
+        }
+        if self.picker.is_some() {
+            return Ok(handle_picker_event(ev));
+        }
+        if self.pager.is_some() {
+            return self.handle_pager_event(ev);
+        }
+        if self.segments.is_some() && matches!(self.mode, Mode::Normal) {
+            return Ok(handle_segments_event(ev));
+        }
+        if self.popup.is_some() {
+            if let Some(action) = self.handle_popup_event(&ev) {
+                return Ok(Some(action));
+            }
+            if matches!(ev, Event::Key(_)) {
+                self.popup = None;
+            }
+        }
+        if matches!(self.mode, Mode::Normal | Mode::Visual) && self.pending.is_none() {
+            match ev {
+                Event::Key(key_event)
+                    if key_event.kind == event::KeyEventKind::Press
+                        && key_event.modifiers.is_empty() =>
+                {
+                    match key_event.code {
+                        // A 0 not after another digit goes to the first column.
+                        event::KeyCode::Char(c @ '0'..='9') if c != '0' || self.count.is_some() => {
+                            let digit = c as usize - '0' as usize;
+                            let count = self.count.unwrap_or(0);
+                            self.count = Some(count.saturating_mul(10).saturating_add(digit));
+                            return Ok(None);
+                        }
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        let action = match self.mode {
+            Mode::Normal => self.handle_normal_event(self.options.lang_map.translate(ev)),
+            Mode::Insert => self.handle_insert_event(ev),
+            Mode::Command => self.handle_command_event(ev),
+            Mode::Visual => Ok(self.handle_visual_event(self.options.lang_map.translate(ev))),
+        };
+        // The count is for the command the keys typed after it complete.
+        if self.pending.is_none() {
+            self.count = None;
+        }
+        action
+    }
+
+    /// Where the motion of `key` goes from the cursor, repeated by the
+    /// count typed: `{` and `}` over paragraphs, `(` and `)` over
+    /// sentences.
+    fn motion_target(&self, key: char) -> (usize, usize) {
+        let count = self.count.unwrap_or(1);
+        let (text, last) = (&self.buffer.text, self.last_line());
+        match key {
+            '{' | '}' => {
+                let line = motion::paragraph(text, self.buffer_line(), last, key == '{', count);
+                match line == last && key == '}' {
+                    true => (line, self.buffer.line_text(line).chars().count()),
+                    false => (line, 0),
+                }
+            }
+            _ => {
+                let end = text.line_to_char(last) + self.buffer.line_text(last).chars().count();
+                let at = motion::sentence(text, self.cursor_char(), key == '(', count);
+                self.buffer.char_to_pos(at.min(end))
+            }
+        }
+    }
+
+    /// The lines an operator such as `gc` acts on when given the motion of
+    /// `key`. One ending in the first column leaves out the line it ends on.
+    fn motion_lines(&self, key: char) -> (usize, usize) {
+        let line = self.buffer_line();
+        match self.motion_target(key) {
+            (target, 0) if target > line => (line, target - 1),
+            (target, _) => (line.min(target), line.max(target)),
+        }
+    }
+
+    fn handle_pager_event(&mut self, ev: Event) -> anyhow::Result<Option<Action>> {
+        if self.confirm.is_some() {
+            return Ok(match ev {
+                Event::Key(key_event) if key_event.kind == event::KeyEventKind::Press => {
+                    match key_event.code {
+                        event::KeyCode::Char('y') => self.confirm.take(),
+                        _ => {
+                            self.confirm = None;
+                            Some(Action::ClosePager)
+                        }
+                    }
+                }
+                _ => None,
+            });
+        }
+
+        if let Some(prefix) = self.pending.take() {
+            return Ok(match (prefix, ev) {
+                ("g", Event::Key(key_event)) if key_event.code == event::KeyCode::Char('f') => {
+                    Some(Action::FollowLink { external: false })
+                }
+                ("g", Event::Key(key_event)) if key_event.code == event::KeyCode::Char('x') => {
+                    Some(Action::FollowLink { external: true })
+                }
+                _ => None,
+            });
+        }
+
+        let action = match ev {
+            Event::Key(key_event) => match key_event.kind {
+                event::KeyEventKind::Press => match key_event.code {
+                    event::KeyCode::Esc | event::KeyCode::Char('q') => Some(Action::ClosePager),
+                    event::KeyCode::Char('g') => {
+                        self.pending = Some("g");
+                        None
+                    }
+                    event::KeyCode::Enter => Some(Action::PagerSelect),
+                    event::KeyCode::Up | event::KeyCode::Char('k') => Some(Action::MoveUp),
+                    event::KeyCode::Down | event::KeyCode::Char('l') => Some(Action::MoveDown),
+                    event::KeyCode::PageDown => Some(Action::PageDown),
+                    event::KeyCode::PageUp => Some(Action::PageUp),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        };
+        Ok(action)
+    }
+
+    /// Scrolls the popup, leaving every other key to close it and act as
+    /// usual.
+    fn handle_popup_event(&self, ev: &Event) -> Option<Action> {
+        let half_page = (self.popup.as_ref()?.height() / 2).max(1) as isize;
+        match ev {
+            Event::Key(key_event) if key_event.modifiers == KeyModifiers::CONTROL => {
+                match key_event.code {
+                    event::KeyCode::Char('e') => Some(Action::ScrollPopup(1)),
+                    event::KeyCode::Char('y') => Some(Action::ScrollPopup(-1)),
+                    event::KeyCode::Char('d') => Some(Action::ScrollPopup(half_page)),
+                    event::KeyCode::Char('u') => Some(Action::ScrollPopup(-half_page)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn handle_command_event(&mut self, ev: Event) -> anyhow::Result<Option<Action>> {
+        if let Some(prefix) = self.pending.take() {
+            return Ok(match (prefix, ev) {
+                // A second `Ctrl-r` searches the history instead.
+                ("<C-r>", Event::Key(key_event))
+                    if key_event.code == event::KeyCode::Char('r')
+                        && key_event.modifiers == KeyModifiers::CONTROL =>
+                {
+                    Some(Action::SearchHistory)
+                }
+                ("<C-r>", ev) => register_key(ev).map(Action::PasteRegister),
+                _ => None,
+            });
+        }
+        let action = match ev {
+            Event::Key(key_event) => match key_event.kind {
+                event::KeyEventKind::Press => match key_event.code {
+                    event::KeyCode::Esc => Some(Action::EnterMode(Mode::Normal)),
+                    event::KeyCode::Enter => Some(Action::ExecuteCommand),
+                    event::KeyCode::Backspace => Some(Action::CommandBackspace),
+                    event::KeyCode::Tab => Some(Action::CommandComplete { backward: false }),
+                    event::KeyCode::BackTab => Some(Action::CommandComplete { backward: true }),
+                    event::KeyCode::Up => Some(Action::RecallHistory { older: true }),
+                    event::KeyCode::Down => Some(Action::RecallHistory { older: false }),
+                    event::KeyCode::Char('r') if key_event.modifiers == KeyModifiers::CONTROL => {
+                        match self.recall {
+                            Some(Recall::Reverse { .. }) => Some(Action::SearchHistory),
+                            _ => {
+                                self.pending = Some("<C-r>");
+                                None
+                            }
+                        }
+                    }
+                    event::KeyCode::Char(c) => Some(Action::CommandChar(c)),
+                    _ => None,
+                },
+                _ => None,
+            },
+            Event::Paste(text) => Some(Action::Paste(text)),
+            _ => None,
+        };
+        Ok(action)
+    }
+
+    fn handle_insert_event(&mut self, ev: Event) -> anyhow::Result<Option<Action>> {
+        if self.pending.take() == Some("<C-r>") {
+            return Ok(register_key(ev).map(Action::PasteRegister));
+        }
+        let menu_open = self.completion.is_some();
+        let action = match ev {
+            Event::Key(key_event) => match key_event.kind {
+                event::KeyEventKind::Press => match key_event.code {
+                    event::KeyCode::Char('n') if key_event.modifiers == KeyModifiers::CONTROL => {
+                        if menu_open {
+                            Some(Action::CompletionNext)
+                        } else {
+                            Some(Action::CompleteWords { backward: false })
+                        }
+                    }
+                    event::KeyCode::Char('p') if key_event.modifiers == KeyModifiers::CONTROL => {
+                        if menu_open {
+                            Some(Action::CompletionPrev)
+                        } else {
+                            Some(Action::CompleteWords { backward: true })
+                        }
+                    }
+                    event::KeyCode::Char(' ') if key_event.modifiers == KeyModifiers::CONTROL => {
+                        Some(Action::TriggerCompletion)
+                    }
+                    event::KeyCode::Char('r') if key_event.modifiers == KeyModifiers::CONTROL => {
+                        self.pending = Some("<C-r>");
+                        None
+                    }
+                    event::KeyCode::Char('w') if key_event.modifiers == KeyModifiers::CONTROL => {
+                        Some(Action::DeleteBack { line: false })
+                    }
+                    event::KeyCode::Char('u') if key_event.modifiers == KeyModifiers::CONTROL => {
+                        Some(Action::DeleteBack { line: true })
+                    }
+                    event::KeyCode::Char('y') if key_event.modifiers == KeyModifiers::CONTROL => {
+                        Some(Action::AcceptCompletion)
+                    }
+                    event::KeyCode::Char('e') if key_event.modifiers == KeyModifiers::CONTROL => {
+                        Some(Action::CloseCompletion)
+                    }
+                    event::KeyCode::Tab if menu_open => Some(Action::CompletionNext),
+                    event::KeyCode::BackTab if menu_open => Some(Action::CompletionPrev),
+                    event::KeyCode::Tab if self.snippet.is_some() => {
+                        Some(Action::JumpTabStop { backward: false })
+                    }
+                    event::KeyCode::BackTab if self.snippet.is_some() => {
+                        Some(Action::JumpTabStop { backward: true })
+                    }
+                    event::KeyCode::Tab if self.snippet_trigger().is_some() => {
+                        Some(Action::ExpandSnippet)
+                    }
+                    event::KeyCode::Tab => Some(Action::CompletePath),
+                    event::KeyCode::Enter if menu_open => Some(Action::AcceptCompletion),
+                    event::KeyCode::Esc => Some(Action::EnterMode(Mode::Normal)),
+                    event::KeyCode::Up => Some(Action::MoveUp),
+                    event::KeyCode::Down => Some(Action::MoveDown),
+                    event::KeyCode::Left => Some(Action::MoveLeft),
+                    event::KeyCode::Right => Some(Action::MoveRight),
+                    event::KeyCode::Enter => Some(Action::NewLine),
+                    event::KeyCode::Char(c) => Some(Action::InsertChar(c)),
+                    event::KeyCode::PageDown => Some(Action::PageDown),
+                    event::KeyCode::PageUp => Some(Action::PageUp),
+                    event::KeyCode::End => Some(Action::EndOfLine),
+                    event::KeyCode::Home => Some(Action::SmartHome),
+                    event::KeyCode::Backspace => Some(Action::DelCharBefore),
+                    event::KeyCode::Delete => Some(Action::DelCharAtCursor),
+                    _ => None,
+                },
+                _ => None,
+            },
+            Event::Paste(text) => Some(Action::Paste(text)),
+            _ => None,
+        };
+        Ok(action)
+    }
+
+    fn handle_normal_event(&mut self, ev: Event) -> anyhow::Result<Option<Action>> {
+        let mapped = match &ev {
+            Event::Key(key_event)
+                if key_event.kind == event::KeyEventKind::Press && self.pending.is_none() =>
+            {
+                self.map_key(MapMode::Normal, key_event)
+            }
+            _ => None,
+        };
+        if let Some(action) = mapped {
+            return Ok(action);
+        }
+        if let Some(prefix) = self.pending.take() {
+            return self.handle_pending_event(prefix, ev);
+        }
+        match ev {
+            Event::Key(key_event)
+                if key_event.kind == event::KeyEventKind::Press
+                    && self.plugins.maps(MapMode::Normal, &key_event) =>
+            {
+                return Ok(Some(Action::RunKeymap(key_event)));
+            }
+            _ => {}
+        }
+
+        let action = match ev {
             Event::Key(key_event) => match key_event.kind {
                 event::KeyEventKind::Press => match key_event.code {
-                    event::KeyCode::Char('q') => Some(Action::Quit),
+                    event::KeyCode::Char('q') => Some(Action::Quit(Quit::default())),
+                    event::KeyCode::Char('Z') => {
+                        self.pending = Some("Z");
+                        None
+                    }
+                    event::KeyCode::Char(':') => Some(Action::EnterMode(Mode::Command)),
+                    event::KeyCode::Char('/') => Some(Action::StartSearch { backward: false }),
+                    event::KeyCode::Char('?') => Some(Action::StartSearch { backward: true }),
+                    event::KeyCode::Char('n') => Some(Action::SearchNext { reverse: false }),
+                    event::KeyCode::Char('N') => Some(Action::SearchNext { reverse: true }),
+                    event::KeyCode::Char('*') => Some(Action::SearchWord {
+                        backward: false,
+                        partial: false,
+                    }),
+                    event::KeyCode::Char('#') => Some(Action::SearchWord {
+                        backward: true,
+                        partial: false,
+                    }),
+                    event::KeyCode::Char('K') => Some(Action::Hover),
+                    event::KeyCode::Char('-') => Some(Action::OpenParent),
+                    event::KeyCode::Enter if self.buffer.listing.is_some() => {
+                        Some(Action::OpenEntry)
+                    }
+                    event::KeyCode::Char('g') => {
+                        self.pending = Some("g");
+                        None
+                    }
+                    event::KeyCode::Char(']') => {
+                        self.pending = Some("]");
+                        None
+                    }
+                    event::KeyCode::Char('[') => {
+                        self.pending = Some("[");
+                        None
+                    }
+                    event::KeyCode::Char('d') => {
+                        self.pending = Some("d");
+                        None
+                    }
+                    event::KeyCode::Char('w') if key_event.modifiers == KeyModifiers::CONTROL => {
+                        self.pending = Some("<C-w>");
+                        None
+                    }
+                    event::KeyCode::Up | event::KeyCode::Char('k')
+                        if key_event.modifiers == KeyModifiers::ALT =>
+                    {
+                        Some(Action::DragLines { down: false })
+                    }
+                    event::KeyCode::Down | event::KeyCode::Char('l')
+                        if key_event.modifiers == KeyModifiers::ALT =>
+                    {
+                        Some(Action::DragLines { down: true })
+                    }
                     event::KeyCode::Up | event::KeyCode::Char('k') => Some(Action::MoveUp),
                     event::KeyCode::Down | event::KeyCode::Char('l') => Some(Action::MoveDown),
                     event::KeyCode::Left | event::KeyCode::Char('j') => Some(Action::MoveLeft),
                     event::KeyCode::Right | event::KeyCode::Char(';') => Some(Action::MoveRight),
                     event::KeyCode::Char('i') => Some(Action::EnterMode(Mode::Insert)),
+                    event::KeyCode::Char('v') => Some(Action::EnterMode(Mode::Visual)),
                     event::KeyCode::PageDown => Some(Action::PageDown),
                     event::KeyCode::PageUp => Some(Action::PageUp),
                     event::KeyCode::Char('f') if key_event.modifiers == KeyModifiers::CONTROL => {
@@ -398,8 +6642,38 @@ impl Editor {
                         Some(Action::PageUp)
                     }
                     event::KeyCode::Char('$') | event::KeyCode::End => Some(Action::EndOfLine),
-                    event::KeyCode::Char('0') | event::KeyCode::Home => Some(Action::StartOfLine),
-                    event::KeyCode::Char('x') => Some(Action::DelCharAtCursor),
+                    event::KeyCode::Char('0') => Some(Action::StartOfLine),
+                    event::KeyCode::Char('^' | '_') => Some(Action::FirstNonBlank),
+                    event::KeyCode::Char(c @ ('{' | '}' | '(' | ')')) => {
+                        let (line, col) = self.motion_target(c);
+                        Some(Action::Goto(line, col))
+                    }
+                    event::KeyCode::Home => Some(Action::SmartHome),
+                    event::KeyCode::Char('x') => Some(Action::DeleteChars(self.count.unwrap_or(1))),
+                    event::KeyCode::Char('p') if key_event.modifiers == KeyModifiers::CONTROL => {
+                        self.pick_command();
+                        None
+                    }
+                    event::KeyCode::Char(c @ ('p' | 'P')) => Some(Action::Put {
+                        before: c == 'P',
+                        count: self.count.unwrap_or(1),
+                    }),
+                    event::KeyCode::Char('J') => Some(Action::JoinLines(self.count.unwrap_or(1))),
+                    event::KeyCode::Char('u') => Some(Action::Undo),
+                    event::KeyCode::Char('r') if key_event.modifiers == KeyModifiers::CONTROL => {
+                        Some(Action::Redo)
+                    }
+                    event::KeyCode::Char('z') if key_event.modifiers == KeyModifiers::CONTROL => {
+                        Some(Action::Suspend)
+                    }
+                    event::KeyCode::Char('z') => {
+                        self.pending = Some("z");
+                        None
+                    }
+                    event::KeyCode::Char('m') => {
+                        self.pending = Some("m");
+                        None
+                    }
 
                     _ => None,
                 },
@@ -409,4 +6683,364 @@ impl Editor {
         };
         Ok(action)
     }
+
+    /// Moves over the selection in visual mode and acts on it. Plugins
+    /// mapping a key in visual mode take it over from the editor.
+    fn handle_visual_event(&mut self, ev: Event) -> Option<Action> {
+        let Event::Key(key_event) = ev else {
+            return None;
+        };
+        if key_event.kind != event::KeyEventKind::Press {
+            return None;
+        }
+        if let Some(prefix) = self.pending.take() {
+            return match (prefix, key_event.code) {
+                ("z", event::KeyCode::Char('f')) => {
+                    let range = self.selection();
+                    Some(Action::CreateFold {
+                        first: self.buffer.text.char_to_line(range.start),
+                        last: self.buffer.text.char_to_line(range.end.saturating_sub(1)),
+                    })
+                }
+                _ => None,
+            };
+        }
+        if let Some(action) = self.map_key(MapMode::Visual, &key_event) {
+            return action;
+        }
+        if self.plugins.maps(MapMode::Visual, &key_event) {
+            return Some(Action::RunKeymap(key_event));
+        }
+        match key_event.code {
+            event::KeyCode::Esc | event::KeyCode::Char('v') => {
+                Some(Action::EnterMode(Mode::Normal))
+            }
+            event::KeyCode::Up | event::KeyCode::Char('k')
+                if key_event.modifiers == KeyModifiers::ALT =>
+            {
+                Some(Action::DragLines { down: false })
+            }
+            event::KeyCode::Down | event::KeyCode::Char('l')
+                if key_event.modifiers == KeyModifiers::ALT =>
+            {
+                Some(Action::DragLines { down: true })
+            }
+            event::KeyCode::Up | event::KeyCode::Char('k') => Some(Action::MoveUp),
+            event::KeyCode::Down | event::KeyCode::Char('l') => Some(Action::MoveDown),
+            event::KeyCode::Left | event::KeyCode::Char('j') => Some(Action::MoveLeft),
+            event::KeyCode::Right | event::KeyCode::Char(';') => Some(Action::MoveRight),
+            event::KeyCode::Char('$') | event::KeyCode::End => Some(Action::EndOfLine),
+            event::KeyCode::Char(':') => Some(Action::EnterMode(Mode::Command)),
+            event::KeyCode::Char('0') => Some(Action::StartOfLine),
+            event::KeyCode::Char('^' | '_') => Some(Action::FirstNonBlank),
+            event::KeyCode::Char(c @ ('{' | '}' | '(' | ')')) => {
+                let (line, col) = self.motion_target(c);
+                Some(Action::Goto(line, col))
+            }
+            event::KeyCode::Home => Some(Action::SmartHome),
+            event::KeyCode::Char('u') => Some(Action::ChangeCase(Case::Lower)),
+            event::KeyCode::Char('U') => Some(Action::ChangeCase(Case::Upper)),
+            event::KeyCode::Char('~') => Some(Action::ChangeCase(Case::Toggle)),
+            event::KeyCode::Char('d' | 'x') => Some(Action::DeleteSelection),
+            event::KeyCode::Char('o' | 'O') => Some(Action::SwapSelectionEnds),
+            event::KeyCode::Char('p' | 'P') => Some(Action::PasteOverSelection),
+            event::KeyCode::Char('z') => {
+                self.pending = Some("z");
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Handles the keys following the first of a normal mode command such
+    /// as `g;` or `gcc`.
+    fn handle_pending_event(
+        &mut self,
+        prefix: &'static str,
+        ev: Event,
+    ) -> anyhow::Result<Option<Action>> {
+        let line = self.buffer_line();
+        let last_line = self.buffer.len().saturating_sub(1);
+        let action = match ev {
+            Event::Key(key_event) => match (prefix, key_event.code) {
+                // Lines are not wrapped, so screen rows are the rows of
+                // lines and of closed folds that `k` and `l` already move
+                // over, and the ends of the row are those scrolled into view.
+                ("g", event::KeyCode::Char('k') | event::KeyCode::Up) => Some(Action::MoveUp),
+                ("g", event::KeyCode::Char('l') | event::KeyCode::Down) => Some(Action::MoveDown),
+                ("g", event::KeyCode::Char('0') | event::KeyCode::Home) => {
+                    Some(Action::Goto(line, self.vleft as usize))
+                }
+                ("g", event::KeyCode::Char('$') | event::KeyCode::End) => {
+                    let width = (self.vwidth() - self.gutter_width()).max(1) as usize;
+                    let col = (self.vleft as usize + width - 1).min(self.line_length() as usize);
+                    Some(Action::Goto(line, col))
+                }
+                ("g", event::KeyCode::Char('v')) => Some(Action::Reselect),
+                ("g", event::KeyCode::Char(';')) => Some(Action::OlderChange),
+                ("g", event::KeyCode::Char('f')) => Some(Action::FollowLink { external: false }),
+                ("g", event::KeyCode::Char('x')) => Some(Action::FollowLink { external: true }),
+                ("g", event::KeyCode::Char(',')) => Some(Action::NewerChange),
+                ("g", event::KeyCode::Char('-')) => Some(Action::OlderState),
+                ("g", event::KeyCode::Char('+')) => Some(Action::NewerState),
+                ("g", event::KeyCode::Char('*')) => Some(Action::SearchWord {
+                    backward: false,
+                    partial: true,
+                }),
+                ("g", event::KeyCode::Char('#')) => Some(Action::SearchWord {
+                    backward: true,
+                    partial: true,
+                }),
+                ("]", event::KeyCode::Char(c @ (']' | 'm')))
+                | ("[", event::KeyCode::Char(c @ ('[' | 'm'))) => {
+                    let backward = prefix == "[";
+                    let count = self.count.unwrap_or(1);
+                    let last = self.last_line();
+                    let text = &self.buffer.text;
+                    motion::section(text, line, last, backward, count, c == 'm')
+                        .map(|line| Action::Goto(line, self.first_non_blank(line)))
+                }
+                ("]", event::KeyCode::Char('c')) => Some(Action::NextHunk { backward: false }),
+                ("[", event::KeyCode::Char('c')) => Some(Action::NextHunk { backward: true }),
+                ("d", event::KeyCode::Char('o')) => Some(Action::DiffCopy { put: false }),
+                ("d", event::KeyCode::Char('p')) => Some(Action::DiffCopy { put: true }),
+                ("<C-w>", event::KeyCode::Char('w' | 'j' | ';')) => Some(Action::SwitchWindow),
+                ("<C-w>", event::KeyCode::Char('c')) => Some(Action::CloseWindow),
+                ("<C-w>", event::KeyCode::Char('q')) => Some(Action::Quit(Quit::default())),
+                ("Z", event::KeyCode::Char('Z')) => Some(Action::Quit(Quit {
+                    write: true,
+                    ..Quit::default()
+                })),
+                ("Z", event::KeyCode::Char('Q')) => Some(Action::Quit(Quit {
+                    force: true,
+                    ..Quit::default()
+                })),
+                ("]", event::KeyCode::Char('x')) => Some(Action::NextConflict { backward: false }),
+                ("[", event::KeyCode::Char('x')) => Some(Action::NextConflict { backward: true }),
+                ("g", event::KeyCode::Char('c')) => {
+                    self.pending = Some("gc");
+                    None
+                }
+                // The lines a motion moves over, or the cursor line for
+                // motions within it.
+                ("gc", event::KeyCode::Char('k') | event::KeyCode::Up) => {
+                    Some(Action::ToggleComment {
+                        first: line.saturating_sub(1),
+                        last: line,
+                    })
+                }
+                ("gc", event::KeyCode::Char('l') | event::KeyCode::Down) => {
+                    Some(Action::ToggleComment {
+                        first: line,
+                        last: self.buffer.folds.next_row(line).min(last_line),
+                    })
+                }
+                ("m", event::KeyCode::Char(c)) if c.is_ascii_lowercase() => {
+                    Some(Action::SetMark(c))
+                }
+                ("z", event::KeyCode::Char(c)) if fold_op(c).is_some() => {
+                    fold_op(c).map(Action::Fold)
+                }
+                ("z", event::KeyCode::Char('f')) => {
+                    self.pending = Some("zf");
+                    None
+                }
+                ("zf", event::KeyCode::Char('k') | event::KeyCode::Up) => {
+                    Some(Action::CreateFold {
+                        first: line.saturating_sub(1),
+                        last: line,
+                    })
+                }
+                ("zf", event::KeyCode::Char('l') | event::KeyCode::Down) => {
+                    Some(Action::CreateFold {
+                        first: line,
+                        last: self.buffer.folds.next_row(line).min(last_line),
+                    })
+                }
+                ("gc" | "zf", event::KeyCode::Char(c @ ('{' | '}' | '(' | ')'))) => {
+                    let (first, last) = self.motion_lines(c);
+                    match prefix {
+                        "gc" => Some(Action::ToggleComment { first, last }),
+                        _ => Some(Action::CreateFold { first, last }),
+                    }
+                }
+                (
+                    "gc",
+                    event::KeyCode::Char('c' | 'j' | ';' | '0' | '$')
+                    | event::KeyCode::Left
+                    | event::KeyCode::Right
+                    | event::KeyCode::Home
+                    | event::KeyCode::End,
+                ) => Some(Action::ToggleComment {
+                    first: line,
+                    last: line,
+                }),
+                _ => None,
+            },
+            _ => {
+                self.pending = Some(prefix);
+                None
+            }
+        };
+        Ok(action)
+    }
+}
+
+/// The columns of the indent guides of `line`: the indentation of each
+/// line above it that is less indented than those after it, down to the
+/// first column.
+fn guide_columns(levels: &[usize], line: usize) -> Vec<usize> {
+    let mut limit = levels.get(line).copied().unwrap_or(0);
+    let mut columns = Vec::new();
+    for &level in levels[..line.min(levels.len())].iter().rev() {
+        if limit == 0 {
+            break;
+        }
+        if level < limit {
+            columns.push(level);
+            limit = level;
+        }
+    }
+    columns
+}
+
+/// The op of the `z` command ending in `c`, other than `zf`.
+fn fold_op(c: char) -> Option<FoldOp> {
+    Some(match c {
+        'a' => FoldOp::Toggle,
+        'o' => FoldOp::Open,
+        'c' => FoldOp::Close,
+        'd' => FoldOp::Delete,
+        'R' => FoldOp::OpenAll,
+        'M' => FoldOp::CloseAll,
+        'E' => FoldOp::DeleteAll,
+        _ => return None,
+    })
+}
+
+/// The register named by the key typed after `Ctrl-r`.
+fn register_key(ev: Event) -> Option<char> {
+    match ev {
+        Event::Key(key_event)
+            if key_event.kind == event::KeyEventKind::Press
+                && !key_event.modifiers.contains(KeyModifiers::CONTROL) =>
+        {
+            match key_event.code {
+                event::KeyCode::Char(c) => Some(c),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Maps keys to picker input: typing edits the query, Tab marks, Ctrl-o
+/// opens the action menu.
+fn handle_picker_event(ev: Event) -> Option<Action> {
+    let Event::Key(key_event) = ev else {
+        return None;
+    };
+    if key_event.kind != event::KeyEventKind::Press {
+        return None;
+    }
+    let control = key_event.modifiers == KeyModifiers::CONTROL;
+    let input = match key_event.code {
+        event::KeyCode::Esc => picker::Input::Cancel,
+        event::KeyCode::Enter => picker::Input::Accept,
+        event::KeyCode::Backspace => picker::Input::Backspace,
+        event::KeyCode::Tab => picker::Input::ToggleMark,
+        event::KeyCode::Up => picker::Input::Up,
+        event::KeyCode::Down => picker::Input::Down,
+        event::KeyCode::PageUp => picker::Input::PageUp,
+        event::KeyCode::PageDown => picker::Input::PageDown,
+        event::KeyCode::Char('p' | 'k') if control => picker::Input::Up,
+        event::KeyCode::Char('n' | 'j') if control => picker::Input::Down,
+        event::KeyCode::Char('o') if control => picker::Input::Menu,
+        event::KeyCode::Char(c) if !control => picker::Input::Char(c),
+        _ => return None,
+    };
+    Some(Action::PickerInput(input))
+}
+
+/// Maps keys to moves over the rows of a segmented buffer, leaving out
+/// those that edit it.
+fn handle_segments_event(ev: Event) -> Option<Action> {
+    let Event::Key(key_event) = ev else {
+        return None;
+    };
+    if key_event.kind != event::KeyEventKind::Press {
+        return None;
+    }
+    Some(match key_event.code {
+        event::KeyCode::Char('q') => Action::Quit(Quit::default()),
+        event::KeyCode::Char(':') => Action::EnterMode(Mode::Command),
+        event::KeyCode::Up | event::KeyCode::Char('k') => Action::MoveUp,
+        event::KeyCode::Down | event::KeyCode::Char('l') => Action::MoveDown,
+        event::KeyCode::Left | event::KeyCode::Char('j') => Action::MoveLeft,
+        event::KeyCode::Right | event::KeyCode::Char(';') => Action::MoveRight,
+        event::KeyCode::PageDown => Action::PageDown,
+        event::KeyCode::PageUp => Action::PageUp,
+        event::KeyCode::Char('$') | event::KeyCode::End => Action::EndOfLine,
+        event::KeyCode::Char('0') | event::KeyCode::Home => Action::StartOfLine,
+        _ => return None,
+    })
+}
+
+/// The start of a text file, or a note saying it cannot be shown.
+fn preview_file(path: &Path) -> Vec<String> {
+    match fs::read(path) {
+        Result::Ok(bytes) if bytes.contains(&0) => vec!["(binary file)".to_string()],
+        Result::Ok(bytes) => String::from_utf8_lossy(&bytes)
+            .lines()
+            .take(100)
+            .map(|line| line.replace('\t', "    "))
+            .collect(),
+        Err(err) => vec![err.to_string()],
+    }
+}
+
+/// `text` in the colors of `style`.
+fn styled(caps: &Capabilities, text: String, style: Style) -> StyledContent<String> {
+    let color = |rgb: Rgb| caps.rgb(rgb.0, rgb.1, rgb.2);
+    let mut text = text.stylize();
+    if let Some(fg) = style.fg {
+        text = text.with(color(fg));
+    }
+    if let Some(bg) = style.bg {
+        text = text.on(color(bg));
+    }
+    if style.underline {
+        text = text.underlined();
+    }
+    text
+}
+
+fn severity_color(severity: Severity) -> style::Color {
+    match severity {
+        Severity::Error => style::Color::Red,
+        Severity::Warning => style::Color::Yellow,
+        Severity::Info => style::Color::Blue,
+        Severity::Hint => style::Color::Cyan,
+    }
+}
+
+/// The language server for `buffer`, held back until its project is
+/// trusted: `trust_workspace` starts it then.
+fn start_lsp(buffer: &Buffer) -> Option<LspClient> {
+    if !trust::is_trusted(Path::new(&buffer.path)) {
+        return None;
+    }
+    LspClient::for_buffer(buffer).unwrap_or_else(|err| {
+        error!("{err:#}");
+        None
+    })
+}
+
+/// The snippets for a filetype, falling back to the built-in ones when the
+/// user's are unreadable.
+fn load_snippets(filetype: Option<&'static str>) -> Vec<Snippet> {
+    snippets::load(filetype).unwrap_or_else(|err| {
+        error!("{err:#}");
+        filetype.map(snippets::builtin).unwrap_or_default()
+    })
 }
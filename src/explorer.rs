@@ -0,0 +1,263 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+
+use crate::diff;
+
+/// A filesystem operation derived from editing a directory listing.
+#[derive(Debug)]
+pub enum FsOp {
+    Create(PathBuf),
+    Rename(PathBuf, PathBuf),
+    Delete(PathBuf),
+}
+
+impl fmt::Display for FsOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FsOp::Create(path) => write!(f, "  CREATE  {}", path.display()),
+            FsOp::Rename(from, to) => {
+                write!(f, "  RENAME  {} -> {}", from.display(), to.display())
+            }
+            FsOp::Delete(path) => write!(f, "  DELETE  {}", path.display()),
+        }
+    }
+}
+
+/// Lists a directory with subdirectories first, each marked by a trailing
+/// `/`.
+pub fn read_dir(dir: &Path) -> anyhow::Result<Vec<String>> {
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type()?.is_dir() {
+            dirs.push(format!("{name}/"));
+        } else {
+            files.push(name);
+        }
+    }
+    dirs.sort();
+    files.sort();
+    dirs.extend(files);
+    Ok(dirs)
+}
+
+/// Directories never worth descending into when listing a project's files.
+const SKIPPED_DIRS: &[&str] = &["target", "node_modules"];
+
+/// Lists the files under `root` as paths relative to it, skipping hidden
+/// entries and build directories, stopping after `limit` files.
+pub fn walk(root: &Path, limit: usize) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(root.join(&dir)) else {
+            continue;
+        };
+        let mut entries: Vec<_> = entries.filter_map(Result::ok).collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.file_name()));
+        for entry in entries {
+            let name = entry.file_name();
+            let path = dir.join(&name);
+            let name = name.to_string_lossy();
+            if name.starts_with('.') {
+                continue;
+            }
+            match entry.file_type() {
+                Ok(kind) if kind.is_dir() => {
+                    if !SKIPPED_DIRS.contains(&name.as_ref()) {
+                        dirs.push(path);
+                    }
+                }
+                Ok(_) => {
+                    files.push(path);
+                    if files.len() == limit {
+                        return files;
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+    }
+    files
+}
+
+/// Works out the operations that turn the `original` listing into the
+/// `edited` one. A changed hunk of as many lines on both sides is taken as
+/// lines renamed in place, paired up in order; otherwise which line became
+/// which cannot be told, and a hunk with lines on both sides is refused
+/// rather than guessed at. A name deleted in one hunk and added in another
+/// was only moved, and is left alone.
+pub fn plan(dir: &Path, original: &[String], edited: &[String]) -> anyhow::Result<Vec<FsOp>> {
+    let edited: Vec<String> = edited
+        .iter()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+
+    let mut seen = HashSet::new();
+    for name in &edited {
+        if matches!(name.trim_end_matches('/'), "." | "..") || name.starts_with('/') {
+            bail!("Invalid entry: {name}");
+        }
+        if !seen.insert(name.trim_end_matches('/')) {
+            bail!("Duplicate entry: {name}");
+        }
+    }
+
+    let mut deletes = Vec::new();
+    let mut renames = Vec::new();
+    let mut creates = Vec::new();
+    let mut removed = Vec::new();
+    let mut added = Vec::new();
+    for hunk in diff::diff(original, &edited) {
+        let old = &original[hunk.old];
+        let new = &edited[hunk.new];
+        if old.len() == new.len() {
+            for (from, to) in old.iter().zip(new) {
+                renames.push(FsOp::Rename(dir.join(from), dir.join(to)));
+            }
+        } else if !old.is_empty() && !new.is_empty() {
+            bail!(
+                "Cannot tell which of {} became {}: write renames and deletions separately",
+                old.join(", "),
+                new.join(", ")
+            );
+        } else {
+            removed.extend(old);
+            added.extend(new);
+        }
+    }
+    for name in &removed {
+        if !added.contains(name) {
+            deletes.push(FsOp::Delete(dir.join(name)));
+        }
+    }
+    for name in &added {
+        if !removed.contains(name) {
+            creates.push(FsOp::Create(dir.join(name)));
+        }
+    }
+
+    deletes.extend(renames);
+    deletes.extend(creates);
+    Ok(deletes)
+}
+
+/// Applies the operations, once they are all known to be possible. The
+/// entries renamed or deleted are first moved aside to temporary names,
+/// so that no rename finds its new name still taken, and whatever was done
+/// is undone if an operation fails. Entries deleted are only removed once
+/// everything else succeeded.
+pub fn apply(ops: &[FsOp]) -> anyhow::Result<()> {
+    check(ops)?;
+    let mut done = Vec::new();
+    let mut aside = Vec::new();
+    if let Err(err) = stage(ops, &mut done, &mut aside) {
+        for step in done.into_iter().rev() {
+            let undone = match &step {
+                Done::Moved(from, to) => fs::rename(to, from),
+                Done::Created(path) if path.is_dir() => fs::remove_dir(path),
+                Done::Created(path) => fs::remove_file(path),
+            };
+            if let Err(undo_err) = undone {
+                return Err(err.context(format!("and could not undo {step}: {undo_err}")));
+            }
+        }
+        return Err(err.context("No change made"));
+    }
+    for path in aside {
+        let removed = match path.is_dir() {
+            true => fs::remove_dir_all(&path),
+            false => fs::remove_file(&path),
+        };
+        removed.with_context(|| format!("Could not remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// A step of applying operations, undone if a later one fails.
+enum Done {
+    Moved(PathBuf, PathBuf),
+    Created(PathBuf),
+}
+
+impl fmt::Display for Done {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Done::Moved(from, to) => write!(f, "{} -> {}", from.display(), to.display()),
+            Done::Created(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Checks that the entries renamed and deleted exist, and that no entry
+/// is created or renamed to a name taken by one that stays.
+fn check(ops: &[FsOp]) -> anyhow::Result<()> {
+    let mut freed = HashSet::new();
+    for op in ops {
+        if let FsOp::Rename(path, _) | FsOp::Delete(path) = op {
+            if path.symlink_metadata().is_err() {
+                bail!("{} does not exist", path.display());
+            }
+            freed.insert(path.as_path());
+        }
+    }
+    let targets = ops.iter().filter_map(|op| match op {
+        FsOp::Rename(_, path) | FsOp::Create(path) => Some(path),
+        FsOp::Delete(_) => None,
+    });
+    for path in targets {
+        if path.symlink_metadata().is_ok() && !freed.contains(path.as_path()) {
+            bail!("{} already exists", path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Carries out the operations, recording each step in `done`, and leaving
+/// the entries deleted under the temporary names in `aside`.
+fn stage(ops: &[FsOp], done: &mut Vec<Done>, aside: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let mut renamed = Vec::new();
+    for (i, op) in ops.iter().enumerate() {
+        let (FsOp::Rename(path, _) | FsOp::Delete(path)) = op else {
+            continue;
+        };
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp = path.with_file_name(format!(".{name}.vim-rs-{}-{i}", std::process::id()));
+        fs::rename(path, &temp).with_context(|| format!("Could not move {}", path.display()))?;
+        done.push(Done::Moved(path.clone(), temp.clone()));
+        match op {
+            FsOp::Rename(_, to) => renamed.push((temp, to)),
+            _ => aside.push(temp),
+        }
+    }
+    for (temp, to) in renamed {
+        if let Some(parent) = to.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(&temp, to).with_context(|| format!("Could not rename to {}", to.display()))?;
+        done.push(Done::Moved(temp, to.clone()));
+    }
+    for op in ops {
+        let FsOp::Create(path) = op else {
+            continue;
+        };
+        if path.to_string_lossy().ends_with('/') {
+            fs::create_dir_all(path)?;
+        } else {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::File::create_new(path)
+                .with_context(|| format!("Could not create {}", path.display()))?;
+        }
+        done.push(Done::Created(path.clone()));
+    }
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use std::path::Path;
+
+/// File extensions mapped to the filetype name used by per-language features.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("rs", "rust"),
+    ("py", "python"),
+    ("c", "c"),
+    ("h", "c"),
+    ("cpp", "cpp"),
+    ("hpp", "cpp"),
+    ("go", "go"),
+    ("js", "javascript"),
+    ("ts", "typescript"),
+    ("sh", "sh"),
+    ("lua", "lua"),
+    ("toml", "toml"),
+    ("json", "json"),
+    ("md", "markdown"),
+];
+
+/// Comment markers of each filetype, the end one empty for line comments.
+const COMMENTS: &[(&str, &str, &str)] = &[
+    ("rust", "//", ""),
+    ("python", "#", ""),
+    ("c", "//", ""),
+    ("cpp", "//", ""),
+    ("go", "//", ""),
+    ("javascript", "//", ""),
+    ("typescript", "//", ""),
+    ("sh", "#", ""),
+    ("lua", "--", ""),
+    ("toml", "#", ""),
+    ("markdown", "<!--", "-->"),
+];
+
+pub fn detect(path: &str) -> Option<&'static str> {
+    let ext = Path::new(path).extension()?.to_str()?;
+    EXTENSIONS
+        .iter()
+        .find(|(e, _)| *e == ext)
+        .map(|(_, filetype)| *filetype)
+}
+
+/// The start and end markers of a comment in `filetype`.
+pub fn comment(filetype: &str) -> Option<(&'static str, &'static str)> {
+    COMMENTS
+        .iter()
+        .find(|(ft, _, _)| *ft == filetype)
+        .map(|&(_, start, end)| (start, end))
+}
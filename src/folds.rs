@@ -0,0 +1,303 @@
+//! Folds: ranges of lines shown as a single row while closed. They are made
+//! by hand with `zf`, or from indentation with `foldmethod=indent`, and move
+//! with the lines around them as the buffer is edited.
+//!
+//! The row of a closed fold is laid out by the `foldtext` option, where
+//! `%n` is the number of lines folded, `%t` the first of them without its
+//! indentation, `%d` a dash for each level of folds holding it and `%%` a
+//! percent sign. Left empty, it reads `+--  4 lines: fn main() {`.
+
+use std::ops::RangeInclusive;
+
+use ropey::Rope;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fold {
+    /// The first line stays shown as the row standing for the fold.
+    pub start: usize,
+    pub end: usize,
+    pub closed: bool,
+}
+
+/// What the `z` commands do to the folds at the cursor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FoldOp {
+    Toggle,
+    Open,
+    Close,
+    Delete,
+    OpenAll,
+    CloseAll,
+    DeleteAll,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Folds {
+    /// Sorted by start, each fold before the folds it holds.
+    folds: Vec<Fold>,
+    /// The revision of the buffer the folds were last made from indentation
+    /// for.
+    pub indented_for: Option<usize>,
+}
+
+impl Folds {
+    /// Lines of the outermost closed fold holding `line`.
+    pub fn closed_at(&self, line: usize) -> Option<RangeInclusive<usize>> {
+        self.folds
+            .iter()
+            .find(|fold| fold.closed && fold.start <= line && line <= fold.end)
+            .map(|fold| fold.start..=fold.end)
+    }
+
+    /// The first line of the row `line` is shown on.
+    pub fn row_start(&self, line: usize) -> usize {
+        self.closed_at(line).map_or(line, |lines| *lines.start())
+    }
+
+    /// The first line of the row after the one `line` is shown on.
+    pub fn next_row(&self, line: usize) -> usize {
+        self.closed_at(line).map_or(line, |lines| *lines.end()) + 1
+    }
+
+    /// The first line of the row `n` rows above the one `line` is on, or
+    /// of the first row.
+    pub fn rows_up(&self, mut line: usize, n: usize) -> usize {
+        line = self.row_start(line);
+        for _ in 0..n {
+            match line.checked_sub(1) {
+                Some(above) => line = self.row_start(above),
+                None => break,
+            }
+        }
+        line
+    }
+
+    /// The first line of the row `n` rows below the one `line` is on, not
+    /// going past the row of `last`.
+    pub fn rows_down(&self, mut line: usize, n: usize, last: usize) -> usize {
+        line = self.row_start(line);
+        for _ in 0..n {
+            match self.next_row(line) {
+                below if below <= last => line = below,
+                _ => break,
+            }
+        }
+        line
+    }
+
+    /// How many folds hold the lines of `fold`, itself included.
+    pub fn level(&self, fold: &RangeInclusive<usize>) -> usize {
+        self.folds
+            .iter()
+            .filter(|f| f.start <= *fold.start() && *fold.end() <= f.end)
+            .count()
+    }
+
+    /// How many rows down from the one `from` is on the row of `to` is.
+    pub fn rows_between(&self, from: usize, to: usize) -> usize {
+        let (mut line, mut rows) = (self.row_start(from), 0);
+        while self.next_row(line) <= to {
+            line = self.next_row(line);
+            rows += 1;
+        }
+        rows
+    }
+
+    /// Adds a closed fold over the lines from `start` to `end`, which must
+    /// be two at least.
+    pub fn create(&mut self, start: usize, end: usize) -> bool {
+        if end <= start {
+            return false;
+        }
+        match self
+            .folds
+            .iter_mut()
+            .find(|fold| fold.start == start && fold.end == end)
+        {
+            Some(fold) => fold.closed = true,
+            None => self.insert(Fold {
+                start,
+                end,
+                closed: true,
+            }),
+        }
+        true
+    }
+
+    /// Carries out `op` on the folds holding `line`, returning whether
+    /// there was one to, which the ops on every fold need not.
+    pub fn apply(&mut self, op: FoldOp, line: usize) -> bool {
+        let holding = |fold: &Fold| fold.start <= line && line <= fold.end;
+        // The outermost closed fold, or the innermost fold.
+        let closed = self.folds.iter().position(|f| f.closed && holding(f));
+        let innermost = self.folds.iter().rposition(holding);
+        match op {
+            FoldOp::Toggle => match closed.or(innermost) {
+                Some(i) => self.folds[i].closed = closed.is_none(),
+                None => return false,
+            },
+            FoldOp::Open => return self.open(line),
+            FoldOp::Close => match self.folds.iter().rposition(|f| !f.closed && holding(f)) {
+                Some(i) => self.folds[i].closed = true,
+                None => return innermost.is_some(),
+            },
+            FoldOp::Delete => match closed.or(innermost) {
+                Some(i) => _ = self.folds.remove(i),
+                None => return false,
+            },
+            FoldOp::OpenAll | FoldOp::CloseAll => {
+                for fold in &mut self.folds {
+                    fold.closed = op == FoldOp::CloseAll;
+                }
+            }
+            FoldOp::DeleteAll => self.folds.clear(),
+        }
+        true
+    }
+
+    /// Opens the closed folds holding `line`, so that it is shown.
+    pub fn open(&mut self, line: usize) -> bool {
+        let mut opened = false;
+        for fold in &mut self.folds {
+            if fold.closed && fold.start <= line && line <= fold.end {
+                fold.closed = false;
+                opened = true;
+            }
+        }
+        opened
+    }
+
+    /// Moves the folds along with an edit on `line` that removed `removed`
+    /// line breaks and inserted `inserted`. Folds left with fewer than two
+    /// lines are dropped.
+    pub fn shift(&mut self, line: usize, removed: usize, inserted: usize) {
+        if removed == inserted || self.folds.is_empty() {
+            return;
+        }
+        let map = |n: usize| match n {
+            n if n <= line => n,
+            n if n > line + removed => n + inserted - removed,
+            _ => line + inserted,
+        };
+        for fold in &mut self.folds {
+            fold.start = map(fold.start);
+            fold.end = match fold.end {
+                end if end < line => end,
+                end if end > line + removed => end + inserted - removed,
+                _ => line + inserted,
+            };
+        }
+        self.folds.retain(|fold| fold.end > fold.start);
+    }
+
+    /// Makes the folds again from the indentation of `text`, each a run of
+    /// lines indented deeper than the line before it. Folds already there
+    /// keep whether they were closed, and new ones are `closed`.
+    pub fn indent(&mut self, text: &Rope, closed: bool) {
+        let old = std::mem::take(&mut self.folds);
+        let levels = indent_levels(text, 8);
+        // The indentation and first line of each fold not yet ended.
+        let mut open: Vec<(usize, usize)> = Vec::new();
+        let mut made = Vec::new();
+        for (line, &level) in levels.iter().enumerate() {
+            while open.last().is_some_and(|&(indent, _)| level < indent) {
+                let (_, start) = open.pop().unwrap_or_default();
+                made.push((start, line - 1));
+            }
+            if level > open.last().map_or(0, |&(indent, _)| indent) {
+                open.push((level, line));
+            }
+        }
+        while let Some((_, start)) = open.pop() {
+            made.push((start, levels.len() - 1));
+        }
+        for (start, end) in made.into_iter().filter(|(start, end)| end > start) {
+            let closed = old
+                .iter()
+                .find(|fold| fold.start == start)
+                .map_or(closed, |fold| fold.closed);
+            self.insert(Fold { start, end, closed });
+        }
+    }
+
+    fn insert(&mut self, fold: Fold) {
+        let at = self.folds.partition_point(|f| {
+            (f.start, usize::MAX - f.end) < (fold.start, usize::MAX - fold.end)
+        });
+        self.folds.insert(at, fold);
+    }
+}
+
+/// The indentation of each line of `text`, a tab reaching the next multiple
+/// of `tab` columns, and blank lines taking the lower of the lines around
+/// them. The empty line after a final line break is left out.
+pub fn indent_levels(text: &Rope, tab: usize) -> Vec<usize> {
+    let mut levels: Vec<Option<usize>> = text
+        .lines()
+        .map(|line| {
+            let mut indent = 0;
+            for c in line.chars() {
+                match c {
+                    ' ' => indent += 1,
+                    '\t' => indent += tab - indent % tab,
+                    '\n' | '\r' => return None,
+                    _ => return Some(indent),
+                }
+            }
+            None
+        })
+        .collect();
+    if text.len_chars() > 0 && text.char(text.len_chars() - 1) == '\n' {
+        levels.pop();
+    }
+    // The indentation of the next line that is not blank, from below.
+    let mut below = vec![0; levels.len()];
+    let mut next = 0;
+    for (i, level) in levels.iter().enumerate().rev() {
+        next = level.unwrap_or(next);
+        below[i] = next;
+    }
+    let mut above = 0;
+    for (level, below) in levels.iter_mut().zip(below) {
+        above = level.unwrap_or(above.min(below));
+        *level = Some(above);
+    }
+    levels.into_iter().flatten().collect()
+}
+
+/// Whether `format` is a valid value of `foldtext`.
+pub fn valid_text(format: &str) -> bool {
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c == '%' && !matches!(chars.next(), Some('n' | 't' | 'd' | '%')) {
+            return false;
+        }
+    }
+    true
+}
+
+/// The row of a closed fold of `lines` lines at `level`, laid out by
+/// `format`, whose first line is `first`.
+pub fn summary(format: &str, lines: usize, level: usize, first: &str) -> String {
+    let dashes = "-".repeat(level);
+    if format.is_empty() {
+        return format!("+-{dashes}{lines:>3} lines: {}", first.trim());
+    }
+    let mut text = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('%', Some(item @ ('n' | 't' | 'd' | '%'))) => {
+                chars.next();
+                match item {
+                    'n' => text.push_str(&lines.to_string()),
+                    't' => text.push_str(first.trim()),
+                    'd' => text.push_str(&dashes),
+                    _ => text.push('%'),
+                }
+            }
+            _ => text.push(c),
+        }
+    }
+    text
+}
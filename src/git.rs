@@ -0,0 +1,449 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Output, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use ropey::Rope;
+
+use crate::diff::{self, Hunk};
+use crate::trust;
+
+/// How a line differs from the version of the file staged in git.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sign {
+    Added,
+    Changed,
+    /// Lines were removed below this one, or above the first line.
+    Deleted,
+}
+
+impl Sign {
+    pub fn glyph(self) -> char {
+        match self {
+            Sign::Added => '+',
+            Sign::Changed => '~',
+            Sign::Deleted => '-',
+        }
+    }
+}
+
+/// Signs by buffer line, empty when nothing changed.
+pub type Signs = BTreeMap<usize, Sign>;
+
+/// The contents of `path` as staged in git, `None` if it is not tracked or
+/// git is unavailable.
+pub fn index_text(path: &str) -> Option<String> {
+    let (dir, name) = split(path)?;
+    let output = git(path, &dir, &["show", &format!(":./{name}")], None).ok()?;
+    String::from_utf8(output.stdout).ok()
+}
+
+/// Makes `contents` the staged version of `path`, keeping its file mode.
+pub fn stage(path: &str, contents: &str) -> anyhow::Result<()> {
+    let (dir, name) = split(path).context("Not a file")?;
+    let staged = git(path, &dir, &["ls-files", "--stage", "--", &name], None)?;
+    let mode = String::from_utf8_lossy(&staged.stdout)
+        .split_whitespace()
+        .next()
+        .unwrap_or("100644")
+        .to_string();
+    let blob = git(
+        path,
+        &dir,
+        &["hash-object", "-w", "--stdin", "--path", &name],
+        Some(contents),
+    )?;
+    let blob = String::from_utf8_lossy(&blob.stdout).trim().to_string();
+    git(
+        path,
+        &dir,
+        &[
+            "update-index",
+            "--add",
+            "--cacheinfo",
+            &format!("{mode},{blob},{name}"),
+        ],
+        None,
+    )?;
+    Ok(())
+}
+
+/// The directory holding `path`, to run git in, and the file's name.
+fn split(path: &str) -> Option<(PathBuf, String)> {
+    let path = std::path::absolute(path).ok()?;
+    let name = path.file_name()?.to_str()?.to_string();
+    Some((path.parent()?.to_path_buf(), name))
+}
+
+/// Runs git in `dir` for the file at `path`, failing with its first line
+/// of errors unless it succeeds.
+fn git(path: &str, dir: &Path, args: &[&str], stdin: Option<&str>) -> anyhow::Result<Output> {
+    let mut child = trust::command("git", Path::new(path))?
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(if stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed to run git")?;
+    if let (Some(text), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(text.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("{}", stderr.lines().next().unwrap_or("git failed"));
+    }
+    Ok(output)
+}
+
+/// The runs of lines of `text` that differ from `old`.
+pub fn hunks(old: &str, text: &str) -> Vec<Hunk> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = text.lines().collect();
+    diff::diff(&old, &new)
+}
+
+/// The line a hunk is shown and found at, which for removed lines is the
+/// one above them.
+pub fn hunk_line(hunk: &Hunk) -> usize {
+    if hunk.new.is_empty() {
+        hunk.new.start.saturating_sub(1)
+    } else {
+        hunk.new.start
+    }
+}
+
+/// Marks the lines of `text` that differ from `old`.
+pub fn signs(old: &str, text: &str) -> Signs {
+    let mut signs = Signs::new();
+    for hunk in hunks(old, text) {
+        if hunk.new.is_empty() {
+            signs.insert(hunk_line(&hunk), Sign::Deleted);
+            continue;
+        }
+        let changed = hunk.old.len().min(hunk.new.len());
+        for line in hunk.new.clone() {
+            let sign = if line - hunk.new.start < changed {
+                Sign::Changed
+            } else {
+                Sign::Added
+            };
+            signs.insert(line, sign);
+        }
+    }
+    signs
+}
+
+/// Who last changed a line, and in which commit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Blame {
+    pub author: String,
+    /// Seconds since the epoch.
+    pub time: u64,
+    pub summary: String,
+    /// Whether the line was changed since the last commit.
+    pub uncommitted: bool,
+}
+
+impl Blame {
+    /// Describes the change as shown after the line, with its age relative
+    /// to `now` in seconds since the epoch.
+    pub fn describe(&self, now: u64) -> String {
+        if self.uncommitted {
+            return "Uncommitted changes".to_string();
+        }
+        format!(
+            "{}, {} \u{2022} {}",
+            self.author,
+            age(now.saturating_sub(self.time)),
+            self.summary
+        )
+    }
+}
+
+fn age(seconds: u64) -> String {
+    let (count, unit) = match seconds {
+        0..60 => return "just now".to_string(),
+        60..3_600 => (seconds / 60, "minute"),
+        3_600..86_400 => (seconds / 3_600, "hour"),
+        86_400..2_592_000 => (seconds / 86_400, "day"),
+        2_592_000..31_536_000 => (seconds / 2_592_000, "month"),
+        _ => (seconds / 31_536_000, "year"),
+    };
+    let plural = if count == 1 { "" } else { "s" };
+    format!("{count} {unit}{plural} ago")
+}
+
+/// Blames `line`, from 0, of `text` as the contents of `path`, so lines
+/// edited since the last commit are told apart.
+fn blame(path: &str, line: usize, text: &str) -> anyhow::Result<Blame> {
+    let (dir, name) = split(path).context("Not a file")?;
+    let range = format!("{0},{0}", line + 1);
+    let output = git(
+        path,
+        &dir,
+        &[
+            "blame",
+            "--porcelain",
+            "-L",
+            &range,
+            "--contents",
+            "-",
+            "--",
+            &name,
+        ],
+        Some(text),
+    )?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let mut lines = output.lines();
+    let commit = lines.next().and_then(|header| header.split(' ').next());
+    let mut blame = Blame {
+        author: String::new(),
+        time: 0,
+        summary: String::new(),
+        uncommitted: commit.is_none_or(|commit| commit.bytes().all(|b| b == b'0')),
+    };
+    for line in lines {
+        if let Some(author) = line.strip_prefix("author ") {
+            blame.author = author.to_string();
+        } else if let Some(time) = line.strip_prefix("author-time ") {
+            blame.time = time.parse().unwrap_or_default();
+        } else if let Some(summary) = line.strip_prefix("summary ") {
+            blame.summary = summary.to_string();
+        }
+    }
+    Ok(blame)
+}
+
+struct BlameJob {
+    path: String,
+    revision: usize,
+    line: usize,
+    text: Rope,
+}
+
+struct BlameResult {
+    revision: usize,
+    line: usize,
+    blame: Option<Blame>,
+}
+
+/// Blames lines on a worker thread as they are asked for, remembering the
+/// answers until the buffer changes.
+pub struct Blamer {
+    jobs: Option<Sender<BlameJob>>,
+    results: Receiver<BlameResult>,
+    results_tx: Sender<BlameResult>,
+    /// Buffer revision the cache holds blames for.
+    revision: usize,
+    /// Blames by line, `None` while being fetched or if the line has none.
+    cache: HashMap<usize, Option<Blame>>,
+}
+
+impl Blamer {
+    pub fn new() -> Self {
+        let (results_tx, results) = mpsc::channel();
+        Self {
+            jobs: None,
+            results,
+            results_tx,
+            revision: 0,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// The blame of `line` at `revision` if it was fetched already.
+    pub fn get(&self, revision: usize, line: usize) -> Option<&Blame> {
+        if revision != self.revision {
+            return None;
+        }
+        self.cache.get(&line)?.as_ref()
+    }
+
+    /// Fetches the blame of `line` at `revision` unless it was asked for.
+    pub fn request(&mut self, path: &str, revision: usize, line: usize, text: &Rope) {
+        if revision != self.revision {
+            self.revision = revision;
+            self.cache.clear();
+        }
+        if self.cache.contains_key(&line) {
+            return;
+        }
+        self.cache.insert(line, None);
+        let jobs = self.jobs.get_or_insert_with(|| {
+            let (tx, rx) = mpsc::channel();
+            let results = self.results_tx.clone();
+            thread::spawn(move || blame_worker(rx, results));
+            tx
+        });
+        _ = jobs.send(BlameJob {
+            path: path.to_string(),
+            revision,
+            line,
+            text: text.clone(),
+        });
+    }
+
+    /// Forgets every blame, as when another file is opened.
+    pub fn clear(&mut self) {
+        self.cache.clear();
+    }
+
+    /// Takes in fetched blames, returning whether any is for the current
+    /// revision.
+    pub fn poll(&mut self) -> bool {
+        let mut received = false;
+        while let Ok(result) = self.results.try_recv() {
+            if result.revision == self.revision && self.cache.contains_key(&result.line) {
+                self.cache.insert(result.line, result.blame);
+                received = true;
+            }
+        }
+        received
+    }
+}
+
+fn blame_worker(jobs: Receiver<BlameJob>, results: Sender<BlameResult>) {
+    while let Ok(job) = jobs.recv() {
+        let result = BlameResult {
+            revision: job.revision,
+            line: job.line,
+            blame: blame(&job.path, job.line, &job.text.to_string()).ok(),
+        };
+        if results.send(result).is_err() {
+            return;
+        }
+    }
+}
+
+/// How often the repository status is refreshed.
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The branch checked out in a repository and how its worktree stands.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepoStatus {
+    /// The branch name, or the abbreviated commit when detached.
+    pub branch: String,
+    /// Commits the branch is ahead of and behind its upstream.
+    pub ahead: usize,
+    pub behind: usize,
+    /// Whether anything is changed, staged or untracked.
+    pub dirty: bool,
+}
+
+fn repo_status(path: &str) -> anyhow::Result<RepoStatus> {
+    let (dir, _) = split(path).context("Not a file")?;
+    let output = git(path, &dir, &["status", "--porcelain=v2", "--branch"], None)?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let mut status = RepoStatus {
+        branch: String::new(),
+        ahead: 0,
+        behind: 0,
+        dirty: false,
+    };
+    let mut oid = "";
+    for line in output.lines() {
+        let Some(header) = line.strip_prefix("# ") else {
+            status.dirty = true;
+            continue;
+        };
+        if let Some(head) = header.strip_prefix("branch.head ") {
+            status.branch = head.to_string();
+        } else if let Some(commit) = header.strip_prefix("branch.oid ") {
+            oid = commit;
+        } else if let Some(counts) = header.strip_prefix("branch.ab ") {
+            for count in counts.split(' ') {
+                if let Some(ahead) = count.strip_prefix('+') {
+                    status.ahead = ahead.parse().unwrap_or_default();
+                } else if let Some(behind) = count.strip_prefix('-') {
+                    status.behind = behind.parse().unwrap_or_default();
+                }
+            }
+        }
+    }
+    if status.branch == "(detached)" {
+        status.branch = oid.chars().take(7).collect();
+    }
+    Ok(status)
+}
+
+/// Keeps the status of the repository a file is in, refreshed on another
+/// thread every `STATUS_INTERVAL` or when invalidated.
+pub struct StatusWatcher {
+    results: Receiver<(usize, Option<RepoStatus>)>,
+    results_tx: Sender<(usize, Option<RepoStatus>)>,
+    /// Bumped when the file changes, so results for another are dropped.
+    generation: usize,
+    running: bool,
+    refreshed: Option<Instant>,
+    /// `None` when the file is not in a repository.
+    pub status: Option<RepoStatus>,
+}
+
+impl StatusWatcher {
+    pub fn new() -> Self {
+        let (results_tx, results) = mpsc::channel();
+        Self {
+            results,
+            results_tx,
+            generation: 0,
+            running: false,
+            refreshed: None,
+            status: None,
+        }
+    }
+
+    /// Refreshes the status of the repository holding `path` if it is due.
+    pub fn refresh(&mut self, path: &str) {
+        let due = self
+            .refreshed
+            .is_none_or(|refreshed| refreshed.elapsed() >= STATUS_INTERVAL);
+        if self.running || !due {
+            return;
+        }
+        self.running = true;
+        self.refreshed = Some(Instant::now());
+        let (generation, path, results) =
+            (self.generation, path.to_string(), self.results_tx.clone());
+        thread::spawn(move || {
+            _ = results.send((generation, repo_status(&path).ok()));
+        });
+    }
+
+    /// Makes the next `refresh` run, as after the file was written.
+    pub fn invalidate(&mut self) {
+        self.refreshed = None;
+    }
+
+    /// Forgets the status, as when another file is opened.
+    pub fn reset(&mut self) {
+        self.generation += 1;
+        self.running = false;
+        self.refreshed = None;
+        self.status = None;
+    }
+
+    /// Takes in a finished refresh, returning whether the status changed.
+    pub fn poll(&mut self) -> bool {
+        let mut changed = false;
+        while let Ok((generation, status)) = self.results.try_recv() {
+            if generation != self.generation {
+                continue;
+            }
+            self.running = false;
+            changed |= status != self.status;
+            self.status = status;
+        }
+        changed
+    }
+}
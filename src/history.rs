@@ -0,0 +1,132 @@
+//! The commands entered on the command line and the patterns searched for,
+//! recalled with Up and Down or searched with Ctrl-r Ctrl-r while typing, and
+//! kept in `history` in the state directory across sessions. Each line of
+//! the file is an entry, starting with `:` for a command or `/` for a
+//! search.
+
+use std::fs;
+use std::path::PathBuf;
+
+use crate::error;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Command,
+    Search,
+}
+
+impl Kind {
+    fn mark(self) -> char {
+        match self {
+            Kind::Command => ':',
+            Kind::Search => '/',
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CommandHistory {
+    /// Entries of both kinds, oldest first.
+    entries: Vec<(Kind, String)>,
+    /// Where the entries are kept, or `None` to forget them on exit.
+    path: Option<PathBuf>,
+}
+
+impl CommandHistory {
+    /// The history kept in the state directory.
+    pub fn load() -> Self {
+        let Some(path) = crate::state_dir().map(|dir| dir.join("history")) else {
+            return Self::default();
+        };
+        let text = fs::read_to_string(&path).unwrap_or_default();
+        let entries = text
+            .lines()
+            .filter_map(|line| match line.chars().next()? {
+                ':' => Some((Kind::Command, line[1..].to_string())),
+                '/' => Some((Kind::Search, line[1..].to_string())),
+                _ => None,
+            })
+            .collect();
+        Self {
+            entries,
+            path: Some(path),
+        }
+    }
+
+    /// The entries of `kind`, oldest first.
+    pub fn entries(&self, kind: Kind) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(k, _)| *k == kind)
+            .map(|(_, entry)| entry.as_str())
+            .collect()
+    }
+
+    /// Adds `entry` as the newest of `kind`, dropping an older copy of it
+    /// and the oldest entries past `max` of each kind.
+    pub fn add(&mut self, kind: Kind, entry: &str, max: usize) {
+        if entry.is_empty() || entry.contains('\n') {
+            return;
+        }
+        self.entries.retain(|(k, e)| !(*k == kind && e == entry));
+        self.entries.push((kind, entry.to_string()));
+        let excess = self.entries(kind).len().saturating_sub(max);
+        let mut dropped = 0;
+        self.entries.retain(|(k, _)| {
+            let drop = *k == kind && dropped < excess;
+            dropped += drop as usize;
+            !drop
+        });
+        if let Err(err) = self.save() {
+            error!("Could not save the command history: {err}");
+        }
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text: String = self
+            .entries
+            .iter()
+            .map(|(kind, entry)| format!("{}{entry}\n", kind.mark()))
+            .collect();
+        fs::write(path, text)
+    }
+}
+
+/// Going through the history from the command line, by prefix with Up and
+/// Down or by what entries hold with Ctrl-r.
+#[derive(Debug)]
+pub enum Recall {
+    /// At the entry `index` of those starting with `typed`, which is given
+    /// back when going past the newest.
+    Prefix { index: usize, typed: String },
+    /// At the entry `index`, found holding `query`, or at none when no
+    /// entry holds it.
+    Reverse { query: String, index: Option<usize> },
+}
+
+/// The index of the newest entry before `from` that `pick` accepts, or of
+/// the oldest after it unless `older`.
+pub fn find(
+    entries: &[&str],
+    from: usize,
+    older: bool,
+    pick: impl Fn(&str) -> bool,
+) -> Option<usize> {
+    match older {
+        true => entries[..from.min(entries.len())]
+            .iter()
+            .rposition(|e| pick(e)),
+        false => entries
+            .iter()
+            .enumerate()
+            .skip(from + 1)
+            .find(|(_, e)| pick(e))
+            .map(|(i, _)| i),
+    }
+}
@@ -0,0 +1,130 @@
+//! Keys written in vim's notation, as fed to tests, mapped by plugins and
+//! recorded in the action log.
+
+use anyhow::{anyhow, bail};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Parses keys in vim's notation: plain characters stand for themselves,
+/// and names in angle brackets such as `<Esc>`, `<CR>`, `<C-w>`, `<S-Tab>`
+/// or `<lt>` for special keys and modifiers.
+pub fn parse_keys(keys: &str) -> anyhow::Result<Vec<KeyEvent>> {
+    let mut events = Vec::new();
+    let mut rest = keys;
+    while let Some(c) = rest.chars().next() {
+        let special = rest
+            .strip_prefix('<')
+            .and_then(|s| Some(&s[..s.find('>')?]));
+        match special.filter(|name| !name.is_empty()) {
+            Some(name) => {
+                events.push(parse_special(name)?);
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                events.push(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    Ok(events)
+}
+
+fn parse_special(name: &str) -> anyhow::Result<KeyEvent> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut key = name;
+    while let Some((modifier, rest)) = key.split_once('-').filter(|(_, rest)| !rest.is_empty()) {
+        modifiers |= match modifier.to_ascii_uppercase().as_str() {
+            "C" => KeyModifiers::CONTROL,
+            "S" => KeyModifiers::SHIFT,
+            "A" | "M" => KeyModifiers::ALT,
+            _ => bail!("unknown modifier in <{name}>"),
+        };
+        key = rest;
+    }
+
+    let code = match key.to_ascii_lowercase().as_str() {
+        "esc" => KeyCode::Esc,
+        "cr" | "enter" | "return" => KeyCode::Enter,
+        "tab" if modifiers.contains(KeyModifiers::SHIFT) => {
+            modifiers.remove(KeyModifiers::SHIFT);
+            KeyCode::BackTab
+        }
+        "tab" => KeyCode::Tab,
+        "bs" | "backspace" => KeyCode::Backspace,
+        "del" | "delete" => KeyCode::Delete,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        "bar" => KeyCode::Char('|'),
+        _ if key.len() > 1 && key.starts_with(['f', 'F']) && key[1..].parse::<u8>().is_ok() => {
+            KeyCode::F(key[1..].parse()?)
+        }
+        _ => {
+            let mut chars = key.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if modifiers.contains(KeyModifiers::CONTROL) => {
+                    KeyCode::Char(c.to_ascii_lowercase())
+                }
+                (Some(c), None) => KeyCode::Char(c),
+                _ => return Err(anyhow!("unknown key <{name}>")),
+            }
+        }
+    };
+    Ok(KeyEvent::new(code, modifiers))
+}
+
+/// `key` in the notation `parse_keys` reads, or `None` for keys it has no
+/// name for.
+pub fn notation(key: &KeyEvent) -> Option<String> {
+    let mut modifiers = key.modifiers;
+    let name = match key.code {
+        KeyCode::Char(c) => {
+            // Shifted chars tell it themselves.
+            modifiers.remove(KeyModifiers::SHIFT);
+            match c {
+                '<' => "lt".to_string(),
+                ' ' if !modifiers.is_empty() => "Space".to_string(),
+                _ if modifiers.is_empty() => return Some(c.to_string()),
+                _ => c.to_string(),
+            }
+        }
+        KeyCode::Esc => "Esc".to_string(),
+        KeyCode::Enter => "CR".to_string(),
+        KeyCode::Tab => "Tab".to_string(),
+        KeyCode::BackTab => {
+            modifiers.insert(KeyModifiers::SHIFT);
+            "Tab".to_string()
+        }
+        KeyCode::Backspace => "BS".to_string(),
+        KeyCode::Delete => "Del".to_string(),
+        KeyCode::Up => "Up".to_string(),
+        KeyCode::Down => "Down".to_string(),
+        KeyCode::Left => "Left".to_string(),
+        KeyCode::Right => "Right".to_string(),
+        KeyCode::Home => "Home".to_string(),
+        KeyCode::End => "End".to_string(),
+        KeyCode::PageUp => "PageUp".to_string(),
+        KeyCode::PageDown => "PageDown".to_string(),
+        KeyCode::F(n) => format!("F{n}"),
+        _ => return None,
+    };
+    let mut notation = String::from("<");
+    for (modifier, prefix) in [
+        (KeyModifiers::CONTROL, "C-"),
+        (KeyModifiers::SHIFT, "S-"),
+        (KeyModifiers::ALT, "A-"),
+    ] {
+        if modifiers.contains(modifier) {
+            notation.push_str(prefix);
+        }
+    }
+    notation.push_str(&name);
+    notation.push('>');
+    Some(notation)
+}
@@ -0,0 +1,98 @@
+//! The `langmap` option, translating keys of another keyboard layout to
+//! the ones normal mode commands are typed with, so that switching layouts
+//! is only needed to type text.
+
+use std::collections::HashMap;
+
+use anyhow::bail;
+use crossterm::event::{Event, KeyCode, KeyModifiers};
+
+/// Keys translated before normal mode handles them.
+#[derive(Debug, Default)]
+pub struct LangMap {
+    keys: HashMap<char, char>,
+    /// The value it was set from, as shown by `:set all`.
+    source: String,
+}
+
+impl LangMap {
+    /// Parses a value in vim's notation: comma-separated parts, each either
+    /// pairs of a typed char and its translation such as `йq`, or the typed
+    /// chars and their translations on both sides of a semicolon such as
+    /// `йцу;qwe`. A backslash takes the next char literally.
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        let mut keys = HashMap::new();
+        for part in split(value, ',') {
+            let sides = split(&part, ';');
+            let (from, to): (Vec<char>, Vec<char>) = match sides.as_slice() {
+                [pairs] => {
+                    let chars: Vec<char> = unescape(pairs).collect();
+                    if !chars.len().is_multiple_of(2) {
+                        bail!("Unpaired char in langmap: {part}");
+                    }
+                    chars.chunks(2).map(|pair| (pair[0], pair[1])).unzip()
+                }
+                [from, to] => (unescape(from).collect(), unescape(to).collect()),
+                _ => bail!("Too many semicolons in langmap: {part}"),
+            };
+            if from.len() != to.len() {
+                bail!("Unequal sides in langmap: {part}");
+            }
+            keys.extend(from.into_iter().zip(to));
+        }
+        Ok(Self {
+            keys,
+            source: value.to_string(),
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// `event` as typed on the layout commands are written for. Keys with
+    /// Ctrl or Alt are left alone.
+    pub fn translate(&self, event: Event) -> Event {
+        match event {
+            Event::Key(mut key_event)
+                if !key_event
+                    .modifiers
+                    .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+            {
+                if let KeyCode::Char(c) = key_event.code {
+                    key_event.code = KeyCode::Char(*self.keys.get(&c).unwrap_or(&c));
+                }
+                Event::Key(key_event)
+            }
+            event => event,
+        }
+    }
+}
+
+/// Splits `value` at each `separator` not escaped by a backslash, keeping
+/// the escapes for `unescape`.
+fn split(value: &str, separator: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut().unwrap();
+        match c {
+            '\\' => {
+                part.push(c);
+                part.extend(chars.next());
+            }
+            _ if c == separator => parts.push(String::new()),
+            _ => part.push(c),
+        }
+    }
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+fn unescape(value: &str) -> impl Iterator<Item = char> + '_ {
+    let mut chars = value.chars();
+    std::iter::from_fn(move || match chars.next()? {
+        '\\' => chars.next(),
+        c => Some(c),
+    })
+}
@@ -0,0 +1,98 @@
+use std::env;
+use std::path::PathBuf;
+
+use logger::Logger;
+use once_cell::sync::OnceCell;
+
+pub mod actionlog;
+mod analysis;
+pub mod buffer;
+pub mod capabilities;
+mod command;
+mod comment;
+mod completion;
+mod config;
+mod conflicts;
+mod diagnostics;
+mod diff;
+mod diffview;
+pub mod editor;
+mod explorer;
+mod filetype;
+mod folds;
+mod git;
+mod history;
+mod keys;
+mod langmap;
+mod links;
+mod linters;
+mod logger;
+mod lsp;
+mod messages;
+mod motion;
+mod notifications;
+mod options;
+mod pager;
+mod pairs;
+pub mod palette;
+pub mod picker;
+mod plugins;
+mod popup;
+mod screen;
+mod search;
+mod segments;
+pub mod session;
+mod signs;
+mod snippets;
+mod state;
+mod statusline;
+pub mod testing;
+mod theme;
+mod trust;
+mod undo;
+mod vimscript;
+mod wasm;
+mod watcher;
+mod workspace_edit;
+
+pub static LOGGER: OnceCell<Logger> = OnceCell::new();
+
+/// Where the user's settings for the editor live.
+fn config_dir() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("vim-rs"))
+}
+
+/// Where the editor keeps what it can make again but would rather not at
+/// every start, such as the parsed config.
+fn cache_dir() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(dir.join("vim-rs"))
+}
+
+/// Where the editor keeps what it remembers between sessions, such as
+/// undo files.
+fn state_dir() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(dir.join("vim-rs"))
+}
+
+/// FNV-1a of the bytes of `chunks` in turn, which unlike the std hashers
+/// stays the same across builds, so that it can be kept in files.
+fn checksum<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> u64 {
+    chunks
+        .into_iter()
+        .flatten()
+        .fold(0xcbf29ce484222325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
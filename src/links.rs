@@ -0,0 +1,122 @@
+//! Paths and URLs found in text, drawn as OSC 8 hyperlinks where the
+//! terminal supports them and followed with `gf` and `gx` otherwise.
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::Context;
+
+/// Schemes of the URLs recognized in text.
+const SCHEMES: &[&str] = &["https://", "http://", "file://"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Link {
+    /// Chars of the text it spans.
+    pub cols: Range<usize>,
+    pub target: Target,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Url(String),
+    /// An existing file, with the line from 1 given after it as in
+    /// `src/main.rs:12`.
+    File {
+        path: PathBuf,
+        line: Option<usize>,
+    },
+}
+
+impl Target {
+    pub fn uri(&self) -> String {
+        match self {
+            Target::Url(url) => url.clone(),
+            Target::File { path, .. } => {
+                let path = path.canonicalize().unwrap_or_else(|_| path.clone());
+                format!("file://{}", path.display())
+            }
+        }
+    }
+}
+
+/// The URLs in `text`, and the paths of files that exist.
+pub fn find(text: &str) -> Vec<Link> {
+    let mut links = Vec::new();
+    let mut col = 0;
+    for word in text.split(' ') {
+        let len = word.chars().count();
+        let trimmed = word.trim_start_matches(['(', '[', '<', '"', '\'', '`']);
+        let start = col + len - trimmed.chars().count();
+        let trimmed = trimmed.trim_end_matches([')', ']', '>', '"', '\'', '`', ',', '.', ';', ':']);
+        let cols = start..start + trimmed.chars().count();
+        col += len + 1;
+
+        if SCHEMES.iter().any(|scheme| trimmed.starts_with(scheme)) {
+            links.push(Link {
+                cols,
+                target: Target::Url(trimmed.to_string()),
+            });
+            continue;
+        }
+        if !trimmed.contains(['/', '.']) {
+            continue;
+        }
+        let (path, line) = match trimmed.split_once(':') {
+            Some((path, rest)) => (path, rest.split(':').next().and_then(|n| n.parse().ok())),
+            None => (trimmed, None),
+        };
+        let path = Path::new(path);
+        if path.is_file() {
+            links.push(Link {
+                cols,
+                target: Target::File {
+                    path: path.to_path_buf(),
+                    line,
+                },
+            });
+        }
+    }
+    links
+}
+
+/// `text` with those of its `links` it shows in full made OSC 8
+/// hyperlinks.
+pub fn hyperlink(text: &str, links: &[Link]) -> String {
+    let len = text.chars().count();
+    let mut links = links
+        .iter()
+        .filter(|link| !link.cols.is_empty() && link.cols.end <= len)
+        .peekable();
+    let mut linked = String::new();
+    let mut end = None;
+    for (i, c) in text.chars().enumerate() {
+        if let Some(link) = links.next_if(|link| end.is_none() && link.cols.start == i) {
+            linked.push_str(&format!("\x1b]8;;{}\x1b\\", link.target.uri()));
+            end = Some(link.cols.end);
+        }
+        linked.push(c);
+        if end == Some(i + 1) {
+            linked.push_str("\x1b]8;;\x1b\\");
+            end = None;
+        }
+    }
+    linked
+}
+
+/// Opens `uri` with the desktop's handler for it, such as a browser.
+pub fn open(uri: &str) -> anyhow::Result<()> {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    Command::new(opener)
+        .arg(uri)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Cannot run {opener}"))?;
+    Ok(())
+}
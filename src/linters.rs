@@ -0,0 +1,383 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::SystemTime;
+
+use anyhow::{bail, Context};
+use ropey::Rope;
+use serde_json::Value;
+
+use crate::analysis::{Analyzer, Snapshot};
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::diff;
+use crate::trust;
+
+/// How a linter gets to see the buffer.
+enum Input {
+    /// The snapshot text is piped to the linter.
+    Stdin,
+    /// The linter reads the saved file, so its findings are mapped onto the
+    /// buffer through a diff against what is on disk.
+    Disk,
+}
+
+/// Reads a linter's output into diagnostics positioned in the text it saw.
+type Parser = fn(output: &str, path: &Path, text: &Rope) -> anyhow::Result<Vec<Diagnostic>>;
+
+/// An external linter run on buffers of one filetype. `{path}` in the
+/// arguments is replaced with the buffer's absolute path.
+struct LinterSpec {
+    name: &'static str,
+    filetype: &'static str,
+    program: &'static str,
+    args: &'static [&'static str],
+    input: Input,
+    parse: Parser,
+}
+
+const LINTERS: &[LinterSpec] = &[
+    LinterSpec {
+        name: "clippy",
+        filetype: "rust",
+        program: "cargo",
+        args: &["clippy", "--quiet", "--message-format=json"],
+        input: Input::Disk,
+        parse: parse_cargo,
+    },
+    LinterSpec {
+        name: "shellcheck",
+        filetype: "sh",
+        program: "shellcheck",
+        args: &["--format=json", "-"],
+        input: Input::Stdin,
+        parse: parse_shellcheck,
+    },
+    LinterSpec {
+        name: "eslint",
+        filetype: "javascript",
+        program: "eslint",
+        args: &["--format=json", "--stdin", "--stdin-filename", "{path}"],
+        input: Input::Stdin,
+        parse: parse_eslint,
+    },
+    LinterSpec {
+        name: "eslint",
+        filetype: "typescript",
+        program: "eslint",
+        args: &["--format=json", "--stdin", "--stdin-filename", "{path}"],
+        input: Input::Stdin,
+        parse: parse_eslint,
+    },
+];
+
+/// The built-in linters whose program is installed.
+pub fn builtin() -> Vec<Arc<dyn Analyzer>> {
+    LINTERS
+        .iter()
+        .filter(|spec| on_path(spec.program))
+        .map(|spec| {
+            Arc::new(Linter {
+                spec,
+                last_run: Mutex::new(None),
+            }) as Arc<dyn Analyzer>
+        })
+        .collect()
+}
+
+/// The names and programs of the built-in linters for `filetype`.
+pub fn for_filetype(filetype: &str) -> Vec<(&'static str, &'static str)> {
+    LINTERS
+        .iter()
+        .filter(|spec| spec.filetype == filetype)
+        .map(|spec| (spec.name, spec.program))
+        .collect()
+}
+
+struct Linter {
+    spec: &'static LinterSpec,
+    /// Modification time and output of the last run on the saved file, so
+    /// edits in between are remapped rather than linted again.
+    last_run: Mutex<Option<(PathBuf, SystemTime, String)>>,
+}
+
+impl Analyzer for Linter {
+    fn name(&self) -> &str {
+        self.spec.name
+    }
+
+    fn applies_to(&self, snapshot: &Snapshot) -> bool {
+        snapshot.filetype == Some(self.spec.filetype)
+            && trust::is_trusted(Path::new(&snapshot.path))
+    }
+
+    fn analyze(&self, snapshot: &Snapshot) -> anyhow::Result<Vec<Diagnostic>> {
+        let path = std::path::absolute(&snapshot.path)?;
+        match self.spec.input {
+            Input::Stdin => {
+                let output = self.run(&path, Some(&snapshot.text))?;
+                (self.spec.parse)(&output, &path, &snapshot.text)
+            }
+            Input::Disk => {
+                let Result::Ok(modified) = fs::metadata(&path).and_then(|m| m.modified()) else {
+                    return Ok(Vec::new());
+                };
+                let saved = Rope::from_str(&fs::read_to_string(&path)?);
+
+                let mut last_run = self.last_run.lock().unwrap();
+                let output = match &*last_run {
+                    Some((p, time, output)) if *p == path && *time == modified => output.clone(),
+                    _ => {
+                        let output = self.run(&path, None)?;
+                        *last_run = Some((path.clone(), modified, output.clone()));
+                        output
+                    }
+                };
+                let diagnostics = (self.spec.parse)(&output, &path, &saved)?;
+                Ok(remap(diagnostics, &saved, &snapshot.text))
+            }
+        }
+    }
+}
+
+impl Linter {
+    fn run(&self, path: &Path, stdin: Option<&Rope>) -> anyhow::Result<String> {
+        let args = self
+            .spec
+            .args
+            .iter()
+            .map(|arg| arg.replace("{path}", &path.to_string_lossy()));
+        let mut child = trust::command(self.spec.program, path)?
+            .args(args)
+            .stdin(if stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to run {}", self.spec.program))?;
+
+        if let (Some(text), Some(mut pipe)) = (stdin, child.stdin.take()) {
+            let text = text.clone();
+            // Written from another thread so a linter that starts printing
+            // before reading all of its input cannot deadlock us.
+            thread::spawn(move || {
+                for chunk in text.chunks() {
+                    if pipe.write_all(chunk.as_bytes()).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        let output = child.wait_with_output()?;
+        let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+        // Linters exit with an error when they find something, so only an
+        // empty output counts as a failure.
+        if !output.status.success() && stdout.trim().is_empty() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!(
+                "{}",
+                stderr.lines().next().unwrap_or("exited with an error")
+            );
+        }
+        Ok(stdout)
+    }
+}
+
+/// Moves diagnostics reported against the saved text onto the buffer,
+/// dropping those on lines changed since.
+fn remap(diagnostics: Vec<Diagnostic>, saved: &Rope, text: &Rope) -> Vec<Diagnostic> {
+    let old: Vec<_> = saved.lines().collect();
+    let new: Vec<_> = text.lines().collect();
+
+    // For each saved line, the buffer line it is now, if unchanged.
+    let mut lines = vec![None; old.len()];
+    let (mut old_i, mut new_i) = (0, 0);
+    for hunk in diff::diff(&old, &new).into_iter().chain([diff::Hunk {
+        old: old.len()..old.len(),
+        new: new.len()..new.len(),
+    }]) {
+        while old_i < hunk.old.start {
+            lines[old_i] = Some(new_i);
+            old_i += 1;
+            new_i += 1;
+        }
+        old_i = hunk.old.end;
+        new_i = hunk.new.end;
+    }
+
+    diagnostics
+        .into_iter()
+        .filter_map(|d| {
+            let line = lines.get(d.line).copied().flatten()?;
+            Some(Diagnostic { line, ..d })
+        })
+        .collect()
+}
+
+/// Builds a diagnostic from 1-based positions, clamping the span to the
+/// first line and to the text.
+fn diagnostic(
+    text: &Rope,
+    (line, col): (u64, u64),
+    (end_line, end_col): (u64, u64),
+    severity: Severity,
+    message: String,
+    source: &str,
+) -> Option<Diagnostic> {
+    let line = (line as usize).checked_sub(1)?;
+    let line_len = text.get_line(line)?.chars().filter(|&c| c != '\n').count();
+    let start_col = (col as usize).saturating_sub(1).min(line_len);
+    let end_col = if end_line as usize > line + 1 || end_col == 0 {
+        line_len
+    } else {
+        (end_col as usize)
+            .saturating_sub(1)
+            .clamp(start_col, line_len)
+    };
+    Some(Diagnostic {
+        line,
+        start_col,
+        end_col,
+        severity,
+        message,
+        source: source.to_string(),
+    })
+}
+
+/// Parses `cargo --message-format=json` output, keeping the primary spans
+/// pointing into `path`. Span paths are relative to the workspace root.
+fn parse_cargo(output: &str, path: &Path, text: &Rope) -> anyhow::Result<Vec<Diagnostic>> {
+    let mut diagnostics = Vec::new();
+    for line in output.lines() {
+        let Result::Ok(message) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        if message["reason"] != "compiler-message" {
+            continue;
+        }
+        let message = &message["message"];
+        let severity = match message["level"].as_str() {
+            Some("error") => Severity::Error,
+            Some("warning") => Severity::Warning,
+            Some("note") => Severity::Info,
+            _ => Severity::Hint,
+        };
+        let text_of = |message: &Value| message["message"].as_str().unwrap_or_default().to_string();
+
+        for span in message["spans"].as_array().into_iter().flatten() {
+            let file = span["file_name"].as_str().unwrap_or_default();
+            if span["is_primary"] != true || !path.ends_with(file) {
+                continue;
+            }
+            let start = (
+                span["line_start"].as_u64().unwrap_or(0),
+                span["column_start"].as_u64().unwrap_or(0),
+            );
+            let end = (
+                span["line_end"].as_u64().unwrap_or(0),
+                span["column_end"].as_u64().unwrap_or(0),
+            );
+            diagnostics.extend(diagnostic(
+                text,
+                start,
+                end,
+                severity,
+                text_of(message),
+                "clippy",
+            ));
+        }
+    }
+    Ok(diagnostics)
+}
+
+/// Parses `shellcheck --format=json`, whose columns count a tab as eight.
+fn parse_shellcheck(output: &str, _path: &Path, text: &Rope) -> anyhow::Result<Vec<Diagnostic>> {
+    let findings: Value = serde_json::from_str(output).context("malformed shellcheck output")?;
+    let column = |line: u64, col: u64| {
+        let Some(text) = (line as usize)
+            .checked_sub(1)
+            .and_then(|l| text.get_line(l))
+        else {
+            return col;
+        };
+        let mut display = 1;
+        for (i, c) in text.chars().enumerate() {
+            if display >= col {
+                return i as u64 + 1;
+            }
+            display += if c == '\t' { 8 - (display - 1) % 8 } else { 1 };
+        }
+        text.len_chars() as u64 + 1
+    };
+
+    Ok(findings
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|f| {
+            let line = f["line"].as_u64()?;
+            let end_line = f["endLine"].as_u64().unwrap_or(line);
+            let severity = match f["level"].as_str()? {
+                "error" => Severity::Error,
+                "warning" => Severity::Warning,
+                "info" => Severity::Info,
+                _ => Severity::Hint,
+            };
+            diagnostic(
+                text,
+                (line, column(line, f["column"].as_u64()?)),
+                (end_line, column(end_line, f["endColumn"].as_u64()?)),
+                severity,
+                format!("SC{}: {}", f["code"], f["message"].as_str()?),
+                "shellcheck",
+            )
+        })
+        .collect())
+}
+
+/// Parses `eslint --format=json`, a list of results per linted file.
+fn parse_eslint(output: &str, _path: &Path, text: &Rope) -> anyhow::Result<Vec<Diagnostic>> {
+    let results: Value = serde_json::from_str(output).context("malformed eslint output")?;
+    Ok(results
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|result| result["messages"].as_array().into_iter().flatten())
+        .filter_map(|m| {
+            let line = m["line"].as_u64()?;
+            let column = m["column"].as_u64().unwrap_or(1);
+            let severity = match m["severity"].as_u64()? {
+                2 => Severity::Error,
+                _ => Severity::Warning,
+            };
+            let message = match m["ruleId"].as_str() {
+                Some(rule) => format!("{} ({rule})", m["message"].as_str()?),
+                None => m["message"].as_str()?.to_string(),
+            };
+            diagnostic(
+                text,
+                (line, column),
+                (
+                    m["endLine"].as_u64().unwrap_or(line),
+                    m["endColumn"].as_u64().unwrap_or(0),
+                ),
+                severity,
+                message,
+                "eslint",
+            )
+        })
+        .collect())
+}
+
+/// Whether `program` is found in a directory of `PATH`.
+pub fn on_path(program: &str) -> bool {
+    env::var_os("PATH")
+        .is_some_and(|paths| env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+}
@@ -4,6 +4,8 @@ use std::{
     sync::Mutex,
 };
 
+use crate::messages::{self, Severity};
+
 #[derive(Debug)]
 pub struct Logger {
     file: Mutex<File>,
@@ -13,7 +15,6 @@ impl Logger {
     pub fn new(file: &str) -> Self {
         let file = OpenOptions::new()
             .create(true)
-            .write(true)
             .append(true)
             .open(file)
             .expect("Unable to open log file");
@@ -29,10 +30,34 @@ impl Logger {
     }
 }
 
+/// Writes `message` to the log file and keeps it in the message history.
+pub fn record(severity: Severity, message: String) {
+    crate::LOGGER
+        .get_or_init(|| Logger::new("vim-rs.log"))
+        .log(&message);
+    messages::push(severity, message);
+}
+
+/// Gives a message, kept for `:messages`.
 #[macro_export]
 macro_rules! log {
     ($($arg:tt)*) => {
-        let message = format!($($arg)*);
-        $crate::LOGGER.get_or_init(|| $crate::Logger::new("vim-rs.log")).log(&message);
+        $crate::logger::record($crate::messages::Severity::Info, format!($($arg)*));
+    };
+}
+
+/// Gives a warning, such as when there is nothing for a command to do.
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        $crate::logger::record($crate::messages::Severity::Warning, format!($($arg)*));
+    };
+}
+
+/// Gives an error, counted in the statusline until `:messages` shows it.
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        $crate::logger::record($crate::messages::Severity::Error, format!($($arg)*));
     };
 }
@@ -0,0 +1,767 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+use ropey::Rope;
+use serde_json::{json, Value};
+
+use crate::buffer::Buffer;
+use crate::completion::CompletionItem;
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::picker::PickerItem;
+use crate::trust;
+
+/// Language servers started for each filetype.
+const SERVERS: &[(&str, &str, &[&str])] = &[
+    ("rust", "rust-analyzer", &[]),
+    ("python", "pylsp", &[]),
+    ("c", "clangd", &[]),
+    ("cpp", "clangd", &[]),
+    ("go", "gopls", &[]),
+    ("javascript", "typescript-language-server", &["--stdio"]),
+    ("typescript", "typescript-language-server", &["--stdio"]),
+];
+
+/// How long to wait for the server to answer `initialize`.
+const INIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Files whose presence marks the root directory of a project.
+const ROOT_MARKERS: &[&str] = &[
+    ".git",
+    "Cargo.toml",
+    "go.mod",
+    "package.json",
+    "pyproject.toml",
+];
+
+pub enum LspEvent {
+    /// The server answered `initialize` and was sent what waited for it.
+    Initialized,
+    Response {
+        method: &'static str,
+        result: anyhow::Result<Value>,
+    },
+    Notification {
+        method: String,
+        params: Value,
+    },
+    /// A request from the server that must be answered with `respond`.
+    Request {
+        id: Value,
+        method: String,
+        params: Value,
+    },
+}
+
+/// Server requests handled by the editor; others are answered with null.
+const HANDLED_REQUESTS: &[&str] = &["workspace/applyEdit"];
+
+/// A language server process speaking JSON-RPC over its stdin/stdout.
+pub struct LspClient {
+    child: Child,
+    stdin: ChildStdin,
+    messages: Receiver<Value>,
+    next_id: u64,
+    pending: HashMap<u64, &'static str>,
+    language_id: &'static str,
+    uri: String,
+    /// Buffer revision last sent to the server.
+    synced: Option<usize>,
+    /// Characters besides identifier ones that should open completion.
+    pub trigger_characters: Vec<char>,
+    /// Diagnostics last published for the document, as sent by the server,
+    /// passed back when asking for code actions.
+    published: Vec<Value>,
+    /// When `initialize` was sent, until the server answers it.
+    initializing: Option<Instant>,
+    /// Requests and notifications held back until the server has
+    /// initialized.
+    queued: Vec<Value>,
+}
+
+/// Publishes diagnostics for the open document only, as diagnostics for
+/// other files in the workspace have nowhere to be shown: `None` when they
+/// are for another `uri`.
+pub fn diagnostics(params: &Value, uri: &str, text: &Rope) -> Option<Vec<Diagnostic>> {
+    if params["uri"] != uri {
+        return None;
+    }
+    let diagnostics = params["diagnostics"].as_array()?;
+    Some(
+        diagnostics
+            .iter()
+            .filter_map(|d| {
+                let start = char_index(text, &d["range"]["start"])?;
+                let end = char_index(text, &d["range"]["end"])?;
+                let line = text.char_to_line(start);
+                let line_start = text.line_to_char(line);
+                let line_end = line_start + text.line(line).len_chars();
+                Some(Diagnostic {
+                    line,
+                    start_col: start - line_start,
+                    end_col: end.min(line_end) - line_start,
+                    severity: Severity::from_lsp(d["severity"].as_u64().unwrap_or(1)),
+                    message: d["message"].as_str()?.lines().next()?.to_string(),
+                    source: d["source"].as_str().unwrap_or("lsp").to_string(),
+                })
+            })
+            .collect(),
+    )
+}
+
+/// The program of the language server started for `filetype`.
+pub fn server(filetype: &str) -> Option<&'static str> {
+    SERVERS
+        .iter()
+        .find(|(ft, _, _)| *ft == filetype)
+        .map(|&(_, cmd, _)| cmd)
+}
+
+impl LspClient {
+    /// Starts the language server configured for the buffer's filetype, if
+    /// there is one, and opens the buffer's document on it.
+    pub fn for_buffer(buffer: &Buffer) -> anyhow::Result<Option<Self>> {
+        let Some(filetype) = buffer.filetype else {
+            return Ok(None);
+        };
+        let Some((language_id, cmd, args)) = SERVERS.iter().find(|(ft, _, _)| *ft == filetype)
+        else {
+            return Ok(None);
+        };
+
+        let path = std::path::absolute(&buffer.path)?;
+        let mut client = Self::start(cmd, args, &path, language_id)?;
+        client.uri = path_to_uri(&path);
+        client.sync(buffer)?;
+        Ok(Some(client))
+    }
+
+    /// Starts the server `cmd` for the project of the file at `path`.
+    fn start(
+        cmd: &str,
+        args: &[&str],
+        path: &Path,
+        language_id: &'static str,
+    ) -> anyhow::Result<Self> {
+        let mut child = trust::command(cmd, path)?
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("failed to start language server {cmd}"))?;
+
+        let stdin = child.stdin.take().context("language server has no stdin")?;
+        let stdout = child
+            .stdout
+            .take()
+            .context("language server has no stdout")?;
+        let (tx, messages) = mpsc::channel();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(stdout);
+            while let Some(message) = read_message(&mut reader) {
+                if tx.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut client = Self {
+            child,
+            stdin,
+            messages,
+            next_id: 0,
+            pending: HashMap::new(),
+            language_id,
+            uri: String::new(),
+            synced: None,
+            trigger_characters: Vec::new(),
+            published: Vec::new(),
+            initializing: None,
+            queued: Vec::new(),
+        };
+
+        let root_uri = path_to_uri(&find_root(path));
+        client.write(&json!({
+            "jsonrpc": "2.0",
+            "id": 0,
+            "method": "initialize",
+            "params": {
+                "processId": std::process::id(),
+                "rootUri": root_uri,
+                "workspaceFolders": [{ "uri": root_uri, "name": "root" }],
+                "capabilities": {
+                    "textDocument": {
+                        "hover": { "contentFormat": ["markdown", "plaintext"] },
+                        "completion": {
+                            "completionItem": { "snippetSupport": true },
+                            "contextSupport": true,
+                        },
+                        "synchronization": { "didSave": true },
+                        "codeAction": {
+                            "codeActionLiteralSupport": {
+                                "codeActionKind": {
+                                    "valueSet": [
+                                        "quickfix",
+                                        "refactor",
+                                        "refactor.extract",
+                                        "refactor.inline",
+                                        "refactor.rewrite",
+                                        "source",
+                                        "source.organizeImports",
+                                    ],
+                                },
+                            },
+                            "resolveSupport": { "properties": ["edit"] },
+                            "dataSupport": true,
+                        },
+                        "rename": { "prepareSupport": false },
+                        "documentSymbol": { "hierarchicalDocumentSymbolSupport": true },
+                    },
+                    "workspace": {
+                        "applyEdit": true,
+                        "workspaceEdit": {
+                            "documentChanges": true,
+                            "resourceOperations": ["create", "rename", "delete"],
+                        },
+                    },
+                    "general": { "positionEncodings": ["utf-16"] },
+                },
+            },
+        }))?;
+        // Nothing else may be sent before the server has initialized, which
+        // `poll` finds out from its answer.
+        client.initializing = Some(Instant::now());
+        Ok(client)
+    }
+
+    /// Takes the capabilities the server answered `initialize` with and
+    /// sends what was held back for it.
+    fn initialized(&mut self, capabilities: &Value) -> anyhow::Result<()> {
+        if let Some(chars) = capabilities["completionProvider"]["triggerCharacters"].as_array() {
+            self.trigger_characters = chars
+                .iter()
+                .filter_map(|c| c.as_str()?.chars().next())
+                .collect();
+        }
+        self.initializing = None;
+        self.notify("initialized", json!({}))?;
+        for message in std::mem::take(&mut self.queued) {
+            self.write(&message)?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, message: Value) -> anyhow::Result<()> {
+        if self.initializing.is_some() {
+            self.queued.push(message);
+            return Ok(());
+        }
+        self.write(&message)
+    }
+
+    fn write(&mut self, message: &Value) -> anyhow::Result<()> {
+        let body = message.to_string();
+        write!(self.stdin, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.stdin.flush()?;
+        Ok(())
+    }
+
+    /// Sends a request whose response is later returned by `poll`.
+    pub fn request(&mut self, method: &'static str, params: Value) -> anyhow::Result<()> {
+        self.next_id += 1;
+        self.pending.insert(self.next_id, method);
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "id": self.next_id,
+            "method": method,
+            "params": params,
+        }))
+    }
+
+    pub fn notify(&mut self, method: &str, params: Value) -> anyhow::Result<()> {
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": method,
+            "params": params,
+        }))
+    }
+
+    /// Answers a request from the server, which may be done before it has
+    /// initialized.
+    pub fn respond(&mut self, id: Value, result: Value) -> anyhow::Result<()> {
+        self.write(&json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+    }
+
+    /// Returns the next message received from the server without blocking.
+    pub fn poll(&mut self) -> anyhow::Result<Option<LspEvent>> {
+        while let Ok(mut message) = self.messages.try_recv() {
+            let id = message.get("id").cloned();
+            let method = message
+                .get("method")
+                .and_then(Value::as_str)
+                .map(str::to_string);
+
+            match (id, method) {
+                (Some(id), None) if id == 0 && self.initializing.is_some() => {
+                    self.initialized(&message["result"]["capabilities"])?;
+                    return Ok(Some(LspEvent::Initialized));
+                }
+                (Some(id), Some(method)) if HANDLED_REQUESTS.contains(&method.as_str()) => {
+                    return Ok(Some(LspEvent::Request {
+                        id,
+                        method,
+                        params: message["params"].take(),
+                    }));
+                }
+                // Other requests from the server are acknowledged without
+                // support.
+                (Some(id), Some(_)) => self.respond(id, Value::Null)?,
+                (Some(id), None) => {
+                    let Some(method) = id.as_u64().and_then(|id| self.pending.remove(&id)) else {
+                        continue;
+                    };
+                    let result = match message.get_mut("error") {
+                        Some(error) => Err(anyhow!(
+                            "{}",
+                            error["message"].as_str().unwrap_or("language server error")
+                        )),
+                        None => Ok(message["result"].take()),
+                    };
+                    return Ok(Some(LspEvent::Response { method, result }));
+                }
+                (None, Some(method)) => {
+                    if method == "textDocument/publishDiagnostics"
+                        && message["params"]["uri"] == self.uri.as_str()
+                    {
+                        self.published = message["params"]["diagnostics"]
+                            .as_array()
+                            .cloned()
+                            .unwrap_or_default();
+                    }
+                    return Ok(Some(LspEvent::Notification {
+                        method,
+                        params: message["params"].take(),
+                    }));
+                }
+                (None, None) => {}
+            }
+        }
+        match self.initializing {
+            Some(sent) if sent.elapsed() > INIT_TIMEOUT => {
+                Err(anyhow!("the server did not initialize"))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Sends the full buffer text to the server if it changed since the
+    /// last sync.
+    pub fn sync(&mut self, buffer: &Buffer) -> anyhow::Result<()> {
+        match self.synced {
+            Some(revision) if revision == buffer.revision => return Ok(()),
+            Some(_) => self.notify(
+                "textDocument/didChange",
+                json!({
+                    "textDocument": { "uri": self.uri, "version": buffer.revision },
+                    "contentChanges": [{ "text": buffer.text.to_string() }],
+                }),
+            )?,
+            None => self.notify(
+                "textDocument/didOpen",
+                json!({
+                    "textDocument": {
+                        "uri": self.uri,
+                        "languageId": self.language_id,
+                        "version": buffer.revision,
+                        "text": buffer.text.to_string(),
+                    },
+                }),
+            )?,
+        }
+        self.synced = Some(buffer.revision);
+        Ok(())
+    }
+
+    pub fn hover(&mut self, buffer: &Buffer, line: usize, col: usize) -> anyhow::Result<()> {
+        self.sync(buffer)?;
+        let params = json!({
+            "textDocument": { "uri": self.uri },
+            "position": position(&buffer.text, line, col),
+        });
+        self.request("textDocument/hover", params)
+    }
+
+    pub fn document_symbols(&mut self, buffer: &Buffer) -> anyhow::Result<()> {
+        self.sync(buffer)?;
+        let params = json!({ "textDocument": { "uri": self.uri } });
+        self.request("textDocument/documentSymbol", params)
+    }
+
+    pub fn completion(
+        &mut self,
+        buffer: &Buffer,
+        line: usize,
+        col: usize,
+        trigger: Option<char>,
+    ) -> anyhow::Result<()> {
+        self.sync(buffer)?;
+        let context = match trigger {
+            Some(c) => json!({ "triggerKind": 2, "triggerCharacter": c.to_string() }),
+            None => json!({ "triggerKind": 1 }),
+        };
+        let params = json!({
+            "textDocument": { "uri": self.uri },
+            "position": position(&buffer.text, line, col),
+            "context": context,
+        });
+        self.request("textDocument/completion", params)
+    }
+
+    pub fn formatting(&mut self, buffer: &Buffer) -> anyhow::Result<()> {
+        self.sync(buffer)?;
+        let params = json!({
+            "textDocument": { "uri": self.uri },
+            "options": { "tabSize": 4, "insertSpaces": true },
+        });
+        self.request("textDocument/formatting", params)
+    }
+
+    pub fn rename(
+        &mut self,
+        buffer: &Buffer,
+        line: usize,
+        col: usize,
+        new_name: &str,
+    ) -> anyhow::Result<()> {
+        self.sync(buffer)?;
+        let params = json!({
+            "textDocument": { "uri": self.uri },
+            "position": position(&buffer.text, line, col),
+            "newName": new_name,
+        });
+        self.request("textDocument/rename", params)
+    }
+
+    /// Asks for the actions available on `line`, passing along the
+    /// diagnostics touching it so quick fixes are offered.
+    pub fn code_action(&mut self, buffer: &Buffer, line: usize) -> anyhow::Result<()> {
+        self.sync(buffer)?;
+        let line = line as u64;
+        let diagnostics: Vec<&Value> = self
+            .published
+            .iter()
+            .filter(|d| {
+                let start = d["range"]["start"]["line"].as_u64().unwrap_or(0);
+                let end = d["range"]["end"]["line"].as_u64().unwrap_or(0);
+                (start..=end).contains(&line)
+            })
+            .collect();
+        let params = json!({
+            "textDocument": { "uri": self.uri },
+            "range": {
+                "start": { "line": line, "character": 0 },
+                "end": { "line": line + 1, "character": 0 },
+            },
+            "context": { "diagnostics": diagnostics },
+        });
+        self.request("textDocument/codeAction", params)
+    }
+
+    pub fn resolve_code_action(&mut self, action: Value) -> anyhow::Result<()> {
+        self.request("codeAction/resolve", action)
+    }
+
+    pub fn execute_command(&mut self, command: Value) -> anyhow::Result<()> {
+        self.request("workspace/executeCommand", command)
+    }
+}
+
+impl Drop for LspClient {
+    fn drop(&mut self) {
+        _ = self.request("shutdown", Value::Null);
+        _ = self.notify("exit", Value::Null);
+        _ = self.child.kill();
+        _ = self.child.wait();
+    }
+}
+
+/// Reads one `Content-Length` framed message, returning `None` once the
+/// server's output is closed or malformed.
+fn read_message(reader: &mut impl BufRead) -> Option<Value> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut body = vec![0; length?];
+    reader.read_exact(&mut body).ok()?;
+    serde_json::from_slice(&body).ok()
+}
+
+/// Converts a char column into an LSP position, which counts UTF-16 units.
+pub fn position(text: &Rope, line: usize, col: usize) -> Value {
+    let line_start = text.line_to_char(line);
+    let character = text.char_to_utf16_cu(line_start + col) - text.char_to_utf16_cu(line_start);
+    json!({ "line": line, "character": character })
+}
+
+/// Converts an LSP position into a char index, clamped to the text.
+pub fn char_index(text: &Rope, position: &Value) -> Option<usize> {
+    let line = (position["line"].as_u64()? as usize).min(text.len_lines() - 1);
+    let character = position["character"].as_u64()? as usize;
+    let line_start = text.line_to_char(line);
+    let line_end = line_start + text.line(line).len_chars();
+    let utf16 = text.char_to_utf16_cu(line_start) + character;
+    let index = text.utf16_cu_to_char(utf16.min(text.len_utf16_cu()));
+    Some(index.min(line_end))
+}
+
+pub fn char_range(text: &Rope, range: &Value) -> Option<Range<usize>> {
+    Some(char_index(text, &range["start"])?..char_index(text, &range["end"])?)
+}
+
+/// Reads a completion response, which is either a list of items or an
+/// object flagging whether the list is incomplete.
+pub fn completion_items(result: &Value, text: &Rope) -> (Vec<CompletionItem>, bool) {
+    let (items, incomplete) = match result {
+        Value::Array(items) => (items, false),
+        Value::Object(list) => match list.get("items") {
+            Some(Value::Array(items)) => (
+                items,
+                list.get("isIncomplete")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false),
+            ),
+            _ => return (Vec::new(), false),
+        },
+        _ => return (Vec::new(), false),
+    };
+
+    let mut items: Vec<(String, CompletionItem)> = items
+        .iter()
+        .filter_map(|item| {
+            let label = item["label"].as_str()?.to_string();
+            let edit = &item["textEdit"];
+            // Insert-replace edits carry two ranges; replacing is what a
+            // completed word wants.
+            let range = edit
+                .get("range")
+                .or_else(|| edit.get("replace"))
+                .and_then(|range| char_range(text, range));
+            let new_text = edit["newText"]
+                .as_str()
+                .or_else(|| item["insertText"].as_str())
+                .unwrap_or(&label)
+                .to_string();
+            let additional_edits = item["additionalTextEdits"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|edit| {
+                    let range = char_range(text, &edit["range"])?;
+                    Some((range, edit["newText"].as_str()?.to_string()))
+                })
+                .collect();
+            let sort_text = item["sortText"].as_str().unwrap_or(&label).to_string();
+
+            Some((
+                sort_text,
+                CompletionItem {
+                    filter_text: item["filterText"].as_str().unwrap_or(&label).to_string(),
+                    detail: item["detail"].as_str().unwrap_or_default().to_string(),
+                    label,
+                    new_text,
+                    range,
+                    additional_edits,
+                    snippet: item["insertTextFormat"] == 2,
+                },
+            ))
+        })
+        .collect();
+    items.sort_by(|a, b| a.0.cmp(&b.0));
+
+    (
+        items.into_iter().map(|(_, item)| item).collect(),
+        incomplete,
+    )
+}
+
+/// Whether the file at `path` is inside a project, as told by the
+/// `ROOT_MARKERS` around it.
+pub fn in_project(path: &Path) -> bool {
+    let Ok(path) = std::path::absolute(path) else {
+        return false;
+    };
+    path.ancestors()
+        .skip(1)
+        .any(|dir| ROOT_MARKERS.iter().any(|marker| dir.join(marker).exists()))
+}
+
+/// The project a file belongs to: the nearest directory above it holding
+/// one of the `ROOT_MARKERS`, or else its own directory.
+pub fn find_root(path: &Path) -> PathBuf {
+    let dir = path.parent().unwrap_or(path);
+    dir.ancestors()
+        .find(|dir| ROOT_MARKERS.iter().any(|marker| dir.join(marker).exists()))
+        .unwrap_or(dir)
+        .to_path_buf()
+}
+
+pub fn path_to_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    uri
+}
+
+/// Converts a `file://` URI back into a path, undoing percent-encoding.
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let encoded = uri.strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut i = 0;
+    while i < encoded.len() {
+        if encoded[i] == b'%' {
+            let hex = std::str::from_utf8(encoded.get(i + 1..i + 3)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            bytes.push(encoded[i]);
+            i += 1;
+        }
+    }
+    Some(PathBuf::from(String::from_utf8(bytes).ok()?))
+}
+
+/// A symbol defined in the document, named after the symbols containing it.
+#[derive(Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: &'static str,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl PickerItem for Symbol {
+    fn label(&self) -> Cow<'_, str> {
+        Cow::Owned(format!("{} ({})", self.name, self.kind))
+    }
+}
+
+/// Reads a document symbol response, either a tree of symbols or a flat
+/// list of symbol information, into symbols in document order.
+pub fn document_symbols(result: &Value, text: &Rope) -> Vec<Symbol> {
+    fn walk(symbols: &Value, parent: Option<&str>, text: &Rope, out: &mut Vec<Symbol>) {
+        for symbol in symbols.as_array().into_iter().flatten() {
+            let Some(name) = symbol["name"].as_str() else {
+                continue;
+            };
+            let name = match parent.or(symbol["containerName"].as_str()) {
+                Some(parent) if !parent.is_empty() => format!("{parent}::{name}"),
+                _ => name.to_string(),
+            };
+            let range = symbol
+                .get("selectionRange")
+                .or_else(|| symbol["location"].get("range"));
+            if let Some(index) = range.and_then(|range| char_index(text, &range["start"])) {
+                let line = text.char_to_line(index);
+                out.push(Symbol {
+                    name: name.clone(),
+                    kind: symbol_kind(symbol["kind"].as_u64().unwrap_or(0)),
+                    line,
+                    col: index - text.line_to_char(line),
+                });
+            }
+            walk(&symbol["children"], Some(&name), text, out);
+        }
+    }
+
+    let mut symbols = Vec::new();
+    walk(result, None, text, &mut symbols);
+    symbols.sort_by_key(|symbol| (symbol.line, symbol.col));
+    symbols
+}
+
+fn symbol_kind(kind: u64) -> &'static str {
+    const KINDS: &[&str] = &[
+        "file",
+        "module",
+        "namespace",
+        "package",
+        "class",
+        "method",
+        "property",
+        "field",
+        "constructor",
+        "enum",
+        "interface",
+        "function",
+        "variable",
+        "constant",
+        "string",
+        "number",
+        "boolean",
+        "array",
+        "object",
+        "key",
+        "null",
+        "variant",
+        "struct",
+        "event",
+        "operator",
+        "type parameter",
+    ];
+    (kind as usize)
+        .checked_sub(1)
+        .and_then(|i| KINDS.get(i))
+        .unwrap_or(&"symbol")
+}
+
+/// Flattens the different shapes a hover result can take into markdown.
+pub fn hover_text(result: &Value) -> Option<String> {
+    fn marked(value: &Value) -> Option<String> {
+        match value {
+            Value::String(s) => Some(s.clone()),
+            Value::Object(o) => {
+                let value = o.get("value")?.as_str()?;
+                match o.get("language").and_then(Value::as_str) {
+                    Some(language) => Some(format!("```{language}\n{value}\n```")),
+                    None => Some(value.to_string()),
+                }
+            }
+            Value::Array(items) => Some(
+                items
+                    .iter()
+                    .filter_map(marked)
+                    .collect::<Vec<_>>()
+                    .join("\n---\n"),
+            ),
+            _ => None,
+        }
+    }
+
+    marked(result.get("contents")?).filter(|text| !text.trim().is_empty())
+}
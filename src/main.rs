@@ -1,19 +1,45 @@
-use anyhow::Ok;
-use buffer::Buffer;
-use editor::Editor;
-use logger::Logger;
-use once_cell::sync::OnceCell;
-
-mod buffer;
-mod editor;
-mod logger;
+use std::io::{self, IsTerminal};
 
-pub static LOGGER: OnceCell<Logger> = OnceCell::new();
+use anyhow::Ok;
+use vim_rs::actionlog;
+use vim_rs::buffer::Buffer;
+use vim_rs::capabilities::Overrides;
+use vim_rs::editor::Editor;
+use vim_rs::session;
 
 fn main() -> anyhow::Result<()> {
-    let filepath = if std::env::args().count() > 1 {
-        std::env::args().nth(1).unwrap()
-    } else {
+    let mut overrides = Overrides::default();
+    let mut filepath = None;
+    let mut diff = false;
+    let mut other = None;
+    let mut replay = None;
+    let mut session = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if let Some(list) = arg.strip_prefix("--caps=") {
+            overrides = Overrides::parse(list)?;
+        } else if arg == "--caps" {
+            overrides = Overrides::parse(&args.next().unwrap_or_default())?;
+        } else if arg == "--replay" {
+            replay = args.next();
+        } else if arg == "-S" {
+            session = Some(
+                args.next()
+                    .unwrap_or_else(|| session::DEFAULT_PATH.to_string()),
+            );
+        } else if arg == "-d" {
+            diff = true;
+        } else if diff && filepath.is_some() {
+            other = Some(arg);
+        } else {
+            filepath = Some(arg);
+        }
+    }
+    let session = match session {
+        Some(path) => Some((std::fs::read_to_string(&path)?, path)),
+        None => None,
+    };
+    let Some(filepath) = filepath.or_else(|| session.as_ref().map(|_| String::new())) else {
         println!(
             "You must pass a filepath!  Only recieved {} arguments.",
             std::env::args().count()
@@ -21,8 +47,38 @@ fn main() -> anyhow::Result<()> {
         panic!()
     };
 
-    let buffer = Buffer::from_file(&filepath)?;
-    let mut editor = Editor::new(buffer)?;
+    let buffer = match filepath.as_str() {
+        "" => Buffer::from_text("", ""),
+        _ => Buffer::open(&filepath)?,
+    };
+    if let Some(log) = replay {
+        let recording = actionlog::parse(&std::fs::read_to_string(&log)?)?;
+        let mut editor = Editor::headless(buffer, recording.size);
+        editor.replay(&recording)?;
+        print!("{}", editor.text());
+        return Ok(());
+    }
+    let other = match (diff, other) {
+        (false, _) => None,
+        (true, Some(other)) => Some(Buffer::open(&other)?),
+        (true, None) => anyhow::bail!("Diff mode needs two files"),
+    };
+    // Without a terminal to draw on, the editor reads ex commands instead.
+    if std::env::var("TERM").is_ok_and(|term| term == "dumb") || !io::stdout().is_terminal() {
+        let mut editor = Editor::headless(buffer, (80, 24));
+        if let Some((script, path)) = session {
+            editor.source(&path, &script);
+        }
+        let prompt = if io::stdin().is_terminal() { ":" } else { "" };
+        return editor.run_lines(io::stdin().lock(), io::stdout(), prompt);
+    }
+    let mut editor = Editor::new(buffer, &overrides)?;
+    if let Some(other) = other {
+        editor.diff_with(other);
+    }
+    if let Some((script, path)) = session {
+        editor.source(&path, &script);
+    }
     editor.run()?;
     Ok(())
 }
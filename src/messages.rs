@@ -0,0 +1,102 @@
+//! The messages the editor gave, kept for `:messages` after the next
+//! redraw hides them. The history is bounded, the oldest message being
+//! dropped first, and counts the errors given since it was last looked at.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+/// Most messages kept, as vim's default `msghistory`.
+const MAX_MESSAGES: usize = 500;
+
+/// How bad what a message tells is, the worst first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+impl Severity {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "error" | "errors" => Some(Severity::Error),
+            "warning" | "warnings" => Some(Severity::Warning),
+            "info" => Some(Severity::Info),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub severity: Severity,
+    pub text: String,
+}
+
+#[derive(Debug, Default)]
+struct History {
+    messages: VecDeque<Message>,
+    /// Errors given since the history was last shown.
+    unseen_errors: usize,
+    /// Messages given since the history was last cleared, including those
+    /// dropped.
+    given: usize,
+}
+
+thread_local! {
+    // Kept per thread, the editor running on one, so that the editors of
+    // tests running side by side keep their own.
+    static HISTORY: RefCell<History> = RefCell::default();
+}
+
+/// Adds a message to the history.
+pub fn push(severity: Severity, text: String) {
+    HISTORY.with_borrow_mut(|history| {
+        if history.messages.len() == MAX_MESSAGES {
+            history.messages.pop_front();
+        }
+        if severity == Severity::Error {
+            history.unseen_errors += 1;
+        }
+        history.messages.push_back(Message { severity, text });
+        history.given += 1;
+    });
+}
+
+/// The messages at least as bad as `severity`, oldest first, which counts
+/// the errors among them as seen.
+pub fn show(severity: Severity) -> Vec<Message> {
+    HISTORY.with_borrow_mut(|history| {
+        history.unseen_errors = 0;
+        history
+            .messages
+            .iter()
+            .filter(|message| message.severity <= severity)
+            .cloned()
+            .collect()
+    })
+}
+
+pub fn clear() {
+    HISTORY.with_borrow_mut(|history| *history = History::default());
+}
+
+/// Number of messages given so far, to find those given after it with
+/// [`since`].
+pub fn given() -> usize {
+    HISTORY.with_borrow(|history| history.given)
+}
+
+/// The messages given after [`given`] returned `given`, oldest first.
+pub fn since(given: usize) -> Vec<Message> {
+    HISTORY.with_borrow(|history| {
+        let count = history.given.saturating_sub(given);
+        let skipped = history.messages.len().saturating_sub(count);
+        history.messages.iter().skip(skipped).cloned().collect()
+    })
+}
+
+/// Number of errors given since the history was last shown.
+pub fn unseen_errors() -> usize {
+    HISTORY.with_borrow(|history| history.unseen_errors)
+}
@@ -0,0 +1,195 @@
+//! Motions over blocks of text. Paragraphs are separated by blank lines,
+//! which `{` and `}` stop at. Sentences end at a `.`, `!` or `?`, maybe
+//! followed by closing brackets or quotes, and then a blank; `(` and `)`
+//! stop where they start and at empty lines.
+//!
+//! Without a syntax tree to tell where definitions are, `]]` and `[[` go
+//! to lines with a `{` in the first column, or starting a definition
+//! without indentation, and `]m` and `[m` to definitions at any depth.
+//! Definitions are found by their keyword, such as `fn` or `class`, or by
+//! a block opened after a parameter list.
+
+use ropey::Rope;
+
+fn is_blank(text: &Rope, line: usize) -> bool {
+    text.line(line).chars().all(char::is_whitespace)
+}
+
+/// The line `count` paragraphs after `line`, or before it when `backward`:
+/// the blank line past the end of each, or the first or `last` line.
+pub fn paragraph(text: &Rope, line: usize, last: usize, backward: bool, count: usize) -> usize {
+    let mut line = line.min(last);
+    for _ in 0..count {
+        match backward {
+            true => {
+                while line > 0 && is_blank(text, line) {
+                    line -= 1;
+                }
+                while line > 0 && !is_blank(text, line) {
+                    line -= 1;
+                }
+            }
+            false => {
+                while line < last && is_blank(text, line) {
+                    line += 1;
+                }
+                while line < last && !is_blank(text, line) {
+                    line += 1;
+                }
+            }
+        }
+    }
+    line
+}
+
+/// The chars where sentences start, and where empty lines are.
+fn sentence_stops(text: &Rope) -> Vec<usize> {
+    let mut stops = Vec::new();
+    // Whether the next char that is not blank starts a sentence, and
+    // whether those so far end one, unless more follows before a blank.
+    let (mut starts, mut ended) = (true, false);
+    let mut prev = '\n';
+    for (i, c) in text.chars().enumerate() {
+        if c == '\n' && prev == '\n' {
+            stops.push(i);
+            starts = true;
+        } else if c.is_whitespace() {
+            starts |= ended;
+            ended = false;
+        } else {
+            if starts {
+                stops.push(i);
+                starts = false;
+            }
+            ended = match c {
+                '.' | '!' | '?' => true,
+                ')' | ']' | '"' | '\'' => ended,
+                _ => false,
+            };
+        }
+        prev = c;
+    }
+    stops
+}
+
+/// The char `count` sentences after char `at`, or before it when
+/// `backward`, or the start or end of `text` when there are fewer.
+pub fn sentence(text: &Rope, at: usize, backward: bool, count: usize) -> usize {
+    let stops = sentence_stops(text);
+    let mut at = at;
+    for _ in 0..count {
+        at = match backward {
+            true => stops
+                .iter()
+                .rev()
+                .find(|&&stop| stop < at)
+                .copied()
+                .unwrap_or(0),
+            false => match stops.iter().find(|&&stop| stop > at) {
+                Some(&stop) => stop,
+                None => text.len_chars(),
+            },
+        };
+    }
+    at
+}
+
+/// Words that may come before the keyword starting a definition.
+const MODIFIERS: &[&str] = &[
+    "pub",
+    "crate",
+    "async",
+    "unsafe",
+    "const",
+    "extern",
+    "static",
+    "export",
+    "default",
+    "abstract",
+    "final",
+    "public",
+    "private",
+    "protected",
+    "override",
+    "virtual",
+    "inline",
+];
+
+/// Keywords starting the definition of a function or type.
+const DEFINITIONS: &[&str] = &[
+    "fn",
+    "def",
+    "class",
+    "function",
+    "func",
+    "struct",
+    "enum",
+    "union",
+    "trait",
+    "impl",
+    "interface",
+    "mod",
+    "module",
+];
+
+/// Keywords starting a block that is not a definition.
+const CONTROL: &[&str] = &[
+    "if", "else", "for", "while", "loop", "match", "switch", "do", "try", "catch", "return",
+];
+
+/// Whether `line` starts a definition: it starts with one of the keywords
+/// of one after modifiers, or opens a block after a parameter list as a
+/// function in C or Java does.
+fn starts_definition(line: &str) -> bool {
+    let words: Vec<&str> = line
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| !word.is_empty())
+        .collect();
+    let keyword = words.iter().find(|word| !MODIFIERS.contains(word));
+    if keyword.is_some_and(|word| DEFINITIONS.contains(word)) {
+        return true;
+    }
+    let line = line.trim_end();
+    line.ends_with('{') && line.contains(')') && !keyword.is_some_and(|word| CONTROL.contains(word))
+}
+
+/// Whether `line` starts a section: it has a `{` in the first column, or
+/// starts a definition without being indented.
+fn starts_section(line: &str) -> bool {
+    line.starts_with('{') || (!line.starts_with(char::is_whitespace) && starts_definition(line))
+}
+
+/// The line `count` sections after `line`, or before it when `backward`,
+/// as `]]` and `[[` go: or the first or `last` line when there are fewer.
+/// With `methods` it is definitions at any depth, as `]m` and `[m` go to,
+/// and there must be `count` of them.
+pub fn section(
+    text: &Rope,
+    line: usize,
+    last: usize,
+    backward: bool,
+    count: usize,
+    methods: bool,
+) -> Option<usize> {
+    let starts = |line: usize| {
+        let line = text.line(line).to_string();
+        match methods {
+            true => starts_definition(&line),
+            false => starts_section(&line),
+        }
+    };
+    let mut at = line;
+    for _ in 0..count {
+        let found = match backward {
+            true => (0..at).rev().find(|&line| starts(line)),
+            false => (at + 1..=last).find(|&line| starts(line)),
+        };
+        at = match (found, methods) {
+            (Some(found), _) => found,
+            (None, true) => return None,
+            (None, false) if backward => 0,
+            (None, false) => last,
+        };
+    }
+    Some(at)
+}
@@ -0,0 +1,85 @@
+//! Telling that a long-running job finished or failed, such as a build, a
+//! grep or the language server, however the `notify` option asks: ringing
+//! the terminal bell, flashing the message in the statusline or sending a
+//! desktop notification. Events that happen on their own, such as a file
+//! being reloaded, are told in toasts stacked in a corner of the screen.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error;
+use crate::messages::Severity;
+
+/// How long the statusline shows a notification in place of itself.
+const FLASH_DURATION: Duration = Duration::from_millis(1500);
+/// How long a toast stays in its corner.
+const TOAST_DURATION: Duration = Duration::from_secs(4);
+/// Toasts shown at once, the oldest going first to make room.
+pub const MAX_TOASTS: usize = 4;
+
+/// How a notification is given, as named in `:set notify`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Channel {
+    Bell,
+    Flash,
+    Desktop,
+}
+
+impl Channel {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "bell" => Some(Channel::Bell),
+            "flash" => Some(Channel::Flash),
+            "desktop" => Some(Channel::Desktop),
+            _ => None,
+        }
+    }
+}
+
+/// A notification shown until it is due to go, in the statusline or as a
+/// toast.
+#[derive(Debug, Clone)]
+pub struct Notice {
+    pub severity: Severity,
+    pub text: String,
+    until: Instant,
+}
+
+impl Notice {
+    /// A notice flashed in the statusline.
+    pub fn flash(severity: Severity, text: String) -> Self {
+        Self::lasting(severity, text, FLASH_DURATION)
+    }
+
+    /// A notice shown as a toast.
+    pub fn toast(severity: Severity, text: String) -> Self {
+        Self::lasting(severity, text, TOAST_DURATION)
+    }
+
+    fn lasting(severity: Severity, text: String, duration: Duration) -> Self {
+        Self {
+            severity,
+            text,
+            until: Instant::now() + duration,
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        Instant::now() >= self.until
+    }
+}
+
+/// Sends `text` to the desktop's notification daemon, without waiting for
+/// it to answer. A failure is only logged to the file, the thread sending
+/// it keeping no history of messages that `:messages` shows.
+pub fn desktop(text: String) {
+    thread::spawn(move || {
+        let shown = notify_rust::Notification::new()
+            .summary("vim-rs")
+            .body(&text)
+            .show();
+        if let Err(err) = shown {
+            error!("Desktop notification failed: {err}");
+        }
+    });
+}
@@ -0,0 +1,743 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context};
+
+use crate::folds;
+use crate::langmap::LangMap;
+use crate::notifications::Channel;
+use crate::signs::Source;
+use crate::statusline::StatusLine;
+
+/// Editor settings changed with `:set`.
+pub struct Options {
+    /// Filetypes formatted by the language server before being written.
+    pub format_on_save: Vec<String>,
+    /// Brackets and quotes are closed as they are typed.
+    pub auto_pairs: bool,
+    /// The buffer is written once typing paused, the terminal lost the
+    /// focus or another buffer is switched to.
+    pub autosave: bool,
+    /// Milliseconds without input after which `autosave` writes.
+    pub autosave_delay: u64,
+    /// The cursor line is highlighted.
+    pub cursor_line: bool,
+    /// The screen column of the cursor is highlighted.
+    pub cursor_column: bool,
+    /// Columns highlighted as guides, counted from 1, or from `textwidth`
+    /// when written with a sign.
+    pub color_column: Vec<String>,
+    /// The commit that last changed the cursor line is shown after it.
+    pub blame: bool,
+    /// Minutes without input after which the screen is hidden until a key
+    /// is pressed, or 0 to never lock.
+    pub idle_lock: u64,
+    /// Sources of the markers in the sign column, highest priority first.
+    pub signs: Vec<String>,
+    /// How long-running jobs tell they finished or failed.
+    pub notify: Vec<String>,
+    /// Events that happen on their own, such as the language server
+    /// starting, are shown for a moment in the top right corner.
+    pub toasts: bool,
+    /// Lines longer than this are marked in the sign column, or 0 for none.
+    pub text_width: usize,
+    /// When a line is longer than this the buffer is shown in segments
+    /// broken at delimiters, or never if 0.
+    pub segment_length: usize,
+    /// Lines kept shown above and below the cursor when scrolling.
+    pub scroll_off: usize,
+    /// Columns kept shown left and right of the cursor when scrolling
+    /// sideways.
+    pub side_scroll_off: usize,
+    /// Keys of another keyboard layout typed for normal mode commands.
+    pub lang_map: LangMap,
+    /// The layout of the statusline, or empty for the built-in one.
+    pub status_line: StatusLine,
+    /// Chars filling the parts of the screen that show no text.
+    pub fill_chars: FillChars,
+    /// How folds are made: `manual` with `zf`, or `indent` from the
+    /// indentation of lines.
+    pub fold_method: String,
+    /// How the row of a closed fold reads, or empty for the built-in
+    /// summary.
+    pub fold_text: String,
+    /// Whitespace at the end of lines is stripped when the buffer is
+    /// written.
+    pub trim_trailing: bool,
+    /// A line break is added when the buffer is written without one at
+    /// the end.
+    pub fix_end_of_line: bool,
+    /// How a line is commented, with `%s` standing for it, or empty for
+    /// the markers known for the filetype.
+    pub comment_string: String,
+    /// Whitespace is shown with the chars of `listchars`.
+    pub list: bool,
+    /// Chars whitespace is shown with under `list`.
+    pub list_chars: ListChars,
+    /// Whitespace at the end of lines is highlighted, except on the line
+    /// being typed in.
+    pub highlight_trailing: bool,
+    /// A faint line is drawn down the indentation at each level of the
+    /// blocks a line is in.
+    pub indent_guides: bool,
+    /// The bracket at or before the cursor and the one matching it are
+    /// highlighted.
+    pub match_paren: bool,
+    /// Brackets are colored by how deeply they are nested.
+    pub rainbow: bool,
+    /// How many commands and searches are kept in the history, of each.
+    pub history: usize,
+    /// Searches match letters of either case.
+    pub ignore_case: bool,
+    /// Under `ignorecase`, a search for a pattern with an uppercase letter
+    /// still matches case.
+    pub smart_case: bool,
+    /// Searches go on from the other end of the buffer once they reach
+    /// one.
+    pub wrap_scan: bool,
+    /// The matches of the last search are highlighted.
+    pub hl_search: bool,
+    /// The undo history of a file is kept when it is written, to undo
+    /// changes made before it was last opened.
+    pub undo_file: bool,
+    /// Most changes that can be undone, the oldest being forgotten first.
+    pub undo_levels: usize,
+    /// Where undo files are kept, or empty for `undo` in the state
+    /// directory.
+    pub undo_dir: String,
+    /// The file as it was before being written is kept as a backup.
+    pub backup: bool,
+    /// A backup is made while writing, to be left if the write fails, and
+    /// removed after it unless `backup` is set.
+    pub write_backup: bool,
+    /// Where backups are made, or empty for next to the file.
+    pub backup_dir: String,
+    /// Appended to the name of a file to name its backup.
+    pub backup_ext: String,
+    /// The time of the write comes before `backup_ext`, so that each write
+    /// keeps its own backup instead of replacing the last.
+    pub backup_stamp: bool,
+    /// Where the options set were last set, by name.
+    origins: HashMap<&'static str, Origin>,
+}
+
+/// Where an option or a mapping was last set, as `:verbose` tells.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    CommandLine,
+    /// A line of a script run with `:source`, such as a session.
+    Script {
+        path: String,
+        line: usize,
+    },
+    /// A plugin, with the line of its script when it is known.
+    Plugin {
+        name: String,
+        line: Option<usize>,
+    },
+}
+
+impl Origin {
+    pub fn describe(&self) -> String {
+        match self {
+            Origin::CommandLine => "Last set from the command line".to_string(),
+            Origin::Script { path, line } => format!("Last set from {path} line {line}"),
+            Origin::Plugin {
+                name,
+                line: Some(line),
+            } => format!("Last set from plugin {name} line {line}"),
+            Origin::Plugin { name, line: None } => format!("Last set from plugin {name}"),
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            format_on_save: Vec::new(),
+            auto_pairs: true,
+            autosave: false,
+            autosave_delay: 1000,
+            cursor_line: false,
+            cursor_column: false,
+            color_column: Vec::new(),
+            blame: false,
+            idle_lock: 0,
+            signs: vec!["diagnostics".to_string(), "git".to_string()],
+            notify: vec!["flash".to_string()],
+            toasts: true,
+            text_width: 0,
+            segment_length: 10_000,
+            scroll_off: 0,
+            side_scroll_off: 0,
+            lang_map: LangMap::default(),
+            status_line: StatusLine::default(),
+            fill_chars: FillChars::default(),
+            fold_method: "manual".to_string(),
+            fold_text: String::new(),
+            trim_trailing: false,
+            fix_end_of_line: false,
+            comment_string: String::new(),
+            list: false,
+            list_chars: ListChars::default(),
+            highlight_trailing: false,
+            indent_guides: false,
+            match_paren: true,
+            rainbow: false,
+            history: 200,
+            ignore_case: false,
+            smart_case: false,
+            wrap_scan: true,
+            hl_search: false,
+            undo_file: false,
+            undo_dir: String::new(),
+            undo_levels: 1000,
+            backup: false,
+            write_backup: true,
+            backup_dir: String::new(),
+            backup_ext: "~".to_string(),
+            backup_stamp: false,
+            origins: HashMap::new(),
+        }
+    }
+}
+
+impl Options {
+    /// Applies one `:set` argument, either `name=value`, `name+=value`,
+    /// `name-=value` for list options, or a bare `name` to reset it.
+    /// Boolean options are switched on with `name`, off with `noname` and
+    /// toggled with `name!`. `origin` is remembered for `:verbose`.
+    pub fn set(&mut self, arg: &str, origin: Origin) -> anyhow::Result<()> {
+        self.apply(arg)?;
+        if let Some(name) = option_name(arg) {
+            self.origins.insert(name, origin);
+        }
+        Ok(())
+    }
+
+    /// The value of the option named by `name`, as `:set all` lists it,
+    /// and where it was last set unless it has its default.
+    pub fn show(&self, name: &str) -> anyhow::Result<(String, Option<&Origin>)> {
+        let Some(name) = canonical(name) else {
+            bail!("Unknown option: {name}");
+        };
+        let value = self
+            .all()
+            .into_iter()
+            .find(|option| {
+                option == name
+                    || option.strip_prefix("no") == Some(name)
+                    || option
+                        .strip_prefix(name)
+                        .is_some_and(|rest| rest.starts_with('='))
+            })
+            .unwrap_or_default();
+        Ok((value, self.origins.get(name)))
+    }
+
+    fn apply(&mut self, arg: &str) -> anyhow::Result<()> {
+        let (flag, toggle) = match arg.strip_suffix('!') {
+            Some(flag) => (flag, true),
+            None => (arg, false),
+        };
+        let (flag, enable) = match flag.strip_prefix("no") {
+            Some(rest) if self.flag(rest).is_some() => (rest, false),
+            _ => (flag, true),
+        };
+        if let Some(value) = self.flag(flag) {
+            *value = if toggle { !*value } else { enable };
+            return Ok(());
+        }
+
+        let (name, op, value) = match arg.find('=') {
+            Some(i) => match arg[..i].strip_suffix(['+', '-']) {
+                Some(name) => (name, &arg[i - 1..i], &arg[i + 1..]),
+                None => (&arg[..i], "", &arg[i + 1..]),
+            },
+            None => (arg, "", ""),
+        };
+
+        match name {
+            "formatonsave" | "fos" => set_list(&mut self.format_on_save, op, value),
+            "colorcolumn" | "cc" => {
+                if value
+                    .split(',')
+                    .any(|item| !item.is_empty() && item.parse::<isize>().is_err())
+                {
+                    bail!("Invalid argument: {arg}");
+                }
+                set_list(&mut self.color_column, op, value);
+            }
+            "signs" => {
+                if value
+                    .split(',')
+                    .any(|name| !name.is_empty() && Source::parse(name).is_none())
+                {
+                    bail!("Invalid argument: {arg}");
+                }
+                set_list(&mut self.signs, op, value);
+            }
+            "notify" => {
+                if value
+                    .split(',')
+                    .any(|name| !name.is_empty() && Channel::parse(name).is_none())
+                {
+                    bail!("Invalid argument: {arg}");
+                }
+                set_list(&mut self.notify, op, value);
+            }
+            "autosavedelay" | "asd" if op.is_empty() => {
+                set_number(&mut self.autosave_delay, arg, value)?
+            }
+            "idlelock" | "il" if op.is_empty() => set_number(&mut self.idle_lock, arg, value)?,
+            "history" | "hi" if op.is_empty() => set_number(&mut self.history, arg, value)?,
+            "undolevels" | "ul" if op.is_empty() => set_number(&mut self.undo_levels, arg, value)?,
+            "textwidth" | "tw" if op.is_empty() => set_number(&mut self.text_width, arg, value)?,
+            "segmentlength" | "sgl" if op.is_empty() => {
+                set_number(&mut self.segment_length, arg, value)?
+            }
+            "scrolloff" | "so" if op.is_empty() => set_number(&mut self.scroll_off, arg, value)?,
+            "sidescrolloff" | "siso" if op.is_empty() => {
+                set_number(&mut self.side_scroll_off, arg, value)?
+            }
+            "langmap" | "lmap" if op.is_empty() => self.lang_map = LangMap::parse(value)?,
+            "fillchars" | "fcs" if op.is_empty() => self.fill_chars = FillChars::parse(value)?,
+            "listchars" | "lcs" if op.is_empty() => self.list_chars = ListChars::parse(value)?,
+            "statusline" | "stl" if op.is_empty() => self.status_line = StatusLine::parse(value)?,
+            "foldmethod" | "fdm" if op.is_empty() => {
+                if !["manual", "indent"].contains(&value) {
+                    bail!("Invalid argument: {arg}");
+                }
+                self.fold_method = value.to_string();
+            }
+            "foldtext" | "fdt" if op.is_empty() => {
+                if !folds::valid_text(value) {
+                    bail!("Invalid argument: {arg}");
+                }
+                self.fold_text = value.to_string();
+            }
+            "commentstring" | "cms" if op.is_empty() => {
+                if !value.is_empty() && !value.contains("%s") {
+                    bail!("Invalid argument: {arg}");
+                }
+                self.comment_string = value.to_string();
+            }
+            "undodir" | "udir" if op.is_empty() => self.undo_dir = value.to_string(),
+            "backupdir" | "bdir" if op.is_empty() => self.backup_dir = value.to_string(),
+            "backupext" | "bex" if op.is_empty() => {
+                if value.is_empty() || value.contains('/') {
+                    bail!("Invalid argument: {arg}");
+                }
+                self.backup_ext = value.to_string();
+            }
+            _ if self.flag(name).is_some() => bail!("Invalid argument: {arg}"),
+            _ => bail!("Unknown option: {name}"),
+        }
+        Ok(())
+    }
+
+    /// The buffer columns of `colorcolumn`, from 0. Those relative to
+    /// `textwidth` are left out while it is 0.
+    pub fn color_columns(&self) -> Vec<usize> {
+        self.color_column
+            .iter()
+            .filter_map(|item| match item.starts_with(['+', '-']) {
+                true if self.text_width == 0 => None,
+                true => (self.text_width as isize).checked_add(item.parse().ok()?),
+                false => item.parse().ok(),
+            })
+            .filter_map(|col: isize| usize::try_from(col - 1).ok())
+            .collect()
+    }
+
+    /// Every option with its value, as listed by `:set all`.
+    pub fn all(&self) -> Vec<String> {
+        let flag = |name: &str, value: bool| match value {
+            true => name.to_string(),
+            false => format!("no{name}"),
+        };
+        vec![
+            flag("autopairs", self.auto_pairs),
+            flag("autosave", self.autosave),
+            format!("autosavedelay={}", self.autosave_delay),
+            flag("backup", self.backup),
+            format!("backupdir={}", self.backup_dir),
+            format!("backupext={}", self.backup_ext),
+            flag("backupstamp", self.backup_stamp),
+            flag("blame", self.blame),
+            format!("colorcolumn={}", self.color_column.join(",")),
+            format!("commentstring={}", self.comment_string),
+            flag("cursorcolumn", self.cursor_column),
+            flag("cursorline", self.cursor_line),
+            format!("fillchars={}", self.fill_chars.source()),
+            flag("fixendofline", self.fix_end_of_line),
+            format!("foldmethod={}", self.fold_method),
+            format!("foldtext={}", self.fold_text),
+            format!("formatonsave={}", self.format_on_save.join(",")),
+            flag("highlighttrailing", self.highlight_trailing),
+            format!("history={}", self.history),
+            flag("hlsearch", self.hl_search),
+            format!("idlelock={}", self.idle_lock),
+            flag("ignorecase", self.ignore_case),
+            flag("indentguides", self.indent_guides),
+            format!("langmap={}", self.lang_map.source()),
+            flag("list", self.list),
+            format!("listchars={}", self.list_chars.source()),
+            flag("matchparen", self.match_paren),
+            format!("notify={}", self.notify.join(",")),
+            flag("rainbow", self.rainbow),
+            format!("scrolloff={}", self.scroll_off),
+            format!("segmentlength={}", self.segment_length),
+            format!("sidescrolloff={}", self.side_scroll_off),
+            format!("signs={}", self.signs.join(",")),
+            flag("smartcase", self.smart_case),
+            format!("statusline={}", self.status_line.source()),
+            format!("textwidth={}", self.text_width),
+            flag("toasts", self.toasts),
+            flag("trimtrailing", self.trim_trailing),
+            format!("undodir={}", self.undo_dir),
+            flag("undofile", self.undo_file),
+            format!("undolevels={}", self.undo_levels),
+            flag("wrapscan", self.wrap_scan),
+            flag("writebackup", self.write_backup),
+        ]
+    }
+
+    /// The start and end markers of `commentstring`, unless empty.
+    pub fn comment_markers(&self) -> Option<(&str, &str)> {
+        let (start, end) = self.comment_string.split_once("%s")?;
+        Some((start.trim_end(), end.trim_start()))
+    }
+
+    /// The value of the option `arg` sets as `show` gives it, and where it
+    /// was set, to `restore` it later.
+    pub fn saved(&self, arg: &str) -> anyhow::Result<(String, Option<Origin>)> {
+        let name = option_name(arg).with_context(|| format!("Unknown option: {arg}"))?;
+        let (shown, origin) = self.show(name)?;
+        Ok((shown, origin.cloned()))
+    }
+
+    /// Sets an option back to a value `show` gave, and to where it was set
+    /// then.
+    pub fn restore(&mut self, shown: &str, origin: Option<Origin>) -> anyhow::Result<()> {
+        self.apply(shown)?;
+        if let Some(name) = option_name(shown) {
+            match origin {
+                Some(origin) => self.origins.insert(name, origin),
+                None => self.origins.remove(name),
+            };
+        }
+        Ok(())
+    }
+
+    /// Where the file at `path` is backed up when written at `now`, or
+    /// `None` when no backup is made.
+    pub fn backup_path(&self, path: &Path, now: SystemTime) -> Option<PathBuf> {
+        if !self.backup && !self.write_backup {
+            return None;
+        }
+        let mut name = path.file_name()?.to_os_string();
+        if self.backup_stamp {
+            let secs = now
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs());
+            name.push(format!(".{}", timestamp(secs)));
+        }
+        name.push(&self.backup_ext);
+        Some(match self.backup_dir.as_str() {
+            "" => path.with_file_name(name),
+            dir => Path::new(dir).join(name),
+        })
+    }
+
+    pub fn undo_dir(&self) -> Option<PathBuf> {
+        match self.undo_dir.as_str() {
+            "" => Some(crate::state_dir()?.join("undo")),
+            dir => Some(PathBuf::from(dir)),
+        }
+    }
+
+    fn flag(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "autopairs" | "ap" => Some(&mut self.auto_pairs),
+            "autosave" | "as" => Some(&mut self.autosave),
+            "backup" | "bk" => Some(&mut self.backup),
+            "backupstamp" | "bks" => Some(&mut self.backup_stamp),
+            "cursorcolumn" | "cuc" => Some(&mut self.cursor_column),
+            "cursorline" | "cul" => Some(&mut self.cursor_line),
+            "highlighttrailing" | "hlt" => Some(&mut self.highlight_trailing),
+            "indentguides" | "ig" => Some(&mut self.indent_guides),
+            "list" => Some(&mut self.list),
+            "ignorecase" | "ic" => Some(&mut self.ignore_case),
+            "smartcase" | "scs" => Some(&mut self.smart_case),
+            "wrapscan" | "ws" => Some(&mut self.wrap_scan),
+            "hlsearch" | "hls" => Some(&mut self.hl_search),
+            "rainbow" => Some(&mut self.rainbow),
+            "matchparen" => Some(&mut self.match_paren),
+            "fixendofline" | "fixeol" => Some(&mut self.fix_end_of_line),
+            "trimtrailing" | "trt" => Some(&mut self.trim_trailing),
+            "blame" => Some(&mut self.blame),
+            "toasts" => Some(&mut self.toasts),
+            "undofile" | "udf" => Some(&mut self.undo_file),
+            "writebackup" | "wb" => Some(&mut self.write_backup),
+            _ => None,
+        }
+    }
+}
+
+/// The `fillchars` option, in vim's notation of comma-separated `item:c`
+/// pairs such as `vert:|,eob:~`. Items left out keep their default.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FillChars {
+    /// Separates the two windows of diff mode.
+    pub vert: char,
+    /// Pads the row standing for a closed fold.
+    pub fold: char,
+    /// Rows below the end of the buffer.
+    pub eob: char,
+    /// Rows of diff mode standing in for lines only the other side has.
+    pub diff: char,
+}
+
+impl Default for FillChars {
+    fn default() -> Self {
+        Self {
+            vert: '\u{2502}',
+            fold: '-',
+            eob: '~',
+            diff: '-',
+        }
+    }
+}
+
+impl FillChars {
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        let mut fill_chars = Self::default();
+        for item in value.split(',').filter(|item| !item.is_empty()) {
+            let (name, c) = item
+                .split_once(':')
+                .with_context(|| format!("Invalid argument: fillchars={value}"))?;
+            let mut chars = c.chars();
+            let (Some(c), None) = (chars.next(), chars.next()) else {
+                bail!("Invalid argument: fillchars={value}");
+            };
+            let slot = match name {
+                "vert" => &mut fill_chars.vert,
+                "fold" => &mut fill_chars.fold,
+                "eob" => &mut fill_chars.eob,
+                "diff" => &mut fill_chars.diff,
+                _ => bail!("Invalid argument: fillchars={value}"),
+            };
+            *slot = c;
+        }
+        Ok(fill_chars)
+    }
+
+    /// The value as shown by `:set all`, with a space escaped.
+    pub fn source(&self) -> String {
+        let items = [
+            ("vert", self.vert),
+            ("fold", self.fold),
+            ("eob", self.eob),
+            ("diff", self.diff),
+        ];
+        items
+            .map(|(name, c)| match c {
+                ' ' => format!("{name}:\\ "),
+                c => format!("{name}:{c}"),
+            })
+            .join(",")
+    }
+}
+
+/// The `listchars` option, in vim's notation of comma-separated `item:c`
+/// pairs such as `tab:>,trail:-`. Items left out are not shown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListChars {
+    /// Takes one cell like any char. Vim's two or three chars for a tab
+    /// are taken, showing the first.
+    pub tab: Option<char>,
+    /// Spaces at the end of a line, shown as `space` when left out.
+    pub trail: Option<char>,
+    pub nbsp: Option<char>,
+    pub space: Option<char>,
+}
+
+impl Default for ListChars {
+    fn default() -> Self {
+        Self {
+            tab: Some('>'),
+            trail: Some('-'),
+            nbsp: Some('+'),
+            space: None,
+        }
+    }
+}
+
+impl ListChars {
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        let mut list_chars = Self {
+            tab: None,
+            trail: None,
+            nbsp: None,
+            space: None,
+        };
+        for item in value.split(',').filter(|item| !item.is_empty()) {
+            let (name, c) = item
+                .split_once(':')
+                .with_context(|| format!("Invalid argument: listchars={value}"))?;
+            let chars: Vec<char> = c.chars().collect();
+            let (slot, most) = match name {
+                "tab" => (&mut list_chars.tab, 3),
+                "trail" => (&mut list_chars.trail, 1),
+                "nbsp" => (&mut list_chars.nbsp, 1),
+                "space" => (&mut list_chars.space, 1),
+                _ => bail!("Invalid argument: listchars={value}"),
+            };
+            if chars.is_empty() || chars.len() > most {
+                bail!("Invalid argument: listchars={value}");
+            }
+            *slot = Some(chars[0]);
+        }
+        Ok(list_chars)
+    }
+
+    /// What `c` is shown as, if it is whitespace with a char to show.
+    pub fn glyph(&self, c: char, trailing: bool) -> Option<char> {
+        match c {
+            '\t' => self.tab,
+            ' ' if trailing => self.trail.or(self.space),
+            ' ' => self.space,
+            '\u{a0}' => self.nbsp,
+            _ => None,
+        }
+    }
+
+    /// The value as shown by `:set all`, with a space escaped.
+    pub fn source(&self) -> String {
+        let items = [
+            ("tab", self.tab),
+            ("trail", self.trail),
+            ("nbsp", self.nbsp),
+            ("space", self.space),
+        ];
+        items
+            .into_iter()
+            .filter_map(|(name, c)| match c? {
+                ' ' => Some(format!("{name}:\\ ")),
+                c => Some(format!("{name}:{c}")),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+/// The full name of the option called `name`, or an abbreviation of it.
+fn canonical(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "autopairs" | "ap" => "autopairs",
+        "autosave" | "as" => "autosave",
+        "autosavedelay" | "asd" => "autosavedelay",
+        "backup" | "bk" => "backup",
+        "backupdir" | "bdir" => "backupdir",
+        "backupext" | "bex" => "backupext",
+        "backupstamp" | "bks" => "backupstamp",
+        "blame" => "blame",
+        "colorcolumn" | "cc" => "colorcolumn",
+        "commentstring" | "cms" => "commentstring",
+        "cursorcolumn" | "cuc" => "cursorcolumn",
+        "cursorline" | "cul" => "cursorline",
+        "fillchars" | "fcs" => "fillchars",
+        "fixendofline" | "fixeol" => "fixendofline",
+        "foldmethod" | "fdm" => "foldmethod",
+        "foldtext" | "fdt" => "foldtext",
+        "formatonsave" | "fos" => "formatonsave",
+        "highlighttrailing" | "hlt" => "highlighttrailing",
+        "history" | "hi" => "history",
+        "hlsearch" | "hls" => "hlsearch",
+        "idlelock" | "il" => "idlelock",
+        "ignorecase" | "ic" => "ignorecase",
+        "indentguides" | "ig" => "indentguides",
+        "langmap" | "lmap" => "langmap",
+        "list" => "list",
+        "listchars" | "lcs" => "listchars",
+        "matchparen" => "matchparen",
+        "notify" => "notify",
+        "rainbow" => "rainbow",
+        "scrolloff" | "so" => "scrolloff",
+        "segmentlength" | "sgl" => "segmentlength",
+        "sidescrolloff" | "siso" => "sidescrolloff",
+        "signs" => "signs",
+        "smartcase" | "scs" => "smartcase",
+        "statusline" | "stl" => "statusline",
+        "textwidth" | "tw" => "textwidth",
+        "toasts" => "toasts",
+        "trimtrailing" | "trt" => "trimtrailing",
+        "undodir" | "udir" => "undodir",
+        "undofile" | "udf" => "undofile",
+        "undolevels" | "ul" => "undolevels",
+        "wrapscan" | "ws" => "wrapscan",
+        "writebackup" | "wb" => "writebackup",
+        _ => return None,
+    })
+}
+
+/// The UTC time `secs` after the epoch as `YYYYMMDD-HHMMSS`, which sorts
+/// backups by when they were made.
+fn timestamp(secs: u64) -> String {
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // From days since the epoch to the civil date, after Howard Hinnant.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}{:02}{:02}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// The option a `:set` argument such as `tw=80` or `nocul` sets.
+fn option_name(arg: &str) -> Option<&'static str> {
+    let name = arg.split(['=', '+', '-', '!']).next().unwrap_or_default();
+    canonical(name).or_else(|| canonical(name.strip_prefix("no")?))
+}
+
+/// Sets a number option, a bare name setting it to 0.
+fn set_number<T: FromStr + Default>(number: &mut T, arg: &str, value: &str) -> anyhow::Result<()> {
+    *number = match value {
+        "" => T::default(),
+        _ => match value.parse() {
+            Result::Ok(value) => value,
+            Err(_) => bail!("Invalid argument: {arg}"),
+        },
+    };
+    Ok(())
+}
+
+fn set_list(list: &mut Vec<String>, op: &str, value: &str) {
+    let items = value.split(',').filter(|item| !item.is_empty());
+    match op {
+        "+" => {
+            for item in items {
+                if !list.iter().any(|existing| existing == item) {
+                    list.push(item.to_string());
+                }
+            }
+        }
+        "-" => {
+            let removed: Vec<&str> = items.collect();
+            list.retain(|existing| !removed.contains(&existing.as_str()));
+        }
+        _ => *list = items.map(str::to_string).collect(),
+    }
+}
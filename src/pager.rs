@@ -0,0 +1,83 @@
+/// A line shown in the pager, optionally carrying what to do when the line
+/// is selected.
+pub struct PagerLine<T> {
+    pub text: String,
+    pub target: Option<T>,
+}
+
+/// Read-only, scrollable listing drawn over the viewport, used for the output
+/// of commands such as `:changes` and for picking from a list of choices.
+pub struct Pager<T> {
+    pub title: String,
+    lines: Vec<PagerLine<T>>,
+    top: usize,
+    selected: usize,
+}
+
+impl<T> Pager<T> {
+    pub fn new(title: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            lines: Vec::new(),
+            top: 0,
+            selected: 0,
+        }
+    }
+
+    pub fn push(&mut self, text: String) {
+        self.lines.push(PagerLine { text, target: None });
+    }
+
+    pub fn push_target(&mut self, text: String, target: T) {
+        self.lines.push(PagerLine {
+            text,
+            target: Some(target),
+        });
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(|line| line.text.as_str())
+    }
+
+    pub fn selected_text(&self) -> Option<&str> {
+        Some(self.lines.get(self.selected)?.text.as_str())
+    }
+
+    pub fn select(&mut self, line: usize) {
+        self.selected = line.min(self.lines.len().saturating_sub(1));
+    }
+
+    pub fn move_up(&mut self, n: usize) {
+        self.selected = self.selected.saturating_sub(n);
+    }
+
+    pub fn move_down(&mut self, n: usize) {
+        self.select(self.selected + n);
+    }
+
+    pub fn into_selected(mut self) -> Option<T> {
+        if self.selected < self.lines.len() {
+            self.lines.swap_remove(self.selected).target
+        } else {
+            None
+        }
+    }
+
+    /// Scrolls so the selection stays within `height` rows and returns the
+    /// visible lines along with whether each one is selected.
+    pub fn visible(&mut self, height: usize) -> impl Iterator<Item = (bool, &str)> {
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + height {
+            self.top = self.selected + 1 - height;
+        }
+
+        let selected = self.selected;
+        self.lines
+            .iter()
+            .enumerate()
+            .skip(self.top)
+            .take(height)
+            .map(move |(i, line)| (i == selected, line.text.as_str()))
+    }
+}
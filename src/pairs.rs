@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use ropey::Rope;
+
+/// Brackets and quotes closed as they are typed, as opening and closing
+/// char.
+const PAIRS: &[(char, char)] = &[
+    ('(', ')'),
+    ('[', ']'),
+    ('{', '}'),
+    ('"', '"'),
+    ('\'', '\''),
+    ('`', '`'),
+];
+
+/// Filetypes where a quote also starts something other than a string, such
+/// as a Rust lifetime, and is never paired.
+const UNPAIRED_QUOTES: &[(&str, char)] = &[("rust", '\'')];
+
+/// The char to insert after `c` typed between `before` and `after`, if it
+/// opens a pair that should be closed. Pairs are only closed in front of
+/// whitespace or a closing char, and quotes not right after a word.
+pub fn closing(
+    c: char,
+    before: Option<char>,
+    after: Option<char>,
+    filetype: Option<&str>,
+) -> Option<char> {
+    let &(open, close) = PAIRS.iter().find(|(open, _)| *open == c)?;
+    let after_word = before.is_some_and(|b| b.is_alphanumeric() || b == '_' || b == open);
+    let unpaired = filetype.is_some_and(|filetype| UNPAIRED_QUOTES.contains(&(filetype, open)));
+    if open == close && (after_word || unpaired) {
+        return None;
+    }
+    let closes =
+        after.is_none_or(|a| a.is_whitespace() || PAIRS.iter().any(|&(_, close)| close == a));
+    closes.then_some(close)
+}
+
+/// Whether typing `c` in front of `after` should step over it instead.
+pub fn skips(c: char, after: Option<char>) -> bool {
+    after == Some(c) && PAIRS.iter().any(|&(_, close)| close == c)
+}
+
+/// Whether the cursor is inside an empty pair, which backspace deletes
+/// whole.
+pub fn is_empty_pair(before: Option<char>, after: Option<char>) -> bool {
+    PAIRS
+        .iter()
+        .any(|&pair| Some(pair.0) == before && Some(pair.1) == after)
+}
+
+/// Brackets matched with each other, as opening and closing char.
+const BRACKETS: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}')];
+
+/// How far from a bracket its match is looked for, in chars.
+const MATCH_LIMIT: usize = 100_000;
+
+/// The bracket at char `at` of `text` and the one matching it, counting
+/// brackets of the same kind nested between them.
+pub fn matching_bracket(text: &Rope, at: usize) -> Option<usize> {
+    let c = text.get_char(at)?;
+    let &(open, close) = BRACKETS.iter().find(|&&(o, cl)| c == o || c == cl)?;
+    let mut depth = 0usize;
+    if c == open {
+        let end = text.len_chars().min(at + MATCH_LIMIT);
+        for (i, c) in text.slice(at..end).chars().enumerate() {
+            match c {
+                _ if c == open => depth += 1,
+                _ if c == close => depth -= 1,
+                _ => continue,
+            }
+            if depth == 0 {
+                return Some(at + i);
+            }
+        }
+    } else {
+        let start = at.saturating_sub(MATCH_LIMIT);
+        let mut chars = text.slice(start..=at).chars_at(at + 1 - start);
+        let mut i = at + 1;
+        while let Some(c) = chars.prev() {
+            i -= 1;
+            match c {
+                _ if c == close => depth += 1,
+                _ if c == open => depth -= 1,
+                _ => continue,
+            }
+            if depth == 0 {
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+/// The brackets among the chars in `range` of `text`, by char index, with
+/// how deeply each is nested in the brackets before it, counted from the
+/// start of the text or at most `MATCH_LIMIT` chars back. Brackets of any
+/// kind nest in each other, and a closing one has the depth of its opening
+/// one.
+pub fn bracket_depths(text: &Rope, range: Range<usize>) -> HashMap<usize, usize> {
+    let start = range.start.saturating_sub(MATCH_LIMIT);
+    let mut depth = 0usize;
+    let mut depths = HashMap::new();
+    for (i, c) in text.slice(start..range.end).chars().enumerate() {
+        let at = start + i;
+        if BRACKETS.iter().any(|&(open, _)| open == c) {
+            if at >= range.start {
+                depths.insert(at, depth);
+            }
+            depth += 1;
+        } else if BRACKETS.iter().any(|&(_, close)| close == c) {
+            depth = depth.saturating_sub(1);
+            if at >= range.start {
+                depths.insert(at, depth);
+            }
+        }
+    }
+    depths
+}
@@ -0,0 +1,144 @@
+//! The command palette, opened with Ctrl-p or `:palette`: every ex command
+//! and every key bound in normal mode, found by fuzzy search over their
+//! names and keys. Choosing an entry types its keys, so it behaves just as
+//! typing them would; a command can instead be left on the command line to
+//! add arguments to it.
+
+use std::borrow::Cow;
+
+use crate::picker::PickerItem;
+
+/// The keys of normal mode and what they do. Keys ending in a part in
+/// braces, such as `m{a-z}`, wait for it to be typed.
+pub const BINDINGS: &[(&str, &str)] = &[
+    ("k", "Move up"),
+    ("l", "Move down"),
+    ("j", "Move left"),
+    (";", "Move right"),
+    ("0", "Go to the start of the line"),
+    ("^", "Go to the first non-blank of the line"),
+    ("_", "Go to the first non-blank of the line"),
+    ("$", "Go to the end of the line"),
+    ("<Home>", "Go to the first non-blank or the start"),
+    ("<End>", "Go to the end of the line"),
+    ("gk", "Move up a screen row"),
+    ("gl", "Move down a screen row"),
+    ("g0", "Go to the start of the screen row"),
+    ("g$", "Go to the end of the screen row"),
+    ("{", "Go to the previous paragraph"),
+    ("}", "Go to the next paragraph"),
+    ("(", "Go to the previous sentence"),
+    (")", "Go to the next sentence"),
+    ("[[", "Go to the previous section"),
+    ("]]", "Go to the next section"),
+    ("[m", "Go to the previous method"),
+    ("]m", "Go to the next method"),
+    ("<C-f>", "Page down"),
+    ("<C-b>", "Page up"),
+    ("<PageDown>", "Page down"),
+    ("<PageUp>", "Page up"),
+    ("m{a-z}", "Set a mark"),
+    ("i", "Insert"),
+    ("v", "Visual mode"),
+    ("gv", "Reselect the last selection"),
+    (":", "Command line"),
+    ("<C-p>", "Command palette"),
+    ("/", "Search forward"),
+    ("?", "Search backward"),
+    ("n", "Next match"),
+    ("N", "Previous match"),
+    ("*", "Search for the word under the cursor"),
+    ("#", "Search backward for the word under the cursor"),
+    ("g*", "Search for the word under the cursor in words"),
+    (
+        "g#",
+        "Search backward for the word under the cursor in words",
+    ),
+    ("x", "Delete the char under the cursor"),
+    ("p", "Put after the cursor"),
+    ("P", "Put before the cursor"),
+    ("J", "Join lines"),
+    ("u", "Undo"),
+    ("<C-r>", "Redo"),
+    ("g-", "Go to the older text state"),
+    ("g+", "Go to the newer text state"),
+    ("g;", "Go to the older change"),
+    ("g,", "Go to the newer change"),
+    ("gcc", "Toggle comment on the line"),
+    ("gc{motion}", "Toggle comment on the lines moved over"),
+    ("<A-k>", "Drag the line up"),
+    ("<A-l>", "Drag the line down"),
+    ("K", "Show hover information"),
+    ("gf", "Edit the file under the cursor"),
+    ("gx", "Open the link under the cursor"),
+    ("-", "Open the parent directory"),
+    ("]c", "Go to the next hunk"),
+    ("[c", "Go to the previous hunk"),
+    ("]x", "Go to the next conflict"),
+    ("[x", "Go to the previous conflict"),
+    ("do", "Get the change from the other window"),
+    ("dp", "Put the change in the other window"),
+    ("zf{motion}", "Fold the lines moved over"),
+    ("za", "Toggle the fold"),
+    ("zo", "Open the fold"),
+    ("zc", "Close the fold"),
+    ("zd", "Delete the fold"),
+    ("zR", "Open all folds"),
+    ("zM", "Close all folds"),
+    ("zE", "Delete all folds"),
+    ("<C-w>w", "Switch window"),
+    ("<C-w>j", "Switch window"),
+    ("<C-w>;", "Switch window"),
+    ("<C-w>c", "Close the window"),
+    ("<C-w>q", "Quit the window"),
+    ("<C-z>", "Suspend"),
+    ("ZZ", "Write and quit"),
+    ("ZQ", "Quit discarding changes"),
+    ("q", "Quit"),
+];
+
+/// The keys of `lhs` in `BINDINGS` typed before the part in braces, left
+/// for the user to type.
+pub fn typed(lhs: &str) -> &str {
+    match lhs.strip_suffix('}').and_then(|rest| rest.rsplit_once('{')) {
+        Some((typed, _)) if !typed.is_empty() => typed,
+        _ => lhs,
+    }
+}
+
+/// A command or key binding offered by the palette.
+#[derive(Debug)]
+pub struct Entry {
+    label: String,
+    /// The keys typed to run it, in the notation of `keys`.
+    pub keys: String,
+}
+
+impl Entry {
+    /// The ex command `name`, run without arguments.
+    pub fn command(name: &str) -> Self {
+        Self {
+            label: format!(":{name}"),
+            keys: format!(":{name}<CR>"),
+        }
+    }
+
+    /// The keys `lhs` doing what `description` says by typing `keys`.
+    pub fn binding(lhs: &str, description: &str, keys: &str) -> Self {
+        Self {
+            label: format!("{description:<50} {lhs}"),
+            keys: keys.to_string(),
+        }
+    }
+
+    /// The keys typed up to the end of the command line, to add to it.
+    pub fn partial(&self) -> &str {
+        self.keys.strip_suffix("<CR>").unwrap_or(&self.keys)
+    }
+}
+
+impl PickerItem for Entry {
+    fn label(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.label)
+    }
+}
@@ -0,0 +1,337 @@
+//! A fuzzy finder over a list of items, shared by every feature that asks
+//! to choose something so they all look and behave the same.
+//!
+//! ```
+//! use vim_rs::picker::{Input, Outcome, Pick, Picker};
+//!
+//! let fruit = vec!["apple".to_string(), "banana".to_string()];
+//! let mut picker = Picker::new("fruit", fruit)
+//!     .with_preview(|name: &String| vec![format!("{name} is a fruit")])
+//!     .on_select(|names: Vec<&String>| names[0].clone())
+//!     .with_action("count", |names: Vec<&String>| names.len().to_string());
+//!
+//! for c in "bna".chars() {
+//!     picker.input(Input::Char(c));
+//! }
+//! assert!(matches!(picker.input(Input::Accept), Outcome::Done(name) if name == "banana"));
+//! ```
+
+use std::borrow::Cow;
+use std::path::PathBuf;
+
+/// Anything a picker can list.
+pub trait PickerItem {
+    fn label(&self) -> Cow<'_, str>;
+}
+
+impl PickerItem for String {
+    fn label(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self)
+    }
+}
+
+impl PickerItem for PathBuf {
+    fn label(&self) -> Cow<'_, str> {
+        self.to_string_lossy()
+    }
+}
+
+/// A key press as far as a picker is concerned.
+#[derive(Debug)]
+pub enum Input {
+    Char(char),
+    Backspace,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    /// Marks or unmarks the highlighted item for acting on several at once.
+    ToggleMark,
+    /// Runs the chosen action, or the default one, on the marked items or
+    /// else the highlighted one.
+    Accept,
+    /// Opens or closes the menu of further actions.
+    Menu,
+    Cancel,
+}
+
+pub enum Outcome<A> {
+    Continue,
+    Cancel,
+    Done(A),
+}
+
+/// What to draw for a picker.
+pub struct View<'a> {
+    pub query: &'a str,
+    pub rows: Vec<Row<'a>>,
+    pub matched: usize,
+    pub total: usize,
+    pub preview: Option<&'a [String]>,
+    /// Action names and the highlighted one while the menu is open.
+    pub menu: Option<(Vec<&'a str>, usize)>,
+}
+
+pub struct Row<'a> {
+    pub label: &'a str,
+    /// Char positions in the label matching the query.
+    pub positions: &'a [usize],
+    pub selected: bool,
+    pub marked: bool,
+}
+
+/// A picker with its item type erased, as the editor keeps it.
+pub trait Pick<A> {
+    fn title(&self) -> &str;
+    fn query(&self) -> &str;
+    fn input(&mut self, input: Input) -> Outcome<A>;
+    /// Scrolls to keep the selection within `height` rows and returns what
+    /// to draw.
+    fn view(&mut self, height: usize) -> View<'_>;
+}
+
+type Callback<T, A> = Box<dyn Fn(Vec<&T>) -> A>;
+type Preview<T> = Box<dyn Fn(&T) -> Vec<String>>;
+
+pub struct Picker<T, A> {
+    title: String,
+    items: Vec<T>,
+    labels: Vec<String>,
+    query: String,
+    /// Items matching the query, best first, with the positions matched.
+    matches: Vec<(usize, Vec<usize>)>,
+    marked: Vec<bool>,
+    selected: usize,
+    top: usize,
+    /// Rows shown when last drawn, for paging.
+    height: usize,
+    preview: Option<Preview<T>>,
+    /// The previewed item and its preview, kept while it stays selected.
+    preview_cache: Option<(usize, Vec<String>)>,
+    select: Option<Callback<T, A>>,
+    actions: Vec<(String, Callback<T, A>)>,
+    menu: Option<usize>,
+}
+
+impl<T: PickerItem, A> Picker<T, A> {
+    pub fn new(title: &str, items: Vec<T>) -> Self {
+        let labels: Vec<String> = items.iter().map(|item| item.label().into_owned()).collect();
+        Self {
+            title: title.to_string(),
+            matches: (0..items.len()).map(|i| (i, Vec::new())).collect(),
+            marked: vec![false; items.len()],
+            items,
+            labels,
+            query: String::new(),
+            selected: 0,
+            top: 0,
+            height: 1,
+            preview: None,
+            preview_cache: None,
+            select: None,
+            actions: Vec::new(),
+            menu: None,
+        }
+    }
+
+    /// Shows the lines returned by `preview` for the highlighted item next
+    /// to the list.
+    pub fn with_preview(mut self, preview: impl Fn(&T) -> Vec<String> + 'static) -> Self {
+        self.preview = Some(Box::new(preview));
+        self
+    }
+
+    /// What Enter does with the chosen items.
+    pub fn on_select(mut self, select: impl Fn(Vec<&T>) -> A + 'static) -> Self {
+        self.select = Some(Box::new(select));
+        self
+    }
+
+    /// Adds an entry to the action menu.
+    pub fn with_action(mut self, name: &str, action: impl Fn(Vec<&T>) -> A + 'static) -> Self {
+        self.actions.push((name.to_string(), Box::new(action)));
+        self
+    }
+
+    fn refilter(&mut self) {
+        let mut scored: Vec<(i64, usize, Vec<usize>)> = self
+            .labels
+            .iter()
+            .enumerate()
+            .filter_map(|(i, label)| {
+                let (score, positions) = fuzzy_match(&self.query, label)?;
+                Some((score, i, positions))
+            })
+            .collect();
+        // Stable, so equally good matches keep the order they were given in.
+        scored.sort_by_key(|(score, ..)| std::cmp::Reverse(*score));
+        self.matches = scored.into_iter().map(|(_, i, p)| (i, p)).collect();
+        self.selected = 0;
+        self.top = 0;
+    }
+
+    /// The marked items, or the highlighted one when none are.
+    fn chosen(&self) -> Vec<&T> {
+        let marked: Vec<&T> = (0..self.items.len())
+            .filter(|&i| self.marked[i])
+            .map(|i| &self.items[i])
+            .collect();
+        if !marked.is_empty() {
+            return marked;
+        }
+        self.matches
+            .get(self.selected)
+            .map(|&(i, _)| &self.items[i])
+            .into_iter()
+            .collect()
+    }
+
+    fn move_by(&mut self, delta: isize) {
+        match self.menu.as_mut() {
+            Some(menu) => {
+                *menu = menu
+                    .saturating_add_signed(delta)
+                    .min(self.actions.len().saturating_sub(1));
+            }
+            None => {
+                self.selected = self
+                    .selected
+                    .saturating_add_signed(delta)
+                    .min(self.matches.len().saturating_sub(1));
+            }
+        }
+    }
+}
+
+impl<T: PickerItem, A> Pick<A> for Picker<T, A> {
+    fn title(&self) -> &str {
+        &self.title
+    }
+
+    fn query(&self) -> &str {
+        &self.query
+    }
+
+    fn input(&mut self, input: Input) -> Outcome<A> {
+        let page = self.height.max(1) as isize;
+        match input {
+            Input::Char(c) => {
+                self.query.push(c);
+                self.refilter();
+            }
+            Input::Backspace => {
+                if self.query.pop().is_some() {
+                    self.refilter();
+                }
+            }
+            Input::Up => self.move_by(-1),
+            Input::Down => self.move_by(1),
+            Input::PageUp => self.move_by(-page),
+            Input::PageDown => self.move_by(page),
+            Input::ToggleMark => {
+                if let Some(&(i, _)) = self.matches.get(self.selected) {
+                    self.marked[i] = !self.marked[i];
+                    self.move_by(1);
+                }
+            }
+            Input::Menu => {
+                self.menu = match self.menu {
+                    None if !self.actions.is_empty() => Some(0),
+                    _ => None,
+                };
+            }
+            Input::Accept => {
+                let chosen = self.chosen();
+                if chosen.is_empty() {
+                    return Outcome::Continue;
+                }
+                let action = match self.menu {
+                    Some(menu) => &self.actions[menu].1,
+                    None => match &self.select {
+                        Some(select) => select,
+                        None => return Outcome::Cancel,
+                    },
+                };
+                return Outcome::Done(action(chosen));
+            }
+            Input::Cancel if self.menu.is_some() => self.menu = None,
+            Input::Cancel => return Outcome::Cancel,
+        }
+        Outcome::Continue
+    }
+
+    fn view(&mut self, height: usize) -> View<'_> {
+        self.height = height;
+        if self.selected < self.top {
+            self.top = self.selected;
+        } else if self.selected >= self.top + height {
+            self.top = self.selected + 1 - height;
+        }
+
+        let highlighted = self.matches.get(self.selected).map(|&(i, _)| i);
+        let stale = self
+            .preview_cache
+            .as_ref()
+            .is_none_or(|(cached, _)| Some(*cached) != highlighted);
+        if let (Some(preview), Some(i), true) = (&self.preview, highlighted, stale) {
+            self.preview_cache = Some((i, preview(&self.items[i])));
+        }
+
+        let rows = self
+            .matches
+            .iter()
+            .enumerate()
+            .skip(self.top)
+            .take(height)
+            .map(|(row, (i, positions))| Row {
+                label: &self.labels[*i],
+                positions,
+                selected: row == self.selected,
+                marked: self.marked[*i],
+            })
+            .collect();
+        View {
+            query: &self.query,
+            rows,
+            matched: self.matches.len(),
+            total: self.items.len(),
+            preview: self
+                .preview_cache
+                .as_ref()
+                .filter(|(i, _)| Some(*i) == highlighted)
+                .map(|(_, lines)| lines.as_slice()),
+            menu: self.menu.map(|menu| {
+                let names = self.actions.iter().map(|(name, _)| name.as_str()).collect();
+                (names, menu)
+            }),
+        }
+    }
+}
+
+/// Scores how well `text` matches the chars of `query` appearing in order,
+/// ignoring case, favoring runs of consecutive chars and matches at word
+/// starts. Returns the score and the matched char positions.
+pub fn fuzzy_match(query: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    let text: Vec<char> = text.chars().collect();
+    let mut positions = Vec::new();
+    let mut score = 0;
+    let mut from = 0;
+    for q in query.chars().flat_map(char::to_lowercase) {
+        let offset = text[from..].iter().position(|c| c.to_lowercase().eq([q]))?;
+        let i = from + offset;
+        let boundary = i == 0
+            || matches!(text[i - 1], '/' | '_' | '-' | ' ' | '.' | ':')
+            || (text[i - 1].is_lowercase() && text[i].is_uppercase());
+        score += 16 - offset as i64;
+        if boundary {
+            score += 8;
+        }
+        if positions.last().is_some_and(|&last| last + 1 == i) {
+            score += 12;
+        }
+        positions.push(i);
+        from = i + 1;
+    }
+    // Among equal matches, shorter texts are closer to what was asked for.
+    Some((score * 256 - text.len().min(255) as i64, positions))
+}
@@ -0,0 +1,400 @@
+//! Plugins: Lua scripts in the `plugins` directory of the configuration,
+//! run at startup, which extend the editor through the global `editor`
+//! table. A script sees a copy of the buffer and cursor while it runs, and
+//! what it changed is applied once it returns. WebAssembly modules can be
+//! plugins too, through the host in `wasm`.
+//!
+//! ```lua
+//! editor.keymap("<C-d>", function()
+//!   local line, col = editor.get_cursor()
+//!   local lines = editor.get_lines()
+//!   table.insert(lines, line + 1, lines[line])
+//!   editor.set_lines(lines)
+//!   editor.set_cursor(line + 1, col)
+//! end)
+//! editor.command("Trim", function(args) editor.exec("w") end)
+//! editor.on("write", function() editor.log("written " .. editor.path()) end)
+//! editor.keymap("u", function() editor.exec("undo") end, "v")
+//! ```
+//!
+//! A key is mapped in normal mode, or in visual mode given `"v"` after
+//! the function. Mappings of one mode never apply in another, and in the
+//! mode they were made for they take precedence over what the key does in
+//! the editor, as `u` above undoes again instead of lowercasing the
+//! selection.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use anyhow::Context;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use mlua::{Function, Lua, RegistryKey};
+
+use crate::keys;
+use crate::options::Origin;
+use crate::{error, log};
+
+/// Editor events plugins can run functions on with `editor.on`: a buffer
+/// was opened or written, or the mode changed, which gets the new one.
+pub const EVENTS: &[&str] = &["open", "write", "mode"];
+
+/// The modes keys are mapped in, each with mappings of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MapMode {
+    Normal,
+    Visual,
+}
+
+impl MapMode {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "n" => Some(MapMode::Normal),
+            "v" | "x" => Some(MapMode::Visual),
+            _ => None,
+        }
+    }
+
+    /// The letter `:map` shows for the mode.
+    pub fn letter(self) -> char {
+        match self {
+            MapMode::Normal => 'n',
+            MapMode::Visual => 'v',
+        }
+    }
+}
+
+/// What a script sees of the editor.
+#[derive(Debug, Default)]
+pub struct View {
+    pub path: String,
+    pub lines: Vec<String>,
+    /// Line and char column, both from 0.
+    pub cursor: (usize, usize),
+    pub mode: String,
+}
+
+/// What a script changed, to be applied to the editor.
+#[derive(Debug, Default)]
+pub struct Outcome {
+    pub lines: Option<Vec<String>>,
+    /// Line and char column, both from 0.
+    pub cursor: Option<(usize, usize)>,
+    /// Ex commands to run, in order, with the plugin code that ran them.
+    pub commands: Vec<(String, Origin)>,
+}
+
+/// The editor as scripts reach it, and what they registered.
+#[derive(Default)]
+struct Host {
+    view: View,
+    outcome: Outcome,
+    keymaps: HashMap<(MapMode, KeyCode, KeyModifiers), (RegistryKey, Origin)>,
+    commands: HashMap<String, RegistryKey>,
+    handlers: Vec<(String, RegistryKey)>,
+}
+
+pub struct Plugins {
+    lua: Lua,
+    host: Rc<RefCell<Host>>,
+}
+
+impl Plugins {
+    pub fn new() -> anyhow::Result<Self> {
+        let lua = Lua::new();
+        let host = Rc::new(RefCell::new(Host::default()));
+        install_api(&lua, &host)?;
+        Ok(Self { lua, host })
+    }
+
+    /// Names and sources of the scripts in the plugins directory, in the
+    /// order of their names. Those that cannot be read are logged.
+    pub fn scripts() -> Vec<(String, String)> {
+        files("lua")
+            .into_iter()
+            .filter_map(|path| match fs::read_to_string(&path) {
+                Ok(source) => Some((path.display().to_string(), source)),
+                Err(err) => {
+                    error!("{}: {err}", path.display());
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Runs the script `source`, named `name` in errors.
+    pub fn load(&self, name: &str, source: &str, view: View) -> anyhow::Result<Outcome> {
+        self.run(view, || self.lua.load(source).set_name(name).exec())
+            .with_context(|| format!("Plugin {name} failed"))
+    }
+
+    /// Whether a plugin mapped `key` in `mode`.
+    pub fn maps(&self, mode: MapMode, key: &KeyEvent) -> bool {
+        self.host
+            .borrow()
+            .keymaps
+            .contains_key(&normalize(mode, key))
+    }
+
+    /// The keys mapped, in which mode and where, in the order of their
+    /// notation.
+    pub fn mappings(&self) -> Vec<(MapMode, String, Origin)> {
+        let host = self.host.borrow();
+        let mut mappings: Vec<(MapMode, String, Origin)> = host
+            .keymaps
+            .iter()
+            .filter_map(|(&(mode, code, modifiers), (_, origin))| {
+                Some((
+                    mode,
+                    keys::notation(&KeyEvent::new(code, modifiers))?,
+                    origin.clone(),
+                ))
+            })
+            .collect();
+        mappings.sort_by(|a, b| (&a.1, a.0).cmp(&(&b.1, b.0)));
+        mappings
+    }
+
+    pub fn run_keymap(&self, mode: MapMode, key: &KeyEvent, view: View) -> anyhow::Result<Outcome> {
+        let function = {
+            let host = self.host.borrow();
+            let (key, _) = host
+                .keymaps
+                .get(&normalize(mode, key))
+                .context("Key not mapped")?;
+            self.lua.registry_value::<Function>(key)?
+        };
+        self.run(view, || function.call(()))
+    }
+
+    pub fn run_command(&self, name: &str, args: &str, view: View) -> anyhow::Result<Outcome> {
+        let function = {
+            let host = self.host.borrow();
+            let key = host
+                .commands
+                .get(name)
+                .with_context(|| format!("Not an editor command: {name}"))?;
+            self.lua.registry_value::<Function>(key)?
+        };
+        self.run(view, || function.call(args))
+    }
+
+    /// Candidates for completing `typed`, returned by the global function
+    /// `function`.
+    pub fn complete(&self, function: &str, typed: &str) -> anyhow::Result<Vec<String>> {
+        let function: Function = self
+            .lua
+            .globals()
+            .get(function)
+            .with_context(|| format!("No completion function {function}"))?;
+        Ok(function.call(typed)?)
+    }
+
+    /// Runs the functions registered for `event`, passing them `arg`.
+    pub fn emit(&self, event: &str, arg: &str, view: View) -> anyhow::Result<Outcome> {
+        let functions = {
+            let host = self.host.borrow();
+            host.handlers
+                .iter()
+                .filter(|(name, _)| name == event)
+                .map(|(_, key)| self.lua.registry_value::<Function>(key))
+                .collect::<mlua::Result<Vec<_>>>()?
+        };
+        self.run(view, || {
+            functions
+                .iter()
+                .try_for_each(|function| function.call::<_, ()>(arg))
+        })
+    }
+
+    fn run(
+        &self,
+        view: View,
+        script: impl FnOnce() -> mlua::Result<()>,
+    ) -> anyhow::Result<Outcome> {
+        {
+            let mut host = self.host.borrow_mut();
+            host.view = view;
+            host.outcome = Outcome::default();
+        }
+        let result = script();
+        let outcome = std::mem::take(&mut self.host.borrow_mut().outcome);
+        result?;
+        Ok(outcome)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let host = self.host.borrow();
+        host.keymaps.is_empty() && host.commands.is_empty() && host.handlers.is_empty()
+    }
+}
+
+/// Files in the plugins directory with `extension`, sorted by name.
+pub fn files(extension: &str) -> Vec<PathBuf> {
+    let Some(dir) = crate::config_dir().map(|dir| dir.join("plugins")) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Fails unless `name` can name a plugin command, telling it apart from
+/// the built-in ones which start lowercase.
+pub fn check_command_name(name: &str) -> anyhow::Result<()> {
+    if !name.starts_with(|c: char| c.is_ascii_uppercase())
+        || !name.chars().all(|c| c.is_ascii_alphanumeric())
+    {
+        anyhow::bail!(
+            "Command names must be alphanumeric and start with an uppercase letter: {name}"
+        );
+    }
+    Ok(())
+}
+
+/// Where the Lua code calling into the editor is.
+fn caller(lua: &Lua) -> Origin {
+    let Some(debug) = lua.inspect_stack(1) else {
+        return Origin::Plugin {
+            name: String::new(),
+            line: None,
+        };
+    };
+    let name = debug.source().source.unwrap_or_default();
+    Origin::Plugin {
+        name: name.trim_start_matches(['@', '=']).to_string(),
+        line: usize::try_from(debug.curr_line()).ok(),
+    }
+}
+
+/// Keys as mapped in `mode`, ignoring Shift for chars which already tell
+/// it.
+fn normalize(mode: MapMode, key: &KeyEvent) -> (MapMode, KeyCode, KeyModifiers) {
+    match key.code {
+        KeyCode::Char(_) => (mode, key.code, key.modifiers - KeyModifiers::SHIFT),
+        _ => (mode, key.code, key.modifiers),
+    }
+}
+
+fn install_api(lua: &Lua, host: &Rc<RefCell<Host>>) -> mlua::Result<()> {
+    let api = lua.create_table()?;
+
+    let h = host.clone();
+    api.set(
+        "path",
+        lua.create_function(move |_, ()| Ok(h.borrow().view.path.clone()))?,
+    )?;
+    let h = host.clone();
+    api.set(
+        "mode",
+        lua.create_function(move |_, ()| Ok(h.borrow().view.mode.clone()))?,
+    )?;
+    let h = host.clone();
+    api.set(
+        "get_lines",
+        lua.create_function(move |_, ()| Ok(h.borrow().view.lines.clone()))?,
+    )?;
+    let h = host.clone();
+    api.set(
+        "set_lines",
+        lua.create_function(move |_, lines: Vec<String>| {
+            let mut host = h.borrow_mut();
+            host.view.lines = lines.clone();
+            host.outcome.lines = Some(lines);
+            Ok(())
+        })?,
+    )?;
+    // Lines count from 1 in Lua, columns from 0.
+    let h = host.clone();
+    api.set(
+        "get_cursor",
+        lua.create_function(move |_, ()| {
+            let (line, col) = h.borrow().view.cursor;
+            Ok((line + 1, col))
+        })?,
+    )?;
+    let h = host.clone();
+    api.set(
+        "set_cursor",
+        lua.create_function(move |_, (line, col): (usize, usize)| {
+            if line == 0 {
+                return Err(mlua::Error::runtime("Lines count from 1"));
+            }
+            let mut host = h.borrow_mut();
+            host.view.cursor = (line - 1, col);
+            host.outcome.cursor = Some((line - 1, col));
+            Ok(())
+        })?,
+    )?;
+    let h = host.clone();
+    api.set(
+        "exec",
+        lua.create_function(move |lua, command: String| {
+            h.borrow_mut().outcome.commands.push((command, caller(lua)));
+            Ok(())
+        })?,
+    )?;
+    api.set(
+        "log",
+        lua.create_function(|_, message: String| {
+            log!("{message}");
+            Ok(())
+        })?,
+    )?;
+    let h = host.clone();
+    api.set(
+        "keymap",
+        lua.create_function(
+            move |lua, (notation, function, mode): (String, Function, Option<String>)| {
+                let keys = keys::parse_keys(&notation).map_err(mlua::Error::runtime)?;
+                let [key] = keys.as_slice() else {
+                    return Err(mlua::Error::runtime(format!(
+                        "Only single keys can be mapped: {notation}"
+                    )));
+                };
+                let mode = match mode.as_deref() {
+                    None => MapMode::Normal,
+                    Some(name) => MapMode::parse(name)
+                        .ok_or_else(|| mlua::Error::runtime(format!("Unknown mode: {name}")))?,
+                };
+                let function = lua.create_registry_value(function)?;
+                h.borrow_mut()
+                    .keymaps
+                    .insert(normalize(mode, key), (function, caller(lua)));
+                Ok(())
+            },
+        )?,
+    )?;
+    let h = host.clone();
+    api.set(
+        "command",
+        lua.create_function(move |lua, (name, function): (String, Function)| {
+            check_command_name(&name).map_err(mlua::Error::runtime)?;
+            let function = lua.create_registry_value(function)?;
+            h.borrow_mut().commands.insert(name, function);
+            Ok(())
+        })?,
+    )?;
+    let h = host.clone();
+    api.set(
+        "on",
+        lua.create_function(move |lua, (event, function): (String, Function)| {
+            if !EVENTS.contains(&event.as_str()) {
+                return Err(mlua::Error::runtime(format!("Unknown event: {event}")));
+            }
+            let function = lua.create_registry_value(function)?;
+            h.borrow_mut().handlers.push((event, function));
+            Ok(())
+        })?,
+    )?;
+
+    lua.globals().set("editor", api)
+}
@@ -0,0 +1,124 @@
+/// Widest a popup is allowed to grow, in columns.
+const MAX_WIDTH: usize = 72;
+/// Tallest a popup is allowed to grow, in rows.
+pub const MAX_HEIGHT: usize = 16;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LineStyle {
+    Text,
+    Code,
+    Heading,
+    Rule,
+}
+
+/// A floating box of text drawn over the viewport next to the cursor, such
+/// as the documentation returned by a hover request.
+pub struct Popup {
+    pub lines: Vec<(LineStyle, String)>,
+    pub scroll: usize,
+    pub width: usize,
+}
+
+impl Popup {
+    /// Lays out markdown for display: fenced blocks keep their lines as code,
+    /// headings and rules get their own style, and prose is word-wrapped with
+    /// inline emphasis markers removed.
+    pub fn from_markdown(markdown: &str, max_width: usize) -> Self {
+        let max_width = max_width.clamp(1, MAX_WIDTH);
+        let mut lines: Vec<(LineStyle, String)> = Vec::new();
+        let mut in_code = false;
+        // Prose lines are joined until a blank line or another block starts,
+        // since documentation is usually hard-wrapped at its own width.
+        let mut paragraph = String::new();
+
+        for line in markdown.lines() {
+            let trimmed = line.trim();
+            let starts_block = in_code
+                || trimmed.is_empty()
+                || trimmed.starts_with("```")
+                || trimmed.starts_with('#')
+                || trimmed.starts_with("- ")
+                || trimmed.starts_with("* ")
+                || matches!(trimmed, "---" | "***" | "___");
+            if starts_block && !paragraph.is_empty() {
+                wrap(&strip_inline(&paragraph), max_width, &mut lines);
+                paragraph.clear();
+            }
+
+            if trimmed.starts_with("```") {
+                in_code = !in_code;
+            } else if in_code {
+                lines.push((LineStyle::Code, line.replace('\t', "    ")));
+            } else if trimmed.starts_with('#') {
+                let heading = trimmed.trim_start_matches('#').trim();
+                lines.push((LineStyle::Heading, strip_inline(heading)));
+            } else if matches!(trimmed, "---" | "***" | "___") {
+                lines.push((LineStyle::Rule, String::new()));
+            } else if trimmed.is_empty() {
+                if lines.last().is_some_and(|(_, l)| !l.is_empty()) {
+                    lines.push((LineStyle::Text, String::new()));
+                }
+            } else if paragraph.is_empty() {
+                paragraph.push_str(line.trim_end());
+            } else {
+                paragraph.push(' ');
+                paragraph.push_str(trimmed);
+            }
+        }
+        if !paragraph.is_empty() {
+            wrap(&strip_inline(&paragraph), max_width, &mut lines);
+        }
+        while lines
+            .last()
+            .is_some_and(|(style, l)| *style != LineStyle::Rule && l.is_empty())
+        {
+            lines.pop();
+        }
+
+        let width = lines
+            .iter()
+            .map(|(_, l)| l.chars().count())
+            .max()
+            .unwrap_or(0)
+            .clamp(1, max_width);
+
+        Self {
+            lines,
+            scroll: 0,
+            width,
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        self.lines.len().min(MAX_HEIGHT)
+    }
+
+    pub fn scroll_by(&mut self, delta: isize) {
+        let max_scroll = self.lines.len().saturating_sub(self.height());
+        self.scroll = self.scroll.saturating_add_signed(delta).min(max_scroll);
+    }
+}
+
+fn strip_inline(text: &str) -> String {
+    text.replace("**", "").replace("__", "").replace('`', "")
+}
+
+fn wrap(text: &str, width: usize, lines: &mut Vec<(LineStyle, String)>) {
+    let indent: String = text.chars().take_while(|c| c.is_whitespace()).collect();
+    let mut current = indent.clone();
+
+    for word in text.split_whitespace() {
+        let len = current.chars().count();
+        if len > indent.len() && len + 1 + word.chars().count() > width {
+            lines.push((
+                LineStyle::Text,
+                std::mem::replace(&mut current, indent.clone()),
+            ));
+        }
+        if current.chars().count() > indent.len() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    lines.push((LineStyle::Text, current));
+}
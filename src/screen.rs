@@ -0,0 +1,157 @@
+//! Redraw scheduling. Each frame, every component draws itself into memory
+//! as runs of output placed on the screen, and only the runs that differ
+//! from what the terminal already shows are written out. Moving the cursor
+//! so rewrites the statusline position, not the whole screen.
+
+use std::io::{self, Write};
+
+use crossterm::cursor::MoveTo;
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::QueueableCommand;
+
+/// Parts of the screen, in the order they are drawn over each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Component {
+    /// The sign column.
+    Gutter,
+    /// The buffer text, or the picker or pager in its place.
+    Viewport,
+    Statusline,
+    /// The command line at the bottom.
+    Message,
+    /// Popups and menus, drawn over the others.
+    Popups,
+}
+
+impl Component {
+    /// Whether runs of the component cover others rather than the screen
+    /// area that is theirs.
+    fn overlays(self) -> bool {
+        self == Component::Popups
+    }
+}
+
+/// Output starting at a column. Runs of a component other than popups
+/// must cover everything up to the next run on their row, or its end.
+#[derive(Debug, PartialEq)]
+struct Run {
+    component: Component,
+    col: u16,
+    bytes: Vec<u8>,
+}
+
+/// The frame being drawn and the one shown, as runs per row.
+pub struct Screen {
+    rows: Vec<Vec<Run>>,
+    shown: Vec<Vec<Run>>,
+    component: Component,
+    /// Row of the run written to, `None` before the first `move_to`.
+    row: Option<usize>,
+    /// Set when the terminal may show something else than `shown`, so the
+    /// next frame is written in full.
+    stale: bool,
+}
+
+impl Screen {
+    pub fn new() -> Self {
+        Self {
+            rows: Vec::new(),
+            shown: Vec::new(),
+            component: Component::Viewport,
+            row: None,
+            stale: true,
+        }
+    }
+
+    /// Has the next frame written in full, as after the terminal resized.
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    /// Makes what is written next part of `component`.
+    pub fn begin(&mut self, component: Component) {
+        self.component = component;
+        self.row = None;
+    }
+
+    /// Starts a run at `col` and `row`, which what is written next goes to.
+    pub fn move_to(&mut self, col: u16, row: u16) -> &mut Self {
+        let row = row as usize;
+        if self.rows.len() <= row {
+            self.rows.resize_with(row + 1, Vec::new);
+        }
+        self.rows[row].push(Run {
+            component: self.component,
+            col,
+            bytes: Vec::new(),
+        });
+        self.row = Some(row);
+        self
+    }
+
+    /// Writes what changed since the last frame to `out`, returning whether
+    /// anything did.
+    pub fn present(&mut self, out: &mut impl Write) -> io::Result<bool> {
+        if self.stale {
+            out.queue(Clear(ClearType::All))?;
+            self.shown.clear();
+        }
+        let mut changed = self.stale;
+        for (row, runs) in self.rows.iter_mut().enumerate() {
+            runs.sort_by_key(|run| run.component);
+            let shown = self.shown.get(row).map_or(&[][..], Vec::as_slice);
+            if runs.as_slice() == shown {
+                continue;
+            }
+            changed = true;
+            let row = row as u16;
+            let same_layout = runs.len() == shown.len()
+                && runs.iter().zip(shown).all(|(run, old)| {
+                    run.component == old.component
+                        && run.col == old.col
+                        && (!run.component.overlays() || run == old)
+                });
+            if !same_layout {
+                out.queue(MoveTo(0, row))?
+                    .queue(Clear(ClearType::CurrentLine))?;
+            }
+            let mut below_changed = false;
+            for (i, run) in runs.iter().enumerate() {
+                let unchanged = same_layout && shown[i] == *run;
+                if unchanged && !(run.component.overlays() && below_changed) {
+                    continue;
+                }
+                below_changed = true;
+                out.queue(MoveTo(run.col, row))?;
+                out.write_all(&run.bytes)?;
+            }
+        }
+        // Rows no longer drawn to, as when the terminal got shorter.
+        for row in self.rows.len()..self.shown.len() {
+            if !self.shown[row].is_empty() {
+                changed = true;
+                out.queue(MoveTo(0, row as u16))?
+                    .queue(Clear(ClearType::CurrentLine))?;
+            }
+        }
+        self.shown = std::mem::take(&mut self.rows);
+        self.row = None;
+        self.stale = false;
+        Ok(changed)
+    }
+}
+
+impl Write for Screen {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let run = self
+            .row
+            .and_then(|row| self.rows[row].last_mut())
+            .ok_or_else(|| io::Error::other("nothing to write to before a move"))?;
+        run.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+//! Searching the buffer with `/` and `?`, for patterns in the syntax of the
+//! regex crate. Under `ignorecase` letters match either case, unless
+//! `smartcase` is set too and the pattern has an uppercase letter.
+//!
+//! `*` and `#` search for the word under the cursor, as a whole word, while
+//! `g*` and `g#` find it inside longer words too.
+
+use std::ops::Range;
+
+use anyhow::Context;
+use regex::{Regex, RegexBuilder};
+use ropey::Rope;
+
+/// The last search made, repeated by `n` and `N`.
+#[derive(Debug, Clone)]
+pub struct Search {
+    pub pattern: String,
+    pub backward: bool,
+}
+
+pub fn compile(pattern: &str, ignore_case: bool, smart_case: bool) -> anyhow::Result<Regex> {
+    let ignore_case = ignore_case && !(smart_case && pattern.chars().any(char::is_uppercase));
+    RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .multi_line(true)
+        .build()
+        .with_context(|| format!("Invalid pattern: {pattern}"))
+}
+
+/// The chars of the matches of `regex` in the chars `range` of `text`.
+pub fn matches(text: &Rope, regex: &Regex, range: Range<usize>) -> Vec<Range<usize>> {
+    let slice = text.slice(range.clone());
+    let string = slice.to_string();
+    regex
+        .find_iter(&string)
+        .filter(|found| !found.is_empty())
+        .map(|found| {
+            range.start + slice.byte_to_char(found.start())
+                ..range.start + slice.byte_to_char(found.end())
+        })
+        .collect()
+}
+
+/// The first match of `regex` in `text` after char `from`, or the last
+/// before it when `backward`. With `wrap` the search goes on from the other
+/// end, and whether it did is given with the match.
+pub fn find(
+    text: &Rope,
+    regex: &Regex,
+    from: usize,
+    backward: bool,
+    wrap: bool,
+) -> Option<(Range<usize>, bool)> {
+    let all = matches(text, regex, 0..text.len_chars());
+    let found = match backward {
+        true => all.iter().rev().find(|found| found.start < from),
+        false => all.iter().find(|found| found.start > from),
+    };
+    match found {
+        Some(found) => Some((found.clone(), false)),
+        None if wrap => {
+            let found = if backward { all.last() } else { all.first() };
+            found.map(|found| (found.clone(), true))
+        }
+        None => None,
+    }
+}
+
+/// The word under column `col` of `line`, or else the first after it, with
+/// the column it starts at.
+pub fn word_at(line: &str, col: usize) -> Option<(usize, String)> {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let chars: Vec<char> = line.chars().collect();
+    let mut start = col.min(chars.len());
+    while start > 0 && chars.get(start).is_some_and(|&c| is_word(c)) && is_word(chars[start - 1]) {
+        start -= 1;
+    }
+    let start = start + chars[start..].iter().position(|&c| is_word(c))?;
+    let word = chars[start..].iter().take_while(|&&c| is_word(c)).collect();
+    Some((start, word))
+}
+
+/// The pattern matching `word`, only as a whole word when `whole`.
+pub fn word_pattern(word: &str, whole: bool) -> String {
+    match whole {
+        true => format!(r"\b{}\b", regex::escape(word)),
+        false => regex::escape(word),
+    }
+}
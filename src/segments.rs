@@ -0,0 +1,176 @@
+//! Segmented display of lines too long to work with line by line, such as
+//! minified JSON or a log written on a single line. Such lines are shown
+//! over several rows, broken after the delimiters in them, while the text
+//! stays as it is. The cursor is a char of the buffer, so it moves the same
+//! however long the line.
+
+use std::ops::Range;
+
+use crate::buffer::Buffer;
+
+/// Chars a row is broken after.
+const DELIMITERS: &[char] = &[',', ';', '{', '[', '(', ' ', '\t'];
+
+pub struct Segments {
+    /// Char of the buffer under the cursor.
+    pub cursor: usize,
+    /// First row shown.
+    pub top: usize,
+    /// Where the cursor was last drawn on the screen.
+    pub screen_cursor: (u16, u16),
+    /// Column in its row the cursor keeps when moving up and down.
+    col: usize,
+    /// Rows as chars of the buffer without line breaks.
+    rows: Vec<Range<usize>>,
+    /// The revision of the buffer and the width `rows` are for.
+    split_for: Option<(usize, usize)>,
+}
+
+impl Segments {
+    pub fn new(cursor: usize) -> Self {
+        Self {
+            cursor,
+            top: 0,
+            screen_cursor: (0, 0),
+            col: 0,
+            rows: Vec::new(),
+            split_for: None,
+        }
+    }
+
+    /// Splits the lines of `buffer` into rows at most `width` wide, unless
+    /// already done for its revision.
+    pub fn refresh(&mut self, buffer: &Buffer, width: usize) {
+        if self.split_for == Some((buffer.revision, width)) {
+            return;
+        }
+        self.rows = split(buffer, width.max(1));
+        self.split_for = Some((buffer.revision, width));
+        self.cursor = self.cursor.min(self.rows.last().map_or(0, |row| row.end));
+    }
+
+    pub fn rows(&self) -> &[Range<usize>] {
+        &self.rows
+    }
+
+    /// The row the cursor is on.
+    pub fn row(&self) -> usize {
+        self.rows()
+            .partition_point(|row| row.start <= self.cursor)
+            .saturating_sub(1)
+    }
+
+    /// Moves the cursor `delta` rows down, or up if negative, keeping its
+    /// column where the row is long enough.
+    pub fn move_rows(&mut self, delta: isize) {
+        let last = self.rows().len().saturating_sub(1);
+        let row = self.row().saturating_add_signed(delta).min(last);
+        if let Some(range) = self.rows().get(row) {
+            self.cursor = range.start + self.col.min(range.len().saturating_sub(1));
+        }
+    }
+
+    /// Moves the cursor a char left, to the row before when it continues
+    /// the same line.
+    pub fn move_left(&mut self) {
+        let row = self.row();
+        let continued = row > 0 && self.continues(row);
+        if self
+            .rows
+            .get(row)
+            .is_some_and(|range| self.cursor > range.start)
+            || continued
+        {
+            self.cursor -= 1;
+        }
+        self.keep_col();
+    }
+
+    /// Moves the cursor a char right, to the row after when it continues
+    /// the same line.
+    pub fn move_right(&mut self) {
+        let row = self.row();
+        let continued = self.continues(row + 1);
+        if self
+            .rows
+            .get(row)
+            .is_some_and(|range| self.cursor + 1 < range.end)
+            || continued
+        {
+            self.cursor += 1;
+        }
+        self.keep_col();
+    }
+
+    /// Moves the cursor to the first char of its line.
+    pub fn line_start(&mut self) {
+        let mut row = self.row();
+        while row > 0 && self.continues(row) {
+            row -= 1;
+        }
+        self.cursor = self.rows().get(row).map_or(0, |range| range.start);
+        self.keep_col();
+    }
+
+    /// Moves the cursor to the last char of its line.
+    pub fn line_end(&mut self) {
+        let mut row = self.row();
+        while self.continues(row + 1) {
+            row += 1;
+        }
+        if let Some(range) = self.rows().get(row) {
+            self.cursor = range.start.max(range.end.saturating_sub(1));
+        }
+        self.keep_col();
+    }
+
+    /// Scrolls so that the cursor row is among the `height` rows shown.
+    pub fn scroll(&mut self, height: usize) {
+        let row = self.row();
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + height {
+            self.top = row + 1 - height;
+        }
+    }
+
+    /// Whether `row` carries on the line of the row before it.
+    fn continues(&self, row: usize) -> bool {
+        let rows = self.rows();
+        row > 0 && row < rows.len() && rows[row - 1].end == rows[row].start
+    }
+
+    fn keep_col(&mut self) {
+        let start = self.rows().get(self.row()).map_or(0, |range| range.start);
+        self.col = self.cursor - start;
+    }
+}
+
+/// Rows showing the lines of `buffer`: one for each line up to `width`
+/// chars, and as many as needed for longer ones, broken at the last
+/// delimiter that fits or at `width` if none does.
+fn split(buffer: &Buffer, width: usize) -> Vec<Range<usize>> {
+    let mut rows = Vec::new();
+    for (i, line) in buffer.text.lines().enumerate() {
+        let start = buffer.text.line_to_char(i);
+        let chars: Vec<char> = line
+            .chars()
+            .take_while(|c| !matches!(c, '\n' | '\r'))
+            .collect();
+        if i > 0 && i + 1 == buffer.len() && chars.is_empty() {
+            // The empty line after a final line break.
+            break;
+        }
+        let mut from = 0;
+        while chars.len() - from > width {
+            let to = (from + 1..from + width)
+                .rev()
+                .find(|&i| DELIMITERS.contains(&chars[i]))
+                .map_or(from + width, |i| i + 1);
+            rows.push(start + from..start + to);
+            from = to;
+        }
+        rows.push(start + from..start + chars.len());
+    }
+    rows
+}
@@ -0,0 +1,69 @@
+//! Sessions, written by `:mksession` and restored with `vim-rs -S FILE` or
+//! `:source FILE`. A session is an ex script that sets the options, opens
+//! the files of the windows and puts their cursors back.
+
+use std::fs;
+
+/// File `:mksession` writes when given none.
+pub const DEFAULT_PATH: &str = "Session.vim";
+
+/// A window as a session restores it.
+pub struct Window {
+    pub path: String,
+    /// Line and column from 0.
+    pub cursor: (usize, usize),
+}
+
+/// The script restoring `options`, as `:set all` lists them, and
+/// `windows` from left to right with the cursor in the one at `focus`.
+pub fn script(options: &[String], windows: &[Window], focus: usize) -> String {
+    let mut lines = vec!["\" vim-rs session".to_string()];
+    lines.extend(
+        options
+            .iter()
+            .map(|option| format!("set {}", escape(option))),
+    );
+    let cursor = |window: &Window| {
+        format!(
+            "call cursor({}, {})",
+            window.cursor.0 + 1,
+            window.cursor.1 + 1
+        )
+    };
+    match windows {
+        [] => {}
+        [window] => {
+            if !window.path.is_empty() {
+                lines.push(format!("edit {}", absolute(&window.path)));
+            }
+            lines.push(cursor(window));
+        }
+        [left, right, ..] => {
+            lines.push(format!("edit {}", absolute(&left.path)));
+            lines.push(format!("diffsplit {}", absolute(&right.path)));
+            // The left window has the focus, so the one that should is
+            // visited last.
+            if focus == 0 {
+                lines.push("wincmd w".to_string());
+                lines.push(cursor(right));
+                lines.push("wincmd w".to_string());
+                lines.push(cursor(left));
+            } else {
+                lines.push(cursor(left));
+                lines.push("wincmd w".to_string());
+                lines.push(cursor(right));
+            }
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// `path` made absolute, for the session to be restored from anywhere.
+fn absolute(path: &str) -> String {
+    fs::canonicalize(path).map_or_else(|_| path.to_string(), |path| path.display().to_string())
+}
+
+/// An option value with its spaces escaped for `:set`.
+fn escape(option: &str) -> String {
+    option.replace("\\ ", " ").replace(' ', "\\ ")
+}
@@ -0,0 +1,127 @@
+//! The sign column left of the text, showing for each line a marker from
+//! the first source, in the order given by `:set signs`, with one for it.
+
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use crossterm::style::Color;
+use ropey::Rope;
+
+use crate::git::{self, Signs};
+
+/// Where markers in the sign column come from, as named in `:set signs`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    /// The most severe diagnostic on the line.
+    Diagnostics,
+    /// How the line differs from the git index.
+    Git,
+    /// The line changed since the buffer was last written.
+    Modified,
+    /// The line is longer than `textwidth`.
+    LongLines,
+}
+
+impl Source {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "diagnostics" => Some(Source::Diagnostics),
+            "git" => Some(Source::Git),
+            "modified" => Some(Source::Modified),
+            "longlines" => Some(Source::LongLines),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Marker {
+    pub glyph: char,
+    pub color: Color,
+}
+
+impl Marker {
+    pub fn new(glyph: char, color: Color) -> Self {
+        Self { glyph, color }
+    }
+}
+
+/// Lines differing from the git index and from the last write, for a
+/// buffer revision.
+pub struct Diffs {
+    pub revision: usize,
+    /// `None` when the file is not tracked by git.
+    pub git: Option<Signs>,
+    pub modified: Signs,
+}
+
+struct Job {
+    path: String,
+    revision: usize,
+    text: Rope,
+    /// The text as last written, when modified lines are wanted.
+    saved: Option<Rope>,
+}
+
+/// Diffs buffers against the git index and their last write on a worker
+/// thread, handing the results back through `poll`.
+pub struct Differ {
+    jobs: Option<Sender<Job>>,
+    results: Receiver<Diffs>,
+    results_tx: Sender<Diffs>,
+    /// Revision of the last buffer submitted.
+    pub submitted: Option<usize>,
+}
+
+impl Differ {
+    pub fn new() -> Self {
+        let (results_tx, results) = mpsc::channel();
+        Self {
+            jobs: None,
+            results,
+            results_tx,
+            submitted: None,
+        }
+    }
+
+    pub fn submit(&mut self, path: &str, revision: usize, text: Rope, saved: Option<Rope>) {
+        self.submitted = Some(revision);
+        let jobs = self.jobs.get_or_insert_with(|| {
+            let (tx, rx) = mpsc::channel();
+            let results = self.results_tx.clone();
+            thread::spawn(move || worker(rx, results));
+            tx
+        });
+        _ = jobs.send(Job {
+            path: path.to_string(),
+            revision,
+            text,
+            saved,
+        });
+    }
+
+    pub fn poll(&self) -> Option<Diffs> {
+        self.results.try_recv().ok()
+    }
+}
+
+fn worker(jobs: Receiver<Job>, results: Sender<Diffs>) {
+    while let Ok(mut job) = jobs.recv() {
+        // Only the newest text is worth diffing.
+        while let Ok(newer) = jobs.try_recv() {
+            job = newer;
+        }
+        let text = job.text.to_string();
+        let diffs = Diffs {
+            revision: job.revision,
+            git: git::index_text(&job.path).map(|index| git::signs(&index, &text)),
+            modified: job
+                .saved
+                .map(|saved| git::signs(&saved.to_string(), &text))
+                .unwrap_or_default(),
+        };
+        if results.send(diffs).is_err() {
+            return;
+        }
+    }
+}
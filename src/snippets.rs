@@ -0,0 +1,530 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use serde_json::Value;
+
+/// Indentation a `\t` in a snippet body expands to, matching the formatting
+/// options sent to language servers.
+const INDENT: &str = "    ";
+
+/// Snippets available without any configuration, as filetype, prefix and
+/// body.
+const BUILTIN: &[(&str, &str, &str)] = &[
+    ("rust", "fn", "fn ${1:name}($2) {\n\t$0\n}"),
+    ("rust", "test", "#[test]\nfn ${1:name}() {\n\t$0\n}"),
+    ("rust", "impl", "impl ${1:Type} {\n\t$0\n}"),
+    ("rust", "derive", "#[derive(${1:Debug})]"),
+    ("python", "def", "def ${1:name}($2):\n\t${0:pass}"),
+    (
+        "python",
+        "main",
+        "if __name__ == \"__main__\":\n\t${0:main()}",
+    ),
+    ("javascript", "fn", "function ${1:name}($2) {\n\t$0\n}"),
+    ("typescript", "fn", "function ${1:name}($2) {\n\t$0\n}"),
+    ("sh", "if", "if [ $1 ]; then\n\t$0\nfi"),
+    (
+        "c",
+        "for",
+        "for (int ${1:i} = 0; $1 < ${2:n}; $1++) {\n\t$0\n}",
+    ),
+];
+
+pub struct Snippet {
+    pub prefix: String,
+    pub body: String,
+}
+
+/// The snippets for `filetype`: those in the user's
+/// `vim-rs/snippets/<filetype>.json`, written in the format VS Code uses,
+/// ahead of the built-in ones.
+pub fn load(filetype: Option<&str>) -> anyhow::Result<Vec<Snippet>> {
+    let Some(filetype) = filetype else {
+        return Ok(Vec::new());
+    };
+    let mut snippets = Vec::new();
+    if let Some(path) = user_file(filetype).filter(|path| path.is_file()) {
+        let contents = fs::read_to_string(&path)?;
+        let json: Value = serde_json::from_str(&contents)
+            .with_context(|| format!("malformed snippets in {}", path.display()))?;
+        snippets.extend(parse_file(&json));
+    }
+    snippets.extend(builtin(filetype));
+    Ok(snippets)
+}
+
+/// Where the user's snippets for `filetype` are read from.
+pub fn user_file(filetype: &str) -> Option<PathBuf> {
+    Some(
+        crate::config_dir()?
+            .join("snippets")
+            .join(format!("{filetype}.json")),
+    )
+}
+
+pub fn builtin(filetype: &str) -> Vec<Snippet> {
+    BUILTIN
+        .iter()
+        .filter(|(ft, _, _)| *ft == filetype)
+        .map(|(_, prefix, body)| Snippet {
+            prefix: prefix.to_string(),
+            body: body.to_string(),
+        })
+        .collect()
+}
+
+/// Reads snippets keyed by name, each with one or more prefixes and a body
+/// given as a string or a list of lines.
+fn parse_file(json: &Value) -> Vec<Snippet> {
+    let mut snippets = Vec::new();
+    for snippet in json.as_object().into_iter().flat_map(|map| map.values()) {
+        let body = match &snippet["body"] {
+            Value::String(body) => body.clone(),
+            Value::Array(lines) => lines
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => continue,
+        };
+        let prefixes = match &snippet["prefix"] {
+            Value::String(prefix) => vec![prefix.as_str()],
+            Value::Array(prefixes) => prefixes.iter().filter_map(Value::as_str).collect(),
+            _ => continue,
+        };
+        for prefix in prefixes {
+            snippets.push(Snippet {
+                prefix: prefix.to_string(),
+                body: body.clone(),
+            });
+        }
+    }
+    snippets
+}
+
+/// A place to fill in, which may occur several times in the snippet.
+pub struct TabStop {
+    /// Char ranges of the occurrences, in text order. The first is the one
+    /// edited, the others mirror it.
+    pub ranges: Vec<Range<usize>>,
+    /// Values offered for the placeholder, the first being its default.
+    pub choices: Vec<String>,
+}
+
+/// A snippet body turned into text, with its tab stops in jump order and
+/// the final `$0` last.
+pub struct Expansion {
+    pub text: String,
+    pub stops: Vec<TabStop>,
+}
+
+/// Expands a body in the LSP snippet syntax: `$1`, `${1:default}`,
+/// `${1|one,two|}`, `$0` for where the cursor ends, and `$NAME` or
+/// `${NAME:default}` for variables. Lines after the first get `indent`.
+pub fn expand(body: &str, indent: &str, variables: &dyn Fn(&str) -> Option<String>) -> Expansion {
+    let mut parser = Parser {
+        chars: body.chars().collect(),
+        pos: 0,
+    };
+    let nodes = parser.parse(false);
+
+    let mut defaults = HashMap::new();
+    collect_defaults(&nodes, &mut defaults);
+    let mut renderer = Renderer {
+        text: String::new(),
+        len: 0,
+        stops: BTreeMap::new(),
+        defaults: &defaults,
+        indent,
+        variables,
+    };
+    renderer.render(&nodes, true);
+
+    let Renderer {
+        text,
+        len,
+        mut stops,
+        ..
+    } = renderer;
+    let last = stops.remove(&0).unwrap_or(TabStop {
+        ranges: vec![Range {
+            start: len,
+            end: len,
+        }],
+        choices: Vec::new(),
+    });
+    let mut stops: Vec<TabStop> = stops.into_values().chain([last]).collect();
+    for stop in &mut stops {
+        stop.ranges.sort_by_key(|range| range.start);
+    }
+    Expansion { text, stops }
+}
+
+enum Node {
+    Text(String),
+    TabStop {
+        number: usize,
+        placeholder: Vec<Node>,
+        choices: Vec<String>,
+    },
+    Variable {
+        name: String,
+        default: Vec<Node>,
+    },
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    /// Parses up to the end of the body, or when `nested` up to the `}`
+    /// closing the enclosing placeholder.
+    fn parse(&mut self, nested: bool) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+        while let Some(&c) = self.chars.get(self.pos) {
+            match c {
+                '}' if nested => break,
+                '\\' if matches!(self.chars.get(self.pos + 1), Some('$' | '}' | '\\')) => {
+                    text.push(self.chars[self.pos + 1]);
+                    self.pos += 2;
+                }
+                '$' => {
+                    let start = self.pos;
+                    self.pos += 1;
+                    match self.parse_dollar() {
+                        Some(node) => {
+                            if !text.is_empty() {
+                                nodes.push(Node::Text(std::mem::take(&mut text)));
+                            }
+                            nodes.push(node);
+                        }
+                        // Not a tab stop or variable after all.
+                        None => {
+                            self.pos = start + 1;
+                            text.push('$');
+                        }
+                    }
+                }
+                _ => {
+                    text.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+        nodes
+    }
+
+    fn parse_dollar(&mut self) -> Option<Node> {
+        if let Some(number) = self.number() {
+            return Some(Node::TabStop {
+                number,
+                placeholder: Vec::new(),
+                choices: Vec::new(),
+            });
+        }
+        if let Some(name) = self.name() {
+            return Some(Node::Variable {
+                name,
+                default: Vec::new(),
+            });
+        }
+        self.eat('{')?;
+
+        if let Some(number) = self.number() {
+            let (placeholder, choices) = if self.eat(':').is_some() {
+                (self.parse(true), Vec::new())
+            } else if self.eat('|').is_some() {
+                (Vec::new(), self.choices()?)
+            } else {
+                (Vec::new(), Vec::new())
+            };
+            self.eat('}')?;
+            return Some(Node::TabStop {
+                number,
+                placeholder,
+                choices,
+            });
+        }
+
+        let name = self.name()?;
+        let default = if self.eat(':').is_some() {
+            self.parse(true)
+        } else if self.eat('/').is_some() {
+            // Transforms are not supported, the value is used unchanged.
+            let mut escaped = false;
+            while let Some(&c) = self.chars.get(self.pos).filter(|&&c| escaped || c != '}') {
+                escaped = !escaped && c == '\\';
+                self.pos += 1;
+            }
+            Vec::new()
+        } else {
+            Vec::new()
+        };
+        self.eat('}')?;
+        Some(Node::Variable { name, default })
+    }
+
+    /// Reads the comma separated values of a choice up to the closing `|`.
+    fn choices(&mut self) -> Option<Vec<String>> {
+        let mut choices = vec![String::new()];
+        loop {
+            match *self.chars.get(self.pos)? {
+                '\\' if matches!(
+                    self.chars.get(self.pos + 1),
+                    Some(',' | '|' | '\\' | '$' | '}')
+                ) =>
+                {
+                    choices.last_mut()?.push(self.chars[self.pos + 1]);
+                    self.pos += 2;
+                    continue;
+                }
+                ',' => choices.push(String::new()),
+                '|' => {
+                    self.pos += 1;
+                    return Some(choices);
+                }
+                c => choices.last_mut()?.push(c),
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let digits = self.take_while(|c, _| c.is_ascii_digit());
+        digits.parse().ok()
+    }
+
+    fn name(&mut self) -> Option<String> {
+        let name = self.take_while(|c, i| {
+            c == '_' || c.is_ascii_alphabetic() || (i > 0 && c.is_ascii_digit())
+        });
+        (!name.is_empty()).then_some(name)
+    }
+
+    fn take_while(&mut self, pred: impl Fn(char, usize) -> bool) -> String {
+        let start = self.pos;
+        while self
+            .chars
+            .get(self.pos)
+            .is_some_and(|&c| pred(c, self.pos - start))
+        {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    fn eat(&mut self, c: char) -> Option<()> {
+        (self.chars.get(self.pos) == Some(&c)).then(|| self.pos += 1)
+    }
+}
+
+/// Finds the first placeholder given for each tab stop, which its bare
+/// occurrences repeat.
+fn collect_defaults<'a>(nodes: &'a [Node], defaults: &mut HashMap<usize, &'a Node>) {
+    for node in nodes {
+        match node {
+            Node::TabStop {
+                number,
+                placeholder,
+                choices,
+            } => {
+                if !placeholder.is_empty() || !choices.is_empty() {
+                    defaults.entry(*number).or_insert(node);
+                }
+                collect_defaults(placeholder, defaults);
+            }
+            Node::Variable { default, .. } => collect_defaults(default, defaults),
+            Node::Text(_) => {}
+        }
+    }
+}
+
+struct Renderer<'a> {
+    text: String,
+    /// Length of `text` in chars.
+    len: usize,
+    stops: BTreeMap<usize, TabStop>,
+    defaults: &'a HashMap<usize, &'a Node>,
+    indent: &'a str,
+    variables: &'a dyn Fn(&str) -> Option<String>,
+}
+
+impl Renderer<'_> {
+    /// Appends the nodes' text, recording where tab stops land unless
+    /// rendering a mirrored placeholder.
+    fn render(&mut self, nodes: &[Node], record: bool) {
+        for node in nodes {
+            match node {
+                Node::Text(text) => self.push(text),
+                Node::TabStop {
+                    number,
+                    placeholder,
+                    choices,
+                } => {
+                    let start = self.len;
+                    if !placeholder.is_empty() {
+                        self.render(placeholder, record);
+                    } else if let Some(choice) = choices.first() {
+                        self.push(choice);
+                    } else if let Some(default) = self.defaults.get(number).filter(|_| record) {
+                        self.render(std::slice::from_ref(*default), false);
+                    }
+                    if record {
+                        let stop = self.stops.entry(*number).or_insert(TabStop {
+                            ranges: Vec::new(),
+                            choices: Vec::new(),
+                        });
+                        stop.ranges.push(start..self.len);
+                        if stop.choices.is_empty() {
+                            stop.choices = choices.clone();
+                        }
+                    }
+                }
+                Node::Variable { name, default } => match (self.variables)(name) {
+                    Some(value) => self.push(&value),
+                    None if default.is_empty() => self.push(name),
+                    None => self.render(default, record),
+                },
+            }
+        }
+    }
+
+    fn push(&mut self, text: &str) {
+        for c in text.chars() {
+            let expanded = match c {
+                '\t' => INDENT,
+                '\n' => {
+                    self.text.push('\n');
+                    self.len += 1;
+                    self.indent
+                }
+                _ => {
+                    self.text.push(c);
+                    self.len += 1;
+                    continue;
+                }
+            };
+            self.text.push_str(expanded);
+            self.len += expanded.chars().count();
+        }
+    }
+}
+
+/// An expanded snippet being filled in, following its tab stops as the
+/// buffer is edited.
+pub struct Session {
+    /// Tab stops with ranges into the buffer rather than the expansion.
+    stops: Vec<TabStop>,
+    current: usize,
+    /// The occurrence of the current tab stop last typed in, which the
+    /// others mirror.
+    edited: usize,
+    /// Whether the current placeholder is selected, so that typing replaces
+    /// it rather than adding to it.
+    pub selected: bool,
+}
+
+impl Session {
+    /// Starts at the first tab stop of `expansion`, inserted at char `start`.
+    pub fn new(start: usize, expansion: Expansion) -> Self {
+        let mut stops = expansion.stops;
+        for range in stops.iter_mut().flat_map(|stop| &mut stop.ranges) {
+            *range = range.start + start..range.end + start;
+        }
+        Self {
+            stops,
+            current: 0,
+            edited: 0,
+            selected: false,
+        }
+    }
+
+    pub fn current(&self) -> &TabStop {
+        &self.stops[self.current]
+    }
+
+    /// The occurrence of the current tab stop being edited.
+    pub fn range(&self) -> Range<usize> {
+        self.current().ranges[self.edited].clone()
+    }
+
+    /// Every occurrence of the current tab stop, which are edited together.
+    pub fn linked(&self) -> &[Range<usize>] {
+        &self.current().ranges
+    }
+
+    /// Whether the current tab stop is the final one, ending the session.
+    pub fn is_last(&self) -> bool {
+        self.current + 1 == self.stops.len()
+    }
+
+    pub fn next(&mut self) {
+        self.current = (self.current + 1).min(self.stops.len() - 1);
+        self.edited = 0;
+    }
+
+    pub fn prev(&mut self) {
+        self.current = self.current.saturating_sub(1);
+        self.edited = 0;
+    }
+
+    /// Follows an edit replacing `range` with `inserted` chars, returning
+    /// `false` if it falls outside every occurrence of the current tab
+    /// stop. An edit within another occurrence makes it the edited one.
+    pub fn edit(&mut self, range: Range<usize>, inserted: usize) -> bool {
+        let within = |r: &Range<usize>| r.start <= range.start && range.end <= r.end;
+        let owner = if within(&self.range()) {
+            self.edited
+        } else {
+            match self.current().ranges.iter().position(within) {
+                Some(i) => i,
+                None => return false,
+            }
+        };
+        self.edited = owner;
+        self.shift(range, inserted, owner);
+        true
+    }
+
+    /// Follows the text of the occurrence `i` of the current tab stop being
+    /// replaced with `inserted` chars to mirror the edited one.
+    pub fn mirror(&mut self, i: usize, inserted: usize) {
+        let range = self.current().ranges[i].clone();
+        self.shift(range, inserted, i);
+    }
+
+    /// Moves every range around an edit, growing or shrinking the
+    /// occurrence `owner` of the current tab stop that contains it.
+    fn shift(&mut self, edit: Range<usize>, inserted: usize, owner: usize) {
+        let removed = edit.len();
+        let after = |p: usize| p + inserted - removed;
+        for (stop_i, stop) in self.stops.iter_mut().enumerate() {
+            for (i, range) in stop.ranges.iter_mut().enumerate() {
+                if stop_i == self.current && i == owner {
+                    range.end = after(range.end);
+                    continue;
+                }
+                let start = match range.start {
+                    p if p < edit.start => p,
+                    p if p >= edit.end => after(p),
+                    _ => edit.start,
+                };
+                let end = match range.end {
+                    p if p <= edit.start => p,
+                    p if p >= edit.end => after(p),
+                    _ => edit.start,
+                };
+                *range = start..end.max(start);
+            }
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! What the editor remembers between sessions besides the command history:
+//! the registers, and the files edited with the marks set in each, most
+//! recent first, as `:oldfiles` lists them. It is kept as JSON in `state`
+//! in the state directory, read at startup and written on exit. Writing
+//! merges in what other sessions wrote meanwhile, so that sessions running
+//! at once keep each other's files and registers.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{self, Path, PathBuf};
+
+use serde_json::{json, Map, Value};
+
+/// How many files are remembered.
+const MAX_FILES: usize = 100;
+
+/// Positions `(line, col)` of marks by name, as a buffer keeps them.
+type Marks = BTreeMap<char, (usize, usize)>;
+
+#[derive(Debug, Default)]
+pub struct State {
+    pub registers: HashMap<char, String>,
+    /// Absolute paths with the marks of each, most recent first.
+    files: Vec<(String, Marks)>,
+    /// The files remembered in this session, whose marks win over those
+    /// another session wrote.
+    remembered: HashSet<String>,
+    /// Where the state is kept, or `None` to forget it on exit.
+    path: Option<PathBuf>,
+}
+
+impl State {
+    /// The state kept in the state directory, or an empty one when it
+    /// cannot be read.
+    pub fn load() -> Self {
+        let Some(path) = crate::state_dir().map(|dir| dir.join("state")) else {
+            return Self::default();
+        };
+        Self {
+            path: Some(path.clone()),
+            ..Self::read(&path)
+        }
+    }
+
+    /// The state kept at `path`, empty when it cannot be read.
+    fn read(path: &Path) -> Self {
+        let value: Value = fs::read_to_string(path)
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        let name = |key: &String| key.chars().next().filter(|_| key.chars().count() == 1);
+        let registers = value["registers"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter_map(|(key, text)| Some((name(key)?, text.as_str()?.to_string())))
+            .collect();
+        let files = value["files"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|file| {
+                let marks = file["marks"]
+                    .as_object()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|(key, pos)| {
+                        let line = pos[0].as_u64()? as usize;
+                        Some((name(key)?, (line, pos[1].as_u64()? as usize)))
+                    })
+                    .collect();
+                Some((file["path"].as_str()?.to_string(), marks))
+            })
+            .collect();
+        Self {
+            registers,
+            files,
+            ..Self::default()
+        }
+    }
+
+    /// The files edited, most recent first.
+    pub fn oldfiles(&self) -> impl Iterator<Item = &str> {
+        self.files.iter().map(|(path, _)| path.as_str())
+    }
+
+    /// The marks last set in the file at `path`.
+    pub fn marks(&self, path: &str) -> Marks {
+        let Some(path) = absolute(path) else {
+            return Marks::new();
+        };
+        self.files
+            .iter()
+            .find(|(file, _)| *file == path)
+            .map(|(_, marks)| marks.clone())
+            .unwrap_or_default()
+    }
+
+    /// Makes the file at `path` the most recent, with `marks` set in it.
+    pub fn remember(&mut self, path: &str, marks: &Marks) {
+        let Some(path) = absolute(path) else {
+            return;
+        };
+        self.files.retain(|(file, _)| *file != path);
+        self.files.insert(0, (path.clone(), marks.clone()));
+        self.files.truncate(MAX_FILES);
+        self.remembered.insert(path);
+    }
+
+    /// Writes the state over what is kept, merged with it: the files
+    /// remembered in this session come first, then the others kept, and
+    /// the registers of this session replace those of the same name.
+    pub fn save(&self) -> std::io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let kept = Self::read(path);
+        let mut registers = kept.registers;
+        registers.extend(self.registers.clone());
+        let registers: Map<String, Value> = registers
+            .iter()
+            .map(|(name, text)| (name.to_string(), json!(text)))
+            .collect();
+        let ours = self
+            .files
+            .iter()
+            .filter(|(file, _)| self.remembered.contains(file));
+        let theirs = kept
+            .files
+            .iter()
+            .filter(|(file, _)| !self.remembered.contains(file));
+        let files: Vec<Value> = ours
+            .chain(theirs)
+            .take(MAX_FILES)
+            .map(|(path, marks)| {
+                let marks: Map<String, Value> = marks
+                    .iter()
+                    .map(|(name, (line, col))| (name.to_string(), json!([line, col])))
+                    .collect();
+                json!({ "path": path, "marks": marks })
+            })
+            .collect();
+        let value = json!({ "registers": registers, "files": files });
+        // Written aside and moved into place, so that the state kept is
+        // never left cut short.
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        fs::write(&tmp, value.to_string() + "\n")?;
+        fs::rename(&tmp, path)
+    }
+}
+
+/// `path` made absolute, or `None` for a buffer with no file.
+fn absolute(path: &str) -> Option<String> {
+    if path.is_empty() {
+        return None;
+    }
+    let path = path::absolute(path).ok()?;
+    Some(path.to_string_lossy().into_owned())
+}
@@ -0,0 +1,164 @@
+//! The `statusline` option, laying the statusline out from a format string
+//! in vim's notation instead of the built-in segments. Items start with a
+//! `%`: `%f` the file, `%m` `[+]` when it has changes not written, `%y` its
+//! filetype, `%l` the cursor line and `%c` its column, both from 1, `%p`
+//! how far through the file the line is, `%L` the number of lines and `%%`
+//! a percent sign. What follows `%=` is drawn against the right edge.
+//! `%#fg:bg#` colors the text after it, each color in `rrggbb` hex or left
+//! out to keep the one before, and `%*` goes back to the default colors.
+
+use crate::theme::Rgb;
+
+#[derive(Debug, Default)]
+pub struct StatusLine {
+    items: Vec<Item>,
+    /// The value it was set from, as shown by `:set all`.
+    source: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    Text(String),
+    Field(char),
+    Color { fg: Option<Rgb>, bg: Option<Rgb> },
+    Reset,
+    Right,
+}
+
+/// What the items of the format show.
+pub struct Fields<'a> {
+    pub file: &'a str,
+    pub modified: bool,
+    pub filetype: Option<&'a str>,
+    /// The cursor line and column, from 0.
+    pub line: usize,
+    pub col: usize,
+    pub lines: usize,
+}
+
+/// Text drawn in the same colors, `None` for the default ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub text: String,
+    pub fg: Option<Rgb>,
+    pub bg: Option<Rgb>,
+}
+
+impl StatusLine {
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow::anyhow!("Invalid argument: statusline={value}");
+        let mut items = Vec::new();
+        let mut text = String::new();
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                text.push(c);
+                continue;
+            }
+            let item = match chars.next().ok_or_else(invalid)? {
+                '%' => {
+                    text.push('%');
+                    continue;
+                }
+                field @ ('f' | 'm' | 'y' | 'l' | 'c' | 'p' | 'L') => Item::Field(field),
+                '=' => Item::Right,
+                '*' => Item::Reset,
+                '#' => {
+                    let colors: String = chars.by_ref().take_while(|&c| c != '#').collect();
+                    let (fg, bg) = colors.split_once(':').unwrap_or((&colors, ""));
+                    Item::Color {
+                        fg: parse_color(fg).ok_or_else(invalid)?,
+                        bg: parse_color(bg).ok_or_else(invalid)?,
+                    }
+                }
+                _ => return Err(invalid()),
+            };
+            if !text.is_empty() {
+                items.push(Item::Text(std::mem::take(&mut text)));
+            }
+            items.push(item);
+        }
+        if !text.is_empty() {
+            items.push(Item::Text(text));
+        }
+        if items.iter().filter(|item| **item == Item::Right).count() > 1 {
+            return Err(invalid());
+        }
+        Ok(Self {
+            items,
+            source: value.to_string(),
+        })
+    }
+
+    /// The value as shown by `:set all`, with spaces escaped.
+    pub fn source(&self) -> String {
+        self.source.replace(' ', "\\ ")
+    }
+
+    /// Whether the built-in statusline is drawn instead.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// The segments drawn from the left edge, and those against the right.
+    pub fn render(&self, fields: &Fields) -> (Vec<Segment>, Vec<Segment>) {
+        let (mut left, mut right) = (Vec::<Segment>::new(), Vec::new());
+        let mut at_right = false;
+        let (mut fg, mut bg) = (None, None);
+        for item in &self.items {
+            let text = match item {
+                Item::Text(text) => text.clone(),
+                Item::Field(field) => field_text(*field, fields),
+                Item::Color {
+                    fg: new_fg,
+                    bg: new_bg,
+                } => {
+                    fg = new_fg.or(fg);
+                    bg = new_bg.or(bg);
+                    continue;
+                }
+                Item::Reset => {
+                    (fg, bg) = (None, None);
+                    continue;
+                }
+                Item::Right => {
+                    at_right = true;
+                    continue;
+                }
+            };
+            let segments = if at_right { &mut right } else { &mut left };
+            match segments.last_mut() {
+                Some(last) if last.fg == fg && last.bg == bg => last.text.push_str(&text),
+                _ => segments.push(Segment { text, fg, bg }),
+            }
+        }
+        (left, right)
+    }
+}
+
+fn field_text(field: char, fields: &Fields) -> String {
+    match field {
+        'f' => fields.file.to_string(),
+        'm' if fields.modified => "[+]".to_string(),
+        'y' => fields
+            .filetype
+            .map_or(String::new(), |filetype| format!("[{filetype}]")),
+        'l' => (fields.line + 1).to_string(),
+        'c' => (fields.col + 1).to_string(),
+        'p' => ((fields.line + 1) * 100 / fields.lines.max(1)).to_string(),
+        'L' => fields.lines.to_string(),
+        _ => String::new(),
+    }
+}
+
+/// A color in `rrggbb` hex, `Some(None)` when left out.
+fn parse_color(hex: &str) -> Option<Option<Rgb>> {
+    if hex.is_empty() {
+        return Some(None);
+    }
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some(Some(Rgb(channel(0)?, channel(2)?, channel(4)?)))
+}
@@ -0,0 +1,193 @@
+//! Keystroke-driven testing of the editor without a terminal.
+//!
+//! ```
+//! use vim_rs::testing::TestEditor;
+//!
+//! TestEditor::new("")
+//!     .feed("ihello<Esc>0x")
+//!     .assert_buffer("ello")
+//!     .assert_cursor(0, 0);
+//! ```
+
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use std::{env, fs, process};
+
+use crossterm::event::Event;
+
+use crate::buffer::Buffer;
+use crate::editor::Editor;
+pub use crate::keys::parse_keys;
+
+/// Terminal size the editor under test believes it runs in.
+const SIZE: (u16, u16) = (80, 24);
+
+/// How often and how long apart background work is waited for.
+const IDLE_TRIES: usize = 250;
+const IDLE_WAIT: Duration = Duration::from_millis(20);
+
+/// A headless editor fed with keys written in vim's notation.
+pub struct TestEditor {
+    editor: Editor,
+    quit: bool,
+}
+
+impl TestEditor {
+    /// Starts an editor on a scratch buffer holding `text`.
+    pub fn new(text: &str) -> Self {
+        Self::with_buffer(Buffer::from_text("", text))
+    }
+
+    pub fn with_buffer(buffer: Buffer) -> Self {
+        Self {
+            editor: Editor::headless(buffer, SIZE),
+            quit: false,
+        }
+    }
+
+    /// Types `keys`, such as `ihello<Esc>` or `<C-n>`, panicking if the
+    /// notation is malformed or the editor fails to handle a key.
+    #[track_caller]
+    pub fn feed(&mut self, keys: &str) -> &mut Self {
+        let events = parse_keys(keys).unwrap_or_else(|err| panic!("{err}"));
+        for event in events {
+            if self.quit {
+                panic!("keys fed after the editor quit: {keys}");
+            }
+            match self.editor.feed(Event::Key(event)) {
+                Ok(running) => self.quit = !running,
+                Err(err) => panic!("editor failed on {keys:?}: {err:#}"),
+            }
+        }
+        self
+    }
+
+    /// Lets the background work of the main loop run as if typing had
+    /// paused until `shown` is drawn, panicking if it is not within a few
+    /// seconds.
+    #[track_caller]
+    pub fn idle_until(&mut self, shown: &str) -> &mut Self {
+        let mut frames = String::new();
+        for _ in 0..IDLE_TRIES {
+            self.editor.idle();
+            match self.editor.render() {
+                Ok(frame) => frames.push_str(&String::from_utf8_lossy(&frame)),
+                Err(err) => panic!("editor failed to draw: {err:#}"),
+            }
+            if frames.contains(shown) {
+                return self;
+            }
+            thread::sleep(IDLE_WAIT);
+        }
+        panic!("{shown:?} was never drawn: {frames:?}");
+    }
+
+    /// Handles `message` as if the language server had sent it, panicking
+    /// if the editor fails to.
+    #[track_caller]
+    pub fn lsp_message(&mut self, message: serde_json::Value) -> &mut Self {
+        match self.editor.lsp_message(message) {
+            Ok(running) => self.quit = !running,
+            Err(err) => panic!("editor failed on a language server message: {err:#}"),
+        }
+        self
+    }
+
+    /// Whether typing `keys` in normal mode comes to an action, which is
+    /// not carried out.
+    #[track_caller]
+    pub fn binds(&mut self, keys: &str) -> bool {
+        let events = parse_keys(keys).unwrap_or_else(|err| panic!("{err}"));
+        match self.editor.binds(&events) {
+            Ok(bound) => bound,
+            Err(err) => panic!("editor failed on {keys:?}: {err:#}"),
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_buffer(&mut self, expected: &str) -> &mut Self {
+        assert_eq!(self.editor.text(), expected, "buffer text");
+        self
+    }
+
+    /// Asserts the cursor is at buffer `line` and char `col`, both from 0.
+    #[track_caller]
+    pub fn assert_cursor(&mut self, line: usize, col: usize) -> &mut Self {
+        assert_eq!(self.editor.cursor(), (line, col), "cursor (line, col)");
+        self
+    }
+
+    /// Asserts the mode as named in the statusline, such as `INSERT`.
+    #[track_caller]
+    pub fn assert_mode(&mut self, mode: &str) -> &mut Self {
+        assert_eq!(self.editor.mode_name(), mode, "mode");
+        self
+    }
+
+    /// Draws the screen, returning how many bytes bringing the terminal
+    /// up to date took.
+    #[track_caller]
+    pub fn redraw(&mut self) -> usize {
+        match self.editor.render() {
+            Ok(frame) => frame.len(),
+            Err(err) => panic!("editor failed to draw: {err:#}"),
+        }
+    }
+
+    pub fn has_quit(&self) -> bool {
+        self.quit
+    }
+
+    pub fn editor(&mut self) -> &mut Editor {
+        &mut self.editor
+    }
+}
+
+/// A git repository in a temporary directory, removed once dropped.
+pub struct GitRepo {
+    dir: PathBuf,
+}
+
+impl GitRepo {
+    /// Creates an empty repository whose directory is named after `name`.
+    #[track_caller]
+    pub fn new(name: &str) -> Self {
+        let dir = env::temp_dir().join(format!("vim-rs-{name}-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap_or_else(|err| panic!("{}: {err}", dir.display()));
+        let repo = Self { dir };
+        repo.git(&["init", "-q"]);
+        repo
+    }
+
+    /// Writes `text` to `file` in the worktree, returning its path.
+    #[track_caller]
+    pub fn write(&self, file: &str, text: &str) -> PathBuf {
+        let path = self.dir.join(file);
+        fs::write(&path, text).unwrap_or_else(|err| panic!("{}: {err}", path.display()));
+        path
+    }
+
+    /// Runs git in the repository as a fixed author, returning its output
+    /// and panicking if it fails.
+    #[track_caller]
+    pub fn git(&self, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.dir)
+            .args(["-c", "user.name=Ann Author"])
+            .args(["-c", "user.email=ann@example.com"])
+            .args(args)
+            .output()
+            .unwrap_or_else(|err| panic!("git {args:?}: {err}"));
+        assert!(output.status.success(), "git {args:?} failed");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+}
+
+impl Drop for GitRepo {
+    fn drop(&mut self) {
+        _ = fs::remove_dir_all(&self.dir);
+    }
+}
@@ -0,0 +1,192 @@
+//! Colors of the text area, and how highlights overlapping on a char are
+//! combined: each is drawn over those below it with its own opacity, so a
+//! selection over a diagnostic still shows both.
+
+use crate::conflicts::Region;
+use crate::diagnostics::Severity;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+impl Rgb {
+    /// `over` drawn on top with `opacity` from 0, invisible, to 1, covering.
+    pub fn blend(self, over: Rgb, opacity: f32) -> Rgb {
+        let opacity = opacity.clamp(0.0, 1.0);
+        let mix = |below: u8, above: u8| {
+            (below as f32 + (above as f32 - below as f32) * opacity).round() as u8
+        };
+        Rgb(
+            mix(self.0, over.0),
+            mix(self.1, over.1),
+            mix(self.2, over.2),
+        )
+    }
+}
+
+/// One kind of highlighting, such as the selection or an error.
+#[derive(Debug, Clone, Copy)]
+pub struct Highlight {
+    pub fg: Option<Rgb>,
+    pub bg: Option<Rgb>,
+    /// How much of what is below shows through, from 0 to 1.
+    pub opacity: f32,
+    pub underline: bool,
+}
+
+impl Highlight {
+    const fn fg(color: Rgb) -> Self {
+        Self {
+            fg: Some(color),
+            bg: None,
+            opacity: 1.0,
+            underline: false,
+        }
+    }
+
+    const fn bg(color: Rgb, opacity: f32) -> Self {
+        Self {
+            fg: None,
+            bg: Some(color),
+            opacity,
+            underline: false,
+        }
+    }
+
+    const fn underlined(mut self) -> Self {
+        self.underline = true;
+        self
+    }
+}
+
+/// What a char is drawn with once its highlights are composed. Colors no
+/// highlight set are left to the terminal.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Style {
+    pub fg: Option<Rgb>,
+    pub bg: Option<Rgb>,
+    pub underline: bool,
+}
+
+pub struct Theme {
+    /// Colors assumed below the terminal's own, to blend highlights with.
+    pub background: Rgb,
+    pub foreground: Rgb,
+    pub cursor_line: Highlight,
+    pub cursor_column: Highlight,
+    /// The columns of `colorcolumn`.
+    pub color_column: Highlight,
+    /// Whitespace shown with `list`, and whitespace at the end of lines.
+    pub whitespace: Highlight,
+    pub trailing: Highlight,
+    /// Matches of the last search under `hlsearch`.
+    pub search: Highlight,
+    /// The brackets of `matchparen`.
+    pub match_paren: Highlight,
+    /// Brackets under `rainbow`, by how deeply they are nested.
+    pub rainbow: [Highlight; 6],
+    /// The lines of `indentguides`.
+    pub indent_guide: Highlight,
+    /// The row standing for the lines of a closed fold.
+    pub folded: Highlight,
+    pub error: Highlight,
+    pub warning: Highlight,
+    pub info: Highlight,
+    pub hint: Highlight,
+    /// Occurrences of a snippet placeholder edited together.
+    pub linked: Highlight,
+    /// The snippet placeholder typing would replace.
+    pub selection: Highlight,
+    /// Sides of a merge conflict, the common ancestor and the markers
+    /// between them.
+    pub ours: Highlight,
+    pub theirs: Highlight,
+    pub conflict_base: Highlight,
+    pub conflict_marker: Highlight,
+    /// Lines only one side of diff mode has, the filler standing in for
+    /// them on the other side, and lines both have but differently.
+    pub diff_add: Highlight,
+    pub diff_filler: Highlight,
+    pub diff_change: Highlight,
+    /// The line between the windows of diff mode.
+    pub separator: Highlight,
+    /// Rows below the end of the buffer.
+    pub end_of_buffer: Highlight,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            background: Rgb(30, 30, 46),
+            foreground: Rgb(205, 214, 244),
+            cursor_line: Highlight::bg(Rgb(255, 255, 255), 0.06),
+            cursor_column: Highlight::bg(Rgb(255, 255, 255), 0.06),
+            color_column: Highlight::bg(Rgb(243, 139, 168), 0.1),
+            whitespace: Highlight::fg(Rgb(88, 91, 112)),
+            trailing: Highlight::bg(Rgb(243, 139, 168), 0.4),
+            search: Highlight::bg(Rgb(250, 179, 135), 0.35),
+            match_paren: Highlight::bg(Rgb(249, 226, 175), 0.3),
+            rainbow: [
+                Highlight::fg(Rgb(249, 226, 175)),
+                Highlight::fg(Rgb(245, 194, 231)),
+                Highlight::fg(Rgb(137, 180, 250)),
+                Highlight::fg(Rgb(250, 179, 135)),
+                Highlight::fg(Rgb(166, 227, 161)),
+                Highlight::fg(Rgb(148, 226, 213)),
+            ],
+            indent_guide: Highlight::fg(Rgb(69, 71, 90)),
+            folded: Highlight::bg(Rgb(137, 180, 250), 0.12),
+            error: Highlight::fg(Rgb(243, 139, 168)).underlined(),
+            warning: Highlight::fg(Rgb(249, 226, 175)).underlined(),
+            info: Highlight::fg(Rgb(137, 180, 250)).underlined(),
+            hint: Highlight::fg(Rgb(148, 226, 213)).underlined(),
+            linked: Highlight::bg(Rgb(184, 144, 243), 0.15).underlined(),
+            selection: Highlight::bg(Rgb(137, 180, 250), 0.3),
+            ours: Highlight::bg(Rgb(166, 227, 161), 0.12),
+            theirs: Highlight::bg(Rgb(137, 180, 250), 0.12),
+            conflict_base: Highlight::bg(Rgb(249, 226, 175), 0.08),
+            conflict_marker: Highlight::bg(Rgb(108, 112, 134), 0.4),
+            diff_add: Highlight::bg(Rgb(166, 227, 161), 0.15),
+            diff_filler: Highlight::fg(Rgb(88, 91, 112)),
+            diff_change: Highlight::bg(Rgb(137, 180, 250), 0.15),
+            separator: Highlight::fg(Rgb(88, 91, 112)),
+            end_of_buffer: Highlight::fg(Rgb(88, 91, 112)),
+        }
+    }
+}
+
+impl Theme {
+    pub fn diagnostic(&self, severity: Severity) -> &Highlight {
+        match severity {
+            Severity::Error => &self.error,
+            Severity::Warning => &self.warning,
+            Severity::Info => &self.info,
+            Severity::Hint => &self.hint,
+        }
+    }
+
+    pub fn conflict(&self, region: Region) -> &Highlight {
+        match region {
+            Region::Marker => &self.conflict_marker,
+            Region::Ours => &self.ours,
+            Region::Base => &self.conflict_base,
+            Region::Theirs => &self.theirs,
+        }
+    }
+
+    /// Draws `highlights` one over the other, lowest first.
+    pub fn compose<'a>(&self, highlights: impl IntoIterator<Item = &'a Highlight>) -> Style {
+        let mut style = Style::default();
+        for highlight in highlights {
+            if let Some(bg) = highlight.bg {
+                let below = style.bg.unwrap_or(self.background);
+                style.bg = Some(below.blend(bg, highlight.opacity));
+            }
+            if let Some(fg) = highlight.fg {
+                let below = style.fg.unwrap_or(self.foreground);
+                style.fg = Some(below.blend(fg, highlight.opacity));
+            }
+            style.underline |= highlight.underline;
+        }
+        style
+    }
+}
@@ -0,0 +1,102 @@
+//! Workspace trust. Language servers, linters and git read a project's own
+//! configuration, which can make them run whatever it says, so they are
+//! only started for projects the user trusted, in the project's directory
+//! and with little of the editor's environment.
+
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+use anyhow::bail;
+
+use crate::lsp;
+
+/// Environment variables programs get to see, all others are dropped.
+const PASSED_ENV: &[&str] = &[
+    "PATH",
+    "HOME",
+    "USER",
+    "LOGNAME",
+    "LANG",
+    "LC_ALL",
+    "LC_CTYPE",
+    "TMPDIR",
+    "XDG_CONFIG_HOME",
+    "XDG_CACHE_HOME",
+    "XDG_DATA_HOME",
+    "CARGO_HOME",
+    "RUSTUP_HOME",
+    "RUSTUP_TOOLCHAIN",
+];
+
+/// Projects trusted until the editor exits.
+static SESSION: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// The project the file at `path` belongs to, whose trust decides for it.
+pub fn root(path: &Path) -> PathBuf {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    lsp::find_root(&path)
+}
+
+/// Whether the project holding `path` or one around it was trusted.
+pub fn is_trusted(path: &Path) -> bool {
+    let root = root(path);
+    let session = SESSION.lock().unwrap();
+    session
+        .iter()
+        .chain(&persisted())
+        .any(|trusted| root.starts_with(trusted))
+}
+
+/// Trusts the project holding `path`, from now on if `persist`, otherwise
+/// until the editor exits.
+pub fn trust(path: &Path, persist: bool) -> anyhow::Result<()> {
+    let root = root(path);
+    if persist {
+        let Some(file) = trust_file() else {
+            bail!("No configuration directory");
+        };
+        if let Some(dir) = file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(file)?;
+        writeln!(file, "{}", root.display())?;
+    }
+    SESSION.lock().unwrap().push(root);
+    Ok(())
+}
+
+/// A command running `program` for the file at `path`, in its project's
+/// directory, refused unless the project is trusted.
+pub fn command(program: impl AsRef<OsStr>, path: &Path) -> anyhow::Result<Command> {
+    if !is_trusted(path) {
+        bail!("Workspace not trusted: {}", root(path).display());
+    }
+    let mut command = Command::new(program);
+    command.current_dir(root(path)).env_clear();
+    for name in PASSED_ENV {
+        if let Some(value) = std::env::var_os(name) {
+            command.env(name, value);
+        }
+    }
+    Ok(command)
+}
+
+fn trust_file() -> Option<PathBuf> {
+    Some(crate::config_dir()?.join("trusted"))
+}
+
+/// Projects trusted for good, one path per line.
+fn persisted() -> Vec<PathBuf> {
+    let Some(contents) = trust_file().and_then(|file| fs::read_to_string(file).ok()) else {
+        return Vec::new();
+    };
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
@@ -0,0 +1,456 @@
+//! Undo history of a buffer, and the undo files it is kept in between
+//! sessions so that `u` still works after a file is closed and opened
+//! again. An undo file holds the history as JSON with a checksum of the
+//! text it applies to, and is ignored once the file changed in another way.
+//!
+//! The history is a tree of the states the text went through, as in vim:
+//! changing the text after undoing starts a new branch instead of dropping
+//! the changes undone, which `g-`, `g+`, `:earlier` and `:later` still
+//! reach by going through the states in the order they were made.
+//!
+//! A step ends when the editor leaves insert mode or carries out a command,
+//! and also at each new line typed and after a pause in typing, so that a
+//! long insert is undone in parts. At most `undolevels` states are kept,
+//! and fewer when their changes would take more than `MAX_MEMORY`.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use ropey::Rope;
+use serde_json::{json, Value};
+
+/// Most states kept besides the first unless `undolevels` says otherwise,
+/// the oldest being dropped first.
+const MAX_STEPS: usize = 1000;
+
+/// Most bytes of text the changes of the states kept take.
+const MAX_MEMORY: usize = 16 << 20;
+
+/// Changes made after a pause this long start a new step.
+const PAUSE: Duration = Duration::from_secs(2);
+
+/// Largest an undo file is written, older states being left out to fit.
+const MAX_FILE_SIZE: usize = 1 << 20;
+
+/// Undo files not written for this long are removed.
+const MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+const VERSION: u64 = 2;
+
+/// The text at char `at` replaced: `removed` taken out and `inserted` put
+/// in its place.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub at: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+/// Changes undone and redone together, such as those of one insert.
+pub type Step = Vec<Change>;
+
+/// A step to make, applied going forward or reverted going back.
+pub type Move = (Step, bool);
+
+/// A state of the text, reached from its parent by applying `step`.
+#[derive(Debug, Clone)]
+struct State {
+    parent: usize,
+    step: Step,
+    /// Seconds since the epoch when the step was made.
+    time: u64,
+    /// The child redone by `<C-r>`, the one last made or gone back from.
+    redo: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct History {
+    /// States by number, in the order they were made, 0 being the oldest
+    /// the text can go back to.
+    states: BTreeMap<usize, State>,
+    /// The state the text is in.
+    current: usize,
+    /// Number of the last state made.
+    last: usize,
+    /// Changes since the last step ended.
+    pending: Step,
+    /// When the last change was recorded.
+    recorded: Option<Instant>,
+    /// Bytes of text the changes of the states take.
+    size: usize,
+    /// Most states kept besides the first.
+    pub levels: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        let first = State {
+            parent: 0,
+            step: Vec::new(),
+            time: now(),
+            redo: None,
+        };
+        Self {
+            states: BTreeMap::from([(0, first)]),
+            current: 0,
+            last: 0,
+            pending: Vec::new(),
+            recorded: None,
+            size: 0,
+            levels: MAX_STEPS,
+        }
+    }
+}
+
+impl History {
+    pub fn record(&mut self, at: usize, removed: &str, inserted: &str) {
+        if self.recorded.is_some_and(|time| time.elapsed() >= PAUSE) {
+            self.end_step();
+        }
+        self.recorded = Some(Instant::now());
+        match self.pending.last_mut() {
+            // Typing runs into one change.
+            Some(last) if removed.is_empty() && last.at + last.inserted.chars().count() == at => {
+                last.inserted.push_str(inserted);
+            }
+            _ => self.pending.push(Change {
+                at,
+                removed: removed.to_string(),
+                inserted: inserted.to_string(),
+            }),
+        }
+    }
+
+    /// Makes the changes since the last step one, undone at once.
+    pub fn end_step(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        self.last += 1;
+        let state = State {
+            parent: self.current,
+            step: std::mem::take(&mut self.pending),
+            time: now(),
+            redo: None,
+        };
+        self.size += size(&state.step);
+        self.states.insert(self.last, state);
+        self.state(self.current).redo = Some(self.last);
+        self.current = self.last;
+        self.trim(self.levels);
+    }
+
+    /// The step to undo, going back to the state before it.
+    pub fn undo(&mut self) -> Option<Step> {
+        self.end_step();
+        if self.current == 0 {
+            return None;
+        }
+        let current = self.current;
+        let State { parent, step, .. } = self.states[&current].clone();
+        self.state(parent).redo = Some(current);
+        self.current = parent;
+        Some(step)
+    }
+
+    /// The step to redo, going forward to the state after it on the branch
+    /// last taken.
+    pub fn redo(&mut self) -> Option<Step> {
+        self.end_step();
+        let child = self.states[&self.current].redo?;
+        self.current = child;
+        Some(self.states[&child].step.clone())
+    }
+
+    /// The moves to the state made `count` states before the current one,
+    /// whatever branch it is on.
+    pub fn older(&mut self, count: usize) -> Vec<Move> {
+        self.end_step();
+        let target = self
+            .states
+            .range(..self.current)
+            .rev()
+            .nth(count.saturating_sub(1))
+            .map_or(0, |(&seq, _)| seq);
+        self.travel(target)
+    }
+
+    /// The moves to the state made `count` states after the current one.
+    pub fn newer(&mut self, count: usize) -> Vec<Move> {
+        self.end_step();
+        let target = self
+            .states
+            .range(self.current + 1..)
+            .take(count)
+            .last()
+            .map_or(self.current, |(&seq, _)| seq);
+        self.travel(target)
+    }
+
+    /// The moves to the last state made at least `secs` seconds before the
+    /// current one, or to the oldest.
+    pub fn earlier(&mut self, secs: u64) -> Vec<Move> {
+        self.end_step();
+        let time = self.states[&self.current].time.saturating_sub(secs);
+        let target = self
+            .states
+            .range(..self.current)
+            .rev()
+            .find(|(_, state)| state.time <= time)
+            .map_or(0, |(&seq, _)| seq);
+        self.travel(target)
+    }
+
+    /// The moves to the last state made at most `secs` seconds after the
+    /// current one.
+    pub fn later(&mut self, secs: u64) -> Vec<Move> {
+        self.end_step();
+        let time = self.states[&self.current].time.saturating_add(secs);
+        let target = self
+            .states
+            .range(self.current + 1..)
+            .take_while(|(_, state)| state.time <= time)
+            .last()
+            .map_or(self.current, |(&seq, _)| seq);
+        self.travel(target)
+    }
+
+    /// Goes to the state `target`, back to where its branch parts from the
+    /// current one and forward from there.
+    fn travel(&mut self, target: usize) -> Vec<Move> {
+        let back = self.ancestors(self.current);
+        let forward = self.ancestors(target);
+        let fork = back
+            .iter()
+            .find(|seq| forward.contains(seq))
+            .copied()
+            .unwrap_or(0);
+        let mut moves: Vec<Move> = back
+            .iter()
+            .take_while(|&&seq| seq != fork)
+            .map(|seq| (self.states[seq].step.clone(), false))
+            .collect();
+        let ahead = forward.iter().position(|&seq| seq == fork).unwrap_or(0);
+        for &seq in forward[..ahead].iter().rev() {
+            let parent = self.states[&seq].parent;
+            self.state(parent).redo = Some(seq);
+            moves.push((self.states[&seq].step.clone(), true));
+        }
+        self.current = target;
+        moves
+    }
+
+    /// `seq` and the states before it on its branch, down to the oldest.
+    fn ancestors(&self, mut seq: usize) -> Vec<usize> {
+        let mut ancestors = vec![seq];
+        while seq != 0 {
+            seq = self.states[&seq].parent;
+            ancestors.push(seq);
+        }
+        ancestors
+    }
+
+    fn state(&mut self, seq: usize) -> &mut State {
+        self.states
+            .get_mut(&seq)
+            .expect("states are kept with their parents")
+    }
+
+    /// Drops the oldest states until at most `max` are kept besides the
+    /// first, and their changes take no more than `MAX_MEMORY`. The oldest
+    /// state becomes the first when the current one comes after it, the
+    /// branches that parted before it going with the old first; otherwise
+    /// it goes with the states that come after it.
+    fn trim(&mut self, max: usize) {
+        while self.states.len() > max + 1 || (self.states.len() > 1 && self.size > MAX_MEMORY) {
+            let oldest = *self
+                .states
+                .range(1..)
+                .next()
+                .expect("more than one state")
+                .0;
+            if self.ancestors(self.current).contains(&oldest) {
+                let others: Vec<usize> = self
+                    .states
+                    .iter()
+                    .filter(|&(&seq, state)| seq != 0 && seq != oldest && state.parent == 0)
+                    .map(|(&seq, _)| seq)
+                    .collect();
+                for seq in others {
+                    self.remove_branch(seq);
+                }
+                let state = self.states.remove(&oldest).expect("oldest state");
+                self.size -= size(&state.step);
+                for other in self.states.values_mut() {
+                    if other.parent == oldest {
+                        other.parent = 0;
+                    }
+                }
+                let first = self.state(0);
+                first.time = state.time;
+                first.redo = state.redo;
+                if self.current == oldest {
+                    self.current = 0;
+                }
+            } else {
+                self.remove_branch(oldest);
+            }
+        }
+    }
+
+    /// Removes `seq` and the states that come after it on its branches.
+    fn remove_branch(&mut self, seq: usize) {
+        let mut removed = vec![seq];
+        for (&other, state) in self.states.range(seq + 1..) {
+            if removed.contains(&state.parent) {
+                removed.push(other);
+            }
+        }
+        for seq in &removed {
+            if let Some(state) = self.states.remove(seq) {
+                self.size -= size(&state.step);
+            }
+        }
+        for state in self.states.values_mut() {
+            if state.redo.is_some_and(|redo| removed.contains(&redo)) {
+                state.redo = None;
+            }
+        }
+    }
+}
+
+/// Bytes of text the changes of `step` take.
+fn size(step: &Step) -> usize {
+    step.iter()
+        .map(|change| change.removed.len() + change.inserted.len())
+        .sum()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// The undo file in `dir` of the file at `path`, named after its absolute
+/// path with `%` for each separator as vim does.
+fn file(dir: &Path, path: &str) -> Option<PathBuf> {
+    let path = fs::canonicalize(path).ok()?;
+    Some(dir.join(path.to_string_lossy().replace(['/', '\\'], "%")))
+}
+
+/// Writes the history of the file at `path` holding `text` to its undo
+/// file in `dir`, and removes the undo files there gone stale.
+pub fn write(dir: &Path, path: &str, text: &Rope, history: &History) -> anyhow::Result<()> {
+    let file = file(dir, path).with_context(|| format!("Cannot find {path}"))?;
+    fs::create_dir_all(dir).with_context(|| format!("Cannot create {}", dir.display()))?;
+    let mut history = history.clone();
+    let contents = loop {
+        let states: Value = history
+            .states
+            .iter()
+            .map(|(seq, state)| {
+                let step: Value = state
+                    .step
+                    .iter()
+                    .map(|change| json!([change.at, change.removed, change.inserted]))
+                    .collect();
+                json!([seq, state.parent, state.time, state.redo, step])
+            })
+            .collect();
+        let contents = json!({
+            "version": VERSION,
+            "checksum": checksum(text),
+            "states": states,
+            "current": history.current,
+            "last": history.last,
+        })
+        .to_string();
+        if contents.len() <= MAX_FILE_SIZE || history.states.len() == 1 {
+            break contents;
+        }
+        history.trim((history.states.len() - 1) / 2);
+    };
+    fs::write(&file, contents).with_context(|| format!("Cannot write {}", file.display()))?;
+    prune(dir);
+    Ok(())
+}
+
+/// The history in the undo file in `dir` of the file at `path`, if it was
+/// written for `text`.
+pub fn read(dir: &Path, path: &str, text: &Rope) -> Option<History> {
+    let contents = fs::read_to_string(file(dir, path)?).ok()?;
+    let value: Value = serde_json::from_str(&contents).ok()?;
+    if value["version"] != VERSION || value["checksum"] != checksum(text) {
+        return None;
+    }
+    let mut states = BTreeMap::new();
+    for state in value["states"].as_array()? {
+        let step = state[4]
+            .as_array()?
+            .iter()
+            .map(|change| {
+                Some(Change {
+                    at: change[0].as_u64()? as usize,
+                    removed: change[1].as_str()?.to_string(),
+                    inserted: change[2].as_str()?.to_string(),
+                })
+            })
+            .collect::<Option<Step>>()?;
+        let seq = state[0].as_u64()? as usize;
+        let state = State {
+            parent: state[1].as_u64()? as usize,
+            step,
+            time: state[2].as_u64()?,
+            redo: state[3].as_u64().map(|redo| redo as usize),
+        };
+        // Parents come before their children, which lets going back
+        // through them end.
+        if seq != 0 && (state.parent >= seq || !states.contains_key(&state.parent)) {
+            return None;
+        }
+        states.insert(seq, state);
+    }
+    let current = value["current"].as_u64()? as usize;
+    let last = value["last"].as_u64()? as usize;
+    let valid = states.contains_key(&0)
+        && states.contains_key(&current)
+        && states.keys().all(|&seq| seq <= last)
+        && states
+            .values()
+            .all(|state| state.redo.is_none_or(|redo| states.contains_key(&redo)));
+    let size = states.values().map(|state| size(&state.step)).sum();
+    valid.then_some(History {
+        states,
+        current,
+        last,
+        pending: Vec::new(),
+        recorded: None,
+        size,
+        levels: MAX_STEPS,
+    })
+}
+
+/// Removes the undo files in `dir` last written longer than `MAX_AGE` ago.
+fn prune(dir: &Path) {
+    let Result::Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let now = SystemTime::now();
+    for entry in entries.flatten() {
+        let stale = entry
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| now.duration_since(modified).is_ok_and(|age| age > MAX_AGE));
+        if stale {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+fn checksum(text: &Rope) -> String {
+    format!("{:016x}", crate::checksum(text.chunks().map(str::as_bytes)))
+}
@@ -0,0 +1,558 @@
+//! The part of vimscript a `.vimrc` is mostly made of, so that one brought
+//! from vim runs without being rewritten: ex commands, `let` of plain
+//! variables, `if` blocks testing `has()` and the like, and lines carried
+//! on with a leading backslash. Functions and loops are skipped whole.
+//! The commands themselves, `:map` and `:autocmd` among them, are run by
+//! the editor.
+
+use std::fmt;
+
+use anyhow::{bail, Context};
+
+use crate::linters;
+
+/// Commands only meaningful to vim, skipped rather than failing.
+const IGNORED: &[&str] = &[
+    "syntax",
+    "syn",
+    "colorscheme",
+    "colo",
+    "highlight",
+    "hi",
+    "scriptencoding",
+    "scripte",
+    "runtime",
+    "ru",
+    "packadd",
+    "pa",
+    "augroup",
+    "aug",
+];
+
+/// A command with the length it can be shortened to.
+type Name = (&'static str, usize);
+
+/// Blocks that are skipped whole: the commands opening and ending each.
+const UNSUPPORTED_BLOCKS: &[(Name, Name)] = &[
+    (("function", 2), ("endfunction", 4)),
+    (("for", 3), ("endfor", 5)),
+    (("while", 2), ("endwhile", 4)),
+    (("try", 3), ("endtry", 4)),
+];
+
+/// What `has()` is true for.
+const FEATURES: &[&str] = &[
+    "autocmd",
+    "eval",
+    "mouse",
+    "persistent_undo",
+    "termguicolors",
+    #[cfg(unix)]
+    "unix",
+    #[cfg(target_os = "linux")]
+    "linux",
+    #[cfg(target_os = "macos")]
+    "mac",
+    #[cfg(target_os = "macos")]
+    "macunix",
+    #[cfg(windows)]
+    "win32",
+    #[cfg(windows)]
+    "win64",
+];
+
+/// The vim version `v:version` claims, so that scripts checking it take
+/// the branch for a recent vim.
+const VERSION: i64 = 900;
+
+/// A value of an expression: vimscript converts between the two as each
+/// operator needs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(i64),
+    String(String),
+}
+
+impl Value {
+    /// The number a string starts with, or 0, as vim reads it.
+    pub fn to_number(&self) -> i64 {
+        match self {
+            Value::Number(n) => *n,
+            Value::String(s) => {
+                let s = s.trim_start();
+                let digits = s
+                    .char_indices()
+                    .take_while(|&(i, c)| c.is_ascii_digit() || (i == 0 && c == '-'))
+                    .count();
+                s[..digits].parse().unwrap_or(0)
+            }
+        }
+    }
+
+    pub fn is_true(&self) -> bool {
+        self.to_number() != 0
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{n}"),
+            Value::String(s) => f.write_str(s),
+        }
+    }
+}
+
+/// The lines of `script` with their line number from 1, those carried on
+/// with a leading backslash joined to the one before and comments, blank
+/// lines and the colons before commands left out.
+pub fn logical_lines(script: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (i, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.starts_with("\"\\ ") {
+            // A comment among continued lines.
+            continue;
+        }
+        if let (Some(rest), Some((_, last))) = (line.strip_prefix('\\'), lines.last_mut()) {
+            last.push_str(rest);
+            continue;
+        }
+        let line = line.trim_start_matches(':').trim_start();
+        if line.is_empty() || line.starts_with('"') {
+            continue;
+        }
+        lines.push((i + 1, line.to_string()));
+    }
+    lines
+}
+
+/// Whether `line` is a command only meaningful to vim.
+pub fn ignored(line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    let name = words.next().unwrap_or_default();
+    IGNORED.contains(&name)
+        || (matches!(name, "filetype" | "filet") && line.contains(" on"))
+        || (matches!(name, "set" | "se") && matches!(words.next(), Some("nocompatible" | "nocp")))
+}
+
+enum Block {
+    /// An `if` block: whether the branch running now is taken, whether one
+    /// was already, and whether the block runs at all.
+    If {
+        taken: bool,
+        done: bool,
+        outer: bool,
+    },
+    /// A block skipped up to the command ending it, and its shortest
+    /// form.
+    Skipped(Name),
+}
+
+/// Keeps track of the blocks a script is in, telling which lines run.
+#[derive(Default)]
+pub struct Runner {
+    blocks: Vec<Block>,
+}
+
+impl Runner {
+    /// Whether `line` is to be run as a command, taking in the lines that
+    /// open, branch and end blocks. Conditions are evaluated with `lookup`
+    /// giving the value of names.
+    pub fn step(
+        &mut self,
+        line: &str,
+        lookup: &dyn Fn(&str) -> Option<Value>,
+    ) -> anyhow::Result<bool> {
+        let (name, args) = match line.split_once(char::is_whitespace) {
+            Some((name, args)) => (name, args.trim()),
+            None => (line, ""),
+        };
+        let name = name.trim_end_matches('!');
+        let opened = UNSUPPORTED_BLOCKS
+            .iter()
+            .find(|(start, _)| abbreviates(name, *start));
+        if let Some(&Block::Skipped(end)) = self.blocks.last() {
+            if let Some(&(_, end)) = opened {
+                self.blocks.push(Block::Skipped(end));
+            } else if abbreviates(name, end) {
+                self.blocks.pop();
+            }
+            return Ok(false);
+        }
+        let running = self.running();
+        match name {
+            "if" => {
+                let taken = running && eval(args, lookup)?.is_true();
+                self.blocks.push(Block::If {
+                    taken,
+                    done: taken,
+                    outer: running,
+                });
+            }
+            "elseif" | "elsei" => {
+                let Some(Block::If { taken, done, outer }) = self.blocks.last_mut() else {
+                    bail!(":elseif without :if");
+                };
+                *taken = *outer && !*done && eval(args, lookup)?.is_true();
+                *done |= *taken;
+            }
+            "else" | "el" => {
+                let Some(Block::If { taken, done, outer }) = self.blocks.last_mut() else {
+                    bail!(":else without :if");
+                };
+                *taken = *outer && !*done;
+                *done = true;
+            }
+            "endif" | "en" => {
+                let Some(Block::If { .. }) = self.blocks.pop() else {
+                    bail!(":endif without :if");
+                };
+            }
+            _ => {
+                let Some(&((start, _), end)) = opened else {
+                    return Ok(running);
+                };
+                self.blocks.push(Block::Skipped(end));
+                if running {
+                    bail!(":{start} blocks are not supported");
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Fails when the script ended inside a block.
+    pub fn finish(&self) -> anyhow::Result<()> {
+        match self.blocks.last() {
+            Some(Block::If { .. }) => bail!("Missing :endif"),
+            Some(Block::Skipped((end, _))) => bail!("Missing :{end}"),
+            None => Ok(()),
+        }
+    }
+
+    fn running(&self) -> bool {
+        self.blocks.iter().all(|block| match block {
+            Block::If { taken, .. } => *taken,
+            Block::Skipped(_) => false,
+        })
+    }
+}
+
+/// Events `:autocmd` can run commands on.
+pub const EVENTS: &[&str] = &[
+    "BufEnter",
+    "BufNewFile",
+    "BufRead",
+    "BufReadPost",
+    "BufWritePost",
+    "FileType",
+    "InsertEnter",
+    "InsertLeave",
+    "VimEnter",
+];
+
+/// A command run on events, for files whose name matches a pattern, or for
+/// `FileType` whose filetype does.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Autocmd {
+    pub events: Vec<&'static str>,
+    pub pattern: String,
+    pub command: String,
+}
+
+impl Autocmd {
+    /// Reads the arguments of `:autocmd`: `[group] {events} {pattern}
+    /// [++once] [++nested] {command}`, the events separated by commas. The
+    /// group is left out, as groups only matter to clearing them.
+    pub fn parse(args: &str) -> anyhow::Result<Self> {
+        let mut words = args.split_whitespace().peekable();
+        let is_events = |word: &str| word.split(',').all(|event| event_named(event).is_some());
+        if words.peek().is_some_and(|word| !is_events(word)) {
+            words.next();
+        }
+        let events = words.next().context("Argument required")?;
+        let events = events
+            .split(',')
+            .map(|event| event_named(event).with_context(|| format!("Unknown event: {event}")))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let pattern = words.next().context("Argument required")?.to_string();
+        let command: Vec<&str> = words.skip_while(|word| word.starts_with("++")).collect();
+        Ok(Self {
+            events,
+            pattern,
+            command: command.join(" "),
+        })
+    }
+
+    /// Whether the command runs on `event` for the file named `name`, or
+    /// the filetype for `FileType`.
+    pub fn matches(&self, event: &str, name: &str) -> bool {
+        if !self.events.contains(&event) {
+            return false;
+        }
+        let name = match event {
+            "FileType" => name,
+            _ if self.pattern.contains('/') => name,
+            _ => name.rsplit('/').next().unwrap_or(name),
+        };
+        self.pattern
+            .split(',')
+            .any(|pattern| glob(pattern.as_bytes(), name.as_bytes()))
+    }
+
+    /// How `:autocmd` lists it.
+    pub fn describe(&self) -> String {
+        format!(
+            "{:<24}{:<12}{}",
+            self.events.join(","),
+            self.pattern,
+            self.command
+        )
+    }
+}
+
+/// The event called `name`, in any case as vim takes it.
+fn event_named(name: &str) -> Option<&'static str> {
+    EVENTS
+        .iter()
+        .find(|event| event.eq_ignore_ascii_case(name))
+        .copied()
+}
+
+/// Whether `name` matches `pattern`, where `*` stands for any chars and
+/// `?` for one.
+fn glob(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, _) => name.is_empty(),
+        (Some(b'*'), _) => {
+            glob(&pattern[1..], name) || (!name.is_empty() && glob(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => glob(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => glob(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/// Whether `name` is the command `full`, shortened to no less than
+/// `shortest` chars.
+fn abbreviates(name: &str, (full, shortest): Name) -> bool {
+    name.len() >= shortest && full.starts_with(name)
+}
+
+/// Evaluates `expr`, with `lookup` giving the value of variables such as
+/// `g:name` or `mapleader`, options as `&name` and `v:` variables.
+pub fn eval(expr: &str, lookup: &dyn Fn(&str) -> Option<Value>) -> anyhow::Result<Value> {
+    let mut parser = Parser {
+        rest: expr.trim(),
+        lookup,
+    };
+    let value = parser.or()?;
+    if !parser.rest.trim().is_empty() {
+        bail!("Trailing characters: {}", parser.rest.trim());
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+    lookup: &'a dyn Fn(&str) -> Option<Value>,
+}
+
+impl Parser<'_> {
+    /// Takes `token` if the expression goes on with it.
+    fn eat(&mut self, token: &str) -> bool {
+        self.rest = self.rest.trim_start();
+        match self.rest.strip_prefix(token) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn or(&mut self) -> anyhow::Result<Value> {
+        let mut value = self.and()?;
+        while self.eat("||") {
+            let right = self.and()?;
+            value = Value::Number((value.is_true() || right.is_true()) as i64);
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> anyhow::Result<Value> {
+        let mut value = self.comparison()?;
+        while self.eat("&&") {
+            let right = self.comparison()?;
+            value = Value::Number((value.is_true() && right.is_true()) as i64);
+        }
+        Ok(value)
+    }
+
+    fn comparison(&mut self) -> anyhow::Result<Value> {
+        let left = self.concat()?;
+        let operators = ["==", "!=", ">=", "<=", ">", "<"];
+        let Some(operator) = operators.into_iter().find(|op| self.eat(op)) else {
+            return Ok(left);
+        };
+        let ignore_case = self.eat("?");
+        if !ignore_case {
+            self.eat("#");
+        }
+        let right = self.concat()?;
+        let ordering = match (&left, &right) {
+            (Value::String(a), Value::String(b)) if ignore_case => {
+                a.to_lowercase().cmp(&b.to_lowercase())
+            }
+            (Value::String(a), Value::String(b)) => a.cmp(b),
+            _ => left.to_number().cmp(&right.to_number()),
+        };
+        let result = match operator {
+            "==" => ordering.is_eq(),
+            "!=" => ordering.is_ne(),
+            ">=" => ordering.is_ge(),
+            "<=" => ordering.is_le(),
+            ">" => ordering.is_gt(),
+            _ => ordering.is_lt(),
+        };
+        Ok(Value::Number(result as i64))
+    }
+
+    fn concat(&mut self) -> anyhow::Result<Value> {
+        let mut value = self.sum()?;
+        while self.eat(".") {
+            self.eat(".");
+            let right = self.sum()?;
+            value = Value::String(format!("{value}{right}"));
+        }
+        Ok(value)
+    }
+
+    fn sum(&mut self) -> anyhow::Result<Value> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat("+") {
+                value = Value::Number(value.to_number() + self.unary()?.to_number());
+            } else if self.eat("-") {
+                value = Value::Number(value.to_number() - self.unary()?.to_number());
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> anyhow::Result<Value> {
+        if self.eat("!") {
+            return Ok(Value::Number(!self.unary()?.is_true() as i64));
+        }
+        if self.eat("-") {
+            return Ok(Value::Number(-self.unary()?.to_number()));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> anyhow::Result<Value> {
+        self.rest = self.rest.trim_start();
+        if self.eat("(") {
+            let value = self.or()?;
+            if !self.eat(")") {
+                bail!("Missing ')'");
+            }
+            return Ok(value);
+        }
+        if let Some(quote) = self.rest.chars().next().filter(|c| matches!(c, '\'' | '"')) {
+            return self.string(quote);
+        }
+        // Options are named after `&` and environment variables after `$`.
+        let sigil = self.rest.starts_with(['&', '$']) as usize;
+        let len = sigil
+            + self.rest[sigil..]
+                .find(|c: char| !(c.is_alphanumeric() || matches!(c, '_' | ':' | '#')))
+                .unwrap_or(self.rest.len() - sigil);
+        let name = &self.rest[..len];
+        if name.is_empty() {
+            bail!("Invalid expression: {}", self.rest);
+        }
+        self.rest = &self.rest[len..];
+        if let Result::Ok(n) = name.parse() {
+            return Ok(Value::Number(n));
+        }
+        if self.eat("(") {
+            let mut args = Vec::new();
+            if !self.eat(")") {
+                loop {
+                    args.push(self.or()?);
+                    if self.eat(")") {
+                        break;
+                    }
+                    if !self.eat(",") {
+                        bail!("Missing ')'");
+                    }
+                }
+            }
+            return self.call(name, &args);
+        }
+        self.variable(name)
+            .with_context(|| format!("Undefined variable: {name}"))
+    }
+
+    /// A string in single quotes, where `''` is a quote, or in double
+    /// quotes, where a backslash escapes the char after it.
+    fn string(&mut self, quote: char) -> anyhow::Result<Value> {
+        let mut chars = self.rest.char_indices().skip(1).peekable();
+        let mut text = String::new();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\'' if quote == '\'' && chars.peek().is_some_and(|&(_, c)| c == '\'') => {
+                    chars.next();
+                    text.push('\'');
+                }
+                '\\' if quote == '"' => match chars.next() {
+                    Some((_, 'n')) => text.push('\n'),
+                    Some((_, 't')) => text.push('\t'),
+                    Some((_, c)) => text.push(c),
+                    None => break,
+                },
+                c if c == quote => {
+                    self.rest = &self.rest[i + 1..];
+                    return Ok(Value::String(text));
+                }
+                c => text.push(c),
+            }
+        }
+        bail!("Missing quote: {quote}")
+    }
+
+    fn variable(&self, name: &str) -> Option<Value> {
+        match name {
+            "v:version" => Some(Value::Number(VERSION)),
+            "v:true" => Some(Value::Number(1)),
+            "v:false" => Some(Value::Number(0)),
+            _ => match name.strip_prefix('$') {
+                Some(var) => Some(Value::String(std::env::var(var).unwrap_or_default())),
+                None => (self.lookup)(name),
+            },
+        }
+    }
+
+    fn call(&self, name: &str, args: &[Value]) -> anyhow::Result<Value> {
+        let [arg] = args else {
+            bail!("Wrong number of arguments for function: {name}");
+        };
+        let truth = |b: bool| Value::Number(b as i64);
+        Ok(match name {
+            "has" => truth(FEATURES.contains(&arg.to_string().as_str())),
+            "exists" => {
+                let name = arg.to_string();
+                truth(!name.starts_with(['*', ':']) && self.variable(&name).is_some())
+            }
+            "executable" => truth(linters::on_path(&arg.to_string())),
+            "empty" => truth(match arg {
+                Value::Number(n) => *n == 0,
+                Value::String(s) => s.is_empty(),
+            }),
+            _ => bail!("Unknown function: {name}"),
+        })
+    }
+}
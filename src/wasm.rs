@@ -0,0 +1,352 @@
+//! WebAssembly plugins: modules in the `plugins` directory of the
+//! configuration, an alternative to Lua for plugins written in any language
+//! compiled to WebAssembly. They run sandboxed, reaching nothing but the
+//! functions imported from the `editor` module, within bounds on their
+//! memory and on the work done per call.
+//!
+//! Strings cross as a pointer and length into the module's exported
+//! `memory`. Functions handing one out copy as much as fits in the buffer
+//! given and return its full length. Lines and columns count from 0.
+//!
+//! | Import | Does |
+//! | --- | --- |
+//! | `log(ptr, len)` | logs the string |
+//! | `path(ptr, len) -> len` | the buffer's path |
+//! | `mode(ptr, len) -> len` | the mode, such as `normal` |
+//! | `line_count() -> count` | lines in the buffer |
+//! | `get_line(line, ptr, len) -> len` | a line, or -1 past the end |
+//! | `set_lines(start, end, ptr, len)` | replaces lines `start..end` with the `\n`-separated text |
+//! | `cursor_line() -> line`, `cursor_col() -> col` | the cursor |
+//! | `set_cursor(line, col)` | moves the cursor |
+//! | `exec(ptr, len)` | runs an ex command once the call returns |
+//! | `arg(ptr, len) -> len` | arguments of the command or event being run |
+//! | `command(name, name_len, export, export_len)` | defines a command calling an export |
+//! | `on(event, event_len, export, export_len)` | calls an export on an event |
+//!
+//! A module's `init` export, if any, is called once it is loaded.
+
+use std::collections::HashMap;
+use std::fs;
+
+use anyhow::{bail, Context};
+use wasmtime::{
+    Caller, Config, Engine, Instance, Linker, Memory, Module, Store, StoreLimits,
+    StoreLimitsBuilder,
+};
+
+use crate::options::Origin;
+use crate::plugins::{self, Outcome, View};
+use crate::{error, log};
+
+/// Most memory a module may grow to.
+const MAX_MEMORY: usize = 64 << 20;
+
+/// Fuel a call gets, roughly the instructions it may run before it is
+/// stopped, so that a plugin looping forever does not hang the editor.
+const FUEL: u64 = 100_000_000;
+
+/// What a module reaches of the editor while one of its functions runs.
+struct Host {
+    /// Name of the module, such as its path.
+    name: String,
+    view: View,
+    outcome: Outcome,
+    arg: String,
+    /// Exports run by commands, by command name.
+    commands: HashMap<String, String>,
+    /// Events and the exports run on them.
+    handlers: Vec<(String, String)>,
+    limits: StoreLimits,
+}
+
+struct Plugin {
+    store: Store<Host>,
+    instance: Instance,
+}
+
+pub struct WasmPlugins {
+    engine: Engine,
+    linker: Linker<Host>,
+    plugins: Vec<Plugin>,
+}
+
+impl WasmPlugins {
+    pub fn new() -> anyhow::Result<Self> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)?;
+        let mut linker = Linker::new(&engine);
+        install_api(&mut linker)?;
+        Ok(Self {
+            engine,
+            linker,
+            plugins: Vec::new(),
+        })
+    }
+
+    /// Names and contents of the modules in the plugins directory, in the
+    /// order of their names. Those that cannot be read are logged.
+    pub fn modules() -> Vec<(String, Vec<u8>)> {
+        plugins::files("wasm")
+            .into_iter()
+            .filter_map(|path| match fs::read(&path) {
+                Ok(bytes) => Some((path.display().to_string(), bytes)),
+                Err(err) => {
+                    error!("{}: {err}", path.display());
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Instantiates the module in `bytes`, binary or text, and calls its
+    /// `init` export.
+    pub fn load(&mut self, name: &str, bytes: &[u8], view: View) -> anyhow::Result<Outcome> {
+        let module = Module::new(&self.engine, bytes)
+            .with_context(|| format!("Plugin {name} failed to compile"))?;
+        let host = Host {
+            name: name.to_string(),
+            view: View::default(),
+            outcome: Outcome::default(),
+            arg: String::new(),
+            commands: HashMap::new(),
+            handlers: Vec::new(),
+            limits: StoreLimitsBuilder::new()
+                .memory_size(MAX_MEMORY)
+                .trap_on_grow_failure(true)
+                .build(),
+        };
+        let mut store = Store::new(&self.engine, host);
+        store.limiter(|host| &mut host.limits);
+        store.set_fuel(FUEL)?;
+        let instance = self
+            .linker
+            .instantiate(&mut store, &module)
+            .with_context(|| format!("Plugin {name} failed to start"))?;
+        let mut plugin = Plugin { store, instance };
+        let outcome = if plugin
+            .instance
+            .get_func(&mut plugin.store, "init")
+            .is_some()
+        {
+            plugin.call("init", "", view)?
+        } else {
+            Outcome::default()
+        };
+        self.plugins.push(plugin);
+        Ok(outcome)
+    }
+
+    pub fn has_command(&self, name: &str) -> bool {
+        self.plugins
+            .iter()
+            .any(|plugin| plugin.store.data().commands.contains_key(name))
+    }
+
+    pub fn run_command(&mut self, name: &str, args: &str, view: View) -> anyhow::Result<Outcome> {
+        let plugin = self
+            .plugins
+            .iter_mut()
+            .rfind(|plugin| plugin.store.data().commands.contains_key(name))
+            .with_context(|| format!("Not an editor command: {name}"))?;
+        let export = plugin.store.data().commands[name].clone();
+        plugin.call(&export, args, view)
+    }
+
+    /// Calls the exports subscribed to `event`, passing them `arg`. Each
+    /// sees the changes of those before.
+    pub fn emit(&mut self, event: &str, arg: &str, mut view: View) -> anyhow::Result<Outcome> {
+        let mut outcome = Outcome::default();
+        for plugin in &mut self.plugins {
+            let exports: Vec<String> = plugin
+                .store
+                .data()
+                .handlers
+                .iter()
+                .filter(|(name, _)| name == event)
+                .map(|(_, export)| export.clone())
+                .collect();
+            for export in exports {
+                let next = plugin.call(&export, arg, view)?;
+                view = std::mem::take(&mut plugin.store.data_mut().view);
+                outcome.lines = next.lines.or(outcome.lines);
+                outcome.cursor = next.cursor.or(outcome.cursor);
+                outcome.commands.extend(next.commands);
+            }
+        }
+        Ok(outcome)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+}
+
+impl Plugin {
+    fn call(&mut self, export: &str, arg: &str, view: View) -> anyhow::Result<Outcome> {
+        let host = self.store.data_mut();
+        host.view = view;
+        host.outcome = Outcome::default();
+        host.arg = arg.to_string();
+        self.store.set_fuel(FUEL)?;
+        let result = self
+            .instance
+            .get_typed_func::<(), ()>(&mut self.store, export)
+            .and_then(|function| function.call(&mut self.store, ()));
+        let outcome = std::mem::take(&mut self.store.data_mut().outcome);
+        result.with_context(|| format!("Plugin {} failed in {export}", self.store.data().name))?;
+        Ok(outcome)
+    }
+}
+
+fn memory(caller: &mut Caller<'_, Host>) -> anyhow::Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .context("Plugin exports no memory")
+}
+
+/// The string at `ptr` in the module's memory.
+fn read(caller: &mut Caller<'_, Host>, ptr: i32, len: i32) -> anyhow::Result<String> {
+    let memory = memory(caller)?;
+    let start = ptr as u32 as usize;
+    let bytes = memory
+        .data(&caller)
+        .get(start..start + len as u32 as usize)
+        .context("String out of bounds")?;
+    Ok(String::from_utf8(bytes.to_vec())?)
+}
+
+/// Copies as much of `text` as fits in the buffer at `ptr`, returning its
+/// full length.
+fn write(caller: &mut Caller<'_, Host>, text: &str, ptr: i32, len: i32) -> anyhow::Result<i32> {
+    let memory = memory(caller)?;
+    let bytes = &text.as_bytes()[..text.len().min(len as u32 as usize)];
+    memory
+        .write(caller, ptr as u32 as usize, bytes)
+        .context("Buffer out of bounds")?;
+    Ok(text.len() as i32)
+}
+
+fn install_api(linker: &mut Linker<Host>) -> anyhow::Result<()> {
+    linker.func_wrap(
+        "editor",
+        "log",
+        |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+            let message = read(&mut caller, ptr, len)?;
+            log!("{message}");
+            anyhow::Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "editor",
+        "path",
+        |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+            let path = caller.data().view.path.clone();
+            write(&mut caller, &path, ptr, len)
+        },
+    )?;
+    linker.func_wrap(
+        "editor",
+        "mode",
+        |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+            let mode = caller.data().view.mode.clone();
+            write(&mut caller, &mode, ptr, len)
+        },
+    )?;
+    linker.func_wrap("editor", "line_count", |caller: Caller<'_, Host>| {
+        caller.data().view.lines.len() as i32
+    })?;
+    linker.func_wrap(
+        "editor",
+        "get_line",
+        |mut caller: Caller<'_, Host>, line: i32, ptr: i32, len: i32| {
+            let Some(text) = caller.data().view.lines.get(line as u32 as usize).cloned() else {
+                return Ok(-1);
+            };
+            write(&mut caller, &text, ptr, len)
+        },
+    )?;
+    linker.func_wrap(
+        "editor",
+        "set_lines",
+        |mut caller: Caller<'_, Host>, start: i32, end: i32, ptr: i32, len: i32| {
+            let text = read(&mut caller, ptr, len)?;
+            let host = caller.data_mut();
+            let (start, end) = (start as u32 as usize, end as u32 as usize);
+            if start > end || end > host.view.lines.len() {
+                bail!("Lines {start}..{end} out of bounds");
+            }
+            let lines = if text.is_empty() {
+                Vec::new()
+            } else {
+                text.split('\n').map(str::to_string).collect()
+            };
+            host.view.lines.splice(start..end, lines);
+            host.outcome.lines = Some(host.view.lines.clone());
+            Ok(())
+        },
+    )?;
+    linker.func_wrap("editor", "cursor_line", |caller: Caller<'_, Host>| {
+        caller.data().view.cursor.0 as i32
+    })?;
+    linker.func_wrap("editor", "cursor_col", |caller: Caller<'_, Host>| {
+        caller.data().view.cursor.1 as i32
+    })?;
+    linker.func_wrap(
+        "editor",
+        "set_cursor",
+        |mut caller: Caller<'_, Host>, line: i32, col: i32| {
+            let host = caller.data_mut();
+            let cursor = (line.max(0) as usize, col.max(0) as usize);
+            host.view.cursor = cursor;
+            host.outcome.cursor = Some(cursor);
+        },
+    )?;
+    linker.func_wrap(
+        "editor",
+        "exec",
+        |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+            let command = read(&mut caller, ptr, len)?;
+            let origin = Origin::Plugin {
+                name: caller.data().name.clone(),
+                line: None,
+            };
+            caller.data_mut().outcome.commands.push((command, origin));
+            anyhow::Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "editor",
+        "arg",
+        |mut caller: Caller<'_, Host>, ptr: i32, len: i32| {
+            let arg = caller.data().arg.clone();
+            write(&mut caller, &arg, ptr, len)
+        },
+    )?;
+    linker.func_wrap(
+        "editor",
+        "command",
+        |mut caller: Caller<'_, Host>, name: i32, name_len: i32, export: i32, export_len: i32| {
+            let name = read(&mut caller, name, name_len)?;
+            let export = read(&mut caller, export, export_len)?;
+            plugins::check_command_name(&name)?;
+            caller.data_mut().commands.insert(name, export);
+            anyhow::Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "editor",
+        "on",
+        |mut caller: Caller<'_, Host>, event: i32, event_len: i32, export: i32, export_len: i32| {
+            let event = read(&mut caller, event, event_len)?;
+            let export = read(&mut caller, export, export_len)?;
+            if !plugins::EVENTS.contains(&event.as_str()) {
+                bail!("Unknown event: {event}");
+            }
+            caller.data_mut().handlers.push((event, export));
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
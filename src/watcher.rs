@@ -0,0 +1,75 @@
+//! Notices the file being edited changing on disk, through the file
+//! notifications of the OS. The directory holding the file is watched
+//! rather than the file itself, which programs saving by renaming a new
+//! file over it replace.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::error;
+
+pub struct FileWatcher {
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    /// The file watched and the directory watched for it.
+    watched: Option<(PathBuf, PathBuf)>,
+}
+
+impl FileWatcher {
+    pub fn new() -> anyhow::Result<Self> {
+        let (tx, events) = mpsc::channel();
+        Ok(Self {
+            watcher: notify::recommended_watcher(tx)?,
+            events,
+            watched: None,
+        })
+    }
+
+    /// Watches the file at `path` in place of the one before, if it can be
+    /// found.
+    pub fn watch(&mut self, path: &str) {
+        if let Some((_, dir)) = self.watched.take() {
+            let _ = self.watcher.unwatch(&dir);
+        }
+        let Some((file, dir)) = locate(Path::new(path)) else {
+            return;
+        };
+        match self.watcher.watch(&dir, RecursiveMode::NonRecursive) {
+            Ok(()) => self.watched = Some((file, dir)),
+            Err(err) => {
+                error!("Cannot watch {}: {err}", dir.display());
+            }
+        }
+    }
+
+    /// Whether the file watched was written, created or removed since last
+    /// asked.
+    pub fn poll(&self) -> bool {
+        let mut changed = false;
+        while let Ok(event) = self.events.try_recv() {
+            let Ok(event) = event else {
+                continue;
+            };
+            changed |= !event.kind.is_access()
+                && self
+                    .watched
+                    .as_ref()
+                    .is_some_and(|(file, _)| event.paths.contains(file));
+        }
+        changed
+    }
+}
+
+/// The absolute path of the file at `path` as notifications name it, and
+/// the directory holding it.
+fn locate(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    let name = path.file_name()?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let dir = dir.canonicalize().ok()?;
+    Some((dir.join(name), dir))
+}
@@ -0,0 +1,200 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use ropey::Rope;
+use serde_json::Value;
+
+use crate::buffer::Buffer;
+use crate::lsp;
+
+/// Applies an LSP `WorkspaceEdit` to the open buffer and to files on disk.
+///
+/// Every change is first made to in-memory copies of the files involved, so
+/// an invalid edit or unreadable file aborts before anything is touched.
+/// New file contents are then written to temporary files next to their
+/// targets and only renamed into place once all of them were written.
+/// Returns the number of files changed.
+pub fn apply(edit: &Value, buffer: &mut Buffer) -> anyhow::Result<usize> {
+    let buffer_path = fs::canonicalize(&buffer.path).ok();
+    // Where the buffer's file ends up, following renames.
+    let mut buffer_target = buffer_path.clone();
+    // `None` marks a file that ends up deleted.
+    let mut files: BTreeMap<PathBuf, Option<Rope>> = BTreeMap::new();
+
+    let load = |files: &mut BTreeMap<PathBuf, Option<Rope>>, path: &Path| {
+        if !files.contains_key(path) {
+            let text = if Some(path) == buffer_path.as_deref() {
+                buffer.text.clone()
+            } else {
+                Rope::from_str(
+                    &fs::read_to_string(path)
+                        .with_context(|| format!("cannot read {}", path.display()))?,
+                )
+            };
+            files.insert(path.to_path_buf(), Some(text));
+        }
+        anyhow::Ok(())
+    };
+
+    let mut operations = Vec::new();
+    if let Some(changes) = edit["documentChanges"].as_array() {
+        operations.extend(changes.iter().cloned());
+    } else if let Some(changes) = edit["changes"].as_object() {
+        for (uri, edits) in changes {
+            operations.push(serde_json::json!({
+                "textDocument": { "uri": uri },
+                "edits": edits,
+            }));
+        }
+    }
+
+    for operation in &operations {
+        match operation["kind"].as_str() {
+            None => {
+                let path = uri_path(&operation["textDocument"]["uri"])?;
+                load(&mut files, &path)?;
+                let Some(Some(text)) = files.get_mut(&path) else {
+                    bail!("{} was deleted before being edited", path.display());
+                };
+                apply_text_edits(text, &operation["edits"])?;
+            }
+            Some("create") => {
+                let path = uri_path(&operation["uri"])?;
+                let overwrite = operation["options"]["overwrite"].as_bool() == Some(true);
+                let exists = match files.get(&path) {
+                    Some(text) => text.is_some(),
+                    None => path.exists(),
+                };
+                if exists && !overwrite {
+                    if operation["options"]["ignoreIfExists"].as_bool() == Some(true) {
+                        continue;
+                    }
+                    bail!("{} already exists", path.display());
+                }
+                files.insert(path, Some(Rope::new()));
+            }
+            Some("rename") => {
+                let from = uri_path(&operation["oldUri"])?;
+                let to = uri_path(&operation["newUri"])?;
+                load(&mut files, &from)?;
+                if to.exists() && operation["options"]["overwrite"].as_bool() != Some(true) {
+                    bail!("{} already exists", to.display());
+                }
+                let text = files.insert(from.clone(), None).flatten();
+                if buffer_target.as_ref() == Some(&from) {
+                    buffer_target = Some(to.clone());
+                }
+                files.insert(to, text);
+            }
+            Some("delete") => {
+                let path = uri_path(&operation["uri"])?;
+                if buffer_target.as_ref() == Some(&path) {
+                    bail!("refusing to delete the file being edited");
+                }
+                files.insert(path, None);
+            }
+            Some(kind) => bail!("unsupported workspace edit operation {kind}"),
+        }
+    }
+
+    let mut written = Vec::new();
+    for (path, text) in &files {
+        let Some(text) = text else {
+            continue;
+        };
+        if Some(path) == buffer_target.as_ref() {
+            continue;
+        }
+        let tmp = tmp_path(path);
+        let result = path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::File::create(&tmp))
+            .and_then(|file| text.write_to(file));
+        if let Err(err) = result {
+            for tmp in written.iter().map(|(_, tmp)| tmp).chain([&tmp]) {
+                _ = fs::remove_file(tmp);
+            }
+            return Err(err).with_context(|| format!("cannot write {}", path.display()));
+        }
+        written.push((path.clone(), tmp));
+    }
+
+    for (path, tmp) in written {
+        fs::rename(&tmp, &path)?;
+    }
+    for (path, text) in &files {
+        if text.is_some() || !path.exists() {
+            continue;
+        }
+        // The buffer's own file moves along with it so unsaved changes
+        // are written to the new name.
+        match &buffer_target {
+            Some(target) if Some(path) == buffer_path.as_ref() => fs::rename(path, target)?,
+            _ => fs::remove_file(path)?,
+        }
+    }
+
+    let target = buffer_target
+        .as_ref()
+        .and_then(|path| Some((path, files.get(path)?.as_ref()?)));
+    if let Some((path, text)) = target {
+        if Some(path) != buffer_path.as_ref() {
+            buffer.path = path.to_string_lossy().into_owned();
+        }
+        if *text != buffer.text {
+            buffer.rewrite(&text.to_string());
+        }
+    }
+
+    Ok(files.len())
+}
+
+/// Applies text edits whose ranges all refer to the text before any of them
+/// is made, starting from the end so earlier ranges stay valid. Edits at the
+/// same position go in in the order they are given, as the protocol asks.
+pub fn apply_text_edits(text: &mut Rope, edits: &Value) -> anyhow::Result<()> {
+    let edits = edits
+        .as_array()
+        .context("malformed text edits")?
+        .iter()
+        .map(|edit| {
+            let range = lsp::char_range(text, &edit["range"]).context("malformed edit range")?;
+            let new_text = edit["newText"].as_str().context("malformed edit text")?;
+            anyhow::Ok((range, new_text))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut edits: Vec<_> = edits.into_iter().enumerate().collect();
+    // Of edits at one position, the last is made first so that each made
+    // after it goes in before it.
+    edits.sort_by_key(|(i, (range, _))| std::cmp::Reverse((range.start, *i)));
+    for (_, (range, new_text)) in edits {
+        text.remove(range.clone());
+        text.insert(range.start, new_text);
+    }
+    Ok(())
+}
+
+/// The path `uri` names, canonical as the buffer's is so that a symlink to
+/// the buffer's file is found to be it. A file yet to be created has its
+/// directory made canonical.
+fn uri_path(uri: &Value) -> anyhow::Result<PathBuf> {
+    let uri = uri.as_str().context("missing document uri")?;
+    let path = lsp::uri_to_path(uri).with_context(|| format!("unsupported uri {uri}"))?;
+    if let Ok(path) = fs::canonicalize(&path) {
+        return Ok(path);
+    }
+    let canonical = path
+        .parent()
+        .zip(path.file_name())
+        .and_then(|(parent, name)| Some(fs::canonicalize(parent).ok()?.join(name)));
+    Ok(canonical.unwrap_or(path))
+}
+
+pub fn tmp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.vim-rs-tmp"))
+}
@@ -0,0 +1,2395 @@
+use crossterm::event::Event;
+use vim_rs::actionlog;
+use vim_rs::buffer::Buffer;
+use vim_rs::editor::Editor;
+use vim_rs::palette;
+use vim_rs::testing::{parse_keys, GitRepo, TestEditor};
+
+#[test]
+fn insert_and_delete() {
+    TestEditor::new("")
+        .feed("ihello<Esc>0x")
+        .assert_buffer("ello")
+        .assert_cursor(0, 0)
+        .assert_mode("NORMAL");
+}
+
+#[test]
+fn insert_mode_backspace() {
+    TestEditor::new("")
+        .feed("iabc<BS><BS>d")
+        .assert_buffer("ad")
+        .assert_cursor(0, 2)
+        .assert_mode("INSERT");
+}
+
+#[test]
+fn insert_newline() {
+    TestEditor::new("")
+        .feed("ione<CR>two<Esc>")
+        .assert_buffer("one\ntwo")
+        .assert_cursor(1, 3);
+}
+
+#[test]
+fn move_down_and_up_keeps_column() {
+    TestEditor::new("first line\nab\nthird line\n")
+        .feed(";;;;;")
+        .assert_cursor(0, 5)
+        .feed("l")
+        .assert_cursor(1, 2)
+        .feed("l")
+        .assert_cursor(2, 5)
+        .feed("kk")
+        .assert_cursor(0, 5);
+}
+
+#[test]
+fn move_left_stops_at_line_start() {
+    TestEditor::new("abc\n")
+        .feed(";;j")
+        .assert_cursor(0, 1)
+        .feed("jj")
+        .assert_cursor(0, 0);
+}
+
+#[test]
+fn start_of_line() {
+    TestEditor::new("abc def\n")
+        .feed(";;;;0")
+        .assert_cursor(0, 0);
+}
+
+#[test]
+fn delete_char_under_cursor() {
+    TestEditor::new("abcdef\n")
+        .feed(";;xx")
+        .assert_buffer("abef\n")
+        .assert_cursor(0, 2);
+}
+
+#[test]
+fn change_list_jumps() {
+    TestEditor::new("one\ntwo\nthree\n")
+        .feed("iX<Esc>llixy<Esc>kk")
+        .assert_cursor(0, 3)
+        .feed("g;")
+        .assert_cursor(2, 2)
+        .feed("g;")
+        .assert_cursor(0, 0)
+        .feed("g,")
+        .assert_cursor(2, 2);
+}
+
+#[test]
+fn command_line_quits() {
+    let mut editor = TestEditor::new("text\n");
+    editor.feed(":q<CR>");
+    assert!(editor.has_quit());
+}
+
+#[test]
+fn command_line_escape_returns_to_normal() {
+    TestEditor::new("text\n")
+        .feed(":wri<Esc>")
+        .assert_mode("NORMAL")
+        .assert_buffer("text\n");
+}
+
+#[test]
+fn buffer_word_completion() {
+    TestEditor::new("apple apricot\n\n")
+        .feed("li")
+        .feed("ap<C-n><C-n><C-y>")
+        .assert_buffer("apple apricot\napricot\n");
+}
+
+#[test]
+fn key_notation() {
+    let keys = parse_keys("a<lt><C-W><S-Tab><Esc>").unwrap();
+    assert_eq!(keys.len(), 5);
+    assert!(parse_keys("<Bogus>").is_err());
+    assert!(parse_keys("<X-a>").is_err());
+}
+
+#[test]
+fn write_refuses_to_clobber_external_changes() {
+    let path = std::env::temp_dir().join(format!("vim-rs-clobber-{}.txt", std::process::id()));
+    std::fs::write(&path, "one\ntwo\n").unwrap();
+    let buffer = Buffer::open(path.to_str().unwrap()).unwrap();
+    let mut editor = TestEditor::with_buffer(buffer);
+
+    editor.feed("iX<Esc>");
+    std::fs::write(&path, "one\nTWO\nthree\n").unwrap();
+    editor.feed(":w<CR>");
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\nTWO\nthree\n");
+
+    // Dismiss the chooser, then force the write.
+    editor.feed("q:w!<CR>");
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "Xone\ntwo\n");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn git_hunks_navigate_stage_and_reset() {
+    let repo = GitRepo::new("hunks");
+    let path = repo.write("f.txt", "one\ntwo\nthree\nfour\nfive\n");
+    repo.git(&["add", "f.txt"]);
+
+    let mut editor = TestEditor::with_buffer(Buffer::open(path.to_str().unwrap()).unwrap());
+    editor
+        .feed("iX<Esc>lll0iY<Esc>kkk")
+        .feed("]c")
+        .assert_cursor(0, 1)
+        .feed(":trust<CR>]c")
+        .assert_cursor(3, 0)
+        .feed(":stagehunk<CR>");
+    assert_eq!(
+        repo.git(&["show", ":f.txt"]),
+        "one\ntwo\nthree\nYfour\nfive\n"
+    );
+
+    editor
+        .feed("[c")
+        .assert_cursor(0, 0)
+        .feed(":resethunk<CR>")
+        .assert_buffer("one\ntwo\nthree\nYfour\nfive\n");
+}
+
+#[test]
+fn git_signs_mark_the_lines_changed_in_a_sign_column() {
+    let repo = GitRepo::new("signs");
+    let path = repo.write("f.txt", "one\ntwo\nthree\n");
+    repo.git(&["add", "f.txt"]);
+
+    let mut editor = TestEditor::with_buffer(Buffer::open(path.to_str().unwrap()).unwrap());
+    let frame = String::from_utf8(editor.feed(":trust<CR>").editor().render().unwrap()).unwrap();
+    // No column while nothing has been diffed yet.
+    assert!(frame.contains("\x1b[1;1Hone "), "{frame:?}");
+    let frame = editor.feed("iX<Esc>]c").editor().render().unwrap();
+    let frame = String::from_utf8(frame).unwrap();
+    assert!(
+        frame.contains("\x1b[1;1H\x1b[38;5;11m~ \x1b[39m\x1b[1;3HXone "),
+        "{frame:?}"
+    );
+    assert!(frame.contains("\x1b[2;1H  \x1b[2;3Htwo "), "{frame:?}");
+}
+
+#[test]
+fn blame_shows_who_last_changed_the_cursor_line() {
+    let repo = GitRepo::new("blame");
+    let path = repo.write("f.txt", "one\ntwo\n");
+    repo.git(&["add", "f.txt"]);
+    repo.git(&["commit", "-q", "-m", "Add the file"]);
+
+    TestEditor::with_buffer(Buffer::open(path.to_str().unwrap()).unwrap())
+        .feed(":trust<CR>:set blame<CR>")
+        .idle_until("    Ann Author, just now \u{2022} Add the file")
+        .feed("liX<Esc>")
+        .idle_until("    Uncommitted changes");
+}
+
+#[test]
+fn statusline_shows_the_git_branch_and_whether_the_worktree_changed() {
+    let repo = GitRepo::new("branch");
+    let path = repo.write("f.txt", "one\n");
+    repo.git(&["add", "f.txt"]);
+    repo.git(&["commit", "-q", "-m", "Add the file"]);
+    repo.git(&["checkout", "-q", "-b", "topic"]);
+
+    TestEditor::with_buffer(Buffer::open(path.to_str().unwrap()).unwrap())
+        .feed(":trust<CR>")
+        // The branch right after the escape setting its color.
+        .idle_until("mtopic ")
+        .feed("iX<Esc>:w<CR>")
+        .idle_until("mtopic * ");
+}
+
+#[test]
+fn snippet_tab_stops() {
+    TestEditor::with_buffer(Buffer::from_text("main.rs", ""))
+        .feed("ifn<Tab>")
+        .assert_buffer("fn name() {\n    \n}")
+        .assert_cursor(0, 3)
+        .feed("main<Tab>argc: usize<S-Tab>")
+        .assert_cursor(0, 3)
+        .feed("<Tab><Tab>body")
+        .assert_buffer("fn main(argc: usize) {\n    body\n}")
+        .assert_cursor(1, 8);
+}
+
+#[test]
+fn snippet_mirrors_edits() {
+    TestEditor::with_buffer(Buffer::from_text("loop.c", "  "))
+        .feed("$ifor<Tab>j<Tab>len")
+        .assert_buffer("  for (int j = 0; j < len; j++) {\n      \n  }")
+        .feed("<BS><BS><BS>count<Tab>x")
+        .assert_buffer("  for (int j = 0; j < count; j++) {\n      x\n  }");
+}
+
+#[test]
+fn snippet_edits_any_linked_placeholder() {
+    TestEditor::with_buffer(Buffer::from_text("loop.c", "  "))
+        .feed("$ifor<Tab><Right><Right><Right><Right><Right><Right><Right>k")
+        .assert_buffer("  for (int ki = 0; ki < n; ki++) {\n      \n  }")
+        .assert_cursor(0, 20)
+        .feed("<BS>")
+        .assert_buffer("  for (int i = 0; i < n; i++) {\n      \n  }")
+        .assert_cursor(0, 18)
+        .feed("j<Tab>m")
+        .assert_buffer("  for (int ji = 0; ji < m; ji++) {\n      \n  }");
+}
+
+#[test]
+fn auto_pairs_brackets() {
+    TestEditor::new("")
+        .feed("if(")
+        .assert_buffer("f()")
+        .assert_cursor(0, 2)
+        .feed("x)")
+        .assert_buffer("f(x)")
+        .assert_cursor(0, 4)
+        .feed(" [<BS>")
+        .assert_buffer("f(x) ");
+}
+
+#[test]
+fn auto_pairs_quotes_and_option() {
+    TestEditor::new("")
+        .feed("i\"a\" don't")
+        .assert_buffer("\"a\" don't")
+        .feed("<Esc>:set noautopairs<CR>0i(")
+        .assert_buffer("(\"a\" don't");
+}
+
+#[test]
+fn file_picker_opens_the_match() {
+    let mut editor = TestEditor::new("");
+    editor.feed(":files<CR>cargotoml<CR>");
+    assert!(editor.editor().text().starts_with("[package]"));
+}
+
+#[test]
+fn comment_toggle() {
+    TestEditor::with_buffer(Buffer::from_text(
+        "lib.rs",
+        "fn a() {\n    x();\n\n    y();\n}\n",
+    ))
+    .feed("lgcl")
+    .assert_buffer("fn a() {\n    // x();\n\n    y();\n}\n")
+    .assert_cursor(1, 0)
+    .feed("llgck")
+    .assert_buffer("fn a() {\n    // x();\n\n    // y();\n}\n")
+    .assert_cursor(2, 0)
+    .feed("gcl")
+    .assert_buffer("fn a() {\n    // x();\n\n    y();\n}\n")
+    // Partly commented lines get commented.
+    .feed("kkgcl")
+    .assert_buffer("// fn a() {\n//     // x();\n\n    y();\n}\n");
+}
+
+#[test]
+fn lock_swallows_the_key_that_unlocks() {
+    TestEditor::new("abc")
+        .feed(":lock<CR>x")
+        .assert_buffer("abc")
+        .feed("x")
+        .assert_buffer("bc");
+}
+
+#[test]
+fn rewrite_replaces_only_changed_lines() {
+    let mut buffer = Buffer::from_text("", "a\nb\nc\nd\n");
+    buffer.changes.push((3, 1));
+    buffer.rewrite("x\na\nB\nc\nd\n");
+    assert_eq!(buffer.text.to_string(), "x\na\nB\nc\nd\n");
+    // The earlier change moved down with its line.
+    assert_eq!(buffer.changes, vec![(4, 1), (0, 0), (2, 0)]);
+}
+
+#[test]
+fn cursor_movement_redraws_only_what_changed() {
+    let mut editor = TestEditor::new("first line\nsecond line\nthird line\n");
+    let full = editor.redraw();
+    assert_eq!(editor.redraw(), editor.redraw(), "unchanged frames");
+    let moved = editor.feed(";").redraw();
+    assert!(moved < 100, "moving right wrote {moved} bytes");
+    let moved = editor.feed("l").redraw();
+    assert!(moved < 100, "moving down wrote {moved} bytes");
+    // The first change also marks the title modified.
+    editor.feed("ix").redraw();
+    let typed = editor.feed("y").redraw();
+    assert!(typed < 400, "typing wrote {typed} bytes");
+    assert!(full > 10 * typed, "the first frame wrote {full} bytes");
+}
+
+#[test]
+fn merge_conflicts_navigate_and_resolve() {
+    let text = "a\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> topic\nb\n\
+                <<<<<<< HEAD\none\n||||||| base\nzero\n=======\ntwo\n>>>>>>> other\n";
+    TestEditor::new(text)
+        .feed("]x")
+        .assert_cursor(1, 0)
+        .feed("]x")
+        .assert_cursor(7, 0)
+        .feed("[x")
+        .assert_cursor(1, 0)
+        .feed("ll:theirs<CR>")
+        .assert_buffer(
+            "a\ntheirs\nb\n<<<<<<< HEAD\none\n||||||| base\nzero\n=======\ntwo\n>>>>>>> other\n",
+        )
+        .assert_cursor(1, 0)
+        .feed("]x:both<CR>")
+        .assert_buffer("a\ntheirs\nb\none\ntwo\n")
+        .assert_cursor(3, 0)
+        .feed(":ours<CR>")
+        .assert_buffer("a\ntheirs\nb\none\ntwo\n");
+}
+
+#[test]
+fn redir_captures_command_output_into_a_register() {
+    TestEditor::new("one\n")
+        .feed(":redir @a<CR>:bogus<CR>:set all<CR><Esc>:redir END<CR>")
+        .feed(":redir @A<CR>:nope<CR>:redir END<CR>")
+        .assert_buffer("one\n")
+        .feed(":put a<CR>")
+        .assert_buffer(
+            "one\nNot an editor command: bogus\nautopairs\nnoautosave\nautosavedelay=1000\n\
+             nobackup\nbackupdir=\nbackupext=~\nnobackupstamp\nnoblame\ncolorcolumn=\ncommentstring=\nnocursorcolumn\nnocursorline\n\
+             fillchars=vert:\u{2502},fold:-,eob:~,diff:-\nnofixendofline\nfoldmethod=manual\nfoldtext=\nformatonsave=\nnohighlighttrailing\nhistory=200\nnohlsearch\nidlelock=0\nnoignorecase\nnoindentguides\n\
+             langmap=\nnolist\nlistchars=tab:>,trail:-,nbsp:+\nmatchparen\nnotify=flash\nnorainbow\nscrolloff=0\nsegmentlength=10000\nsidescrolloff=0\nsigns=diagnostics,git\nnosmartcase\nstatusline=\ntextwidth=0\ntoasts\nnotrimtrailing\nundodir=\n\
+             noundofile\nundolevels=1000\nwrapscan\nwritebackup\nNot an editor command: nope\n",
+        )
+        .assert_cursor(1, 0);
+}
+
+#[test]
+fn redir_to_a_file_refuses_to_overwrite() {
+    let dir = std::env::temp_dir().join(format!("vim-rs-redir-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("out.txt");
+    std::fs::write(&file, "kept\n").unwrap();
+    let path = file.display();
+    TestEditor::new("")
+        .feed(&format!(":redir > {path}<CR>:bogus<CR>:redir END<CR>"))
+        .feed(&format!(":redir >> {path}<CR>:bogus<CR>:redir END<CR>"));
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "kept\nNot an editor command: bogus\n"
+    );
+    TestEditor::new("").feed(&format!(":redir! > {path}<CR>:nope<CR>:redir END<CR>"));
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "Not an editor command: nope\n"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn diff_mode_navigates_and_copies_differences() {
+    let mut editor = TestEditor::new("one\ntwo\nthree\nfour\n");
+    editor
+        .editor()
+        .diff_with(Buffer::from_text("", "one\nTWO\nthree\nextra\nfour\n"));
+    editor
+        .feed("]c")
+        .assert_cursor(1, 0)
+        .feed("do")
+        .assert_buffer("one\nTWO\nthree\nfour\n")
+        .feed("]c")
+        .assert_cursor(2, 0)
+        .feed("dp")
+        .feed("[c")
+        .assert_cursor(2, 0)
+        .feed("<C-w>w")
+        .assert_buffer("one\nTWO\nthree\nfour\n")
+        .assert_cursor(0, 0)
+        .feed("l<C-w>w")
+        .assert_cursor(2, 0);
+}
+
+#[test]
+fn langmap_translates_normal_mode_keys_only() {
+    TestEditor::new("abc\ndef\n")
+        .feed(":set langmap=ж\\;,шi,дl,ЖЁ;Kx<CR>")
+        .feed("жж")
+        .assert_cursor(0, 2)
+        .feed("д")
+        .assert_cursor(1, 2)
+        .feed("шжд<Esc>")
+        .assert_buffer("abc\ndeждf\n")
+        .assert_mode("NORMAL")
+        .feed("0Ё")
+        .assert_buffer("abc\neждf\n");
+}
+
+#[test]
+fn plugins_map_keys_define_commands_and_run_on_events() {
+    let mut editor = TestEditor::new("one\ntwo\n");
+    editor
+        .editor()
+        .load_plugin(
+            "test",
+            r#"
+            editor.keymap("<C-d>", function()
+              local line, col = editor.get_cursor()
+              local lines = editor.get_lines()
+              table.insert(lines, line + 1, lines[line])
+              editor.set_lines(lines)
+              editor.set_cursor(line + 1, col)
+            end)
+            editor.command("Upper", function(args)
+              local lines = editor.get_lines()
+              for i, line in ipairs(lines) do lines[i] = line:upper() .. args end
+              editor.set_lines(lines)
+            end)
+            modes = {}
+            editor.on("mode", function(mode) table.insert(modes, mode) end)
+            editor.command("Modes", function()
+              editor.set_lines({ table.concat(modes, " ") })
+            end)
+            "#,
+        )
+        .unwrap();
+    editor
+        .feed(";<C-d>")
+        .assert_buffer("one\none\ntwo\n")
+        .assert_cursor(1, 1)
+        .feed(":Upper !<CR>")
+        .assert_buffer("ONE!\nONE!\nTWO!\n")
+        .feed(":Nope<CR>")
+        .feed("ix<Esc>:Modes<CR>")
+        .assert_buffer("command normal command normal insert normal command normal\n");
+    let err = editor
+        .editor()
+        .load_plugin("bad", "editor.keymap('ab', function() end)")
+        .unwrap_err();
+    assert!(format!("{err:#}").contains("Only single keys can be mapped: ab"));
+}
+
+#[test]
+fn verbose_tells_where_options_and_mappings_were_set() {
+    let dir = std::env::temp_dir().join(format!("vim-rs-verbose-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("settings.vim");
+    std::fs::write(&script, "\" settings\nset cul\n").unwrap();
+    let mut editor = TestEditor::new("");
+    editor
+        .editor()
+        .load_plugin(
+            "wrap",
+            "editor.exec('set tw=80')\neditor.keymap('<C-d>', function() end)",
+        )
+        .unwrap();
+    editor
+        .feed(&format!(":source {}<CR>:set il=5<CR>", script.display()))
+        .feed(":redir @a<CR>:verbose set tw? cul?<CR><Esc>:set il? ap?<CR><Esc>")
+        .feed(":verbose map <lt>C-d><CR><Esc>:map x<CR>:redir END<CR>:put a<CR>")
+        .assert_buffer(&format!(
+            "\ntextwidth=80\n\tLast set from plugin wrap line 1\ncursorline\n\
+             \tLast set from {} line 2\nidlelock=5\nautopairs\n\
+             n  <C-d>       <Lua function>\n\tLast set from plugin wrap line 2\n\
+             No mapping found",
+            script.display()
+        ));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn closed_diff_window_can_be_reopened() {
+    let mut editor = TestEditor::new("one\ntwo\n");
+    editor
+        .editor()
+        .diff_with(Buffer::from_text("", "one\nTWO\nthree\n"));
+    editor
+        .feed("l;:q<CR>")
+        .assert_buffer("one\nTWO\nthree\n")
+        .assert_cursor(0, 0)
+        .feed("ll<C-w>c:reopen<CR>")
+        .assert_buffer("one\ntwo\n")
+        .assert_cursor(1, 1)
+        .feed("<C-w>w")
+        .assert_cursor(2, 0)
+        .feed("<C-w>q")
+        .assert_buffer("one\ntwo\n")
+        .feed(":reopen<CR>:reopen<CR>")
+        .assert_buffer("one\nTWO\nthree\n")
+        .feed(":q<CR>:q<CR>");
+}
+
+#[test]
+fn wasm_plugins_run_sandboxed() {
+    let mut editor = TestEditor::new("one\ntwo\n");
+    editor
+        .editor()
+        .load_wasm_plugin(
+            "test",
+            br#"(module
+              (import "editor" "get_line" (func $get_line (param i32 i32 i32) (result i32)))
+              (import "editor" "set_lines" (func $set_lines (param i32 i32 i32 i32)))
+              (import "editor" "cursor_line" (func $cursor_line (result i32)))
+              (import "editor" "set_cursor" (func $set_cursor (param i32 i32)))
+              (import "editor" "arg" (func $arg (param i32 i32) (result i32)))
+              (import "editor" "command" (func $command (param i32 i32 i32 i32)))
+              (import "editor" "on" (func $on (param i32 i32 i32 i32)))
+              (memory (export "memory") 1)
+              (data (i32.const 0) "Dupdupmodeon_modeSpinspin")
+              (func (export "init")
+                (call $command (i32.const 0) (i32.const 3) (i32.const 3) (i32.const 3))
+                (call $on (i32.const 6) (i32.const 4) (i32.const 10) (i32.const 7))
+                (call $command (i32.const 17) (i32.const 4) (i32.const 21) (i32.const 4)))
+              (func (export "dup")
+                (local $line i32)
+                (local.set $line (call $cursor_line))
+                (call $set_lines
+                  (i32.add (local.get $line) (i32.const 1))
+                  (i32.add (local.get $line) (i32.const 1))
+                  (i32.const 1024)
+                  (call $get_line (local.get $line) (i32.const 1024) (i32.const 256)))
+                (call $set_cursor (i32.add (local.get $line) (i32.const 1)) (i32.const 0)))
+              (func (export "on_mode")
+                (call $set_lines (i32.const 0) (i32.const 0)
+                  (i32.const 2048) (call $arg (i32.const 2048) (i32.const 256))))
+              (func (export "spin") (loop $forever (br $forever))))"#,
+        )
+        .unwrap();
+    editor
+        .feed("l:Dup<CR>")
+        .assert_buffer("normal\ncommand\none\ntwo\ntwo\n")
+        .assert_cursor(4, 0)
+        .feed(":Spin<CR>")
+        .assert_buffer("normal\ncommand\nnormal\ncommand\none\ntwo\ntwo\n");
+    let err = editor
+        .editor()
+        .load_wasm_plugin("bad", b"(module (import \"env\" \"open\" (func)))")
+        .unwrap_err();
+    assert!(format!("{err:#}").contains("Plugin bad failed to start"));
+}
+
+#[test]
+fn action_log_replays_the_same_edits() {
+    let path = std::env::temp_dir().join(format!("vim-rs-actionlog-{}", std::process::id()));
+    let mut editor = TestEditor::new("one\ntwo\n");
+    editor
+        .feed("lihi <lt>!<Esc>x:set langmap=ж\\;<CR>0жжx")
+        .feed(&format!(":actionlog {}<CR>", path.display()))
+        .assert_buffer("one\nhi<!wo\n");
+    let log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(log.starts_with("# vim-rs action log of \n# size 80x24\n0\tl\tMoveDown\n"));
+    assert!(log.contains("\n3\t<lt>\tInsertChar('<')\n"));
+
+    let recording = actionlog::parse(&log).unwrap();
+    let mut replayed = Editor::headless(Buffer::from_text("", "one\ntwo\n"), recording.size);
+    replayed.replay(&recording).unwrap();
+    assert_eq!(replayed.text(), "one\nhi<!wo\n");
+
+    let mut diverged = Editor::headless(Buffer::from_text("", "one\n"), recording.size);
+    let err = diverged.replay(&recording).unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("Action 10 (EnterMode(Command)) ran at revision 6 but replays at 5"));
+}
+
+#[test]
+fn user_commands_take_ranges_arguments_and_completions() {
+    let mut editor = TestEditor::new("a\nb\nc\nd\ne\n");
+    editor
+        .editor()
+        .load_plugin(
+            "test",
+            r#"
+            editor.command("Cut", function(args)
+              local first, last = args:match("(%d+) (%d+)")
+              local lines = editor.get_lines()
+              for _ = first, last do table.remove(lines, tonumber(first)) end
+              editor.set_lines(lines)
+            end)
+            editor.command("Append", function(args)
+              local lines = editor.get_lines()
+              table.insert(lines, args)
+              editor.set_lines(lines)
+            end)
+            function Fruits(typed) return { "apple", "apricot", "banana" } end
+            "#,
+        )
+        .unwrap();
+    editor
+        .feed(":command -range Rm Cut <lt>line1> <lt>line2><CR>")
+        .feed(":2,3Rm<CR>")
+        .assert_buffer("a\nd\ne\n")
+        .feed("l:Rm<CR>")
+        .assert_buffer("a\ne\n")
+        .feed(":Rm x<CR>:1Cut 1 1<CR>:command Rm Cut 1 1<CR>:.,$Rm<CR>")
+        .assert_buffer("a\n")
+        .feed(":command -nargs=1 -complete=custom,Fruits Pick Append <lt>args><CR>")
+        .feed(":Pick ap<Tab><Tab><CR>")
+        .assert_buffer("a\napricot\n")
+        .feed(":command Loop Loop<CR>:Loop<CR>:delcommand Loop<CR>:Loop<CR>")
+        .feed(":command! -range=% Rm Cut <lt>line1> <lt>line2><CR>:Rm<CR>")
+        .assert_buffer("\n");
+}
+
+#[test]
+fn gf_edits_the_file_named_under_the_cursor() {
+    let path = std::env::temp_dir().join(format!("vim-rs-links-{}.txt", std::process::id()));
+    std::fs::write(&path, "one\ntwo\nthree\n").unwrap();
+    TestEditor::new(&format!("see {}:2 here\n", path.display()))
+        .feed("gf")
+        .assert_buffer(&format!("see {}:2 here\n", path.display()))
+        .feed(";;;;gf")
+        .assert_buffer("one\ntwo\nthree\n")
+        .assert_cursor(1, 0);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn fillchars_fill_rows_past_the_end_of_the_buffer() {
+    let mut editor = TestEditor::new("text\n");
+    editor.feed(":set fillchars=eob:@,diff:.<CR>");
+    let frame = editor.editor().render().unwrap();
+    assert!(String::from_utf8_lossy(&frame).contains('@'));
+    editor.feed(":set fillchars=eob:\\ <CR>");
+    let frame = editor.editor().render().unwrap();
+    assert!(!String::from_utf8_lossy(&frame).contains('@'));
+}
+
+#[test]
+fn sessions_restore_windows_cursors_and_options() {
+    let dir = std::env::temp_dir().join(format!("vim-rs-session-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (left, right) = (dir.join("left"), dir.join("right"));
+    std::fs::write(&left, "one\ntwo\nthree\n").unwrap();
+    std::fs::write(&right, "one\n2\nthree\n").unwrap();
+    let (session, again) = (dir.join("Session.vim"), dir.join("again.vim"));
+
+    TestEditor::with_buffer(Buffer::open(left.to_str().unwrap()).unwrap())
+        .feed("l;:set cursorline fillchars=eob:\\ <CR>")
+        .feed(&format!(":diffsplit {}<CR><C-w>wll", right.display()))
+        .feed(&format!(":mksession {}<CR>", session.display()));
+    let mut restored = TestEditor::new("");
+    restored
+        .feed(&format!(":source {}<CR>", session.display()))
+        .assert_buffer("one\n2\nthree\n")
+        .assert_cursor(2, 0)
+        .feed(&format!(":mksession {}<CR>", again.display()));
+    assert_eq!(
+        std::fs::read_to_string(&session).unwrap(),
+        std::fs::read_to_string(&again).unwrap()
+    );
+    restored
+        .feed("<C-w>w")
+        .assert_buffer("one\ntwo\nthree\n")
+        .assert_cursor(1, 1)
+        .feed(&format!(":mksession {}<CR>", again.display()))
+        .assert_cursor(1, 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn empty_buffers_show_tildes_and_keep_the_cursor_in_place() {
+    let mut editor = TestEditor::new("");
+    editor
+        .feed("x;l<PageDown>kj<PageUp>")
+        .assert_buffer("")
+        .assert_cursor(0, 0);
+    let frame = editor.editor().render().unwrap();
+    // Every row of the viewport but the buffer's one line.
+    assert_eq!(String::from_utf8_lossy(&frame).matches('~').count(), 21);
+
+    for size in [(0, 0), (1, 1), (12, 2)] {
+        let mut editor = Editor::headless(Buffer::from_text("", ""), size);
+        editor.render().unwrap();
+    }
+}
+
+#[test]
+fn undo_steps_survive_reopening_with_undofile() {
+    let dir = std::env::temp_dir().join(format!("vim-rs-undo-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("file.txt");
+    std::fs::write(&path, "one\n").unwrap();
+    let set = format!(":set undofile undodir={}<CR>", dir.join("undo").display());
+
+    let mut editor = TestEditor::new("");
+    editor
+        .feed(&set)
+        .feed(&format!(":e {}<CR>", path.display()))
+        .feed("ia<Esc>ib<Esc>xx")
+        .assert_buffer("abe\n")
+        .feed("u")
+        .assert_buffer("abne\n")
+        .feed("u")
+        .assert_buffer("abone\n")
+        .feed("uu")
+        .assert_buffer("one\n")
+        .feed("<C-r><C-r>")
+        .assert_buffer("abone\n")
+        .feed(":w<CR>");
+
+    TestEditor::new("")
+        .feed(&set)
+        .feed(&format!(":e {}<CR>", path.display()))
+        .feed("u")
+        .assert_buffer("aone\n")
+        .feed("<C-r><C-r>")
+        .assert_buffer("abne\n")
+        .feed("uuu")
+        .assert_buffer("one\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn undo_tree_keeps_branches_reached_in_the_order_they_were_made() {
+    TestEditor::new("")
+        .feed("ione<Esc>u")
+        .assert_buffer("")
+        .feed("itwo<Esc>u<C-r>")
+        .assert_buffer("two")
+        .feed("g-")
+        .assert_buffer("one")
+        .feed("g-")
+        .assert_buffer("")
+        .feed("g-g+")
+        .assert_buffer("one")
+        .feed("g+")
+        .assert_buffer("two")
+        .feed("g+")
+        .assert_buffer("two")
+        .feed(":earlier 1h<CR>")
+        .assert_buffer("")
+        .feed(":later 1<CR>")
+        .assert_buffer("one")
+        .feed(":later 1d<CR>")
+        .assert_buffer("two")
+        .feed(":earlier 2<CR>u")
+        .assert_buffer("")
+        .feed("<C-r>")
+        .assert_buffer("two");
+}
+
+#[test]
+fn messages_are_kept_and_unseen_errors_counted_in_the_statusline() {
+    let mut editor = TestEditor::new("");
+    editor.feed(":messages clear<CR>:bogus<CR>ug;:nope<CR>");
+    let frame = editor.editor().render().unwrap();
+    assert!(String::from_utf8_lossy(&frame).contains(" E2 "));
+    editor
+        .feed(":redir @a<CR>:messages warning<CR><Esc>:messages<CR><Esc>:redir END<CR>")
+        .feed(":put a<CR>")
+        .assert_buffer(
+            "\nNot an editor command: bogus\nNo change to jump to in the changelist\n\
+             Not an editor command: nope\nNot an editor command: bogus\n\
+             Already at oldest change\nNo change to jump to in the changelist\n\
+             Not an editor command: nope",
+        );
+    let frame = editor.editor().render().unwrap();
+    assert!(!String::from_utf8_lossy(&frame).contains(" E2 "));
+    TestEditor::new("")
+        .feed(":messages clear<CR>u:mes clear<CR>:oops<CR>")
+        .feed(":redir @a<CR>:mes<CR><Esc>:redir END<CR>:put a<CR>")
+        .assert_buffer("\nNot an editor command: oops");
+}
+
+#[test]
+fn autosave_writes_when_the_focus_is_lost_or_the_buffer_switched() {
+    let dir = std::env::temp_dir().join(format!("vim-rs-autosave-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (path, other) = (dir.join("file.txt"), dir.join("other.txt"));
+    std::fs::write(&path, "one\n").unwrap();
+    std::fs::write(&other, "other\n").unwrap();
+    let read = || std::fs::read_to_string(&path).unwrap();
+
+    let mut editor = TestEditor::with_buffer(Buffer::open(path.to_str().unwrap()).unwrap());
+    editor.feed("x").editor().feed(Event::FocusLost).unwrap();
+    assert_eq!(read(), "one\n");
+    editor
+        .feed(":set autosave<CR>")
+        .editor()
+        .feed(Event::FocusLost)
+        .unwrap();
+    assert_eq!(read(), "ne\n");
+    editor.feed("ia").editor().feed(Event::FocusLost).unwrap();
+    assert_eq!(read(), "ne\n");
+    editor
+        .feed("<Esc>x")
+        .feed(&format!(":e {}<CR>", other.display()))
+        .assert_buffer("other\n");
+    assert_eq!(read(), "ae\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn ctrl_z_saves_with_autosave_and_redraws_the_whole_screen_on_resuming() {
+    let path = std::env::temp_dir().join(format!("vim-rs-suspend-{}.txt", std::process::id()));
+    std::fs::write(&path, "one\n").unwrap();
+    let mut editor = TestEditor::with_buffer(Buffer::open(path.to_str().unwrap()).unwrap());
+    editor.feed(":set autosave<CR>x").redraw();
+    let frame = String::from_utf8(editor.feed("<C-z>").editor().render().unwrap()).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "ne\n");
+    assert!(frame.starts_with("\x1b[2J"), "{frame:?}");
+    assert!(frame.contains("\x1b[1;1Hne "), "{frame:?}");
+    editor.assert_mode("NORMAL").assert_buffer("ne\n");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn bursts_of_resize_events_are_laid_out_once_they_stop() {
+    let mut editor = TestEditor::new("one\n");
+    editor.redraw();
+    for width in [60, 70, 100] {
+        editor.editor().feed(Event::Resize(width, 30)).unwrap();
+    }
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(!frame.contains("\x1b[2J"), "{frame:?}");
+    editor.editor().idle();
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.starts_with("\x1b[2J"), "{frame:?}");
+    assert!(
+        frame.contains(&format!("\x1b[1;1Hone{}\x1b[2;1H", " ".repeat(97))),
+        "{frame:?}"
+    );
+    // Or once another event arrives.
+    editor.editor().feed(Event::Resize(40, 10)).unwrap();
+    let frame = String::from_utf8(editor.feed("x").editor().render().unwrap()).unwrap();
+    assert!(
+        frame.contains(&format!("\x1b[1;1Hne{}\x1b[2;1H", " ".repeat(38))),
+        "{frame:?}"
+    );
+}
+
+#[test]
+fn checktime_offers_to_reload_a_file_changed_on_disk() {
+    let path = std::env::temp_dir().join(format!("vim-rs-checktime-{}.txt", std::process::id()));
+    std::fs::write(&path, "one\n").unwrap();
+    let mut editor = TestEditor::with_buffer(Buffer::open(path.to_str().unwrap()).unwrap());
+
+    std::fs::write(&path, "two\n").unwrap();
+    editor
+        .feed(":checktime<CR><CR>")
+        .assert_buffer("two\n")
+        .feed(":checktime<CR>x")
+        .assert_buffer("wo\n");
+    std::fs::write(&path, "three\n").unwrap();
+    editor
+        .feed(":checktime<CR>q:checktime<CR>")
+        .assert_buffer("wo\n")
+        .feed(":w<CR>lll<CR>")
+        .assert_buffer("three\n")
+        .feed("u")
+        .assert_buffer("wo\n");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn visual_u_changes_case_unless_a_plugin_maps_it() {
+    let mut editor = TestEditor::new("Hello World\nnext\n");
+    editor
+        .feed("v;;;;u")
+        .assert_buffer("hello World\nnext\n")
+        .assert_mode("NORMAL")
+        .assert_cursor(0, 0)
+        .feed(";;;;;;v;;;;U")
+        .assert_buffer("hello WORLD\nnext\n")
+        .feed("0v~")
+        .assert_buffer("Hello WORLD\nnext\n")
+        .feed(";;;;;vl0x")
+        .assert_buffer("Helloext\n")
+        .feed("u")
+        .assert_buffer("Hello WORLD\nnext\n");
+    editor
+        .editor()
+        .load_plugin(
+            "undo",
+            "editor.keymap('u', function() editor.exec('undo') end, 'v')",
+        )
+        .unwrap();
+    editor
+        .feed("vu")
+        .assert_buffer("hello WORLD\nnext\n")
+        .assert_mode("VISUAL")
+        .feed("<Esc>u")
+        .assert_buffer("hello World\nnext\n")
+        .feed(":redir @a<CR>:map u<CR><Esc>:redir END<CR>:put a<CR>")
+        .assert_buffer("hello World\nv  u           <Lua function>\nnext\n");
+    let err = editor
+        .editor()
+        .load_plugin("bad", "editor.keymap('a', function() end, 'q')")
+        .unwrap_err();
+    assert!(format!("{err:#}").contains("Unknown mode: q"));
+}
+
+#[cfg(unix)]
+#[test]
+fn writing_through_a_symlink_replaces_its_target_keeping_the_permissions() {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    let dir = std::env::temp_dir().join(format!("vim-rs-atomic-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (target, link) = (dir.join("target.sh"), dir.join("link.sh"));
+    std::fs::write(&target, "echo one\n").unwrap();
+    std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o750)).unwrap();
+    symlink(&target, &link).unwrap();
+
+    let mut editor = TestEditor::with_buffer(Buffer::open(link.to_str().unwrap()).unwrap());
+    editor.feed("x:w<CR>");
+    assert_eq!(std::fs::read_to_string(&target).unwrap(), "cho one\n");
+    assert!(link.symlink_metadata().unwrap().file_type().is_symlink());
+    let mode = target.metadata().unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o750);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn line_mode_runs_ex_commands_and_prints_what_they_show() {
+    let mut editor = TestEditor::new("one\ntwo\nthree\n");
+    let input = "2,3p\n:set tw=72\nset tw?\n\nbogus\nq\np\n";
+    let mut output = Vec::new();
+    editor
+        .editor()
+        .run_lines(input.as_bytes(), &mut output, ":")
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        ":two\nthree\n::textwidth=72\n::Not an editor command: bogus\n:"
+    );
+}
+
+#[test]
+fn backup_keeps_the_file_as_it_was_before_writing() {
+    let dir = std::env::temp_dir().join(format!("vim-rs-backup-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("file.txt");
+    std::fs::write(&path, "one\n").unwrap();
+    let backup = dir.join("file.txt~");
+
+    let mut editor = TestEditor::with_buffer(Buffer::open(path.to_str().unwrap()).unwrap());
+    editor.feed("x:w<CR>");
+    assert!(!backup.exists());
+    editor.feed(":set backup<CR>x:w<CR>");
+    assert_eq!(std::fs::read_to_string(&backup).unwrap(), "ne\n");
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "e\n");
+
+    let backups = dir.join("backups");
+    editor.feed(&format!(
+        ":set backupdir={} backupstamp bex=.bak<CR>ia<Esc>:w<CR>",
+        backups.display()
+    ));
+    let names: Vec<String> = std::fs::read_dir(&backups)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(names.len(), 1);
+    let stamp = names[0]
+        .strip_prefix("file.txt.")
+        .and_then(|name| name.strip_suffix(".bak"))
+        .unwrap();
+    assert_eq!((stamp.len(), &stamp[8..9]), (15, "-"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn quitting_refuses_to_drop_changes_not_written() {
+    let path = std::env::temp_dir().join(format!("vim-rs-quit-{}.txt", std::process::id()));
+    std::fs::write(&path, "one\n").unwrap();
+    let open = || TestEditor::with_buffer(Buffer::open(path.to_str().unwrap()).unwrap());
+
+    let mut editor = open();
+    editor.feed("x:q<CR>q:qa<CR>");
+    assert!(!editor.has_quit());
+    editor.feed(":q!<CR>");
+    assert!(editor.has_quit());
+    let mut editor = open();
+    editor.feed("xZQ");
+    assert!(editor.has_quit());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "one\n");
+
+    let mut editor = open();
+    editor.feed("xZZ");
+    assert!(editor.has_quit());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "ne\n");
+
+    let mut editor = open();
+    editor.editor().diff_with(Buffer::from_text("", "other\n"));
+    editor.feed("x<C-w>wx:q<CR>").assert_buffer("e\n");
+    assert!(!editor.has_quit());
+    editor.feed(":q<CR>:wqa<CR>");
+    assert!(!editor.has_quit());
+    editor.feed(":reopen<CR>:qa!<CR>");
+    assert!(editor.has_quit());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "e\n");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn statusline_shows_the_position_from_1_and_whether_the_buffer_changed() {
+    let mut editor = TestEditor::new("one\ntwo\nthree\nfour\n");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains(" 1:1 25% 4L "), "{frame:?}");
+    assert!(!frame.contains("[+]"));
+    let frame = String::from_utf8(editor.feed("l;x").editor().render().unwrap()).unwrap();
+    assert!(frame.contains(" 2:2 50% 4L "), "{frame:?}");
+    assert!(frame.contains(" [+]"));
+}
+
+#[test]
+fn statusline_option_lays_the_statusline_out() {
+    let mut editor = TestEditor::new("one\ntwo\nthree\nfour\n");
+    editor.feed(r":set stl=%#000000:ff0000#%f%*\ %m%=%l,%c\ %p%%\ of\ %L<CR>l;x");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains("[+]"), "{frame:?}");
+    assert!(frame.contains("2,2 50% of 4"), "{frame:?}");
+    assert!(!frame.contains("NORMAL"), "{frame:?}");
+    editor
+        .feed(":redir @a<CR>:set stl=%q<CR>:set stl=%#12:#<CR>:set stl?<CR><Esc>:redir END<CR>")
+        .feed(":put a<CR>")
+        .assert_buffer(
+            "one\nto\nInvalid argument: statusline=%q\nInvalid argument: statusline=%#12:#\n\
+             statusline=%#000000:ff0000#%f%*\\ %m%=%l,%c\\ %p%%\\ of\\ %L\nthree\nfour\n",
+        );
+}
+
+#[test]
+fn notifications_flash_in_the_statusline_and_are_kept_as_messages() {
+    let mut editor = TestEditor::new("one\n");
+    editor.feed(":messages clear<CR>:notify Build finished<CR>");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains(" Build finished "), "{frame:?}");
+    assert!(!frame.contains("NORMAL"), "{frame:?}");
+    editor
+        .feed(":set notify=bell,desktop<CR>:notify Grep done<CR>")
+        .feed(":redir @a<CR>:set notify=toast<CR>:notify<CR>:messages<CR><Esc>:redir END<CR>")
+        .feed(":put a<CR>")
+        .assert_buffer(
+            "one\nInvalid argument: notify=toast\nArgument required\nBuild finished\n\
+             Grep done\nInvalid argument: notify=toast\nArgument required\n",
+        );
+}
+
+#[test]
+fn message_area_shows_the_last_message_until_a_key_is_pressed() {
+    let mut editor = TestEditor::new("one\ntwo\n");
+    editor.feed(":bogus<CR>");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains("Not an editor command: bogus"), "{frame:?}");
+    let frame = String::from_utf8(editor.feed("l").editor().render().unwrap()).unwrap();
+    assert!(!frame.contains("Not an editor command"), "{frame:?}");
+    editor.feed("ug;");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains("No change to jump to"), "{frame:?}");
+    assert!(!frame.contains("Already at oldest change"), "{frame:?}");
+    editor.feed(&format!(":{}<CR>", "x".repeat(100)));
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains(&format!("Not an editor command: {}", "x".repeat(57))));
+    assert!(!frame.contains(&"x".repeat(58)), "{frame:?}");
+}
+
+#[test]
+fn filetype_info_tells_what_is_set_up_for_the_buffer() {
+    let mut editor = TestEditor::with_buffer(Buffer::from_text("notes.md", "text\n"));
+    editor.feed(":redir @a<CR>:filetype info<CR><Esc>:redir END<CR>:put a<CR>");
+    let text = editor.editor().text();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(
+        lines[..8],
+        [
+            "text",
+            "filetype     markdown, from the extension .md",
+            "highlighter  none, no grammar is built in",
+            "indent       none, new lines start at the first column",
+            "formatter    none, formatting needs the language server",
+            "lsp          none, no server is configured for markdown",
+            "linters      none, no linter is configured for markdown",
+            "comments     <!-- -->, from the built-in markers",
+        ]
+    );
+    assert!(lines[8].starts_with("snippets     0 built-in, "), "{text}");
+    assert!(lines[8].ends_with("snippets/markdown.json"), "{text}");
+    TestEditor::with_buffer(Buffer::from_text("data.xyz", ""))
+        .feed(":redir @a<CR>:filet info<CR><Esc>:filetype<CR>:redir END<CR>:put a<CR>")
+        .assert_buffer("\nfiletype     none, the extension .xyz is not known\nArgument required");
+}
+
+#[test]
+fn long_lines_are_shown_in_segments_broken_at_delimiters() {
+    let items: Vec<String> = (0..40).map(|i| format!("{{\"id\":{i}}}")).collect();
+    let json = format!("[{}]", items.join(","));
+    let text = format!("head\n{json}\n");
+    let mut editor = TestEditor::new(&text);
+    editor
+        .feed(":set segmentlength=100<CR>ll")
+        .assert_buffer(&text)
+        .assert_mode("NORMAL");
+    let (line, col) = editor.editor().cursor();
+    assert_eq!(line, 1);
+    assert!(0 < col && col <= 80, "{col}");
+    assert!(
+        matches!(json.chars().nth(col - 1), Some(',' | '{')),
+        "{col}"
+    );
+    editor
+        .feed("xiab<Esc>")
+        .assert_buffer(&text)
+        .feed("$")
+        .assert_cursor(1, json.len() - 1)
+        .feed("0")
+        .assert_cursor(1, 0)
+        .feed("j")
+        .assert_cursor(1, 0)
+        .feed("k")
+        .assert_cursor(0, 0);
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains("{\"id\":0},{\"id\":1},"), "{frame:?}");
+    editor
+        .feed("l:set segmentlength=0<CR>")
+        .assert_cursor(1, 0)
+        .feed("ix<Esc>")
+        .assert_buffer(&text.replacen("head\n[", "head\nx[", 1));
+}
+
+#[test]
+fn async_events_are_shown_as_toasts_in_the_corner() {
+    let path = std::env::temp_dir().join(format!("vim-rs-toast-{}.txt", std::process::id()));
+    std::fs::write(&path, "one\n").unwrap();
+    let name = path.to_str().unwrap();
+    let mut editor = TestEditor::with_buffer(Buffer::open(name).unwrap());
+
+    std::fs::write(&path, "two\n").unwrap();
+    editor.feed(":checktime<CR><CR>").assert_buffer("two\n");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains(&format!(" Reloaded {name} ")), "{frame:?}");
+
+    editor
+        .feed(":redir @a<CR>:messages<CR><Esc>:redir END<CR>:put a<CR>")
+        .assert_buffer(&format!("two\nReloaded {name}\n"));
+
+    let mut editor = TestEditor::with_buffer(Buffer::open(name).unwrap());
+    std::fs::write(&path, "three\n").unwrap();
+    editor
+        .feed(":set notoasts<CR>:checktime<CR><CR>")
+        .assert_buffer("three\n");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(!frame.contains(&format!(" Reloaded {name} ")), "{frame:?}");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn cursor_is_a_bar_in_insert_mode_and_a_block_otherwise() {
+    const BLOCK: &str = "\x1b[2 q";
+    const BAR: &str = "\x1b[6 q";
+    let mut editor = TestEditor::new("one\n");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains(BLOCK), "{frame:?}");
+    let frame = String::from_utf8(editor.feed("i").editor().render().unwrap()).unwrap();
+    assert!(frame.contains(BAR), "{frame:?}");
+    let frame = String::from_utf8(editor.feed("x").editor().render().unwrap()).unwrap();
+    assert!(!frame.contains(BAR), "{frame:?}");
+    let frame = String::from_utf8(editor.feed("<Esc>").editor().render().unwrap()).unwrap();
+    assert!(frame.contains(BLOCK), "{frame:?}");
+}
+
+#[test]
+fn vimrc_subset_of_vimscript_is_interpreted() {
+    let dir = std::env::temp_dir().join(format!("vim-rs-vimrc-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let script = dir.join("vimrc");
+    std::fs::write(
+        &script,
+        "\" migrated from vim\n\
+         set nocompatible\n\
+         syntax on\n\
+         filetype plugin indent on\n\
+         let mapleader = ','\n\
+         let g:width = 60\n\
+         let g:width += 20\n\
+         if has('eval') && &textwidth == 0\n\
+         \x20 let &textwidth = g:width\n\
+         elseif has('nvim')\n\
+         \x20 set textwidth=10\n\
+         else\n\
+         \x20 set textwidth=20\n\
+         endif\n\
+         function! Strip()\n\
+         \x20 %s/\\s\\+$//e\n\
+         endfunction\n\
+         nnoremap <silent> <leader>d xx\n\
+         nnoremap <Space>y 0x\n\
+         autocmd InsertEnter *\n\
+         \x20   \\ set cursorline\n",
+    )
+    .unwrap();
+    let mut editor = TestEditor::new("one\ntwo\nthree\n");
+    editor
+        .feed(&format!(":source {}<CR>", script.display()))
+        .feed(",d")
+        .assert_buffer("e\ntwo\nthree\n")
+        .feed("l;; y")
+        .assert_buffer("e\nwo\nthree\n")
+        .feed(" ;")
+        .assert_cursor(1, 1)
+        .feed("ix<Esc>")
+        .assert_buffer("e\nwxo\nthree\n")
+        .feed(":redir @a<CR>:set tw? cul?<CR><Esc>:echo 'width: ' . g:width<CR>")
+        .feed(":nmap ,d<CR><Esc>:redir END<CR>:put a<CR>")
+        .assert_buffer("e\nwxo\ntextwidth=80\ncursorline\nwidth: 80\nn  ,d          xx\nthree\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn terminal_title_shows_the_file_and_whether_it_is_modified() {
+    let path = std::env::temp_dir().join(format!("vim-rs-title-{}.txt", std::process::id()));
+    std::fs::write(&path, "one\n").unwrap();
+    let name = path.file_name().unwrap().to_str().unwrap().to_string();
+    let mut editor = TestEditor::with_buffer(Buffer::open(path.to_str().unwrap()).unwrap());
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(
+        frame.contains(&format!("\x1b]0;{name} \u{2014} vim-rs\x07")),
+        "{frame:?}"
+    );
+    let frame = String::from_utf8(editor.feed("x").editor().render().unwrap()).unwrap();
+    assert!(
+        frame.contains(&format!("\x1b]0;{name} + \u{2014} vim-rs\x07")),
+        "{frame:?}"
+    );
+    let frame = String::from_utf8(editor.feed(";").editor().render().unwrap()).unwrap();
+    assert!(!frame.contains("\x1b]0;"), "{frame:?}");
+    let frame = String::from_utf8(editor.feed(":w<CR>").editor().render().unwrap()).unwrap();
+    assert!(
+        frame.contains(&format!("\x1b]0;{name} \u{2014} vim-rs\x07")),
+        "{frame:?}"
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn scrolloff_keeps_lines_and_columns_around_the_cursor_shown() {
+    let text: String = (0..100).map(|i| format!("l{i}\n")).collect();
+    let mut editor = TestEditor::new(&text);
+    let row = |editor: &mut TestEditor, row: usize, text: &str| {
+        let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+        assert!(
+            frame.contains(&format!("\x1b[{row};1H{text} ")),
+            "{frame:?}"
+        );
+    };
+    // The 22 rows of the viewport scroll 3 lines before the cursor reaches
+    // the last of them.
+    editor
+        .feed(":set so=3<CR>")
+        .feed(&"l".repeat(19))
+        .assert_cursor(19, 0);
+    row(&mut editor, 1, "l1");
+    editor.feed(&"k".repeat(16)).assert_cursor(3, 0);
+    row(&mut editor, 1, "l0");
+    // Larger than half the viewport keeps the cursor line centered.
+    editor.feed(":set so=99<CR>").feed(&"l".repeat(20));
+    row(&mut editor, 1, "l12");
+
+    let line = "0123456789".repeat(20);
+    let mut editor = TestEditor::new(&format!("{line}\n"));
+    editor.feed(":set siso=5<CR>$").assert_cursor(0, 200);
+    row(&mut editor, 1, &line[126..]);
+    editor.feed(&"j".repeat(70));
+    row(&mut editor, 1, &line[125..]);
+}
+
+#[test]
+fn cursorcolumn_highlights_the_cursor_column_past_line_ends() {
+    let mut editor = TestEditor::new("ab\nabcd\n");
+    let frame = String::from_utf8(editor.feed(";;").editor().render().unwrap()).unwrap();
+    assert!(frame.contains("\x1b[2;1Habcd "), "{frame:?}");
+    let frame = String::from_utf8(editor.feed(":set cuc<CR>").editor().render().unwrap()).unwrap();
+    // The column is highlighted under the cursor and after the shorter line.
+    assert!(frame.contains("ab\x1b[48;5;0mc\x1b[49md"), "{frame:?}");
+    assert!(
+        frame.contains("\x1b[1;1Hab\x1b[48;5;0m \x1b[49m "),
+        "{frame:?}"
+    );
+    let frame =
+        String::from_utf8(editor.feed(":set nocuc<CR>").editor().render().unwrap()).unwrap();
+    assert!(!frame.contains("\x1b[48;5;0m"), "{frame:?}");
+}
+
+#[test]
+fn colorcolumn_draws_guides_down_the_viewport() {
+    let mut editor = TestEditor::new("abcdef\nab\n");
+    let frame = String::from_utf8(
+        editor
+            .feed(":set cc=3,+1 tw=4<CR>")
+            .editor()
+            .render()
+            .unwrap(),
+    )
+    .unwrap();
+    // Columns 3 and 5, past the end of shorter lines too.
+    assert!(
+        frame.contains("\x1b[1;1Hab\x1b[48;5;0mc\x1b[49md\x1b[48;5;0me\x1b[49mf "),
+        "{frame:?}"
+    );
+    assert!(
+        frame.contains("\x1b[2;1Hab\x1b[48;5;0m \x1b[49m \x1b[48;5;0m \x1b[49m "),
+        "{frame:?}"
+    );
+    let frame = String::from_utf8(editor.feed(":set tw=0<CR>").editor().render().unwrap()).unwrap();
+    assert!(
+        frame.contains("\x1b[1;1Hab\x1b[48;5;0mc\x1b[49mdef "),
+        "{frame:?}"
+    );
+}
+
+#[test]
+fn list_shows_whitespace_and_trailing_whitespace_is_highlighted() {
+    const DIM: &str = "\x1b[38;5;8m";
+    let mut editor = TestEditor::new("\ta\u{a0}b c  \n");
+    let frame = String::from_utf8(editor.feed(":set list<CR>").editor().render().unwrap()).unwrap();
+    assert!(
+        frame.contains(&format!("{DIM}>\x1b[39ma{DIM}+\x1b[39mb c{DIM}--\x1b[39m ")),
+        "{frame:?}"
+    );
+    editor.feed(":set lcs=tab:>-,space:.<CR>");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(
+        frame.contains(&format!(
+            "{DIM}>\x1b[39ma\u{a0}b{DIM}.\x1b[39mc{DIM}..\x1b[39m "
+        )),
+        "{frame:?}"
+    );
+    editor.feed(":set nolist hlt<CR>");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(
+        frame.contains("\ta\u{a0}b c\x1b[48;5;8m  \x1b[49m "),
+        "{frame:?}"
+    );
+    // Not on the line being typed in.
+    let frame = String::from_utf8(editor.feed("i").editor().render().unwrap()).unwrap();
+    assert!(frame.contains("\ta\u{a0}b c   "), "{frame:?}");
+}
+
+#[test]
+fn trailing_whitespace_is_trimmed_and_final_newline_added_on_write() {
+    let path = std::env::temp_dir().join(format!("vim-rs-tidy-{}.txt", std::process::id()));
+    std::fs::write(&path, "a  \nb\t\nc").unwrap();
+    let name = path.to_str().unwrap();
+    let mut editor = TestEditor::with_buffer(Buffer::open(name).unwrap());
+    editor.feed(":w<CR>");
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "a  \nb\t\nc");
+    editor
+        .feed(":set trt fixeol<CR>l;;:w<CR>")
+        .assert_buffer("a\nb\nc\n")
+        .assert_cursor(1, 1);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\nb\nc\n");
+    editor
+        .feed(":redir @a<CR>:messages<CR><Esc>:redir END<CR>:put a<CR>")
+        .assert_buffer("a\nb\nTrimmed trailing whitespace on 2 lines, added a final newline\nc\n");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn config_sets_options_for_the_filetype_of_the_buffer() {
+    let dir = std::env::temp_dir().join(format!("vim-rs-config-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.toml");
+    std::fs::write(
+        &config,
+        "[filetype.python]\ntextwidth = 79\ncolorcolumn = [\"+1\", \"100\"]\n\
+         commentstring = \"## %s\"\n\n[filetype.rust]\nlist = true\n",
+    )
+    .unwrap();
+    let script = dir.join("main.py");
+    std::fs::write(&script, "x = 1\n").unwrap();
+    let notes = dir.join("notes.txt");
+    std::fs::write(&notes, "notes\n").unwrap();
+
+    let mut editor = TestEditor::with_buffer(Buffer::open(script.to_str().unwrap()).unwrap());
+    editor.feed(":set tw=60<CR>");
+    editor.editor().load_config(&config, None).unwrap();
+    editor
+        .feed("gcc:w<CR>")
+        .assert_buffer("## x = 1\n")
+        .feed(":redir @a<CR>:verbose set tw? cc?<CR><Esc>")
+        .feed(&format!(":e {}<CR>", notes.display()))
+        .feed(":verbose set tw? cc?<CR><Esc>:redir END<CR>:put a<CR>")
+        .assert_buffer(&format!(
+            "notes\ntextwidth=79\n\tLast set from {0} line 2\ncolorcolumn=+1,100\n\
+             \tLast set from {0} line 3\ntextwidth=60\n\tLast set from the command line\n\
+             colorcolumn=\n",
+            config.display()
+        ));
+    std::fs::write(&config, "[python]\ntextwidth = 79\n").unwrap();
+    let err = editor.editor().load_config(&config, None).unwrap_err();
+    assert!(
+        format!("{err:#}").contains("line 1: unknown section python"),
+        "{err:#}"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn folds_hide_lines_behind_a_summary_row() {
+    let mut editor = TestEditor::new("fn main() {\n    one\n    two\n}\nend\n");
+    editor
+        .feed("lzfl")
+        .assert_cursor(1, 0)
+        .feed("ll")
+        .assert_cursor(4, 0)
+        .feed("kk")
+        .assert_cursor(1, 0);
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains("+--  2 lines: one---"), "{frame:?}");
+    assert!(frame.contains("\x1b[3;1H}"), "{frame:?}");
+    editor
+        .feed("zal")
+        .assert_cursor(2, 0)
+        .feed("zMl")
+        .assert_cursor(3, 0)
+        .feed("kzRl")
+        .assert_cursor(2, 0)
+        .feed("zcx")
+        .assert_buffer("fn main() {\n   one\n    two\n}\nend\n")
+        .feed("l")
+        .assert_cursor(2, 0)
+        .feed("zMzEl")
+        .assert_cursor(2, 0);
+
+    // Under foldmethod=indent, each block more indented than the line
+    // above is a fold, closed at first.
+    TestEditor::new("if a:\n    b\n\n    c\nd\n")
+        .feed(":set fdm=indent<CR>l")
+        .assert_cursor(1, 0)
+        .feed("l")
+        .assert_cursor(4, 0)
+        .feed("kzoll")
+        .assert_cursor(3, 0)
+        .feed("zfk")
+        .assert_cursor(3, 0);
+}
+
+#[test]
+fn foldtext_lays_out_the_row_of_a_closed_fold() {
+    let mut editor = TestEditor::new("a\n  b\n    c\n    d\n  e\nf\n");
+    editor.feed(":set fdm=indent<CR>lzo");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains("+---  2 lines: c---"), "{frame:?}");
+    editor.feed(":set fdt=%t:%n%d%%<CR>");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains("c:2--%---"), "{frame:?}");
+    editor.feed(":set fdt=%x<CR>").assert_mode("NORMAL");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains("Invalid argument: fdt=%x"), "{frame:?}");
+}
+
+#[test]
+fn indentguides_draw_a_line_down_each_indent_level() {
+    let mut editor = TestEditor::new("fn a() {\n    if b {\n        c\n\n        d\n    }\n}\n");
+    let frame = String::from_utf8(editor.feed(":set ig<CR>").editor().render().unwrap()).unwrap();
+    let guide = "\x1b[38;5;8m|\x1b[39m";
+    assert!(
+        frame.contains(&format!("\x1b[2;1H{guide}   if b {{")),
+        "{frame:?}"
+    );
+    assert!(
+        frame.contains(&format!("\x1b[3;1H{guide}   {guide}   c ")),
+        "{frame:?}"
+    );
+    // Blank lines within a block carry its guides on.
+    assert!(
+        frame.contains(&format!("\x1b[4;1H{guide}   {guide}  ")),
+        "{frame:?}"
+    );
+    assert!(frame.contains("\x1b[7;1H}  "), "{frame:?}");
+}
+
+#[test]
+fn matchparen_highlights_the_bracket_under_the_cursor_and_its_match() {
+    const MATCH: &str = "\x1b[48;5;8m";
+    let mut editor = TestEditor::new("f(a[b]c) x\n");
+    let frame = String::from_utf8(editor.feed(";").editor().render().unwrap()).unwrap();
+    assert!(
+        frame.contains(&format!("f{MATCH}(\x1b[49ma[b]c{MATCH})\x1b[49m x")),
+        "{frame:?}"
+    );
+    // Right after a bracket too.
+    let frame = String::from_utf8(editor.feed(";;;").editor().render().unwrap()).unwrap();
+    assert!(
+        frame.contains(&format!("f(a{MATCH}[\x1b[49mb{MATCH}]\x1b[49mc) x")),
+        "{frame:?}"
+    );
+    let frame = String::from_utf8(
+        editor
+            .feed(":set nomatchparen<CR>")
+            .editor()
+            .render()
+            .unwrap(),
+    )
+    .unwrap();
+    assert!(frame.contains("f(a[b]c) x"), "{frame:?}");
+}
+
+#[test]
+fn rainbow_colors_brackets_by_depth() {
+    let mut editor = TestEditor::new("a(b[c]{d})\n)(\n");
+    let frame =
+        String::from_utf8(editor.feed(":set rainbow<CR>").editor().render().unwrap()).unwrap();
+    // Brackets nested alike share a run of color, the others start their
+    // own, even when the terminal has too few colors to tell them apart.
+    let color = |text: &str| format!("\x1b[38;5;7m{text}\x1b[39m");
+    assert!(
+        frame.contains(&format!(
+            "a{}b{}c{}d{}{}",
+            color("("),
+            color("["),
+            color("]{"),
+            color("}"),
+            color(")")
+        )),
+        "{frame:?}"
+    );
+    // Stray closing brackets do not go below the top level.
+    assert!(frame.contains(&color(")(")), "{frame:?}");
+}
+
+#[test]
+fn search_honors_ignorecase_smartcase_and_wrapscan() {
+    let mut editor = TestEditor::new("one Two\ntwo one\nthree\n");
+    editor
+        .feed("/two<CR>")
+        .assert_cursor(1, 0)
+        .feed("n")
+        .assert_cursor(1, 0)
+        .feed(":set ic<CR>n")
+        .assert_cursor(0, 4)
+        .feed(":set scs<CR>/Two<CR>")
+        .assert_cursor(0, 4)
+        .feed("?one<CR>")
+        .assert_cursor(0, 0)
+        .feed("N")
+        .assert_cursor(1, 4)
+        .feed(":set nows<CR>nn")
+        .assert_cursor(0, 0);
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(
+        frame.contains("Search hit TOP without match for: one"),
+        "{frame:?}"
+    );
+    let frame = String::from_utf8(editor.feed(":set hls<CR>").editor().render().unwrap()).unwrap();
+    assert!(
+        frame.contains("\x1b[1;1H\x1b[48;5;8mone\x1b[49m Two"),
+        "{frame:?}"
+    );
+    assert!(frame.contains("two \x1b[48;5;8mone\x1b[49m "), "{frame:?}");
+    let frame = String::from_utf8(editor.feed(":noh<CR>").editor().render().unwrap()).unwrap();
+    assert!(frame.contains("\x1b[1;1Hone Two "), "{frame:?}");
+}
+
+#[test]
+fn star_and_hash_search_for_the_word_under_the_cursor() {
+    let mut editor = TestEditor::new("foo foobar\nbar foo\nfoo_x foo\n");
+    editor
+        .feed("*")
+        .assert_cursor(1, 4)
+        .feed("*")
+        .assert_cursor(2, 6)
+        .feed("#")
+        .assert_cursor(1, 4)
+        .feed("g*")
+        .assert_cursor(2, 0)
+        .feed("n")
+        .assert_cursor(2, 6)
+        .feed("N")
+        .assert_cursor(2, 0)
+        .feed("k;;;;g#")
+        .assert_cursor(0, 4);
+}
+
+#[test]
+fn global_runs_a_command_on_each_matching_line() {
+    let mut editor = TestEditor::new("a TODO\nb\nc TODO\nd TODO\ne\n");
+    editor
+        .feed(":g/TODO/d<CR>")
+        .assert_buffer("b\ne\n")
+        .assert_cursor(1, 0)
+        .feed("u:v/TODO/d<CR>")
+        .assert_buffer("a TODO\nc TODO\nd TODO\n")
+        .feed("u:2,4g!#TODO#d<CR>")
+        .assert_buffer("a TODO\nc TODO\nd TODO\ne\n")
+        .feed("u:g/TODO/.,$d<CR>")
+        .assert_buffer("");
+    let frame =
+        String::from_utf8(editor.feed("u:g/^[ce]/<CR>").editor().render().unwrap()).unwrap();
+    assert!(frame.contains("c TODO"), "{frame:?}");
+    assert!(!frame.contains("\x1b[2;1Hb"), "{frame:?}");
+}
+
+#[test]
+fn ranges_take_offsets_marks_and_the_selection() {
+    let mut editor = TestEditor::new("1\n2\n3\n4\n5\n6\n7\n8\n");
+    editor
+        .feed(":2,3d<CR>")
+        .assert_buffer("1\n4\n5\n6\n7\n8\n")
+        .assert_cursor(1, 0)
+        .feed(":.,+1d<CR>")
+        .assert_buffer("1\n6\n7\n8\n")
+        .feed(":$-1d<CR>")
+        .assert_buffer("1\n6\n8\n")
+        .feed("u:2<CR>")
+        .assert_cursor(1, 0)
+        .feed("ma:4<CR>:'a;+1d<CR>")
+        .assert_buffer("1\n8\n")
+        .feed("u:3<CR>vl:d<CR>")
+        .assert_buffer("1\n6\n");
+}
+
+#[test]
+fn move_and_copy_lines_with_ex_commands_and_alt_keys() {
+    let mut editor = TestEditor::new("a\nb\nc\nd\n");
+    editor
+        .feed(":1m$<CR>")
+        .assert_buffer("b\nc\nd\na\n")
+        .assert_cursor(3, 0)
+        .feed(":m0<CR>")
+        .assert_buffer("a\nb\nc\nd\n")
+        .feed(":1,2t$<CR>")
+        .assert_buffer("a\nb\nc\nd\na\nb\n")
+        .assert_cursor(5, 0)
+        .feed("u:2,3m0<CR>")
+        .assert_buffer("b\nc\na\nd\n")
+        .feed(":1,2m1<CR>")
+        .assert_buffer("b\nc\na\nd\n")
+        .feed(":1<CR><A-l>")
+        .assert_buffer("c\nb\na\nd\n")
+        .assert_cursor(1, 0)
+        .feed("<A-l><A-l><A-l>")
+        .assert_buffer("c\na\nd\nb\n")
+        .assert_cursor(3, 0)
+        .feed("<A-k>")
+        .assert_buffer("c\na\nb\nd\n")
+        .feed(":1<CR>vl<A-l>")
+        .assert_buffer("b\nc\na\nd\n")
+        .assert_cursor(2, 0)
+        .feed("<Esc>:3t0<CR>")
+        .assert_buffer("a\nb\nc\na\nd\n");
+    let mut editor = TestEditor::new("x\ny");
+    editor.feed(":1m$<CR>").assert_buffer("y\nx");
+}
+
+#[test]
+fn sort_orders_lines_by_text_or_number() {
+    let mut editor = TestEditor::new("b10\nA\nb9\na\nb10\nx-3\n");
+    editor
+        .feed(":sort<CR>")
+        .assert_buffer("A\na\nb10\nb10\nb9\nx-3\n")
+        .feed("u:sort n<CR>")
+        .assert_buffer("A\na\nx-3\nb9\nb10\nb10\n")
+        .feed("u")
+        .assert_buffer("b10\nA\nb9\na\nb10\nx-3\n")
+        .feed(":sort! ui<CR>")
+        .assert_buffer("x-3\nb9\nb10\nA\n")
+        .feed("u:2,4sort<CR>")
+        .assert_buffer("b10\nA\na\nb9\nb10\nx-3\n");
+}
+
+#[test]
+fn normal_types_keys_on_each_line_of_a_range() {
+    let mut editor = TestEditor::new("a\nb\nc\n");
+    editor
+        .feed(":%norm 0i-<CR>")
+        .assert_buffer("-a\n-b\n-c\n")
+        .assert_mode("NORMAL")
+        .feed("u")
+        .assert_buffer("a\nb\nc\n")
+        .feed(":g/b/norm! x<CR>")
+        .assert_buffer("a\n\nc\n")
+        .feed("u:nnoremap Q 0i+<lt>Esc><CR>:2,3norm Q<CR>")
+        .assert_buffer("a\n+b\n+c\n")
+        .feed(":1norm! Q<CR>")
+        .assert_buffer("a\n+b\n+c\n");
+}
+
+#[test]
+fn registers_and_marks_are_listed_in_the_pager() {
+    let mut editor = TestEditor::new("one\ntwo\nthree\n");
+    editor.feed(":2y<CR>/thr<CR>mb0kma:registers<CR>");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains("Name Content"));
+    assert!(frame.contains("  \"\" two^J"));
+    assert!(frame.contains("  \"/ thr"));
+    editor.feed("q:marks b<CR>");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains(" b      3    0 three"));
+    assert!(!frame.contains(" a      2"));
+    editor
+        .feed("q:marks<CR>l<CR>")
+        .assert_cursor(1, 0)
+        .feed(":reg z<CR>q")
+        .assert_mode("NORMAL");
+}
+
+#[test]
+fn ctrl_r_pastes_a_register_while_typing() {
+    TestEditor::with_buffer(Buffer::from_text("notes.txt", "one\ntwo\n"))
+        .feed(":2y<CR>/tw<CR>i<C-r>\"<C-r>/<Esc>")
+        .assert_buffer("one\ntwo\ntwtwo\n")
+        .feed("gg/<C-r>\"<CR>")
+        .assert_cursor(1, 0)
+        .feed("i<C-r>q<C-r>%<C-r>:<Esc>")
+        .assert_buffer("one\nnotes.txt2ytwo\ntwtwo\n");
+}
+
+#[test]
+fn ctrl_w_and_ctrl_u_delete_back_while_inserting() {
+    TestEditor::new("let x = old\n")
+        .feed("$i foo.bar<C-w>")
+        .assert_buffer("let x = old foo.\n")
+        .feed("<C-w><C-w><C-w>")
+        .assert_buffer("let x = old\n")
+        .feed("<C-w>")
+        .assert_buffer("let x = \n")
+        .feed("new<C-u>")
+        .assert_buffer("let x = \n")
+        .feed("<C-u>")
+        .assert_buffer("\n")
+        .feed("a b<Esc>u")
+        .assert_buffer("let x = old\n");
+}
+
+#[test]
+fn caret_and_home_go_to_the_first_non_blank() {
+    TestEditor::new("    indented\n")
+        .feed("$^")
+        .assert_cursor(0, 4)
+        .feed("0_")
+        .assert_cursor(0, 4)
+        .feed("<Home>")
+        .assert_cursor(0, 0)
+        .feed("<Home>")
+        .assert_cursor(0, 4)
+        .feed("$i<Home>x<Home><Home>y")
+        .assert_buffer("y    xindented\n");
+}
+
+#[test]
+fn braces_and_parens_move_over_paragraphs_and_sentences() {
+    let text = "first para one.\nstill one.\n\nsecond. Two here!  Three\n\nlast\n";
+    TestEditor::new(text)
+        .feed("}")
+        .assert_cursor(2, 0)
+        .feed("}}")
+        .assert_cursor(5, 4)
+        .feed("{")
+        .assert_cursor(4, 0)
+        .feed("2{")
+        .assert_cursor(0, 0)
+        .feed(")")
+        .assert_cursor(1, 0)
+        .feed("3)")
+        .assert_cursor(3, 8)
+        .feed("(")
+        .assert_cursor(3, 0)
+        .feed("12)")
+        .assert_cursor(5, 4)
+        .feed("0")
+        .assert_cursor(5, 0);
+    TestEditor::with_buffer(Buffer::from_text("lib.rs", "a();\nb();\n\nc();\n"))
+        .feed("gc}")
+        .assert_buffer("// a();\n// b();\n\nc();\n")
+        .feed("v}d")
+        .assert_buffer("c();\n");
+}
+
+#[test]
+fn brackets_jump_between_sections_and_definitions() {
+    let text = "use x;\n\npub(crate) fn a() {\n    if b() {\n    }\n}\n\nimpl S {\n    fn c(&self) {}\n}\n";
+    TestEditor::with_buffer(Buffer::from_text("lib.rs", text))
+        .feed("]]")
+        .assert_cursor(2, 0)
+        .feed("]]")
+        .assert_cursor(7, 0)
+        .feed("]m")
+        .assert_cursor(8, 4)
+        .feed("]m")
+        .assert_cursor(8, 4)
+        .feed("]]")
+        .assert_cursor(9, 0)
+        .feed("2[[")
+        .assert_cursor(2, 0)
+        .feed("[[")
+        .assert_cursor(0, 0)
+        .feed("3]m")
+        .assert_cursor(8, 4)
+        .feed("[m")
+        .assert_cursor(7, 0);
+}
+
+#[test]
+fn g_motions_move_over_screen_rows() {
+    let long = "x".repeat(100);
+    TestEditor::new(&format!("one\n{long}\nthree\n"))
+        .feed("gl$")
+        .assert_cursor(1, 100)
+        .feed("g0")
+        .assert_cursor(1, 21)
+        .feed("g$")
+        .assert_cursor(1, 100)
+        .feed("0g$")
+        .assert_cursor(1, 79)
+        .feed("gk")
+        .assert_cursor(0, 3)
+        .feed("g<Down>g0")
+        .assert_cursor(1, 0);
+}
+
+#[test]
+fn gv_reselects_and_o_swaps_the_ends_of_the_selection() {
+    TestEditor::new("one two three\nfour\n")
+        .feed("gv")
+        .assert_mode("NORMAL")
+        .feed(";;;;v;;o")
+        .assert_cursor(0, 4)
+        .feed("jj<Esc>")
+        .assert_cursor(0, 2)
+        .feed("lgv")
+        .assert_mode("VISUAL")
+        .assert_cursor(0, 2)
+        .feed("Od")
+        .assert_buffer("on three\nfour\n");
+}
+
+#[test]
+fn visual_p_swaps_the_selection_with_the_register() {
+    TestEditor::new("one two\nthree\n")
+        .feed("v;;d")
+        .assert_buffer(" two\nthree\n")
+        .feed(";v;;p")
+        .assert_buffer(" one\nthree\n")
+        .assert_cursor(0, 3)
+        .feed("0vp")
+        .assert_buffer("twoone\nthree\n")
+        .feed(":2y<CR>0v;;p")
+        .assert_buffer("\nthree\none\nthree\n")
+        .assert_cursor(1, 0)
+        .feed("u")
+        .assert_buffer("twoone\nthree\n");
+}
+
+#[test]
+fn x_p_and_j_take_a_count() {
+    TestEditor::new("abcdef\n  second\n\n  (third\n)\n")
+        .feed("3x")
+        .assert_buffer("def\n  second\n\n  (third\n)\n")
+        .feed("2p")
+        .assert_buffer("dabcabcef\n  second\n\n  (third\n)\n")
+        .assert_cursor(0, 6)
+        .feed("u09x")
+        .assert_buffer("\n  second\n\n  (third\n)\n")
+        .feed("P")
+        .assert_buffer("def\n  second\n\n  (third\n)\n")
+        .feed("J")
+        .assert_buffer("def second\n\n  (third\n)\n")
+        .assert_cursor(0, 3)
+        .feed("4J")
+        .assert_buffer("def second (third)\n")
+        .feed(":1y<CR>3p")
+        .assert_buffer(
+            "def second (third)\ndef second (third)\ndef second (third)\ndef second (third)\n",
+        )
+        .assert_cursor(1, 0);
+}
+
+#[test]
+fn deletes_rotate_through_the_numbered_registers() {
+    let mut editor = TestEditor::new("one\ntwo\nthree\nfour\n");
+    editor
+        .feed(":1d<CR>:1d<CR>2x:2y<CR>:put 2<CR>:put 1<CR>:put -<CR>:put 0<CR>")
+        .assert_buffer("ree\none\ntwo\nth\nfour\nfour\n")
+        .feed(":1d<CR>:reg 123-<CR>");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains("  \"1 ree^J"), "{frame:?}");
+    assert!(frame.contains("  \"2 two^J"), "{frame:?}");
+    assert!(frame.contains("  \"3 one^J"), "{frame:?}");
+    assert!(frame.contains("  \"- th"), "{frame:?}");
+}
+
+#[test]
+fn marks_are_remembered_for_each_file_edited() {
+    let dir = std::env::temp_dir().join(format!("vim-rs-oldfiles-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (a, b) = (dir.join("a.txt"), dir.join("b.txt"));
+    std::fs::write(&a, "a1\na2\n").unwrap();
+    std::fs::write(&b, "b1\n").unwrap();
+    let mut editor = TestEditor::new("");
+    editor
+        .feed(&format!(":e {}<CR>lma", a.display()))
+        .feed(&format!(":e {}<CR>:e {}<CR>", b.display(), a.display()))
+        .assert_cursor(0, 0)
+        .feed(":'a<CR>")
+        .assert_cursor(1, 0)
+        .feed(":oldfiles<CR>");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains(&format!("1: {}", a.display())), "{frame:?}");
+    assert!(frame.contains(&format!("2: {}", b.display())), "{frame:?}");
+    editor.feed("l<CR>").assert_buffer("b1\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn undo_breaks_at_new_lines_and_keeps_undolevels_steps() {
+    TestEditor::new("\n")
+        .feed("ione<CR>two<CR>three<Esc>")
+        .assert_buffer("one\ntwo\nthree\n")
+        .feed("u")
+        .assert_buffer("one\ntwo\n")
+        .feed("u")
+        .assert_buffer("one\n")
+        .feed("<C-r><C-r>")
+        .assert_buffer("one\ntwo\nthree\n");
+    TestEditor::new("one\n")
+        .feed(":set ul=1<CR>xxx")
+        .assert_buffer("\n")
+        .feed("uuu")
+        .assert_buffer("e\n");
+}
+
+#[test]
+fn highlights_overlapping_on_a_char_are_drawn_over_each_other() {
+    let mut editor = TestEditor::with_buffer(Buffer::from_text("main.rs", "fn main() {}\n"));
+    let uri = format!(
+        "file://{}",
+        std::path::absolute("main.rs").unwrap().display()
+    );
+    editor.lsp_message(
+        serde_json::json!({ "method": "textDocument/publishDiagnostics", "params": {
+            "uri": uri,
+            "diagnostics": [{
+                "range": {
+                    "start": { "line": 0, "character": 0 },
+                    "end": { "line": 0, "character": 2 },
+                },
+                "severity": 1,
+                "message": "bad",
+            }],
+        }}),
+    );
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(
+        frame.contains("\x1b[38;5;7m\x1b[4mfn\x1b[0m main() {}"),
+        "{frame:?}"
+    );
+    // The diagnostic keeps its color and underline over the cursor line,
+    // which shows on the rest of the line.
+    let frame = editor
+        .feed(":set cursorline<CR>")
+        .editor()
+        .render()
+        .unwrap();
+    let frame = String::from_utf8(frame).unwrap();
+    assert!(
+        frame.contains("\x1b[48;5;0m\x1b[38;5;7m\x1b[4mfn\x1b[0m\x1b[48;5;0m main() {}\x1b[49m"),
+        "{frame:?}"
+    );
+}
+
+#[test]
+fn sign_column_shows_the_marker_of_the_first_source_with_one() {
+    let mut editor = TestEditor::with_buffer(Buffer::from_text(
+        "main.rs",
+        "fn main() {}\nfn other() {}\n",
+    ));
+    let uri = format!(
+        "file://{}",
+        std::path::absolute("main.rs").unwrap().display()
+    );
+    editor.lsp_message(
+        serde_json::json!({ "method": "textDocument/publishDiagnostics", "params": {
+            "uri": uri,
+            "diagnostics": [{
+                "range": {
+                    "start": { "line": 0, "character": 0 },
+                    "end": { "line": 0, "character": 2 },
+                },
+                "severity": 1,
+                "message": "bad",
+            }],
+        }}),
+    );
+    let frame = editor
+        .feed(":set signs=diagnostics,longlines tw=5<CR>")
+        .editor()
+        .render()
+        .unwrap();
+    let frame = String::from_utf8(frame).unwrap();
+    assert!(
+        frame.contains("\x1b[1;1H\x1b[38;5;9mE \x1b[39m"),
+        "{frame:?}"
+    );
+    assert!(
+        frame.contains("\x1b[2;1H\x1b[38;5;13m> \x1b[39m"),
+        "{frame:?}"
+    );
+    let frame = editor
+        .feed(":set signs=longlines,diagnostics<CR>")
+        .editor()
+        .render()
+        .unwrap();
+    let frame = String::from_utf8(frame).unwrap();
+    assert!(
+        frame.contains("\x1b[1;1H\x1b[38;5;13m> \x1b[39m"),
+        "{frame:?}"
+    );
+}
+
+#[test]
+fn palette_runs_the_key_binding_or_command_chosen() {
+    TestEditor::new("one\ntwo\n")
+        .feed("<C-p>join<CR>")
+        .assert_buffer("one two\n")
+        .assert_mode("NORMAL")
+        .feed(":palette<CR>:yank<CR>p")
+        .assert_buffer("one two\none two\n")
+        .feed("<C-p>xyzzy<Esc>")
+        .assert_buffer("one two\none two\n")
+        .assert_mode("NORMAL");
+}
+
+#[test]
+fn diagnostics_published_for_other_files_are_not_shown() {
+    let mut editor = TestEditor::with_buffer(Buffer::from_text("main.rs", "fn main() {}\n"));
+    let uri = |path: &str| format!("file://{}", std::path::absolute(path).unwrap().display());
+    let publish = |uri: String, message: &str| {
+        serde_json::json!({ "method": "textDocument/publishDiagnostics", "params": {
+            "uri": uri,
+            "diagnostics": [{
+                "range": {
+                    "start": { "line": 0, "character": 0 },
+                    "end": { "line": 0, "character": 2 },
+                },
+                "severity": 1,
+                "message": message,
+            }],
+        }})
+    };
+    editor.lsp_message(publish(uri("other.rs"), "elsewhere"));
+    let frame = editor.editor().render().unwrap();
+    assert!(!String::from_utf8_lossy(&frame).contains("elsewhere"));
+    editor.lsp_message(publish(uri("main.rs"), "here"));
+    let frame = editor.editor().render().unwrap();
+    assert!(String::from_utf8_lossy(&frame).contains("here [lsp]"));
+    editor.lsp_message(publish(uri("other.rs"), "elsewhere"));
+    let frame = editor.editor().render().unwrap();
+    // The line is drawn again only if its diagnostics changed.
+    assert!(!String::from_utf8_lossy(&frame).contains("elsewhere"));
+}
+
+#[test]
+fn listing_edits_that_cannot_be_told_apart_are_refused() {
+    let dir = std::env::temp_dir().join(format!("vim-rs-listing-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for name in ["a", "b", "d"] {
+        std::fs::write(dir.join(name), name.to_uppercase()).unwrap();
+    }
+    let contents = || {
+        let mut names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .map(|path| {
+                let name = path.file_name().unwrap().to_string_lossy().into_owned();
+                format!("{name}={}", std::fs::read_to_string(&path).unwrap())
+            })
+            .collect();
+        names.sort();
+        names.join(" ")
+    };
+    let listing = || Buffer::from_dir(&dir.to_string_lossy()).unwrap();
+    // Deleting a and renaming b to c could as well be renaming a to c and
+    // deleting b.
+    TestEditor::with_buffer(listing()).feed(":1d<CR>xic<Esc>:w<CR>y");
+    assert_eq!(contents(), "a=A b=B d=D");
+    // Moving a line changes nothing.
+    TestEditor::with_buffer(listing()).feed(":1m$<CR>:w<CR>y");
+    assert_eq!(contents(), "a=A b=B d=D");
+    TestEditor::with_buffer(listing())
+        .feed(":1d<CR>:w<CR>y")
+        .feed(":1<CR>xic<Esc>:w<CR>y");
+    assert_eq!(contents(), "c=B d=D");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn listing_changes_are_checked_before_any_is_made() {
+    let dir = std::env::temp_dir().join(format!("vim-rs-apply-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for name in ["a", "x", "z"] {
+        std::fs::write(dir.join(name), name.to_uppercase()).unwrap();
+    }
+    let mut editor = TestEditor::with_buffer(Buffer::from_dir(&dir.to_string_lossy()).unwrap());
+    editor.feed(":1d<CR>:2<CR>xib<Esc>:w<CR>");
+    // The new name is taken after the changes were planned.
+    std::fs::write(dir.join("b"), "new").unwrap();
+    editor.feed("y");
+    for (name, text) in [("a", "A"), ("b", "new"), ("x", "X"), ("z", "Z")] {
+        assert_eq!(std::fs::read_to_string(dir.join(name)).unwrap(), text);
+    }
+    std::fs::remove_file(dir.join("b")).unwrap();
+    TestEditor::with_buffer(Buffer::from_dir(&dir.to_string_lossy()).unwrap())
+        .feed(":1d<CR>:2<CR>xib<Esc>:w<CR>y");
+    assert_eq!(std::fs::read_to_string(dir.join("b")).unwrap(), "Z");
+    assert!(!dir.join("a").exists() && !dir.join("z").exists());
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn workspace_edits_create_files_in_new_directories() {
+    let dir = std::env::temp_dir().join(format!("vim-rs-create-{}", std::process::id()));
+    let file = dir.join("new/sub/lib.rs");
+    let uri = format!("file://{}", file.display());
+    TestEditor::new("").lsp_message(serde_json::json!({
+        "id": 1,
+        "method": "workspace/applyEdit",
+        "params": { "edit": { "documentChanges": [
+            { "kind": "create", "uri": uri },
+            { "textDocument": { "uri": uri }, "edits": [{
+                "range": {
+                    "start": { "line": 0, "character": 0 },
+                    "end": { "line": 0, "character": 0 },
+                },
+                "newText": "pub fn f() {}\n",
+            }] },
+        ] } },
+    }));
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "pub fn f() {}\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn workspace_edits_at_one_position_go_in_in_order() {
+    let dir = std::env::temp_dir().join(format!("vim-rs-order-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("lib.rs");
+    std::fs::write(&file, "fn f() {}\n").unwrap();
+    let insert = |text: &str| {
+        serde_json::json!({
+            "range": {
+                "start": { "line": 0, "character": 0 },
+                "end": { "line": 0, "character": 0 },
+            },
+            "newText": text,
+        })
+    };
+    let edits = [insert("use a;\n"), insert("use b;\n"), insert("\n")];
+    let uri = format!("file://{}", file.display());
+    TestEditor::new("").lsp_message(serde_json::json!({
+        "id": 1,
+        "method": "workspace/applyEdit",
+        "params": { "edit": { "changes": { uri: edits } } },
+    }));
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "use a;\nuse b;\n\nfn f() {}\n"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn workspace_edits_through_a_symlink_change_the_open_buffer() {
+    let dir = std::env::temp_dir().join(format!("vim-rs-symlink-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("lib.rs");
+    std::fs::write(&file, "fn f() {}\n").unwrap();
+    std::os::unix::fs::symlink(&file, dir.join("link.rs")).unwrap();
+    let buffer = Buffer::from_text(&file.to_string_lossy(), "fn f() {}\n");
+    let uri = format!("file://{}", dir.join("link.rs").display());
+    TestEditor::with_buffer(buffer)
+        .lsp_message(serde_json::json!({
+            "id": 1,
+            "method": "workspace/applyEdit",
+            "params": { "edit": { "changes": { uri: [{
+                "range": {
+                    "start": { "line": 0, "character": 3 },
+                    "end": { "line": 0, "character": 4 },
+                },
+                "newText": "g",
+            }] } } },
+        }))
+        .assert_buffer("fn g() {}\n");
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "fn f() {}\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn config_is_read_from_its_cache_until_the_file_changes() {
+    let dir = std::env::temp_dir().join(format!("vim-rs-cache-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.toml");
+    let cache = dir.join("cache/config");
+    let script = dir.join("main.py");
+    std::fs::write(&script, "x = 1\n").unwrap();
+    let write = |text: &str, modified: std::time::SystemTime| {
+        std::fs::write(&config, text).unwrap();
+        let file = std::fs::File::options().write(true).open(&config).unwrap();
+        file.set_modified(modified).unwrap();
+    };
+    let then = std::time::SystemTime::now() - std::time::Duration::from_secs(60);
+    write("[filetype.python]\ntextwidth = 79\n", then);
+    let mut editor = TestEditor::with_buffer(Buffer::open(script.to_str().unwrap()).unwrap());
+    let load = |editor: &mut TestEditor| {
+        editor.editor().load_config(&config, Some(&cache)).unwrap();
+        editor.feed(":redir @a<CR>:set tw?<CR><Esc>:redir END<CR>:put a<CR>");
+    };
+    load(&mut editor);
+    assert!(cache.is_file());
+    // Changed, though its time and size tell the same file.
+    write("[filetype.python]\ntextwidth = 72\n", then);
+    load(&mut editor);
+    write(
+        "[filetype.python]\ntextwidth = 72\n",
+        std::time::SystemTime::now(),
+    );
+    load(&mut editor);
+    write(
+        "[filetype.python]\ntextwidth = 60\n",
+        std::time::SystemTime::now(),
+    );
+    std::fs::write(&cache, "damaged").unwrap();
+    load(&mut editor);
+    editor.assert_buffer("x = 1\ntextwidth=79\ntextwidth=72\ntextwidth=72\ntextwidth=60\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_large_config_loads_from_its_cache_faster_than_parsed() {
+    let dir = std::env::temp_dir().join(format!("vim-rs-large-config-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.toml");
+    let cache = dir.join("cache/config");
+    let mut text = String::new();
+    for n in 0..500 {
+        text.push_str(&format!(
+            "[filetype.lang{n}]\ntextwidth = 79\ncolorcolumn = \"+1\"\n\
+             commentstring = \"# %s\"\nexpandtab = true\n\n"
+        ));
+    }
+    std::fs::write(&config, text).unwrap();
+    let mut editor = TestEditor::new("");
+    let mut load = |cache: Option<&std::path::Path>| {
+        let start = std::time::Instant::now();
+        editor.editor().load_config(&config, cache).unwrap();
+        start.elapsed()
+    };
+    load(Some(&cache));
+    let parsed = load(None);
+    let cached = load(Some(&cache));
+    assert!(cached * 10 < parsed, "cached {cached:?}, parsed {parsed:?}");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn palette_lists_every_key_bound_in_normal_mode() {
+    // Between two definitions, for `[m` and `]m` to find one.
+    let mut editor = TestEditor::new("fn a() {}\nfn b() {}\nfn c() {}\n");
+    editor.feed("l");
+    for (lhs, _) in palette::BINDINGS {
+        let keys = lhs.replace("{a-z}", "a").replace("{motion}", "l");
+        assert!(editor.binds(&keys), "{lhs} is listed but not bound");
+    }
+    let listed = |keys: &str| {
+        palette::BINDINGS
+            .iter()
+            .any(|(lhs, _)| match palette::typed(lhs) {
+                typed if typed == *lhs => typed == keys,
+                typed => keys.len() > typed.len() && keys.starts_with(typed),
+            })
+    };
+    let printable = ('!'..='~').map(|c| match c {
+        '<' => "<lt>".to_string(),
+        c => c.to_string(),
+    });
+    let special = [
+        "<Home>",
+        "<End>",
+        "<PageUp>",
+        "<PageDown>",
+        "<A-k>",
+        "<A-l>",
+    ];
+    // Control keys not bound themselves do what the letter does.
+    let control: Vec<String> = ('a'..='z')
+        .filter(|c| !editor.binds(&c.to_string()))
+        .map(|c| format!("<C-{c}>"))
+        .collect();
+    let first: Vec<String> = printable
+        .clone()
+        .chain(special.map(String::from))
+        .chain(control)
+        .collect();
+    let prefixes = ["g", "gc", "z", "zf", "[", "]", "d", "m", "Z", "<C-w>"];
+    let second = prefixes
+        .iter()
+        .flat_map(|prefix| printable.clone().map(move |key| format!("{prefix}{key}")));
+    for keys in first.into_iter().chain(second) {
+        if editor.binds(&keys) {
+            assert!(listed(&keys), "{keys} is bound but not listed");
+        }
+    }
+}
+
+#[test]
+fn language_servers_wait_for_the_project_to_be_trusted() {
+    let dir = std::env::temp_dir().join(format!("vim-rs-untrusted-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("Cargo.toml"), "").unwrap();
+    let path = dir.join("main.rs");
+    std::fs::write(&path, "fn main() {}\n").unwrap();
+    TestEditor::new("")
+        .feed(&format!(":e {}<CR>", path.display()))
+        .assert_buffer("fn main() {}\n")
+        .feed(":redir @a<CR>:messages<CR><Esc>:redir END<CR>:put a<CR>")
+        .assert_buffer("fn main() {}\n\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn history_is_recalled_by_prefix_and_searched_with_ctrl_r() {
+    let mut editor = TestEditor::new("one\ntwo\nthree\n");
+    editor
+        .feed("/two<CR>gg/three<CR>")
+        .assert_cursor(2, 0)
+        .feed("gg/t<Up><Up><CR>")
+        .assert_cursor(1, 0)
+        .feed("gg/t<Up><Up><Down><Down>hree<CR>")
+        .assert_cursor(2, 0)
+        .feed("gg/<C-r><C-r>w<CR>")
+        .assert_cursor(1, 0)
+        .feed(":set ic<CR>:set noic<CR>:set i<Up><Up><CR>G/ONE<CR>")
+        .assert_cursor(0, 0)
+        .feed(":<C-r><C-r>nox");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(
+        frame.contains("(failed reverse-i-search)'nox': "),
+        "{frame:?}"
+    );
+    let frame = String::from_utf8(editor.feed("<BS>").editor().render().unwrap()).unwrap();
+    assert!(
+        frame.contains("(reverse-i-search)'no': set noic"),
+        "{frame:?}"
+    );
+    editor.feed("<Esc>").assert_mode("NORMAL");
+}
+
+#[test]
+fn tab_completes_command_and_option_names_in_a_wildmenu() {
+    let mut editor = TestEditor::new("two\none\n");
+    editor
+        .feed(":set ignorec<Tab><CR>/ONE<CR>")
+        .assert_cursor(1, 0)
+        .feed(":set wr<Tab>");
+    let frame = String::from_utf8(editor.editor().render().unwrap()).unwrap();
+    assert!(frame.contains(" wrapscan "), "{frame:?}");
+    assert!(frame.contains(" writebackup "), "{frame:?}");
+    assert!(frame.contains(":set wrapscan"), "{frame:?}");
+    let frame = String::from_utf8(editor.feed("<Tab>").editor().render().unwrap()).unwrap();
+    assert!(frame.contains(":set writebackup"), "{frame:?}");
+    let frame =
+        String::from_utf8(editor.feed("<Esc>:1nohl<Tab>").editor().render().unwrap()).unwrap();
+    assert!(frame.contains(":1nohlsearch"), "{frame:?}");
+}